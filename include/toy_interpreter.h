@@ -0,0 +1,32 @@
+/* C interface of toy-interpreter, built with `cargo build --release --features ffi`
+ * as `libinterpreter.so` (or `.dylib`, `.dll`). Strings are NUL terminated
+ * UTF-8, returned strings are owned by the interpreter and valid until its
+ * next call. */
+
+#ifndef TOY_INTERPRETER_H
+#define TOY_INTERPRETER_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct ti_interpreter ti_interpreter;
+
+/* New interpreter with builtin functions and constants */
+ti_interpreter *ti_new(void);
+
+/* Evaluates all statements of `src`, giving result of the last one - empty
+ * string if it has none (eg. definition), or NULL on error */
+const char *ti_eval(ti_interpreter *ti, const char *src);
+
+/* Message of error of the last `ti_eval`, NULL if it succeeded */
+const char *ti_last_error(const ti_interpreter *ti);
+
+/* Releases the interpreter, NULL is ignored */
+void ti_free(ti_interpreter *ti);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,408 @@
+//! Best effort analysis of source for editors, given by
+//! `Interpreter::analyze`. Every statement which parses gets its tree and
+//! type, problems of the others are diagnostics, and every occurrence of
+//! symbol is resolved to its kind. Nothing is evaluated - definitions are
+//! only declared for the following statements.
+
+use crate::builtins::Func;
+use crate::check::{self, Diagnostic, Problem};
+use crate::parser::{self, Callee, Expr, Tokens};
+use crate::{import, lexer};
+use crate::{Context, Error, Interpreter, Operator, Result, Span, Token, Value};
+
+/// Kind of value, as far as it is known without evaluating
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Type {
+    Number,
+    String,
+    List,
+    Function,
+    Unknown,
+}
+
+impl Type {
+    pub fn of(val: &Value) -> Self {
+        match val {
+            Value::Int(_) | Value::Float(_) | Value::Decimal(_) => Type::Number,
+            Value::Str(_) => Type::String,
+            Value::List(_) => Type::List,
+            Value::Func(_) => Type::Function,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Type::Number => "number",
+            Type::String => "string",
+            Type::List => "list",
+            Type::Function => "function",
+            Type::Unknown => "unknown",
+        }
+    }
+}
+
+/// Type of result of the expression, `None` if it has no result
+pub fn infer(expr: &Expr) -> Option<Type> {
+    let ty = match expr {
+        Expr::Unit | Expr::Define(_) => return None,
+        Expr::Value(val) => Type::of(val),
+        Expr::Argument(_) | Expr::Variable(_) | Expr::Index(..) => Type::Unknown,
+        Expr::Assign(_, val) | Expr::Const(_, val) | Expr::Let(_, _, val) => return infer(val),
+        // Empty range gives number
+        Expr::Fold(fold) => match infer(&fold.body) {
+            Some(Type::Number) => Type::Number,
+            _ => Type::Unknown,
+        },
+        Expr::Cond(_, then, other) => match (infer(then), infer(other)) {
+            (Some(then), Some(other)) if then == other => then,
+            _ => Type::Unknown,
+        },
+        Expr::Not(_) => Type::Number,
+        Expr::List(_) => Type::List,
+        Expr::Partial(..) => Type::Function,
+        // Only `+` works on anything but numbers
+        Expr::Binary(Operator::Add, left, right) => match (infer(left), infer(right)) {
+            (Some(Type::String), _) | (_, Some(Type::String)) => Type::String,
+            (Some(Type::List), Some(Type::List)) => Type::List,
+            (Some(Type::Unknown), _) | (_, Some(Type::Unknown)) => Type::Unknown,
+            _ => Type::Number,
+        },
+        Expr::Binary(..) => Type::Number,
+        Expr::Call(Callee::Native(native), _) => match native.func {
+            Func::Float(_) | Func::Host(_) => Type::Number,
+            Func::Generic(_) => Type::Unknown,
+        },
+        Expr::Call(Callee::Function(body), _) => infer(&body.expr).unwrap_or(Type::Unknown),
+        Expr::Call(Callee::Argument(_), _) => Type::Unknown,
+    };
+    Some(ty)
+}
+
+/// Statement which parses, line counts from 1
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    pub line: usize,
+    pub expr: Expr,
+    pub ty: Option<Type>,
+}
+
+/// Occurrence of symbol name, `kind` is as given by `Context::kind`, or
+/// `None` for undefined symbol
+#[derive(Debug, Clone, PartialEq)]
+pub struct Usage {
+    pub name: String,
+    pub line: usize,
+    pub span: Span,
+    pub kind: Option<&'static str>,
+    /// Defined function, its argument or assigned variable
+    pub definition: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Analysis {
+    pub statements: Vec<Statement>,
+    /// Ordered by line
+    pub diagnostics: Vec<Diagnostic>,
+    pub usages: Vec<Usage>,
+}
+
+fn usage(
+    name: &str,
+    line: usize,
+    span: Span,
+    kind: Option<&'static str>,
+    definition: bool,
+) -> Usage {
+    Usage {
+        name: name.to_owned(),
+        line,
+        span,
+        kind,
+        definition,
+    }
+}
+
+/// Symbols of expression tokens, names bound by `let`, `sum` and `prod` are
+/// locals till the end of expression. Names of arguments in brackets of
+/// calls are arguments of called functions.
+fn expression(context: &Context, tokens: &[(Token, Span)], line: usize) -> Vec<Usage> {
+    let mut locals: Vec<&str> = vec![];
+    let mut result = vec![];
+    let mut bound = false;
+    // Function called by every open bracket
+    let mut calls: Vec<Option<&str>> = vec![];
+    for (idx, (token, span)) in tokens.iter().enumerate() {
+        let previous = idx.checked_sub(1).map(|idx| &tokens[idx].0);
+        // `sum` and `prod` followed by loop variable are keywords
+        let fold = match (token, tokens.get(idx + 1)) {
+            (Token::Id(name), Some((Token::Assign(_), _))) => name == "sum" || name == "prod",
+            _ => false,
+        };
+
+        match token {
+            _ if fold => (),
+            Token::Id(name) => {
+                let kind = if locals.contains(&name.as_str()) {
+                    Some("local")
+                } else {
+                    context.kind(name)
+                };
+                result.push(usage(name, line, *span, kind, false));
+            }
+            Token::LBracket => calls.push(match previous {
+                Some(Token::Id(name)) => Some(name),
+                _ => None,
+            }),
+            Token::RBracket => {
+                calls.pop();
+            }
+            Token::Assign(var) => {
+                let span = Span {
+                    start: span.start,
+                    end: span.start + var.len(),
+                };
+                let named = matches!(previous, Some(Token::LBracket) | Some(Token::Comma))
+                    && matches!(calls.last(), Some(Some(func)) if context.get_params(func).contains(var));
+                let (kind, definition) = if named {
+                    ("argument", false)
+                } else if previous == Some(&Token::Const) {
+                    ("constant", true)
+                } else if bound {
+                    locals.push(var);
+                    ("local", true)
+                } else if locals.contains(&var.as_str()) {
+                    ("local", false)
+                } else {
+                    ("variable", true)
+                };
+                result.push(usage(var, line, span, Some(kind), definition));
+            }
+            _ => (),
+        }
+        bound = fold || *token == Token::Let;
+    }
+    result
+}
+
+/// Symbols of the statement, before it is declared
+fn usages(context: &Context, statement: &Tokens, line: usize) -> Vec<Usage> {
+    let mut tokens = vec![];
+    let mut ahead = statement.clone();
+    while ahead.peek().is_some() {
+        let span = ahead.span();
+        tokens.extend(ahead.next().map(|token| (token, span)));
+    }
+
+    // Function definition is name, arguments and body seeing only them
+    if let Some(arrow) = tokens.iter().position(|(token, _)| *token == Token::Func) {
+        let (head, body) = tokens.split_at(arrow);
+        // Arguments with defaults are assigned, and default values do not
+        // see the arguments
+        let defaulted = head
+            .iter()
+            .position(|(token, _)| matches!(token, Token::Assign(_)))
+            .unwrap_or(head.len());
+        let (head, defaults) = head.split_at(defaulted);
+
+        let mut result = vec![];
+        let mut args = vec![];
+        for (idx, (token, span)) in head.iter().enumerate() {
+            if let Token::Id(name) = token {
+                let kind = if idx == 0 { "function" } else { "argument" };
+                result.push(usage(name, line, *span, Some(kind), true));
+                args.extend((idx > 0).then(|| name.clone()));
+            }
+        }
+        let outer = Context::function_ctx(vec![], context);
+        for (token, span) in defaults {
+            match token {
+                Token::Assign(arg) => {
+                    let span = Span {
+                        start: span.start,
+                        end: span.start + arg.len(),
+                    };
+                    result.push(usage(arg, line, span, Some("argument"), true));
+                    args.push(arg.clone());
+                }
+                Token::Id(_) => result.extend(expression(&outer, &[(token.clone(), *span)], line)),
+                _ => (),
+            }
+        }
+        result.extend(expression(
+            &Context::function_ctx(args, context),
+            body,
+            line,
+        ));
+        return result;
+    }
+
+    expression(context, &tokens, line)
+}
+
+impl Analysis {
+    fn error(&mut self, line: usize, src: &str, err: Error) {
+        self.diagnostics.push(Diagnostic {
+            line,
+            column: err.offset().map(|offset| src[..offset].chars().count() + 1),
+            problem: Problem::Error(err),
+        });
+    }
+
+    /// Statements of the line, every one even if the previous ones fail
+    fn statements(&mut self, context: &mut Context, line: usize, src: &str) -> Result<()> {
+        let tokens: Result<Vec<_>> = lexer::tokenize(src).collect();
+        for statement in parser::statements(tokens?) {
+            self.usages.extend(usages(context, &statement, line));
+            match context.parse(statement) {
+                Ok(expr) => {
+                    check::declare(context, &expr);
+                    if !matches!(expr, Expr::Unit | Expr::Define(_)) {
+                        context.set_last_result(Value::Float(f64::NAN));
+                    }
+                    if expr != Expr::Unit {
+                        let ty = infer(&expr);
+                        self.statements.push(Statement { line, expr, ty });
+                    }
+                }
+                Err(err) => self.error(line, src, err),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Analysis of every line, lines with commands are skipped and imported
+/// files are loaded. Duplicate definitions are warnings.
+pub fn analyze(interpreter: &mut Interpreter, src: &str) -> Analysis {
+    let mut analysis = Analysis::default();
+
+    for (idx, line) in src.lines().enumerate() {
+        if line.trim_start().starts_with(':') {
+            continue;
+        }
+
+        let result = match import::parse(line) {
+            Some(import) => import.and_then(|import| import::load(interpreter, &import).map(drop)),
+            None => analysis.statements(interpreter.context_mut(), idx + 1, line),
+        };
+        if let Err(err) = result {
+            analysis.error(idx + 1, line, err);
+        }
+
+        analysis
+            .diagnostics
+            .extend(
+                interpreter
+                    .context_mut()
+                    .take_warnings()
+                    .into_iter()
+                    .map(|warning| Diagnostic {
+                        line: idx + 1,
+                        column: None,
+                        problem: Problem::Warning(warning),
+                    }),
+            );
+    }
+
+    for err in check::duplicates(src) {
+        if let Error::DuplicateDefinition { second, .. } = err {
+            analysis.diagnostics.push(Diagnostic {
+                line: second,
+                column: None,
+                problem: Problem::Warning(err.to_string()),
+            });
+        }
+    }
+    analysis
+        .diagnostics
+        .sort_by_key(|diagnostic| diagnostic.line);
+
+    analysis
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn partial() {
+        let interpreter = Interpreter::new();
+        let analysis = interpreter.analyze("a = 1 +; b = \"x\" + 2\nsq x => x * y\nsq 3");
+        let lines: Vec<_> = analysis
+            .statements
+            .iter()
+            .map(|statement| (statement.line, statement.ty))
+            .collect();
+        assert_eq!(vec![(1, Some(Type::String))], lines);
+        let diagnostics: Vec<_> = analysis.diagnostics.iter().map(|d| d.line).collect();
+        assert_eq!(vec![1, 2, 3], diagnostics);
+    }
+
+    #[test]
+    fn types() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .eval("sq x => x * x; first xs => xs[0]")
+            .unwrap();
+        let ty = |src| infer(&interpreter.parse(src).unwrap());
+        assert_eq!(Some(Type::Number), ty("sq 2 + sqrt(4)"));
+        assert_eq!(Some(Type::Unknown), ty("len [1]"));
+        assert_eq!(Some(Type::List), ty("[1] + [sq 2]"));
+        assert_eq!(Some(Type::Unknown), ty("first([\"a\"]) + 1"));
+        assert_eq!(Some(Type::Function), ty("sq"));
+        assert_eq!(Some(Type::Number), ty("sq(2) == 4 ? 1 : sq 3"));
+        assert_eq!(Some(Type::Unknown), ty("sq(2) == 4 ? 1 : \"a\""));
+        assert_eq!(None, ty("f x => x"));
+    }
+
+    #[test]
+    fn symbols() {
+        let mut interpreter = Interpreter::new();
+        interpreter.eval("r = 2").unwrap();
+        let analysis = interpreter
+            .analyze("area x => pi * x * x\narea r + q\nlet t = r in t * r\nsum i = 1 to r of i\nf a b=pi => b\nf(1, b=2)");
+        let usages: Vec<_> = analysis
+            .usages
+            .iter()
+            .map(|usage| {
+                (
+                    usage.name.as_str(),
+                    usage.line,
+                    usage.span.start,
+                    usage.kind,
+                    usage.definition,
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                ("area", 1, 0, Some("function"), true),
+                ("x", 1, 5, Some("argument"), true),
+                ("pi", 1, 10, Some("constant"), false),
+                ("x", 1, 15, Some("argument"), false),
+                ("x", 1, 19, Some("argument"), false),
+                ("area", 2, 0, Some("function"), false),
+                ("r", 2, 5, Some("variable"), false),
+                ("q", 2, 9, None, false),
+                ("t", 3, 4, Some("local"), true),
+                ("r", 3, 8, Some("variable"), false),
+                ("t", 3, 13, Some("local"), false),
+                ("r", 3, 17, Some("variable"), false),
+                ("i", 4, 4, Some("local"), true),
+                ("r", 4, 13, Some("variable"), false),
+                ("i", 4, 18, Some("local"), false),
+                ("f", 5, 0, Some("function"), true),
+                ("a", 5, 2, Some("argument"), true),
+                ("b", 5, 4, Some("argument"), true),
+                ("pi", 5, 6, Some("constant"), false),
+                ("b", 5, 12, Some("argument"), false),
+                ("f", 6, 0, Some("function"), false),
+                ("b", 6, 5, Some("argument"), false),
+            ],
+            usages
+        );
+        // Session is not changed
+        assert!(interpreter.context().get_arity("area").is_none());
+    }
+}
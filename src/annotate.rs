@@ -0,0 +1,55 @@
+use crate::Interpreter;
+
+const MARKER: &str = "# =>";
+
+/// Source line without annotation added by previous run
+fn strip(line: &str) -> &str {
+    match line.find(MARKER) {
+        Some(idx) => line[..idx].trim_end(),
+        None => line,
+    }
+}
+
+/// Evaluates source line by line, appending result to every line which has
+/// any; running it again on annotated source just refreshes annotations
+pub fn annotate(src: &str) -> String {
+    let mut interpreter = Interpreter::new();
+
+    src.lines()
+        .map(|line| {
+            let line = strip(line);
+            match interpreter.eval(line) {
+                Ok(Some(val)) => {
+                    let val = interpreter.context().rounded(val);
+                    format!("{} {} {}", line, MARKER, val)
+                }
+                Ok(None) => line.to_owned(),
+                Err(err) => format!("{} {} Error[{}]: {}", line, MARKER, err.code(), err),
+            }
+        })
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn results() {
+        let src = "# Area\nr = 2\n\nsq x => x * x\nsq r\npi * r * r # of circle\n";
+        let expected =
+            "# Area\nr = 2 # => 2\n\nsq x => x * x\nsq r # => 4\npi * r * r # of circle # => 12.5663706143592\n";
+        assert_eq!(expected, annotate(src));
+        assert_eq!(expected, annotate(expected));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            "a = 1 # => 1\nb # => Error[E010]: Undefined symbol: b\n",
+            annotate("a = 1\nb")
+        );
+    }
+}
@@ -0,0 +1,217 @@
+use crate::envelope::{self, Kind};
+use crate::{Error, Result};
+use std::env;
+use std::fs;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, Once, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+const INTERVAL: Duration = Duration::from_secs(30);
+
+/// Autosaves alive, saved by the panic hook
+static REGISTERED: Mutex<Vec<Weak<Mutex<Pending>>>> = Mutex::new(vec![]);
+
+/// The panic hook is installed by the first autosave, and never replaced
+static HOOK: Once = Once::new();
+
+/// Definitions waiting to be saved, shared with the saving thread and the
+/// panic hook until autosave is dropped
+struct Pending {
+    path: PathBuf,
+    definitions: Vec<String>,
+    saved: Vec<String>,
+    /// Of the last save, until it is reported
+    failure: Option<Error>,
+}
+
+/// Dumps session definitions to the recovery file every 30 seconds from
+/// background thread, so they can be restored after a crash
+pub struct Autosave {
+    path: PathBuf,
+    pending: Arc<Mutex<Pending>>,
+    /// Dropped to stop the saving thread
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+pub fn default_path() -> PathBuf {
+    env::var_os("HOME")
+        .map(PathBuf::from)
+        .unwrap_or_default()
+        .join(".toy-interpreter.recovery")
+}
+
+/// Writes through temporary file, so crash while saving doesn't corrupt
+/// the previous dump
+fn write(path: &Path, definitions: &[String]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    let content: String = definitions.iter().map(|def| format!("{}\n", def)).collect();
+
+    fs::write(&tmp, envelope::wrap(Kind::Session, &content))
+        .and_then(|_| fs::rename(&tmp, path))
+        .map_err(|err| format!("Cannot save recovery file {}: {}", path.display(), err).into())
+}
+
+pub fn recover(path: &Path) -> Result<Vec<String>> {
+    let content = fs::read_to_string(path)
+        .map_err(|err| format!("Cannot read recovery file {}: {}", path.display(), err))?;
+    let definitions = envelope::open(Kind::Session, &content)
+        .map_err(|err| format!("Cannot recover {}: {}", path.display(), err))?;
+    Ok(definitions.lines().map(str::to_owned).collect())
+}
+
+impl Pending {
+    /// Empty session is never saved, so just starting interpreter doesn't
+    /// overwrite the previous recovery file
+    fn save(&mut self) {
+        if self.definitions.is_empty() || self.definitions == self.saved {
+            return;
+        }
+
+        match write(&self.path, &self.definitions) {
+            Ok(()) => self.saved = self.definitions.clone(),
+            Err(err) => self.failure = Some(err),
+        }
+    }
+}
+
+impl Autosave {
+    pub fn new(path: PathBuf) -> Self {
+        let pending = Arc::new(Mutex::new(Pending {
+            path: path.clone(),
+            definitions: vec![],
+            saved: vec![],
+            failure: None,
+        }));
+
+        HOOK.call_once(|| {
+            let hook = panic::take_hook();
+            panic::set_hook(Box::new(move |info| {
+                // Panic may occur while lock is held, so never block here
+                if let Ok(registered) = REGISTERED.try_lock() {
+                    for pending in registered.iter().filter_map(Weak::upgrade) {
+                        if let Ok(mut pending) = pending.try_lock() {
+                            pending.save();
+                        }
+                    }
+                }
+                hook(info);
+            }));
+        });
+        if let Ok(mut registered) = REGISTERED.lock() {
+            registered.push(Arc::downgrade(&pending));
+        }
+
+        let (stop, stopped) = mpsc::channel::<()>();
+        let shared = Arc::clone(&pending);
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(INTERVAL) {
+                match shared.lock() {
+                    Ok(mut pending) => pending.save(),
+                    Err(_) => break,
+                }
+            }
+        });
+
+        Autosave {
+            path,
+            pending,
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// To be called after every evaluated line, the definitions are written
+    /// by the next periodic save. Failure of the previous save is returned,
+    /// once - the next save is attempted anyway.
+    pub fn update(&mut self, definitions: Vec<String>) -> Result<()> {
+        self.set(definitions, false)
+    }
+
+    /// Writes the definitions right away
+    pub fn save(&mut self, definitions: Vec<String>) -> Result<()> {
+        self.set(definitions, true)
+    }
+
+    fn set(&mut self, definitions: Vec<String>, save: bool) -> Result<()> {
+        let mut pending = self.pending.lock().map_err(|_| "Autosave is broken")?;
+        pending.definitions = definitions;
+        if save {
+            pending.save();
+        }
+        pending.failure.take().map_or(Ok(()), Err)
+    }
+}
+
+/// Stops the saving thread and unregisters from the panic hook, the
+/// definitions are not saved once more
+impl Drop for Autosave {
+    fn drop(&mut self) {
+        if let Ok(mut registered) = REGISTERED.lock() {
+            registered.retain(|pending| pending.as_ptr() != Arc::as_ptr(&self.pending));
+        }
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn save_and_recover() {
+        let path = env::temp_dir().join(format!("toy-autosave-{}.recovery", std::process::id()));
+        let definitions = vec!["a = 4".to_owned(), "add x y => x + y".to_owned()];
+
+        let mut autosave = Autosave::new(path.clone());
+        autosave.save(definitions.clone()).unwrap();
+        assert_eq!(Ok(definitions.clone()), recover(&path));
+
+        autosave.save(vec![]).unwrap();
+        assert_eq!(2, recover(&path).unwrap().len());
+
+        autosave.update(vec!["a = 5".to_owned()]).unwrap();
+        assert_eq!(2, recover(&path).unwrap().len());
+
+        fs::write(&path, "a = 4\n").unwrap();
+        recover(&path).unwrap_err();
+
+        fs::remove_file(&path).unwrap();
+
+        // Failure is reported once
+        let mut autosave = Autosave::new(path.join("missing"));
+        autosave.save(definitions.clone()).unwrap_err();
+        autosave.update(definitions).unwrap();
+    }
+
+    #[test]
+    fn dropped() {
+        let path = env::temp_dir().join(format!("toy-autosave-{}.dropped", std::process::id()));
+        let autosave = Autosave::new(path);
+        let pending = Arc::downgrade(&autosave.pending);
+        let registered = || {
+            REGISTERED
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|registered| registered.ptr_eq(&pending))
+        };
+        assert!(registered());
+
+        // Neither the thread nor the hook keeps the definitions
+        drop(autosave);
+        assert!(!registered());
+        assert!(pending.upgrade().is_none());
+    }
+}
@@ -0,0 +1,134 @@
+use crate::clock;
+use crate::{Context, Error, Result, Value};
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub struct Stats {
+    pub runs: usize,
+    pub min: Duration,
+    pub mean: Duration,
+    pub max: Duration,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} runs: min {:?}, mean {:?}, max {:?}",
+            self.runs, self.min, self.mean, self.max
+        )
+    }
+}
+
+/// Calls function `runs` times with given arguments, measuring every call
+pub fn bench(context: &mut Context, func: &str, args: &[Value], runs: usize) -> Result<Stats> {
+    let (arity, callee) = match (context.get_arity(func), context.get_func(func)) {
+        (Some(arity), Some(callee)) => (arity, callee),
+        _ => return Err(Error::UndefinedSymbol(func.to_owned())),
+    };
+
+    if arity != args.len() {
+        return Err(Error::ArityMismatch {
+            func: func.to_owned(),
+            expected: arity,
+            got: args.len(),
+        });
+    }
+
+    measure(runs, || {
+        callee.call(context, args);
+        Ok(())
+    })
+}
+
+/// Runs `run` given number of times, measuring every run. The first failed
+/// run stops measuring.
+pub fn measure(runs: usize, mut run: impl FnMut() -> Result<()>) -> Result<Stats> {
+    if runs == 0 {
+        return Err("Number of runs has to be positive".into());
+    }
+
+    let mut total = Duration::default();
+    let mut min = Duration::MAX;
+    let mut max = Duration::default();
+
+    for _ in 0..runs {
+        let start = clock::now().ok_or_else(|| Error::from("There is no clock to measure runs"))?;
+        run()?;
+        let time = start.elapsed();
+
+        total += time;
+        min = min.min(time);
+        max = max.max(time);
+    }
+
+    Ok(Stats {
+        runs,
+        min,
+        mean: total / runs as u32,
+        max,
+    })
+}
+
+/// Parses `:bench` arguments: function name, its arguments and number of runs
+pub fn parse_args(args: &str) -> Result<(&str, Vec<Value>, usize)> {
+    let usage = || "Usage: :bench function args... runs".to_owned();
+
+    let mut words: Vec<_> = args.split_whitespace().collect();
+    let runs = words.pop().ok_or_else(usage)?;
+    let runs = runs
+        .parse()
+        .map_err(|err| format!("Invalid number of runs: {}, {}", runs, err))?;
+
+    let (func, args) = words.split_first().ok_or_else(usage)?;
+    let args: Result<Vec<_>> = args
+        .iter()
+        .map(|arg| {
+            arg.parse()
+                .map_err(|err| format!("Invalid argument: {}, {}", arg, err).into())
+        })
+        .collect();
+
+    Ok((func, args?, runs))
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn args() {
+        assert_eq!(
+            Ok(("f", vec![Value::Int(10)], 1000)),
+            parse_args("f 10 1000")
+        );
+        assert_eq!(Ok(("f", vec![], 5)), parse_args(" f  5 "));
+        parse_args("").unwrap_err();
+        parse_args("10").unwrap_err();
+        parse_args("f x 10").unwrap_err();
+        parse_args("f 10 x").unwrap_err();
+    }
+
+    #[test]
+    fn runs() {
+        let mut context = Context::new();
+        let tokens = crate::lexer::tokenize("sq x => x * x")
+            .map(|t| t.unwrap())
+            .collect();
+        let statement = crate::parser::statements(tokens).remove(0);
+        context
+            .parse(statement)
+            .unwrap()
+            .evaluate(&mut context, &[]);
+
+        let stats = bench(&mut context, "sq", &[Value::Int(3)], 10).unwrap();
+        assert_eq!(10, stats.runs);
+        assert!(stats.min <= stats.mean && stats.mean <= stats.max);
+
+        bench(&mut context, "sq", &[], 10).unwrap_err();
+        bench(&mut context, "sq", &[Value::Int(3)], 0).unwrap_err();
+        bench(&mut context, "cube", &[Value::Int(3)], 10).unwrap_err();
+    }
+}
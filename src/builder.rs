@@ -0,0 +1,236 @@
+//! Configuration of `Interpreter` in one place, started with
+//! `Interpreter::builder()`
+
+use crate::context::{DEFAULT_EQ_EPSILON, DEFAULT_PRECISION};
+use crate::feature::{Feature, Features};
+use crate::limits::Limits;
+use crate::sink::OutputSink;
+use crate::trace::Tracer;
+use crate::{Capture, Context, Interpreter, Mode, Prelude, Result};
+
+/// Configures interpreter before it is created, every setting but prelude,
+/// output and tracer can be still changed through its `Context`
+#[derive(Default)]
+pub struct Builder {
+    prelude: Prelude,
+    features: Features,
+    limits: Limits,
+    mode: Mode,
+    capture: Capture,
+    precision: Option<usize>,
+    eq_epsilon: Option<f64>,
+    force: bool,
+    partial: bool,
+    strict: bool,
+    selfcheck: bool,
+    output: Option<Box<dyn OutputSink>>,
+    tracer: Option<Box<dyn Tracer>>,
+}
+
+impl Builder {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Symbols defined before any evaluation, builtin math by default
+    pub fn with_prelude(mut self, prelude: Prelude) -> Self {
+        self.prelude = prelude;
+        self
+    }
+
+    /// Enables or disables single feature
+    pub fn feature(mut self, feature: Feature, enabled: bool) -> Self {
+        self.features.set(feature, enabled);
+        self
+    }
+
+    /// Replaces all features, eg. with `Features::minimal()`
+    pub fn with_features(mut self, features: Features) -> Self {
+        self.features = features;
+        self
+    }
+
+    /// Bounds of every evaluated statement
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Backend of numbers with fraction
+    pub fn with_mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// How functions see variables defined outside of them
+    pub fn with_capture(mut self, capture: Capture) -> Self {
+        self.capture = capture;
+        self
+    }
+
+    /// Significant digits of shown results, validated by `build`
+    pub fn with_precision(mut self, digits: usize) -> Self {
+        self.precision = Some(digits);
+        self
+    }
+
+    /// Tolerance of `==`, validated by `build`
+    pub fn with_eq_epsilon(mut self, epsilon: f64) -> Self {
+        self.eq_epsilon = Some(epsilon);
+        self
+    }
+
+    /// Receiver of warnings, as they are emitted by `eval`
+    pub fn with_output(mut self, output: impl OutputSink + 'static) -> Self {
+        self.output = Some(Box::new(output));
+        self
+    }
+
+    /// Receiver of phases of evaluated statements, see `trace`
+    pub fn with_tracer(mut self, tracer: impl Tracer + 'static) -> Self {
+        self.tracer = Some(Box::new(tracer));
+        self
+    }
+
+    /// Allows redefinitions breaking other functions
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Calls with fewer arguments than arity of function are its partial
+    /// applications
+    pub fn partial(mut self, partial: bool) -> Self {
+        self.partial = partial;
+        self
+    }
+
+    /// Forbids redefinitions of functions, even forced ones
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Checks that every statement parses back after it is printed
+    pub fn selfcheck(mut self, selfcheck: bool) -> Self {
+        self.selfcheck = selfcheck;
+        self
+    }
+
+    pub fn build(self) -> Result<Interpreter> {
+        let mut context = Context::with_prelude(self.prelude);
+        context.set_features(self.features);
+        context.set_limits(self.limits);
+        context.set_mode(self.mode);
+        context.set_capture(self.capture);
+        context.set_precision(self.precision.unwrap_or(DEFAULT_PRECISION))?;
+        context.set_eq_epsilon(self.eq_epsilon.unwrap_or(DEFAULT_EQ_EPSILON))?;
+        context.set_force(self.force);
+        context.set_partial(self.partial);
+        context.set_strict(self.strict);
+
+        let mut interpreter = Interpreter::with_context(context);
+        interpreter.output = self.output;
+        if self.tracer.is_some() {
+            interpreter.tracer = self.tracer;
+        }
+        interpreter.set_selfcheck(self.selfcheck);
+        Ok(interpreter)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::sink::{Callback, Event};
+    use crate::{Error, Value};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn features() {
+        let mut interpreter = Interpreter::builder()
+            .with_features(Features::minimal())
+            .feature(Feature::Definitions, true)
+            .build()
+            .unwrap();
+        interpreter.eval("sq a => a * a").unwrap();
+        assert_eq!(Ok(Some(Value::Int(9))), interpreter.eval("sq 3"));
+
+        let err = interpreter.eval("x = 1").unwrap_err();
+        assert!(matches!(err, Error::Parse { .. }));
+        interpreter.eval("import \"lib.calc\"").unwrap_err();
+        interpreter.eval("f a => (b = a)").unwrap_err();
+        for src in &[
+            "\"text\"",
+            "[1, 2]",
+            "sq(3)[0]",
+            "sum i = 1 to 3 of i",
+            "let a = 2 in a",
+            "1 ? 2 : 3",
+            "4 |> sq",
+        ] {
+            match interpreter.eval(src) {
+                Err(Error::Parse { message, .. }) => {
+                    assert!(
+                        message.ends_with(" are disabled in this interpreter"),
+                        "{}: {}",
+                        src,
+                        message
+                    )
+                }
+                result => panic!("{} gives {:?}", src, result),
+            }
+        }
+
+        interpreter
+            .context_mut()
+            .set_feature(Feature::Assignments, true);
+        assert_eq!(Ok(Some(Value::Int(1))), interpreter.eval("x = 1"));
+    }
+
+    #[test]
+    fn settings() {
+        let mut interpreter = Interpreter::builder()
+            .with_prelude(Prelude::Empty)
+            .with_limits(Limits::default().operations(4))
+            .with_mode(Mode::Decimal)
+            .strict(true)
+            .build()
+            .unwrap();
+        interpreter.eval("sqrt 4").unwrap_err();
+        interpreter.eval("pi").unwrap_err();
+        assert_eq!(Mode::Decimal, interpreter.context().mode());
+
+        interpreter.eval("sq x => x * x").unwrap();
+        interpreter.eval("sq(sq 2)").unwrap();
+        interpreter.eval("sq(sq(sq 2))").unwrap_err();
+        interpreter.eval("sq x => x ^ 2").unwrap_err();
+
+        assert!(Interpreter::builder().with_precision(0).build().is_err());
+        assert!(Interpreter::builder()
+            .with_eq_epsilon(-1.0)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn output() {
+        let events = Rc::new(RefCell::new(vec![]));
+        let sink = Rc::clone(&events);
+        let mut interpreter = Interpreter::builder()
+            .with_output(Callback(move |event| sink.borrow_mut().push(event)))
+            .force(true)
+            .build()
+            .unwrap();
+        interpreter.eval("sqrt x => x").unwrap();
+        assert!(interpreter.context_mut().take_warnings().is_empty());
+        assert_eq!(
+            vec![Event::Warning(
+                "Builtin function sqrt is shadowed".to_owned()
+            )],
+            *events.borrow()
+        );
+    }
+}
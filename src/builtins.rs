@@ -0,0 +1,121 @@
+use crate::lexer::{approx_eq, truth};
+use crate::{Result, Value};
+use std::f64::consts::{E, PI, TAU};
+use std::sync::Arc;
+
+pub type Builtin = fn(&[f64]) -> f64;
+
+/// Builtin taking values of any kind, not only numbers
+pub type Generic = fn(&[Value]) -> Value;
+
+/// Function registered by the host, its error stops evaluation
+pub type Host = Arc<dyn Fn(&[f64]) -> Result<f64> + Send + Sync>;
+
+/// Implementation of native function
+#[derive(Clone)]
+pub enum Func {
+    Float(Builtin),
+    Generic(Generic),
+    Host(Host),
+}
+
+/// Symbols defined in new interpreter
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Prelude {
+    /// No symbols at all, the host defines everything evaluated code uses
+    Empty,
+    /// Builtin functions and constants
+    #[default]
+    Math,
+}
+
+/// Read only variables
+pub const CONSTANTS: &[(&str, f64)] = &[("pi", PI), ("e", E), ("tau", TAU)];
+
+/// Name, arity and implementation of every builtin function
+pub const BUILTINS: &[(&str, usize, Builtin)] = &[
+    ("approx", 3, approx),
+    ("sin", 1, |args| args[0].sin()),
+    ("cos", 1, |args| args[0].cos()),
+    ("tan", 1, |args| args[0].tan()),
+    ("asin", 1, |args| args[0].asin()),
+    ("acos", 1, |args| args[0].acos()),
+    ("atan", 1, |args| args[0].atan()),
+    ("sinh", 1, |args| args[0].sinh()),
+    ("cosh", 1, |args| args[0].cosh()),
+    ("tanh", 1, |args| args[0].tanh()),
+    ("sqrt", 1, |args| args[0].sqrt()),
+    ("cbrt", 1, |args| args[0].cbrt()),
+    ("exp", 1, |args| args[0].exp()),
+    ("ln", 1, |args| args[0].ln()),
+    ("log2", 1, |args| args[0].log2()),
+    ("log10", 1, |args| args[0].log10()),
+    ("abs", 1, |args| args[0].abs()),
+    ("floor", 1, |args| args[0].floor()),
+    ("ceil", 1, |args| args[0].ceil()),
+    ("round", 1, |args| args[0].round()),
+    ("trunc", 1, |args| args[0].trunc()),
+    ("min", 2, |args| args[0].min(args[1])),
+    ("max", 2, |args| args[0].max(args[1])),
+    ("atan2", 2, |args| args[0].atan2(args[1])),
+    ("hypot", 2, |args| args[0].hypot(args[1])),
+];
+
+/// Name, arity and implementation of builtins not limited to numbers
+pub const GENERICS: &[(&str, usize, Generic)] = &[("len", 1, len)];
+
+/// `len xs` - number of list elements or string characters, `NaN` for numbers
+fn len(args: &[Value]) -> Value {
+    match args[0].length() {
+        Some(len) => Value::Int(len as i64),
+        None => Value::Float(f64::NAN),
+    }
+}
+
+/// `approx a b eps` - equality with explicitly given tolerance
+fn approx(args: &[f64]) -> f64 {
+    truth(approx_eq(args[0], args[1], args[2]))
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn call(name: &str, args: &[f64]) -> f64 {
+        let (_, arity, func) = BUILTINS.iter().find(|(n, _, _)| *n == name).unwrap();
+        assert_eq!(*arity, args.len());
+        func(args)
+    }
+
+    #[test]
+    fn math() {
+        assert_eq!(3.0, call("sqrt", &[9.0]));
+        assert_eq!(1.0, call("exp", &[0.0]));
+        assert_eq!(0.0, call("ln", &[1.0]));
+        assert_eq!(2.0, call("abs", &[-2.0]));
+        assert_eq!(2.0, call("max", &[1.0, 2.0]));
+        assert_eq!(5.0, call("hypot", &[3.0, 4.0]));
+        assert_eq!(1.0, call("approx", &[1.0, 1.05, 0.1]));
+    }
+
+    #[test]
+    fn generics() {
+        let list = Value::from(vec![Value::Int(1), Value::Int(2)]);
+        assert_eq!(Value::Int(2), len(&[list]));
+        assert_eq!(Value::Int(3), len(&[Value::from("abc")]));
+        assert!(len(&[Value::Int(3)]).as_f64().is_nan());
+    }
+
+    #[test]
+    fn unique_names() {
+        let names: Vec<_> = BUILTINS
+            .iter()
+            .map(|(name, _, _)| name)
+            .chain(GENERICS.iter().map(|(name, _, _)| name))
+            .collect();
+        for (idx, name) in names.iter().enumerate() {
+            assert!(names[idx + 1..].iter().all(|n| n != name));
+        }
+    }
+}
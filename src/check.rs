@@ -0,0 +1,195 @@
+use crate::parser::{self, Callee, Expr};
+use crate::{import, lexer};
+use crate::{Context, Error, Interpreter, Lang, Result, Token, Value};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    Error(Error),
+    Warning(String),
+}
+
+/// Problem found in source, line and column count from 1
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub column: Option<usize>,
+    pub problem: Problem,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:", self.line)?;
+        if let Some(column) = self.column {
+            write!(f, "{}:", column)?;
+        }
+        match &self.problem {
+            Problem::Error(err) => write!(f, " {}", Lang::En.error(err)),
+            Problem::Warning(warning) => write!(f, " {}", Lang::En.warning(warning)),
+        }
+    }
+}
+
+/// Variables assigned anywhere in the expression, also inside bodies of
+/// called functions, get value which is not known without evaluating
+pub(crate) fn declare(context: &mut Context, expr: &Expr) {
+    match expr {
+        Expr::Assign(var, val) => {
+            declare(context, val);
+            // Assignments to constants are rejected while parsing, and host
+            // variables are never substituted
+            if !context.is_external(var) {
+                context.update_var(var, Value::Float(f64::NAN)).ok();
+            }
+        }
+        Expr::Const(var, val) => {
+            declare(context, val);
+            context.update_const(var, Value::Float(f64::NAN)).ok();
+        }
+        Expr::Binary(_, left, right) => {
+            declare(context, left);
+            declare(context, right);
+        }
+        Expr::Not(expr) => declare(context, expr),
+        Expr::List(items) | Expr::Partial(_, items) => {
+            for item in items {
+                declare(context, item);
+            }
+        }
+        Expr::Index(list, idx) => {
+            declare(context, list);
+            declare(context, idx);
+        }
+        Expr::Let(_, val, body) => {
+            declare(context, val);
+            declare(context, body);
+        }
+        Expr::Cond(cond, then, other) => {
+            declare(context, cond);
+            declare(context, then);
+            declare(context, other);
+        }
+        Expr::Fold(fold) => {
+            declare(context, &fold.from);
+            declare(context, &fold.to);
+            declare(context, &fold.body);
+        }
+        Expr::Call(callee, args) => {
+            for arg in args {
+                declare(context, arg);
+            }
+            if let Callee::Function(body) = callee {
+                declare(context, &body.expr);
+            }
+        }
+        Expr::Define(func) => context.update_func(func),
+        Expr::Unit | Expr::Value(_) | Expr::Argument(_) | Expr::Variable(_) => (),
+    }
+}
+
+/// Symbol defined by the statement: function or constant. Variables are
+/// meant to be assigned again.
+fn definition(tokens: &[Token]) -> Option<&str> {
+    match tokens {
+        [Token::Id(name), ..] if tokens.contains(&Token::Func) => Some(name),
+        [Token::Const, Token::Assign(name), ..] => Some(name),
+        _ => None,
+    }
+}
+
+/// Symbols defined more than once in the source, every redefinition is
+/// reported with the previous definition
+pub fn duplicates(src: &str) -> Vec<Error> {
+    let mut defined: HashMap<String, usize> = HashMap::new();
+    let mut duplicates = vec![];
+
+    for (idx, line) in src.lines().enumerate() {
+        if line.trim_start().starts_with(':') || import::parse(line).is_some() {
+            continue;
+        }
+
+        // Invalid lines are reported by evaluating or checking them
+        let tokens = match lexer::tokenize(line).collect::<Result<Vec<_>>>() {
+            Ok(tokens) => tokens,
+            Err(_) => continue,
+        };
+
+        for statement in parser::statements(tokens) {
+            let tokens: Vec<_> = statement.collect();
+            if let Some(name) = definition(&tokens) {
+                if let Some(first) = defined.insert(name.to_owned(), idx + 1) {
+                    duplicates.push(Error::DuplicateDefinition {
+                        name: name.to_owned(),
+                        first,
+                        second: idx + 1,
+                    });
+                }
+            }
+        }
+    }
+
+    duplicates
+}
+
+/// Lexes and parses every line without evaluating it, definitions are only
+/// declared so the following lines can use them. Lines with commands are
+/// skipped, imported files are loaded. Duplicate definitions are warnings.
+pub fn check(src: &str) -> Vec<Diagnostic> {
+    Interpreter::new().analyze(src).diagnostics
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn valid() {
+        let src = "#!/usr/bin/env toy-interpreter\na = 1; b = a * 2\nf x => x * 2\n:set force on\nf(_) + 2 + 0 && c = 3\nc\n";
+        assert_eq!(Vec::<Diagnostic>::new(), check(src));
+    }
+
+    #[test]
+    fn problems() {
+        let src = "a = 1 + $\nb + 1\nf x => y\nmax(1)\nx = 2\nx = 3; x y => y\ng x => x; g y => y";
+        let diagnostics: Vec<_> = check(src).iter().map(ToString::to_string).collect();
+        assert_eq!(
+            vec![
+                "1:9: Error[E002]: Invalid token: $",
+                "2: Error[E010]: Undefined symbol: b",
+                "3: Error[E010]: Undefined symbol: y",
+                "4: Error[E011]: Function `max` expects 2 arguments, got 1",
+                "6: Error[E012]: x is already defined as variable, use `--force` to redefine it",
+                "7: Warning: g is defined twice, at lines 7 and 7",
+            ],
+            diagnostics
+        );
+    }
+
+    #[test]
+    fn duplicated() {
+        let src = "f x => x\na = 1\na = a + 1\ng x => f x\nf y => y\nb = a = 2; a = 3\nconst c = 1\nconst c = 2\n";
+        let duplicates: Vec<_> = duplicates(src).iter().map(ToString::to_string).collect();
+        assert_eq!(
+            vec![
+                "f is defined twice, at lines 1 and 5",
+                "c is defined twice, at lines 7 and 8",
+            ],
+            duplicates
+        );
+    }
+
+    #[test]
+    fn imports() {
+        let src = "import \"missing.calc\"\nimport \"lib.calc\" as\n";
+        let diagnostics: Vec<_> = check(src).iter().map(|d| d.line).collect();
+        assert_eq!(vec![1, 2], diagnostics);
+    }
+
+    #[test]
+    fn called_assignments() {
+        let src = "set a => x = a\nset 1\nx + 1\n";
+        assert!(check(src).is_empty());
+    }
+}
@@ -0,0 +1,17 @@
+//! Reading of the clock, for durations of traced phases and benchmarks, and
+//! deadlines of evaluation. `std::time::Instant` panics on
+//! `wasm32-unknown-unknown`, which has no clock without help of the host -
+//! there nothing is timed, and evaluation has no deadline.
+
+use std::time::Instant;
+
+/// Current instant, `None` if there is no clock
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub fn now() -> Option<Instant> {
+    Some(Instant::now())
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub fn now() -> Option<Instant> {
+    None
+}
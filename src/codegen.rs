@@ -0,0 +1,431 @@
+//! Experimental backend compiling user functions to standalone WebAssembly
+//! module, as `:compile formulas.wasm`. Every function which bytecode uses
+//! only numbers is exported under its name, taking and returning `f64`:
+//! ```text
+//! const { instance } = await WebAssembly.instantiate(bytes, { env: { sin: Math.sin, pow: Math.pow } });
+//! instance.exports.area(2);
+//! ```
+//! `sqrt`, `abs`, `floor`, `ceil` and `trunc` are instructions, other
+//! builtins the functions call are imported from `env` by their names, as
+//! are `pow` for `^` and `mod` for `%`. All numbers are floats, so results
+//! may differ from the interpreter where it would keep integers or decimals.
+
+use crate::builtins::Func;
+use crate::parser::{Callee, Function};
+use crate::vm::{Op, Program};
+use crate::{Context, Error, Operator, Result, Value};
+
+/// Value type `f64`, also block type of `if` giving it
+const F64: u8 = 0x7c;
+
+/// Functions of the module, and the ones which could not be compiled
+#[derive(Debug, Default, PartialEq)]
+pub struct Module {
+    pub bytes: Vec<u8>,
+    pub exported: Vec<String>,
+    /// Names of skipped functions, with the reason
+    pub skipped: Vec<(String, String)>,
+}
+
+fn unsigned(mut val: usize, out: &mut Vec<u8>) {
+    loop {
+        let byte = (val & 0x7f) as u8;
+        val >>= 7;
+        if val == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn name(name: &str, out: &mut Vec<u8>) {
+    unsigned(name.len(), out);
+    out.extend_from_slice(name.as_bytes());
+}
+
+fn constant(val: f64, out: &mut Vec<u8>) {
+    out.push(0x44);
+    out.extend_from_slice(&val.to_le_bytes());
+}
+
+fn call(idx: usize, out: &mut Vec<u8>) {
+    out.push(0x10);
+    unsigned(idx, out);
+}
+
+/// Replaces operand with `i32` 1 if it is true, 0 otherwise
+fn condition(out: &mut Vec<u8>) {
+    constant(0.0, out);
+    out.push(0x62);
+}
+
+/// Replaces operand with `1.0` if it is true, `0.0` otherwise
+fn truth(out: &mut Vec<u8>) {
+    condition(out);
+    out.push(0xb8);
+}
+
+/// Section of given id, prefixed with its size
+fn section(id: u8, items: &[Vec<u8>], out: &mut Vec<u8>) {
+    let mut content = vec![];
+    unsigned(items.len(), &mut content);
+    for item in items {
+        content.extend_from_slice(item);
+    }
+    out.push(id);
+    unsigned(content.len(), out);
+    out.extend(content);
+}
+
+fn unsupported(what: &str) -> Error {
+    Error::Eval(format!("{} cannot be compiled", what))
+}
+
+/// Builtins with their own instruction
+fn instruction(builtin: &str) -> Option<u8> {
+    match builtin {
+        "sqrt" => Some(0x9f),
+        "abs" => Some(0x99),
+        "floor" => Some(0x9c),
+        "ceil" => Some(0x9b),
+        "trunc" => Some(0x9d),
+        _ => None,
+    }
+}
+
+/// Body of `==` helper, comparing arguments as `lexer::approx_eq` does
+fn eq_helper(epsilon: f64) -> Vec<u8> {
+    // No locals, `left == right || |left - right| <= epsilon * max(|left|, |right|, 1)`
+    let mut code = vec![0x00, 0x20, 0, 0x20, 1, 0x61, 0x20, 0, 0x20, 1, 0xa1, 0x99];
+    constant(epsilon, &mut code);
+    code.extend_from_slice(&[0x20, 0, 0x99, 0x20, 1, 0x99, 0xa5]);
+    constant(1.0, &mut code);
+    code.extend_from_slice(&[0xa5, 0xa2, 0x65, 0x72, 0xb8, 0x0b]);
+    code
+}
+
+/// Index space of functions - imports, the `==` helper, then user
+/// functions. Indices are right once all imports are known.
+struct Layout<'a> {
+    context: &'a Context,
+    imports: Vec<(String, usize)>,
+    functions: Vec<&'a Function>,
+}
+
+impl<'a> Layout<'a> {
+    fn import(&mut self, name: &str, arity: usize) -> usize {
+        match self.imports.iter().position(|(import, _)| import == name) {
+            Some(idx) => idx,
+            None => {
+                self.imports.push((name.to_owned(), arity));
+                self.imports.len() - 1
+            }
+        }
+    }
+
+    fn helper(&self) -> usize {
+        self.imports.len()
+    }
+
+    /// Bodies of all functions, or index of the first failing one
+    fn bodies(&mut self) -> std::result::Result<Vec<Vec<u8>>, (usize, Error)> {
+        let functions = self.functions.clone();
+        functions
+            .iter()
+            .enumerate()
+            .map(|(idx, func)| {
+                let program = func.body.program.as_ref();
+                program
+                    .ok_or_else(|| unsupported("Local binding"))
+                    .and_then(|program| self.body(program))
+                    .map_err(|err| (idx, err))
+            })
+            .collect()
+    }
+
+    /// Body of function, it declares no locals
+    fn body(&mut self, program: &Program) -> Result<Vec<u8>> {
+        let mut code = vec![0x00];
+        self.code(program, 0, program.ops().len(), &mut code)?;
+        code.push(0x0b);
+        Ok(code)
+    }
+
+    /// Code of instructions in `start..end`, jumps are structured as `vm`
+    /// emits them - only for `?:`, `&&` and `||`
+    fn code(
+        &mut self,
+        program: &Program,
+        start: usize,
+        end: usize,
+        out: &mut Vec<u8>,
+    ) -> Result<()> {
+        let ops = program.ops();
+        let mut next = start;
+        while next < end {
+            let op = &ops[next];
+            next += 1;
+            match op {
+                Op::Push(val @ Value::Int(_))
+                | Op::Push(val @ Value::Float(_))
+                | Op::Push(val @ Value::Decimal(_)) => constant(val.as_f64(), out),
+                Op::Push(_) => return Err(unsupported("Value which is not number")),
+                Op::Arg(idx) => {
+                    out.push(0x20);
+                    unsigned(*idx, out);
+                }
+                Op::Binop(Operator::Add) => out.push(0xa0),
+                Op::Binop(Operator::Sub) => out.push(0xa1),
+                Op::Binop(Operator::Mul) => out.push(0xa2),
+                Op::Binop(Operator::Div) => out.push(0xa3),
+                Op::Binop(Operator::ExactEq) => out.extend_from_slice(&[0x61, 0xb8]),
+                Op::Binop(Operator::Less) => out.extend_from_slice(&[0x63, 0xb8]),
+                Op::Binop(Operator::Greater) => out.extend_from_slice(&[0x64, 0xb8]),
+                Op::Binop(Operator::Eq) => call(self.helper(), out),
+                Op::Binop(Operator::Mod) => call(self.import("mod", 2), out),
+                Op::Binop(Operator::Pow) => call(self.import("pow", 2), out),
+                // Emitted with their short circuit
+                Op::Binop(_) => return Err(unsupported("Logical operator")),
+                Op::Not => {
+                    constant(0.0, out);
+                    out.extend_from_slice(&[0x61, 0xb8]);
+                }
+                // Right operand ends with the operator
+                Op::ShortCircuit(op, to) => {
+                    condition(out);
+                    out.extend_from_slice(&[0x04, F64]);
+                    if *op == Operator::Or {
+                        constant(1.0, out);
+                        out.push(0x05);
+                    }
+                    self.code(program, next, to - 1, out)?;
+                    truth(out);
+                    if *op == Operator::And {
+                        out.push(0x05);
+                        constant(0.0, out);
+                    }
+                    out.push(0x0b);
+                    next = *to;
+                }
+                // Branch taken ends with jump over the other one
+                Op::JumpUnless(other) => {
+                    let end = match ops.get(other - 1) {
+                        Some(Op::Jump(end)) => *end,
+                        _ => return Err(unsupported("Jump")),
+                    };
+                    condition(out);
+                    out.extend_from_slice(&[0x04, F64]);
+                    self.code(program, next, other - 1, out)?;
+                    out.push(0x05);
+                    self.code(program, *other, end, out)?;
+                    out.push(0x0b);
+                    next = end;
+                }
+                Op::Call { callee, args } => match &program.callees()[*callee] {
+                    Callee::Native(native) => match (&native.func, instruction(&native.name)) {
+                        (Func::Float(_), Some(instruction)) => out.push(instruction),
+                        (Func::Float(_), None) => call(self.import(&native.name, *args), out),
+                        _ => return Err(unsupported(&format!("Call of {}", native.name))),
+                    },
+                    Callee::Function(body) => {
+                        let name = self
+                            .context
+                            .function_name(body)
+                            .ok_or_else(|| unsupported("Call of redefined function"))?;
+                        let idx = self
+                            .functions
+                            .iter()
+                            .position(|func| func.name == name)
+                            .ok_or_else(|| unsupported(&format!("Call of {}", name)))?;
+                        call(self.helper() + 1 + idx, out);
+                    }
+                    Callee::Argument(_) => return Err(unsupported("Call of argument")),
+                },
+                Op::Load(_) | Op::Store(_) => return Err(unsupported("Variable")),
+                Op::List(_) | Op::Index => return Err(unsupported("List")),
+                Op::Bind(..) => return Err(unsupported("Partial application")),
+                Op::Jump(_) => return Err(unsupported("Jump")),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Type section entry of function taking `arity` floats and giving one
+fn signature(arity: usize) -> Vec<u8> {
+    let mut ty = vec![0x60];
+    unsigned(arity, &mut ty);
+    ty.extend(std::iter::repeat_n(F64, arity));
+    ty.extend_from_slice(&[1, F64]);
+    ty
+}
+
+/// Module of all user functions which can be compiled. Functions calling
+/// skipped ones are skipped too.
+pub fn compile(context: &Context) -> Module {
+    let mut skipped = vec![];
+    let mut functions = vec![];
+    for func in context.functions() {
+        if func.variadic {
+            skipped.push((func.name.clone(), unsupported("List").to_string()));
+        } else {
+            functions.push(func);
+        }
+    }
+
+    let mut layout = Layout {
+        context,
+        imports: vec![],
+        functions,
+    };
+    // Functions calling skipped ones fail in the next pass
+    while let Err((idx, err)) = layout.bodies() {
+        let func = layout.functions.remove(idx);
+        skipped.push((func.name.clone(), err.to_string()));
+        layout.imports.clear();
+    }
+    // All imports are known now, so indices of calls are right
+    let bodies = layout.bodies().unwrap_or_default();
+
+    // Types are deduplicated by arity
+    let mut arities: Vec<usize> = layout.imports.iter().map(|(_, arity)| *arity).collect();
+    arities.push(2);
+    arities.extend(layout.functions.iter().map(|func| func.arity));
+    let mut types: Vec<usize> = vec![];
+    for arity in &arities {
+        if !types.contains(arity) {
+            types.push(*arity);
+        }
+    }
+    let ty = |arity: usize| types.iter().position(|known| *known == arity).unwrap();
+
+    let mut bytes = b"\0asm".to_vec();
+    bytes.extend_from_slice(&[1, 0, 0, 0]);
+    let signatures: Vec<_> = types.iter().map(|arity| signature(*arity)).collect();
+    section(1, &signatures, &mut bytes);
+
+    let imports: Vec<_> = layout
+        .imports
+        .iter()
+        .map(|(import, arity)| {
+            let mut entry = vec![];
+            name("env", &mut entry);
+            name(import, &mut entry);
+            entry.push(0x00);
+            unsigned(ty(*arity), &mut entry);
+            entry
+        })
+        .collect();
+    section(2, &imports, &mut bytes);
+
+    let declared: Vec<_> = arities[layout.imports.len()..]
+        .iter()
+        .map(|arity| {
+            let mut entry = vec![];
+            unsigned(ty(*arity), &mut entry);
+            entry
+        })
+        .collect();
+    section(3, &declared, &mut bytes);
+
+    let exports: Vec<_> = layout
+        .functions
+        .iter()
+        .enumerate()
+        .map(|(idx, func)| {
+            let mut entry = vec![];
+            name(&func.name, &mut entry);
+            entry.push(0x00);
+            unsigned(layout.helper() + 1 + idx, &mut entry);
+            entry
+        })
+        .collect();
+    section(7, &exports, &mut bytes);
+
+    let codes: Vec<_> = std::iter::once(eq_helper(context.eq_epsilon()))
+        .chain(bodies)
+        .map(|body| {
+            let mut entry = vec![];
+            unsigned(body.len(), &mut entry);
+            entry.extend(body);
+            entry
+        })
+        .collect();
+    section(10, &codes, &mut bytes);
+
+    Module {
+        bytes,
+        exported: layout
+            .functions
+            .iter()
+            .map(|func| func.name.clone())
+            .collect(),
+        skipped,
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::Interpreter;
+
+    #[test]
+    fn leb128() {
+        let encoded = |val| {
+            let mut out = vec![];
+            unsigned(val, &mut out);
+            out
+        };
+        assert_eq!(vec![0], encoded(0));
+        assert_eq!(vec![0x7f], encoded(127));
+        assert_eq!(vec![0x80, 0x01], encoded(128));
+        assert_eq!(vec![0xe5, 0x8e, 0x26], encoded(624_485));
+    }
+
+    #[test]
+    fn module() {
+        let mut interpreter = Interpreter::new();
+        interpreter.eval("inc x => x + 1").unwrap();
+        let module = compile(interpreter.context());
+        assert_eq!(vec!["inc".to_owned()], module.exported);
+        assert!(module.bytes.starts_with(b"\0asm\x01\0\0\0"));
+
+        // Export of function after the helper, and its body at the end
+        let export = [7, 7, 1, 3, b'i', b'n', b'c', 0, 1];
+        assert!(module
+            .bytes
+            .windows(export.len())
+            .any(|part| part == export));
+        let mut body = vec![14, 0, 0x20, 0, 0x44];
+        body.extend_from_slice(&1f64.to_le_bytes());
+        body.extend_from_slice(&[0xa0, 0x0b]);
+        assert!(module.bytes.ends_with(&body));
+    }
+
+    #[test]
+    fn skipped() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .eval("l xs => len xs; h x => l(x) + sin(x); t x => let y = x in y; s x => x ^ 2")
+            .unwrap();
+        let module = compile(interpreter.context());
+        assert_eq!(vec!["s".to_owned()], module.exported);
+        let skipped: Vec<_> = module
+            .skipped
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(vec!["l", "h", "t"], skipped);
+        assert_eq!("Call of l cannot be compiled", module.skipped[1].1);
+
+        // Import of `sin` is dropped with `h`
+        let import = [3, b'p', b'o', b'w'];
+        assert!(module
+            .bytes
+            .windows(import.len())
+            .any(|part| part == import));
+        assert!(!module.bytes.windows(3).any(|part| part == b"sin"));
+    }
+}
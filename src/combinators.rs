@@ -1,4 +1,6 @@
-use crate::{Operator, Result, Token};
+use crate::decimal::Decimal;
+use crate::lexer::Span;
+use crate::{Error, Operator, Result, Token, Value};
 
 #[derive(Debug, PartialEq)]
 pub struct ParseProgress<'a, T> {
@@ -24,45 +26,217 @@ impl<'a, T> ParseProgress<'a, T> {
 macro_rules! assume {
     ($e:expr, $tail:expr) => {{
         let e = $e?;
-        if e.token.is_some() {
-            (e.tail, e.token.unwrap())
+        if let Some(token) = e.token {
+            (e.tail, token)
         } else {
             return ParseProgress::none($tail);
         }
     }};
 }
 
-fn number(src: &str) -> ParseResult<f32> {
+/// Error on the first `len` bytes of parsed source, lexer moves it to the
+/// actual position in line
+fn lex_error(message: String, len: usize) -> Error {
+    Error::Lex {
+        message,
+        span: Span { start: 0, end: len },
+    }
+}
+
+/// Exponent of scientific notation literal, as `e-3` in `1.5e-3`
+fn exponent(src: &str) -> ParseResult<'_, i32> {
+    let digits = match src.strip_prefix(['e', 'E']) {
+        Some(digits) => digits,
+        None => return ParseProgress::none(src),
+    };
+
+    let sign = digits.starts_with(['+', '-']) as usize;
+    let len = digits[sign..]
+        .find(|c: char| !c.is_ascii_digit())
+        .map_or(digits.len(), |len| sign + len);
+    let (exponent, tail) = digits.split_at(len);
+    if len == sign {
+        return Err(lex_error(
+            format!(
+                "Invalid number, exponent `{}` has no digits",
+                &src[..len + 1]
+            ),
+            len + 1,
+        ));
+    }
+
+    match exponent.parse() {
+        Ok(exponent) if (-MAX_EXPONENT..=MAX_EXPONENT).contains(&exponent) => {
+            ParseProgress::some(tail, exponent)
+        }
+        _ => Err(lex_error(
+            format!(
+                "Exponent out of range: {}, it can be from -{} to {}",
+                exponent, MAX_EXPONENT, MAX_EXPONENT
+            ),
+            len + 1,
+        )),
+    }
+}
+
+/// Biggest exponent of scientific notation, far beyond float range
+const MAX_EXPONENT: i32 = 10_000;
+
+/// Integer literal with base prefix, as `0xff`, `0o17` or `0b1010`
+fn radix_number(src: &str) -> ParseResult<'_, Value> {
+    let (radix, name) = match src.get(..2) {
+        Some("0x") | Some("0X") => (16, "hexadecimal"),
+        Some("0o") | Some("0O") => (8, "octal"),
+        Some("0b") | Some("0B") => (2, "binary"),
+        _ => return ParseProgress::none(src),
+    };
+
+    let len = src
+        .find(|c: char| !c.is_ascii_alphanumeric())
+        .unwrap_or(src.len());
+    let (literal, tail) = src.split_at(len);
+    let digits = &literal[2..];
+    if digits.is_empty() {
+        return Err(lex_error(
+            format!("Invalid number: {}, expected {} digits", literal, name),
+            len,
+        ));
+    }
+    if let Some(digit) = digits.chars().find(|c| !c.is_digit(radix)) {
+        return Err(lex_error(
+            format!(
+                "Invalid number: {}, `{}` is not {} digit",
+                literal, digit, name
+            ),
+            len,
+        ));
+    }
+
+    // Too big integers are kept exact, as decimal ones
+    let val = match i64::from_str_radix(digits, radix) {
+        Ok(val) => Value::Int(val),
+        Err(_) => {
+            let base = Decimal::from(radix as i64);
+            let val = digits.chars().fold(Decimal::from(0), |val, digit| {
+                let digit = Decimal::from(digit.to_digit(radix).unwrap_or(0) as i64);
+                val.mul(&base).add(&digit)
+            });
+            Value::Decimal(val)
+        }
+    };
+    ParseProgress::some(tail, val)
+}
+
+/// Literals without decimal point nor exponent are integers, unless they are
+/// too big
+fn number(src: &str) -> ParseResult<'_, Value> {
+    let radix = radix_number(src)?;
+    if radix.token.is_some() {
+        return Ok(radix);
+    }
+
     let first_not = src
         .find(|c| !"0123456789.".contains(c))
-        .unwrap_or_else(|| src.len());
+        .unwrap_or(src.len());
 
     if first_not == 0 {
         return ParseProgress::none(src);
     }
 
     let literal = &src[..first_not];
-    let tail = &src[first_not..];
+    let exponent = exponent(&src[first_not..]).map_err(|err| match err {
+        Error::Lex { message, span } => Error::Lex {
+            message,
+            span: span.shift(first_not),
+        },
+        err => err,
+    })?;
+    let tail = exponent.tail;
     if literal.chars().filter(|&c| c == '.').count() > 1 {
-        Err(format!(
-            "Invalid number: {}, only one decimal point allowed",
-            literal
+        Err(lex_error(
+            format!(
+                "Invalid number: {}, only one decimal point allowed",
+                literal
+            ),
+            literal.len(),
         ))
+    } else if let Some(number) = literal
+        .parse()
+        .ok()
+        .filter(|_| !literal.contains('.') && exponent.token.is_none())
+    {
+        ParseProgress::some(tail, Value::Int(number))
     } else {
-        let number = literal
-            .parse()
-            .map_err(|err| format!("Invalid numer: {}, {}", literal, err))?;
-        ParseProgress::some(tail, number)
+        // Fractions are kept exact, evaluation mode decides what they become
+        let number = Decimal::parse(literal)
+            .ok_or_else(|| lex_error(format!("Invalid number: {}", literal), literal.len()))?;
+        let number = number.scaled(exponent.token.unwrap_or(0));
+        ParseProgress::some(tail, Value::Decimal(number))
     }
 }
 
-fn identifier(src: &str) -> ParseResult<&str> {
+/// Character of `\u{1F600}` escape, and length of the escape after `\`
+fn unicode_escape(escape: &str) -> Option<(char, usize)> {
+    let hex = escape.strip_prefix("u{")?;
+    let len = hex.find('}')?;
+    let code = u32::from_str_radix(&hex[..len], 16).ok()?;
+    Some((char::from_u32(code)?, len + 3))
+}
+
+/// Double quoted string, with escapes as in Rust: `\n`, `\t`, `\r`, `\0`,
+/// `\\`, `\"`, `\'` and `\u{1F600}`
+fn string(src: &str) -> ParseResult<'_, String> {
+    let mut rest = match src.strip_prefix('"') {
+        Some(rest) => rest,
+        None => return ParseProgress::none(src),
+    };
+
+    let mut text = String::new();
+    loop {
+        let idx = rest.find(['"', '\\']).ok_or_else(|| {
+            lex_error(
+                "Unterminated string, expected closing `\"`".to_owned(),
+                src.len(),
+            )
+        })?;
+        text += &rest[..idx];
+        if let Some(tail) = rest[idx..].strip_prefix('"') {
+            return ParseProgress::some(tail, text);
+        }
+
+        let escape = &rest[idx + 1..];
+        let escaped = match escape.chars().next() {
+            Some('n') => Some(('\n', 1)),
+            Some('t') => Some(('\t', 1)),
+            Some('r') => Some(('\r', 1)),
+            Some('0') => Some(('\0', 1)),
+            Some(c @ '\\') | Some(c @ '"') | Some(c @ '\'') => Some((c, 1)),
+            Some('u') => unicode_escape(escape),
+            _ => None,
+        };
+        let (c, len) = escaped.ok_or_else(|| {
+            let start = src.len() - rest.len() + idx;
+            let len = escape.chars().next().map_or(0, char::len_utf8);
+            Error::Lex {
+                message: format!("Invalid escape sequence: \\{}", &escape[..len]),
+                span: Span {
+                    start,
+                    end: start + 1 + len,
+                },
+            }
+        })?;
+        text.push(c);
+        rest = &escape[len..];
+    }
+}
+
+fn identifier(src: &str) -> ParseResult<'_, &str> {
     if src.is_empty() {
         ParseProgress::none(src)
     } else if src.chars().next().unwrap().is_ascii_alphabetic() || src.starts_with('_') {
         let first_not = src
             .find(|c: char| -> bool { !(c == '_' || c.is_ascii_alphanumeric()) })
-            .unwrap_or_else(|| src.len());
+            .unwrap_or(src.len());
         let literal = &src[..first_not];
         let tail = &src[first_not..];
         ParseProgress::some(tail, literal)
@@ -71,18 +245,19 @@ fn identifier(src: &str) -> ParseResult<&str> {
     }
 }
 
-fn assignment(src: &str) -> ParseResult<&str> {
+fn assignment(src: &str) -> ParseResult<'_, &str> {
     let (tail, ident) = assume!(identifier(src), src);
     let tail = tail.trim_start();
-    if tail.starts_with('=') && !tail.starts_with("=>") {
+    if tail.starts_with('=') && !tail.starts_with("=>") && !tail.starts_with("==") {
         ParseProgress::some(&tail[1..], ident)
     } else {
         ParseProgress::none(src)
     }
 }
 
-pub fn next_token(src: &str) -> ParseResult<Token> {
-    if src.is_empty() {
+fn token(src: &str) -> ParseResult<'_, Token> {
+    // Comment lasts till the end of line
+    if src.is_empty() || src.starts_with('#') {
         return ParseProgress::none("");
     }
 
@@ -93,7 +268,20 @@ pub fn next_token(src: &str) -> ParseResult<Token> {
 
     let id = identifier(src)?;
     if let Some(tok) = id.token {
-        return ParseProgress::some(id.tail, Token::Id(tok.to_owned()));
+        let token = match tok {
+            "let" => Token::Let,
+            "const" => Token::Const,
+            "in" => Token::In,
+            "to" => Token::To,
+            "of" => Token::Of,
+            _ => Token::Id(tok.to_owned()),
+        };
+        return ParseProgress::some(id.tail, token);
+    }
+
+    // Before numbers, which may start with `.`
+    if let Some(tail) = src.strip_prefix("...") {
+        return ParseProgress::some(tail, Token::Ellipsis);
     }
 
     let num = number(src)?;
@@ -101,8 +289,33 @@ pub fn next_token(src: &str) -> ParseResult<Token> {
         return ParseProgress::some(num.tail, Token::Number(tok));
     }
 
-    if src.starts_with("=>") {
-        return ParseProgress::some(&src[2..], Token::Func);
+    let text = string(src)?;
+    if let Some(tok) = text.token {
+        return ParseProgress::some(text.tail, Token::Str(tok));
+    }
+
+    if let Some(tail) = src.strip_prefix("=>") {
+        return ParseProgress::some(tail, Token::Func);
+    }
+
+    if let Some(tail) = src.strip_prefix("===") {
+        return ParseProgress::some(tail, Token::Operator(Operator::ExactEq));
+    }
+
+    if let Some(tail) = src.strip_prefix("==") {
+        return ParseProgress::some(tail, Token::Operator(Operator::Eq));
+    }
+
+    if let Some(tail) = src.strip_prefix("&&") {
+        return ParseProgress::some(tail, Token::Operator(Operator::And));
+    }
+
+    if let Some(tail) = src.strip_prefix("|>") {
+        return ParseProgress::some(tail, Token::Pipe);
+    }
+
+    if let Some(tail) = src.strip_prefix("||") {
+        return ParseProgress::some(tail, Token::Operator(Operator::Or));
     }
 
     let tok = match src {
@@ -111,29 +324,123 @@ pub fn next_token(src: &str) -> ParseResult<Token> {
         _ if src.starts_with('*') => Token::Operator(Operator::Mul),
         _ if src.starts_with('/') => Token::Operator(Operator::Div),
         _ if src.starts_with('%') => Token::Operator(Operator::Mod),
+        _ if src.starts_with('^') => Token::Operator(Operator::Pow),
+        _ if src.starts_with('<') => Token::Operator(Operator::Less),
+        _ if src.starts_with('>') => Token::Operator(Operator::Greater),
+        _ if src.starts_with('!') => Token::Not,
+        _ if src.starts_with(';') => Token::Semicolon,
+        _ if src.starts_with(',') => Token::Comma,
+        _ if src.starts_with('?') => Token::Question,
+        _ if src.starts_with(':') => Token::Colon,
         _ if src.starts_with('(') => Token::LBracket,
         _ if src.starts_with(')') => Token::RBracket,
-        _ => return Err(format!("Invalid token: {}", src)),
+        _ if src.starts_with('[') => Token::LSquare,
+        _ if src.starts_with(']') => Token::RSquare,
+        _ => {
+            let len = src.chars().next().map_or(0, char::len_utf8);
+            return Err(lex_error(format!("Invalid token: {}", src), len));
+        }
     };
 
     ParseProgress::some(&src[1..], tok)
 }
 
+/// Next token with its span, which is relative to `src`
+pub fn next_token(src: &str) -> ParseResult<'_, (Token, Span)> {
+    let progress = token(src)?;
+    let span = Span {
+        start: 0,
+        end: src.len() - progress.tail.len(),
+    };
+    Ok(ParseProgress {
+        tail: progress.tail,
+        token: progress.token.map(|token| (token, span)),
+    })
+}
+
 #[cfg(test)]
 mod test {
 
     use super::*;
 
+    fn decimal(src: &str) -> Value {
+        Value::Decimal(Decimal::parse(src).unwrap())
+    }
+
     #[test]
     fn test_number() {
         assert_eq!(ParseProgress::none(""), number(""));
         assert_eq!(ParseProgress::none("tail"), number("tail"));
-        assert_eq!(ParseProgress::some("", 10.0f32), number("10"));
-        assert_eq!(ParseProgress::some("", 10.4f32), number("10.4"));
-        assert_eq!(ParseProgress::some("tail", 10.4f32), number("10.4tail"));
+        assert_eq!(ParseProgress::some("", Value::Int(10)), number("10"));
+        assert_eq!(ParseProgress::some("", decimal("10.0")), number("10.0"));
+        assert_eq!(ParseProgress::some("", decimal("10.4")), number("10.4"));
+        assert_eq!(
+            ParseProgress::some("tail", decimal("10.4")),
+            number("10.4tail")
+        );
+        assert_eq!(
+            ParseProgress::some("", decimal("100000000000000000000")),
+            number("100000000000000000000")
+        );
+        number(".").unwrap_err();
         number("10.4.5").unwrap_err();
     }
 
+    #[test]
+    fn test_radix() {
+        assert_eq!(ParseProgress::some("", Value::Int(255)), number("0xFF"));
+        assert_eq!(
+            ParseProgress::some(" + 1", Value::Int(15)),
+            number("0o17 + 1")
+        );
+        assert_eq!(ParseProgress::some("", Value::Int(10)), number("0b1010"));
+        assert_eq!(
+            ParseProgress::some("", decimal("18446744073709551616")),
+            number("0x10000000000000000")
+        );
+
+        let err = number("0x").unwrap_err();
+        assert_eq!(Some(Span { start: 0, end: 2 }), err.span());
+        assert_eq!(
+            "Invalid number: 0b102, `2` is not binary digit",
+            number("0b102").unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_scientific() {
+        assert_eq!(ParseProgress::some("", decimal("0.001")), number("1e-3"));
+        assert_eq!(ParseProgress::some("", decimal("2500000")), number("2.5E6"));
+        assert_eq!(ParseProgress::some("", decimal("50")), number(".5e+2"));
+        assert_eq!(ParseProgress::some("", decimal("0.5")), number(".5"));
+        assert_eq!(ParseProgress::some("x", decimal("30")), number("3e1x"));
+
+        let err = number("1e").unwrap_err();
+        assert_eq!(Some(Span { start: 1, end: 2 }), err.span());
+        let err = number("1e+ 2").unwrap_err();
+        assert_eq!(Some(Span { start: 1, end: 3 }), err.span());
+        number("1e99999").unwrap_err();
+    }
+
+    #[test]
+    fn test_string() {
+        assert_eq!(ParseProgress::none("x"), string("x"));
+        assert_eq!(
+            ParseProgress::some(" + 1", "a # b".to_owned()),
+            string("\"a # b\" + 1")
+        );
+        assert_eq!(
+            ParseProgress::some("", "say \"hi\"\n\u{e9}".to_owned()),
+            string(r#""say \"hi\"\n\u{e9}""#)
+        );
+
+        let err = string("\"abc").unwrap_err();
+        assert_eq!(Some(Span { start: 0, end: 4 }), err.span());
+        let err = string(r#""a\qb""#).unwrap_err();
+        assert_eq!(Some(Span { start: 2, end: 4 }), err.span());
+        string(r#""\u{110000}""#).unwrap_err();
+    }
+
     #[test]
     fn test_identifier() {
         assert_eq!(ParseProgress::none(""), identifier(""));
@@ -152,48 +459,112 @@ mod test {
         assert_eq!(ParseProgress::none("x"), assignment("x"));
         assert_eq!(ParseProgress::some("", "x"), assignment("x ="));
         assert_eq!(ParseProgress::none("x =>"), assignment("x =>"));
+        assert_eq!(ParseProgress::none("x == 1"), assignment("x == 1"));
     }
 
     #[test]
-    fn test_next_token() {
-        assert_eq!(ParseProgress::none(""), next_token(""));
+    fn test_token() {
+        assert_eq!(ParseProgress::none(""), token(""));
+        assert_eq!(ParseProgress::none(""), token("# 1 + 2"));
         assert_eq!(
             ParseProgress::some("", Token::Operator(Operator::Add)),
-            next_token("+")
+            token("+")
         );
         assert_eq!(
             ParseProgress::some("", Token::Operator(Operator::Sub)),
-            next_token("-")
+            token("-")
         );
         assert_eq!(
             ParseProgress::some("", Token::Operator(Operator::Mul)),
-            next_token("*")
+            token("*")
         );
         assert_eq!(
             ParseProgress::some("", Token::Operator(Operator::Div)),
-            next_token("/")
+            token("/")
         );
         assert_eq!(
             ParseProgress::some("", Token::Operator(Operator::Mod)),
-            next_token("%")
+            token("%")
+        );
+        assert_eq!(
+            ParseProgress::some("", Token::Operator(Operator::Pow)),
+            token("^")
+        );
+        assert_eq!(
+            ParseProgress::some("", Token::Operator(Operator::And)),
+            token("&&")
+        );
+        assert_eq!(
+            ParseProgress::some("", Token::Operator(Operator::Or)),
+            token("||")
+        );
+        assert_eq!(ParseProgress::some("x", Token::Not), token("!x"));
+        assert_eq!(
+            ParseProgress::some(" 1", Token::Operator(Operator::Eq)),
+            token("== 1")
+        );
+        assert_eq!(
+            ParseProgress::some("", Token::Operator(Operator::ExactEq)),
+            token("===")
+        );
+        assert_eq!(
+            ParseProgress::some(" 0", Token::Operator(Operator::Less)),
+            token("< 0")
+        );
+        assert_eq!(
+            ParseProgress::some("", Token::Operator(Operator::Greater)),
+            token(">")
+        );
+        assert_eq!(ParseProgress::some("", Token::LBracket), token("("));
+        assert_eq!(ParseProgress::some("", Token::RBracket), token(")"));
+        assert_eq!(ParseProgress::some("0]", Token::LSquare), token("[0]"));
+        assert_eq!(ParseProgress::some("", Token::RSquare), token("]"));
+        assert_eq!(ParseProgress::some(" a", Token::Semicolon), token("; a"));
+        assert_eq!(ParseProgress::some(" 1", Token::Question), token("? 1"));
+        assert_eq!(ParseProgress::some(" 2", Token::Colon), token(": 2"));
+        assert_eq!(ParseProgress::some(" f", Token::Pipe), token("|> f"));
+        assert_eq!(ParseProgress::some("xs", Token::Ellipsis), token("...xs"));
+        assert_eq!(ParseProgress::some(" x", Token::Let), token("let x"));
+        assert_eq!(ParseProgress::some(" 1", Token::In), token("in 1"));
+        assert_eq!(ParseProgress::some(" g", Token::Const), token("const g"));
+        assert_eq!(
+            ParseProgress::some("", Token::Id("inner".to_owned())),
+            token("inner")
         );
-        assert_eq!(ParseProgress::some("", Token::LBracket), next_token("("));
-        assert_eq!(ParseProgress::some("", Token::RBracket), next_token(")"));
         assert_eq!(
             ParseProgress::some("", Token::Assign("x".to_owned())),
-            next_token("x =")
+            token("x =")
         );
-        assert_eq!(ParseProgress::some("", Token::Func), next_token("=>"));
+        assert_eq!(ParseProgress::some("", Token::Func), token("=>"));
         assert_eq!(
             ParseProgress::some("x", Token::Operator(Operator::Mod)),
-            next_token("%x")
+            token("%x")
         );
         assert_eq!(
             ParseProgress::some(" =>", Token::Id("x".to_owned())),
-            next_token("x =>")
+            token("x =>")
         );
 
-        next_token("10.0.4").unwrap_err();
-        next_token("=").unwrap_err();
+        token("10.0.4").unwrap_err();
+        token("=").unwrap_err();
+        token("&").unwrap_err();
+        token("|").unwrap_err();
+    }
+
+    #[test]
+    fn spans() {
+        let progress = next_token("x = 1").unwrap();
+        assert_eq!(" 1", progress.tail);
+        assert_eq!(
+            Some((Token::Assign("x".to_owned()), Span { start: 0, end: 3 })),
+            progress.token
+        );
+        assert_eq!(
+            Err(Error::Lex {
+                message: "Invalid token: ł ".to_owned(),
+                span: Span { start: 0, end: 2 }
+            }),
+            next_token("ł ")
+        );
     }
 }
@@ -1,47 +1,186 @@
-use crate::parser::{Function, AST};
+use crate::builtins::{Builtin, Func, Prelude, BUILTINS, CONSTANTS, GENERICS};
+use crate::feature::{Feature, Features};
+use crate::graph::{self, Node};
+use crate::limits::Limits;
+use crate::parser::{Body, Callee, Expr, Function, Native};
+use crate::{Error, Mode, Result, Value};
 use std::collections::HashMap;
-use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Reads variable of the host application
+type Getter = Arc<dyn Fn() -> f64 + Send + Sync>;
+
+/// Writes variable of the host application, its error stops evaluation
+type Setter = Arc<dyn Fn(f64) -> Result<()> + Send + Sync>;
 
 #[derive(Clone)]
 enum Symbol {
-    Variable(f32),
-    Function(usize, Rc<dyn AST>),
+    Variable(Value),
+    Function(Function),
     Argument(usize),
+    /// Name bound by `let`, passed as argument of given index
+    Local(usize),
+    Native(usize, Arc<str>, Func),
+    Constant(Value),
+    /// Variable of enclosing context, read when function is called
+    Captured,
+    /// Variable of the host, read and written through its hooks
+    External(Getter, Setter),
 }
 
 impl Symbol {
     fn is_var(&self) -> bool {
-        match self {
-            Symbol::Variable(_) => true,
-            _ => false,
-        }
+        matches!(
+            self,
+            Symbol::Variable(_) | Symbol::Captured | Symbol::External(..)
+        )
     }
 
     fn is_func(&self) -> bool {
+        matches!(self, Symbol::Function(..) | Symbol::Native(..))
+    }
+}
+
+pub const DEFAULT_EQ_EPSILON: f64 = 1e-6;
+
+/// Significant digits of shown results, enough to hide noise of binary
+/// fractions as in `0.1 + 0.2`
+pub const DEFAULT_PRECISION: usize = 15;
+
+/// Nesting of calls through function arguments, which may recurse
+pub const MAX_CALL_DEPTH: usize = 256;
+
+/// Steps between checks of evaluation deadline, reading the clock is not
+/// cheap
+const DEADLINE_CHECK_STEPS: u64 = 1024;
+
+/// Variable holding result of last evaluated statement
+pub const LAST_RESULT: &str = "_";
+
+/// How function bodies see variables defined outside of them
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Capture {
+    /// Only functions, constants and arguments are visible
+    #[default]
+    None,
+    /// Values of variables are substituted when function is defined
+    Copy,
+    /// Variables are read when function is called, so later assignments
+    /// change results
+    Reference,
+}
+
+impl Capture {
+    pub fn name(self) -> &'static str {
         match self {
-            Symbol::Function(_, _) => true,
-            _ => false,
+            Capture::None => "none",
+            Capture::Copy => "copy",
+            Capture::Reference => "reference",
         }
     }
 }
 
+impl FromStr for Capture {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Capture::None),
+            "copy" => Ok(Capture::Copy),
+            "reference" => Ok(Capture::Reference),
+            _ => Err(format!(
+                "Unknown capture: {}, expected `none`, `copy` or `reference`",
+                s
+            )
+            .into()),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Context {
     symbols: HashMap<String, Symbol>,
+    order: Vec<String>,                // Symbols in order of first definition
+    shadowed: HashMap<String, Symbol>, // Builtins replaced by user symbols
+    eq_epsilon: f64,
+    precision: usize,
+    mode: Mode,
+    capture: Capture,
+    features: Features,
+    limits: Limits,
+    force: bool,            // Allows redefinitions breaking other functions
+    partial: bool,          // Calls with fewer arguments are partial applications
+    strict: bool,           // Forbids redefinitions of functions at all
+    depth: usize,           // Calls through function arguments being evaluated
+    memory: usize,          // Bytes allocated by evaluated statement
+    steps: u64,             // Expressions evaluated by evaluated statement
+    failure: Option<Error>, // Reason of stopped evaluation
+    warnings: Vec<String>,
+    // When evaluation times out, and the timeout itself
+    deadline: Option<(Instant, Duration)>,
 }
 
 impl Context {
     pub fn new() -> Self {
+        Self::with_prelude(Prelude::default())
+    }
+
+    pub fn with_prelude(prelude: Prelude) -> Self {
+        let (builtins, generics, constants) = match prelude {
+            Prelude::Empty => (&[][..], &[][..], &[][..]),
+            Prelude::Math => (BUILTINS, GENERICS, CONSTANTS),
+        };
+        let builtins = builtins
+            .iter()
+            .map(|&(name, arity, func)| (name, arity, Func::Float(func)));
+        let generics = generics
+            .iter()
+            .map(|&(name, arity, func)| (name, arity, Func::Generic(func)));
+        let functions = builtins
+            .chain(generics)
+            .map(|(name, arity, func)| (name.to_owned(), Symbol::Native(arity, name.into(), func)));
+        let constants = constants
+            .iter()
+            .map(|&(name, val)| (name.to_owned(), Symbol::Constant(Value::Float(val))));
+        let symbols = functions.chain(constants).collect();
+
         Context {
-            symbols: HashMap::new(),
+            symbols,
+            order: vec![],
+            shadowed: HashMap::new(),
+            eq_epsilon: DEFAULT_EQ_EPSILON,
+            precision: DEFAULT_PRECISION,
+            mode: Mode::default(),
+            capture: Capture::default(),
+            features: Features::default(),
+            limits: Limits::default(),
+            force: false,
+            partial: false,
+            strict: false,
+            depth: 0,
+            memory: 0,
+            steps: 0,
+            deadline: None,
+            failure: None,
+            warnings: vec![],
         }
     }
 
     pub fn function_ctx(args: Vec<String>, parent: &Context) -> Self {
-        let functions = parent
-            .symbols
-            .iter()
-            .filter(|(_, item)| item.is_func())
-            .map(|(name, item)| (name.clone(), item.clone()));
+        // Constants never change, so they are as safe to inline as functions
+        let functions =
+            parent
+                .symbols
+                .iter()
+                .filter_map(|(name, item)| match (item, parent.capture) {
+                    (Symbol::Variable(_), Capture::None) => None,
+                    (Symbol::Variable(_), Capture::Reference) => {
+                        Some((name.clone(), Symbol::Captured))
+                    }
+                    (item, _) => Some((name.clone(), item.clone())),
+                });
 
         let args = args
             .into_iter()
@@ -50,65 +189,672 @@ impl Context {
 
         let symbols = functions.chain(args).collect();
 
-        Self { symbols }
+        Self {
+            symbols,
+            order: vec![],
+            shadowed: HashMap::new(),
+            eq_epsilon: parent.eq_epsilon,
+            precision: parent.precision,
+            mode: parent.mode,
+            capture: parent.capture,
+            features: parent.features.clone(),
+            limits: parent.limits,
+            force: parent.force,
+            partial: parent.partial,
+            strict: parent.strict,
+            depth: 0,
+            memory: 0,
+            steps: 0,
+            deadline: None,
+            failure: None,
+            warnings: vec![],
+        }
     }
 
-    pub fn update_var(&mut self, var: impl ToString, val: f32) {
-        self.symbols
-            .entry(var.to_string())
-            .and_modify(|v|
-                if let Symbol::Variable(ref mut v) = v {
-                    *v = val;
-                }
-            )
-            .or_insert(Symbol::Variable(val));
+    /// Context seeing also name bound by `let`, as the next free argument
+    pub fn scoped(&self, name: &str) -> Self {
+        let slot = self
+            .symbols
+            .values()
+            .filter_map(|symbol| match symbol {
+                Symbol::Argument(idx) | Symbol::Local(idx) => Some(idx + 1),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+
+        let mut scoped = self.clone();
+        scoped.symbols.insert(name.to_owned(), Symbol::Local(slot));
+        scoped
+    }
+
+    /// Tolerance used by `==` operator
+    pub fn eq_epsilon(&self) -> f64 {
+        self.eq_epsilon
+    }
+
+    pub fn set_eq_epsilon(&mut self, epsilon: f64) -> Result<()> {
+        if epsilon >= 0.0 {
+            self.eq_epsilon = epsilon;
+            Ok(())
+        } else {
+            Err(format!("Tolerance cannot be negative: {}", epsilon).into())
+        }
+    }
+
+    /// Significant digits of shown results
+    pub fn precision(&self) -> usize {
+        self.precision
+    }
+
+    pub fn set_precision(&mut self, digits: usize) -> Result<()> {
+        if (1..=17).contains(&digits) {
+            self.precision = digits;
+            Ok(())
+        } else {
+            Err(format!("Precision has to be from 1 to 17 digits, got: {}", digits).into())
+        }
+    }
+
+    /// Value as it is shown, rounded to configured precision - integers
+    /// are always exact
+    pub fn rounded(&self, val: Value) -> Value {
+        match val {
+            Value::Float(val) => Value::Float(significant(val, self.precision)),
+            val => val,
+        }
+    }
+
+    /// Backend of literals parsed from now on, values already evaluated are
+    /// not converted
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+    }
+
+    /// Capture of variables by functions defined from now on
+    pub fn capture(&self) -> Capture {
+        self.capture
+    }
+
+    pub fn set_capture(&mut self, capture: Capture) {
+        self.capture = capture;
+    }
+
+    pub fn is_enabled(&self, feature: Feature) -> bool {
+        self.features.is_enabled(feature)
+    }
+
+    pub fn set_feature(&mut self, feature: Feature, enabled: bool) {
+        self.features.set(feature, enabled);
+    }
+
+    pub fn set_features(&mut self, features: Features) {
+        self.features = features;
+    }
+
+    /// Bounds of statements evaluated from now on
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn force(&self) -> bool {
+        self.force
+    }
+
+    pub fn set_force(&mut self, force: bool) {
+        self.force = force;
+    }
+
+    /// Functions called with fewer arguments than their arity give
+    /// functions taking the rest, instead of error
+    pub fn partial(&self) -> bool {
+        self.partial
+    }
+
+    pub fn set_partial(&mut self, partial: bool) {
+        self.partial = partial;
+    }
+
+    /// Functions cannot be redefined, even with force
+    pub fn strict(&self) -> bool {
+        self.strict
+    }
+
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    pub(crate) fn warn(&mut self, warning: String) {
+        self.warnings.push(warning);
+    }
+
+    /// Starts call through function argument, `false` with warning if it
+    /// would nest too deep
+    pub(crate) fn enter(&mut self) -> bool {
+        if self.depth >= MAX_CALL_DEPTH {
+            self.warn(format!(
+                "Calls through function arguments nest deeper than {}, evaluation is stopped",
+                MAX_CALL_DEPTH
+            ));
+            return false;
+        }
+        self.depth += 1;
+        true
+    }
+
+    pub(crate) fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    /// Accounts memory of created value, `None` once the statement exceeds
+    /// memory limit - evaluation is stopped then, and the failure is
+    /// reported by `take_failure`
+    pub(crate) fn allocate(&mut self, val: Value) -> Option<Value> {
+        if self.failure.is_some() {
+            return None;
+        }
+
+        self.memory = self.memory.saturating_add(val.heap_size());
+        match self.limits.memory {
+            Some(limit) if self.memory > limit => {
+                self.failure = Some(Error::MemoryLimit {
+                    used: self.memory,
+                    limit,
+                });
+                None
+            }
+            _ => Some(val),
+        }
+    }
+
+    /// Accounts single step of evaluation, `None` once the statement exceeds
+    /// step limit, as `allocate`
+    pub(crate) fn step(&mut self) -> Option<()> {
+        if self.failure.is_some() {
+            return None;
+        }
+
+        self.steps += 1;
+        match (self.limits.steps, self.deadline) {
+            (Some(limit), _) if self.steps > limit => {
+                self.failure = Some(Error::StepLimit { limit });
+                None
+            }
+            (_, Some((deadline, timeout)))
+                if self.steps.is_multiple_of(DEADLINE_CHECK_STEPS) && Instant::now() > deadline =>
+            {
+                self.failure = Some(Error::Timeout(timeout));
+                None
+            }
+            _ => Some(()),
+        }
+    }
+
+    /// Evaluation running after the instant is stopped, the timeout is
+    /// reported
+    pub(crate) fn set_deadline(&mut self, deadline: Option<(Instant, Duration)>) {
+        self.deadline = deadline;
+    }
+
+    /// Stops evaluation, unless it is already stopped
+    pub(crate) fn fail(&mut self, err: Error) {
+        self.failure.get_or_insert(err);
+    }
+
+    /// Why the evaluation was stopped, accounting starts again for the next
+    /// statement
+    pub(crate) fn take_failure(&mut self) -> Option<Error> {
+        self.memory = 0;
+        self.steps = 0;
+        self.failure.take()
+    }
+
+    /// Warnings emitted since last call
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// User functions calling given function
+    pub fn dependents(&self, func: &str) -> Vec<String> {
+        self.graph()
+            .into_iter()
+            .filter(|node| node.uses.iter().any(|used| used == func))
+            .map(|node| node.name)
+            .collect()
+    }
+
+    /// Removes user symbol, so its name is undefined again. Symbols used by
+    /// other functions are removed only if forced, as those could not be
+    /// exported any more.
+    pub fn remove_symbol(&mut self, name: &str) -> Result<&'static str> {
+        let kind = self
+            .kind(name)
+            .ok_or_else(|| Error::UndefinedSymbol(name.to_owned()))?;
+        if !self.order.iter().any(|defined| defined == name) {
+            return Err(Error::Eval(format!("Cannot remove {} {}", kind, name)));
+        }
+
+        let dependents = self.dependents(name);
+        if !dependents.is_empty() {
+            if !self.force {
+                return Err(Error::Eval(format!(
+                    "{} is used by {}, use `--force` to remove anyway",
+                    name,
+                    dependents.join(", ")
+                )));
+            }
+            self.warnings.push(format!(
+                "Exported definitions of {} will fail to load",
+                dependents.join(", ")
+            ));
+        }
+
+        self.remove(name);
+        Ok(kind)
+    }
+
+    /// Removes all user symbols, returning how many there were. Settings are
+    /// kept.
+    pub fn reset(&mut self) -> usize {
+        let order = std::mem::take(&mut self.order);
+        for name in &order {
+            self.remove(name);
+        }
+        order.len()
+    }
+
+    /// Builtin shadowed by removed symbol is defined again
+    fn remove(&mut self, name: &str) {
+        self.symbols.remove(name);
+        self.order.retain(|defined| defined != name);
+        if let Some(builtin) = self.shadowed.remove(name) {
+            self.symbols.insert(name.to_owned(), builtin);
+        }
+    }
+
+    /// Defines user symbol, keeping builtin it shadows
+    fn insert(&mut self, name: String, symbol: Symbol) {
+        self.define(&name);
+        if let Some(builtin @ Symbol::Native(..)) = self.symbols.insert(name.clone(), symbol) {
+            self.shadowed.entry(name).or_insert(builtin);
+        }
+    }
+
+    fn define(&mut self, name: &str) {
+        if !self.order.iter().any(|defined| defined == name) {
+            self.order.push(name.to_owned());
+        }
+    }
+
+    pub fn update_var(&mut self, var: impl ToString, val: Value) -> Result<()> {
+        let var = var.to_string();
+        if let Some(Symbol::External(_, set)) = self.symbols.get(&var) {
+            return match val {
+                Value::Int(_) | Value::Float(_) | Value::Decimal(_) => set(val.as_f64()),
+                _ => Err(Error::Eval(format!(
+                    "Host variable {} holds only numbers",
+                    var
+                ))),
+            };
+        }
+        self.bind(var, Symbol::Variable(val), "variable")
+    }
+
+    /// Defines constant, which cannot be assigned to any more
+    pub fn update_const(&mut self, var: impl ToString, val: Value) -> Result<()> {
+        self.bind(var.to_string(), Symbol::Constant(val), "constant")
+    }
+
+    /// Variable read where it is used, for trees of statements using
+    /// variable which value is not known
+    #[cfg(feature = "cli")]
+    pub(crate) fn declare_var(&mut self, var: &str) {
+        if !self.is_external(var) {
+            self.insert(var.to_owned(), Symbol::Captured);
+        }
+    }
+
+    fn bind(&mut self, var: String, symbol: Symbol, kind: &str) -> Result<()> {
+        if self.is_const(&var) {
+            return Err(Error::Eval(format!("Cannot assign to constant: {}", var)));
+        }
+
+        // Parser allows replacing function only if forced
+        if let Some(Symbol::Function(_)) | Some(Symbol::Native(..)) = self.symbols.get(&var) {
+            self.warnings
+                .push(format!("Function {} is replaced by {}", var, kind));
+        }
+
+        self.insert(var, symbol);
+        Ok(())
     }
 
     pub fn update_func(&mut self, func: &Function) {
-        self.symbols
-            .entry(func.name.clone())
-            .and_modify(|v|
-                if let Symbol::Function(ref mut arity, ref mut expr) = v {
-                    *arity = func.arity;
-                    *expr = func.expr.clone();
-                }
-            )
-            .or_insert_with(|| Symbol::Function(func.arity, func.expr.clone()));
+        let arity = self.get_arity(&func.name);
+        if arity.is_some_and(|arity| arity != func.arity) {
+            let dependents = self.dependents(&func.name);
+            if !dependents.is_empty() {
+                self.warnings.push(format!(
+                    "Arity of {} changed, exported definitions of {} will fail to load",
+                    func.name,
+                    dependents.join(", ")
+                ));
+            }
+        }
+
+        match self.symbols.get(&func.name) {
+            Some(Symbol::Argument(_)) | Some(Symbol::Constant(_)) => return,
+            // Parser allows replacing variable only if forced
+            Some(Symbol::Variable(_)) => self
+                .warnings
+                .push(format!("Variable {} is replaced by function", func.name)),
+            Some(Symbol::Native(..)) => self
+                .warnings
+                .push(format!("Builtin function {} is shadowed", func.name)),
+            _ => (),
+        }
+
+        self.insert(func.name.clone(), Symbol::Function(func.clone()));
+    }
+
+    /// Function implemented by the host application, it may replace builtin
+    /// but not any user symbol
+    pub fn define_native(&mut self, name: &str, arity: usize, func: Builtin) -> Result<()> {
+        self.native(name, arity, Func::Float(func))
+    }
+
+    /// Closure of the host application, as `define_native`. Its error stops
+    /// evaluation.
+    pub fn register_fn(
+        &mut self,
+        name: &str,
+        arity: usize,
+        func: impl Fn(&[f64]) -> Result<f64> + Send + Sync + 'static,
+    ) -> Result<()> {
+        self.native(name, arity, Func::Host(Arc::new(func)))
+    }
+
+    /// Variable of the host application, every read calls `get` and every
+    /// assignment calls `set`. It is visible in function bodies, and read
+    /// when they are called.
+    pub fn bind_external(
+        &mut self,
+        name: &str,
+        get: impl Fn() -> f64 + Send + Sync + 'static,
+        set: impl Fn(f64) -> Result<()> + Send + Sync + 'static,
+    ) -> Result<()> {
+        match self.symbols.get(name) {
+            None | Some(Symbol::External(..)) => {
+                self.symbols.insert(
+                    name.to_owned(),
+                    Symbol::External(Arc::new(get), Arc::new(set)),
+                );
+                Ok(())
+            }
+            Some(_) => Err(Error::Eval(format!("Symbol {} is already defined", name))),
+        }
+    }
+
+    fn native(&mut self, name: &str, arity: usize, func: Func) -> Result<()> {
+        match self.symbols.get(name) {
+            None | Some(Symbol::Native(..)) => {
+                self.symbols
+                    .insert(name.to_owned(), Symbol::Native(arity, name.into(), func));
+                Ok(())
+            }
+            Some(_) => Err(Error::Eval(format!("Symbol {} is already defined", name))),
+        }
+    }
+
+    /// What the symbol is, `None` if it is not defined
+    pub fn kind(&self, name: &str) -> Option<&'static str> {
+        match self.symbols.get(name)? {
+            Symbol::Variable(_) | Symbol::Captured => Some("variable"),
+            Symbol::Function(_) => Some("function"),
+            Symbol::Argument(_) => Some("argument"),
+            Symbol::Local(_) => Some("local"),
+            Symbol::Native(..) => Some("builtin function"),
+            Symbol::Constant(_) => Some("constant"),
+            Symbol::External(..) => Some("host variable"),
+        }
     }
 
     pub fn is_var(&self, var: &str) -> bool {
-        self.symbols.get(var).map_or(true, Symbol::is_var)
+        self.symbols.get(var).is_none_or(Symbol::is_var)
     }
 
     pub fn is_func(&self, var: &str) -> bool {
-        self.symbols.get(var).map_or(true, Symbol::is_func)
+        self.symbols.get(var).is_none_or(Symbol::is_func)
+    }
+
+    pub fn is_native(&self, var: &str) -> bool {
+        matches!(self.symbols.get(var), Some(Symbol::Native(..)))
+    }
+
+    /// Last result is read only for the user, so it cannot be broken while
+    /// expression using it is evaluated
+    pub fn set_last_result(&mut self, val: Value) {
+        self.symbols
+            .insert(LAST_RESULT.to_owned(), Symbol::Constant(val));
+    }
+
+    pub fn is_const(&self, var: &str) -> bool {
+        matches!(self.symbols.get(var), Some(Symbol::Constant(_)))
     }
 
-    pub fn get_var(&self, var: &str) -> Option<f32> {
+    pub fn get_var(&self, var: &str) -> Option<Value> {
         match self.symbols.get(var)? {
-            Symbol::Variable(v) => Some(*v),
+            Symbol::Variable(v) | Symbol::Constant(v) => Some(v.clone()),
+            Symbol::External(get, _) => Some(Value::Float(get())),
             _ => None,
         }
     }
 
+    /// Variable captured by reference, it is read when function is called
+    pub fn is_captured(&self, var: &str) -> bool {
+        matches!(self.symbols.get(var), Some(Symbol::Captured))
+    }
+
+    /// Variable of the host, it is read every time expression is evaluated
+    pub fn is_external(&self, var: &str) -> bool {
+        matches!(self.symbols.get(var), Some(Symbol::External(..)))
+    }
+
     pub fn get_arg(&self, var: &str) -> Option<usize> {
         match self.symbols.get(var)? {
-            Symbol::Argument(idx) => Some(*idx),
+            Symbol::Argument(idx) | Symbol::Local(idx) => Some(*idx),
             _ => None,
         }
     }
 
     pub fn get_arity(&self, var: &str) -> Option<usize> {
         match self.symbols.get(var)? {
-            Symbol::Function(arity, _) => Some(*arity),
+            Symbol::Function(func) => Some(func.arity),
+            Symbol::Native(arity, _, _) => Some(*arity),
             _ => None,
         }
     }
 
-    pub fn get_func(&self, var: &str) -> Option<Rc<dyn AST>> {
+    /// Names of arguments of user function
+    pub fn get_params(&self, var: &str) -> &[String] {
+        match self.symbols.get(var) {
+            Some(Symbol::Function(func)) => &func.params,
+            _ => &[],
+        }
+    }
+
+    /// User function taking the rest of arguments as list
+    pub fn is_variadic(&self, var: &str) -> bool {
+        matches!(self.symbols.get(var), Some(Symbol::Function(func)) if func.variadic)
+    }
+
+    /// Defaults of trailing arguments of user function
+    pub fn get_defaults(&self, var: &str) -> &[Expr] {
+        match self.symbols.get(var) {
+            Some(Symbol::Function(func)) => &func.defaults,
+            _ => &[],
+        }
+    }
+
+    pub fn get_func(&self, var: &str) -> Option<Callee> {
         match self.symbols.get(var)? {
-            Symbol::Function(_, expr) => Some(expr.clone()),
+            Symbol::Function(func) => Some(Callee::Function(func.body.clone())),
+            Symbol::Native(_, name, func) => Some(Callee::Native(Native {
+                name: name.clone(),
+                func: func.clone(),
+            })),
             _ => None,
         }
     }
+
+    /// Name of user function with given body, if it is still defined
+    pub fn function_name(&self, body: &Arc<Body>) -> Option<&str> {
+        self.symbols.iter().find_map(|(name, symbol)| match symbol {
+            Symbol::Function(func) if Arc::ptr_eq(&func.body, body) => Some(name.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Names of variables, functions and constants starting with `prefix`,
+    /// sorted
+    pub fn complete(&self, prefix: &str) -> Vec<String> {
+        let mut names: Vec<_> = self
+            .symbols
+            .iter()
+            .filter(|(name, symbol)| {
+                name.starts_with(prefix)
+                    && !matches!(symbol, Symbol::Argument(_) | Symbol::Local(_))
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// User variables and constants with their values, in order of definition
+    pub fn variables(&self) -> Vec<(&str, &Value)> {
+        self.order
+            .iter()
+            .filter_map(|name| match self.symbols.get(name)? {
+                Symbol::Variable(val) | Symbol::Constant(val) => Some((name.as_str(), val)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// User functions, in order of first definition
+    pub fn functions(&self) -> Vec<&Function> {
+        self.order
+            .iter()
+            .filter_map(|name| match self.symbols.get(name)? {
+                Symbol::Function(func) => Some(func),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Source lines recreating all user definitions, every symbol after all
+    /// symbols it uses
+    pub fn definitions(&self) -> Result<Vec<String>> {
+        let graph = self.graph();
+        let ordered = graph::topological(&graph)?;
+
+        Ok(ordered
+            .into_iter()
+            .filter_map(|node| self.definition(&node.name))
+            .collect())
+    }
+
+    /// Source line recreating user symbol
+    pub fn definition(&self, name: &str) -> Option<String> {
+        match self.symbols.get(name)? {
+            Symbol::Variable(val) => Some(format!("{} = {}", name, literal(val))),
+            // Only constants defined by user are ordered
+            Symbol::Constant(val) if self.order.iter().any(|defined| defined == name) => {
+                Some(format!("const {} = {}", name, literal(val)))
+            }
+            Symbol::Function(func) => Some(func.source.clone()),
+            Symbol::Argument(_)
+            | Symbol::Local(_)
+            | Symbol::Native(..)
+            | Symbol::Constant(_)
+            | Symbol::Captured
+            | Symbol::External(..) => None,
+        }
+    }
+
+    /// User symbols with symbols they use, in order they were introduced
+    pub fn graph(&self) -> Vec<Node> {
+        self.order
+            .iter()
+            .filter_map(|name| {
+                let (function, uses) = match self.symbols.get(name)? {
+                    Symbol::Variable(_) | Symbol::Constant(_) => (false, vec![]),
+                    Symbol::Function(func) => (true, func.calls.clone()),
+                    Symbol::Argument(_)
+                    | Symbol::Local(_)
+                    | Symbol::Native(..)
+                    | Symbol::Captured
+                    | Symbol::External(..) => return None,
+                };
+
+                Some(Node {
+                    name: name.clone(),
+                    function,
+                    uses,
+                })
+            })
+            .collect()
+    }
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Value rounded to given number of significant digits
+fn significant(val: f64, digits: usize) -> f64 {
+    if val == 0.0 || !val.is_finite() {
+        return val;
+    }
+
+    let scale = digits as i32 - 1 - val.abs().log10().floor() as i32;
+    let factor = 10f64.powi(scale.abs());
+    if !factor.is_finite() {
+        val
+    } else if scale >= 0 {
+        (val * factor).round() / factor
+    } else {
+        (val / factor).round() * factor
+    }
+}
+
+/// Expression evaluating to given value - there are no negative literals,
+/// nor infinity literals
+pub(crate) fn literal(val: &Value) -> String {
+    match val {
+        Value::Int(val) if *val < 0 => format!("0 - {}", -i128::from(*val)),
+        Value::Float(val) if val.is_nan() => "0 / 0".to_owned(),
+        Value::Float(val) if *val == f64::INFINITY => "1 / 0".to_owned(),
+        Value::Float(val) if *val == f64::NEG_INFINITY => "0 - 1 / 0".to_owned(),
+        Value::Float(val) if val.is_sign_negative() => {
+            format!("0 - {}", Value::Float(-val).source())
+        }
+        Value::Decimal(val) if val.is_negative() => format!("0 - {}", val.neg().source()),
+        Value::List(items) => {
+            let items: Vec<_> = items.iter().map(literal).collect();
+            format!("[{}]", items.join(", "))
+        }
+        val => val.source(),
+    }
 }
@@ -0,0 +1,288 @@
+use crate::parser::{Body, Callee, Expr, Fold};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Estimated cost of evaluating expression
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cost {
+    /// Nodes of the expression, without bodies of called functions
+    pub nodes: usize,
+    /// Operations and calls evaluated in the worst case, with called
+    /// functions included
+    pub operations: u64,
+    /// Deepest nesting of calls
+    pub depth: usize,
+    /// Evaluation cannot recurse forever
+    pub terminates: bool,
+}
+
+impl fmt::Display for Cost {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "nodes: {}, operations: {}, call depth: {}, terminates: {}",
+            self.nodes,
+            self.operations,
+            self.depth,
+            if self.terminates { "yes" } else { "no" }
+        )
+    }
+}
+
+/// Cost of evaluating function body, without its nodes
+#[derive(Clone, Copy)]
+struct Call {
+    operations: u64,
+    depth: usize,
+    terminates: bool,
+}
+
+/// Cost of expression without subexpressions
+const LEAF: Cost = Cost {
+    nodes: 1,
+    operations: 0,
+    depth: 0,
+    terminates: true,
+};
+
+/// Work left to do, subexpressions are visited before their costs are
+/// combined
+enum Task<'a> {
+    Visit(&'a Expr),
+    /// Costs of given number of subexpressions are on the stack
+    Combine(&'a Expr, usize),
+}
+
+/// Bodies are shared by all their calls, so every one is estimated once
+#[derive(Default)]
+struct Estimator {
+    bodies: HashMap<*const Body, Call>,
+    visiting: Vec<*const Body>,
+}
+
+impl Estimator {
+    fn body(&mut self, body: &Body) -> Call {
+        let key = body as *const Body;
+        if let Some(call) = self.bodies.get(&key) {
+            return *call;
+        }
+
+        // Functions are bound while they are defined, so they cannot call
+        // themselves, but it is not assumed here
+        if self.visiting.contains(&key) {
+            return Call {
+                operations: 0,
+                depth: 0,
+                terminates: false,
+            };
+        }
+
+        self.visiting.push(key);
+        let cost = self.expr(&body.expr);
+        self.visiting.pop();
+
+        let call = Call {
+            operations: cost.operations,
+            depth: cost.depth,
+            terminates: cost.terminates,
+        };
+        self.bodies.insert(key, call);
+        call
+    }
+
+    /// Costs of subexpressions are computed first, from the leaves up with
+    /// explicit stack, so deep trees cannot overflow the native one
+    fn expr(&mut self, expr: &Expr) -> Cost {
+        let mut tasks = vec![Task::Visit(expr)];
+        let mut costs: Vec<Cost> = vec![];
+
+        while let Some(task) = tasks.pop() {
+            let cost = match task {
+                Task::Visit(expr) => {
+                    let children: Vec<&Expr> = match expr {
+                        Expr::Unit | Expr::Value(_) | Expr::Argument(_) | Expr::Variable(_) => {
+                            costs.push(LEAF);
+                            continue;
+                        }
+                        Expr::Define(func) => vec![&func.body.expr],
+                        Expr::Fold(fold) => vec![&fold.from, &fold.to, &fold.body],
+                        Expr::Cond(cond, then, other) => vec![cond, then, other],
+                        Expr::Assign(_, val) | Expr::Const(_, val) | Expr::Not(val) => vec![val],
+                        Expr::Binary(_, left, right)
+                        | Expr::Index(left, right)
+                        | Expr::Let(_, left, right) => vec![left, right],
+                        Expr::Call(_, args) | Expr::List(args) | Expr::Partial(_, args) => {
+                            args.iter().collect()
+                        }
+                    };
+                    tasks.push(Task::Combine(expr, children.len()));
+                    tasks.extend(children.into_iter().rev().map(Task::Visit));
+                    continue;
+                }
+                Task::Combine(expr, count) => {
+                    let children = costs.split_off(costs.len() - count);
+                    self.combine(expr, &children)
+                }
+            };
+            costs.push(cost);
+        }
+        costs.pop().unwrap_or(LEAF)
+    }
+
+    /// Cost of expression with costs of its subexpressions
+    fn combine(&mut self, expr: &Expr, children: &[Cost]) -> Cost {
+        match (expr, children) {
+            // Definition costs as single call of the function
+            (Expr::Define(_), [body]) => return *body,
+            (Expr::Fold(fold), [from, to, body]) => return Self::fold(fold, from, to, body),
+            (Expr::Cond(..), [cond, then, other]) => return Self::cond(cond, then, other),
+            _ => (),
+        }
+
+        let mut cost = Cost {
+            operations: 1,
+            ..LEAF
+        };
+        for child in children {
+            cost.nodes += child.nodes;
+            cost.operations = cost.operations.saturating_add(child.operations);
+            cost.depth = cost.depth.max(child.depth);
+            cost.terminates &= child.terminates;
+        }
+
+        if let Expr::Call(callee, _) = expr {
+            let call = match callee {
+                Callee::Native(_) => Call {
+                    operations: 0,
+                    depth: 0,
+                    terminates: true,
+                },
+                Callee::Function(body) => self.body(body),
+                // Passed function is not known, it may be even the caller
+                Callee::Argument(_) => Call {
+                    operations: 0,
+                    depth: 0,
+                    terminates: false,
+                },
+            };
+            cost.operations = cost.operations.saturating_add(call.operations);
+            cost.depth = cost.depth.max(call.depth + 1);
+            cost.terminates &= call.terminates;
+        }
+
+        cost
+    }
+
+    /// Body is evaluated for every step, number of steps is known only
+    /// for literal bounds
+    fn fold(fold: &Fold, from: &Cost, to: &Cost, body: &Cost) -> Cost {
+        let steps = match (fold.from.value(), fold.to.value()) {
+            (Some(from), Some(to)) => {
+                let steps = (to.as_f64() - from.as_f64()).floor() + 1.0;
+                if steps.is_nan() {
+                    0
+                } else {
+                    steps.max(0.0) as u64
+                }
+            }
+            _ => u64::MAX,
+        };
+
+        Cost {
+            nodes: 1 + from.nodes + to.nodes + body.nodes,
+            operations: (body.operations.saturating_add(1))
+                .saturating_mul(steps)
+                .saturating_add(from.operations)
+                .saturating_add(to.operations),
+            depth: from.depth.max(to.depth).max(body.depth),
+            terminates: from.terminates && to.terminates && body.terminates,
+        }
+    }
+
+    /// Only one branch is evaluated, the more expensive one is counted
+    fn cond(cond: &Cost, then: &Cost, other: &Cost) -> Cost {
+        Cost {
+            nodes: 1 + cond.nodes + then.nodes + other.nodes,
+            operations: cond
+                .operations
+                .saturating_add(then.operations.max(other.operations))
+                .saturating_add(1),
+            depth: cond.depth.max(then.depth).max(other.depth),
+            terminates: cond.terminates && then.terminates && other.terminates,
+        }
+    }
+}
+
+/// Estimates cost of the expression, or of the function body if it is
+/// definition
+pub fn cost(expr: &Expr) -> Cost {
+    Estimator::default().expr(expr)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::Interpreter;
+
+    fn estimate(interpreter: &Interpreter, src: &str) -> Cost {
+        cost(&interpreter.parse(src).unwrap())
+    }
+
+    #[test]
+    fn expressions() {
+        let interpreter = Interpreter::new();
+        assert_eq!(
+            Cost {
+                nodes: 1,
+                operations: 0,
+                depth: 0,
+                terminates: true
+            },
+            estimate(&interpreter, "2 * 3 + 1")
+        );
+        assert_eq!(
+            "nodes: 4, operations: 2, call depth: 1, terminates: yes",
+            estimate(&interpreter, "max(sqrt 4, 2)").to_string()
+        );
+    }
+
+    #[test]
+    fn calls() {
+        let mut interpreter = Interpreter::new();
+        interpreter.eval("sq x => x * x").unwrap();
+        interpreter.eval("f a b => sq(a) + sq(b)").unwrap();
+        interpreter.eval("g a => f(a, a) * f(a, 1)").unwrap();
+
+        let cost = estimate(&interpreter, "g 2");
+        assert_eq!(2, cost.nodes);
+        // `g` 2 calls and multiplication, `f` 2 calls and addition, `sq` one
+        // multiplication each, and the call of `g` itself
+        assert_eq!(1 + 3 + 2 * (3 + 2), cost.operations);
+        assert_eq!(3, cost.depth);
+        assert!(cost.terminates);
+
+        let body = estimate(&interpreter, "h a => g a");
+        assert_eq!(cost.operations, body.operations);
+    }
+
+    #[test]
+    fn folds() {
+        let mut interpreter = Interpreter::new();
+        let cost = estimate(&interpreter, "sum i = 1 to 10 of i * 2");
+        assert_eq!((6, 20), (cost.nodes, cost.operations));
+        assert_eq!(0, estimate(&interpreter, "sum i = 2 to 1 of i").operations);
+
+        interpreter.eval("n = 3").unwrap();
+        let cost = estimate(&interpreter, "sum i = 1 to n of sum j = 1 to i of j");
+        assert_eq!(u64::MAX, cost.operations);
+        assert!(cost.terminates);
+    }
+
+    #[test]
+    fn conditions() {
+        let interpreter = Interpreter::new();
+        let cost = estimate(&interpreter, "f a => a ? 3 : [4, a + 6]");
+        assert_eq!((8, 3), (cost.nodes, cost.operations));
+    }
+}
@@ -0,0 +1,507 @@
+//! Decimal numbers of arbitrary precision, used instead of floats in decimal
+//! mode. Sums, differences and products are exact, quotients are rounded to
+//! `SCALE` fractional digits.
+
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// Fractional digits kept by inexact operations
+pub const SCALE: u32 = 40;
+
+/// Most decimal digits of integral part of power, bigger ones would take
+/// too long to compute
+const MAX_DIGITS: f64 = 10_000.0;
+
+/// Natural number as base 2^32 digits, the least significant first and
+/// without leading zeros, so zero has no digits
+type Digits = Vec<u32>;
+
+fn trim(mut digits: Digits) -> Digits {
+    while digits.last() == Some(&0) {
+        digits.pop();
+    }
+    digits
+}
+
+fn compare(left: &[u32], right: &[u32]) -> Ordering {
+    left.len()
+        .cmp(&right.len())
+        .then_with(|| left.iter().rev().cmp(right.iter().rev()))
+}
+
+fn add(left: &[u32], right: &[u32]) -> Digits {
+    let (long, short) = if left.len() >= right.len() {
+        (left, right)
+    } else {
+        (right, left)
+    };
+
+    let mut result = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0;
+    for (idx, &digit) in long.iter().enumerate() {
+        let sum = digit as u64 + *short.get(idx).unwrap_or(&0) as u64 + carry;
+        result.push(sum as u32);
+        carry = sum >> 32;
+    }
+    if carry > 0 {
+        result.push(carry as u32);
+    }
+    result
+}
+
+/// `left` cannot be smaller than `right`
+fn sub(left: &[u32], right: &[u32]) -> Digits {
+    let mut result = Vec::with_capacity(left.len());
+    let mut borrow = 0;
+    for (idx, &digit) in left.iter().enumerate() {
+        let mut diff = digit as i64 - *right.get(idx).unwrap_or(&0) as i64 - borrow;
+        borrow = 0;
+        if diff < 0 {
+            diff += 1 << 32;
+            borrow = 1;
+        }
+        result.push(diff as u32);
+    }
+    trim(result)
+}
+
+fn mul(left: &[u32], right: &[u32]) -> Digits {
+    let mut result = vec![0; left.len() + right.len()];
+    for (i, &l) in left.iter().enumerate() {
+        let mut carry = 0;
+        for (j, &r) in right.iter().enumerate() {
+            let product = result[i + j] as u64 + l as u64 * r as u64 + carry;
+            result[i + j] = product as u32;
+            carry = product >> 32;
+        }
+        result[i + right.len()] = carry as u32;
+    }
+    trim(result)
+}
+
+/// `digits * factor + addend`
+fn mul_add(digits: &[u32], factor: u32, addend: u32) -> Digits {
+    let mut result = Vec::with_capacity(digits.len() + 1);
+    let mut carry = addend as u64;
+    for &digit in digits {
+        let product = digit as u64 * factor as u64 + carry;
+        result.push(product as u32);
+        carry = product >> 32;
+    }
+    result.push(carry as u32);
+    trim(result)
+}
+
+fn divrem_small(digits: &[u32], divisor: u32) -> (Digits, u32) {
+    let mut quotient = vec![0; digits.len()];
+    let mut rem = 0;
+    for (idx, &digit) in digits.iter().enumerate().rev() {
+        let current = (rem << 32) | digit as u64;
+        quotient[idx] = (current / divisor as u64) as u32;
+        rem = current % divisor as u64;
+    }
+    (trim(quotient), rem as u32)
+}
+
+/// Long division bit by bit, numbers are never big enough to need anything
+/// smarter. `divisor` cannot be zero.
+fn divrem(digits: &[u32], divisor: &[u32]) -> (Digits, Digits) {
+    if let [divisor] = divisor {
+        let (quotient, rem) = divrem_small(digits, *divisor);
+        return (quotient, trim(vec![rem]));
+    }
+
+    let mut quotient = vec![0; digits.len()];
+    let mut rem: Digits = vec![];
+    for bit in (0..digits.len() * 32).rev() {
+        let mut carry = (digits[bit / 32] >> (bit % 32)) & 1;
+        for digit in &mut rem {
+            let shifted = (*digit << 1) | carry;
+            carry = *digit >> 31;
+            *digit = shifted;
+        }
+        if carry > 0 {
+            rem.push(carry);
+        }
+
+        if compare(&rem, divisor) != Ordering::Less {
+            rem = sub(&rem, divisor);
+            quotient[bit / 32] |= 1 << (bit % 32);
+        }
+    }
+    (trim(quotient), rem)
+}
+
+fn pow10(exp: u32) -> Digits {
+    (0..exp).fold(vec![1], |digits, _| mul_add(&digits, 10, 0))
+}
+
+/// `digits / divisor` rounded half away from zero
+fn div_rounded(digits: &[u32], divisor: &[u32]) -> Digits {
+    let (quotient, rem) = divrem(digits, divisor);
+    if compare(&add(&rem, &rem), divisor) == Ordering::Less {
+        quotient
+    } else {
+        add(&quotient, &[1])
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Decimal {
+    negative: bool,
+    digits: Digits, // Magnitude multiplied by `10^scale`
+    scale: u32,
+}
+
+impl Decimal {
+    fn new(negative: bool, digits: Digits, scale: u32) -> Self {
+        let digits = trim(digits);
+        Decimal {
+            negative: negative && !digits.is_empty(),
+            digits,
+            scale,
+        }
+    }
+
+    /// Number written as `-123.45`, fractional digits are kept as written
+    pub fn parse(src: &str) -> Option<Self> {
+        let (negative, src) = match src.strip_prefix('-') {
+            Some(src) => (true, src),
+            None => (false, src),
+        };
+        let (integral, fraction) = src.split_once('.').unwrap_or((src, ""));
+        let all = || integral.bytes().chain(fraction.bytes());
+        if all().next().is_none() || !all().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+
+        let digits = all().fold(vec![], |digits, digit| {
+            mul_add(&digits, 10, (digit - b'0') as u32)
+        });
+        Some(Decimal::new(negative, digits, fraction.len() as u32))
+    }
+
+    /// Exact value of the shortest representation of the float, which is
+    /// what was written to get it
+    pub fn from_f64(val: f64) -> Option<Self> {
+        if val.is_finite() {
+            Decimal::parse(&val.to_string())
+        } else {
+            None
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.to_string().parse().unwrap_or(f64::NAN)
+    }
+
+    /// Value if it is integer fitting into `i64`
+    pub fn to_i64(&self) -> Option<i64> {
+        let val = self.normalized();
+        if val.scale > 0 || val.digits.len() > 2 {
+            return None;
+        }
+
+        let magnitude = val
+            .digits
+            .iter()
+            .rev()
+            .fold(0, |acc, &digit| (acc << 32) | digit as i128);
+        let val = if val.negative { -magnitude } else { magnitude };
+        i64::try_from(val).ok()
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.digits.is_empty()
+    }
+
+    /// Logarithm of magnitude, from its two most significant digits -
+    /// negative infinity for zero
+    fn log10(&self) -> f64 {
+        let top = self
+            .digits
+            .iter()
+            .rev()
+            .take(2)
+            .fold(0.0, |top, &digit| top * 2f64.powi(32) + digit as f64);
+        let shift = self.digits.len().saturating_sub(2) * 32;
+        (top.log2() + shift as f64) * std::f64::consts::LOG10_2 - self.scale as f64
+    }
+
+    /// Bytes of memory taken by digits
+    pub fn heap_size(&self) -> usize {
+        self.digits.len() * std::mem::size_of::<u32>()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn neg(&self) -> Self {
+        Decimal::new(!self.negative, self.digits.clone(), self.scale)
+    }
+
+    /// Trailing fractional zeros removed
+    fn normalized(&self) -> Self {
+        let mut val = self.clone();
+        while val.scale > 0 {
+            match divrem_small(&val.digits, 10) {
+                (digits, 0) => val.digits = digits,
+                _ => break,
+            }
+            val.scale -= 1;
+        }
+        val
+    }
+
+    /// Digits of magnitude with given scale, which cannot be smaller than
+    /// the current one
+    fn digits_at(&self, scale: u32) -> Digits {
+        mul(&self.digits, &pow10(scale - self.scale))
+    }
+
+    /// Rounded to at most `scale` fractional digits
+    fn round(self, scale: u32) -> Self {
+        if self.scale <= scale {
+            return self;
+        }
+
+        let digits = div_rounded(&self.digits, &pow10(self.scale - scale));
+        Decimal::new(self.negative, digits, scale)
+    }
+
+    pub fn add(&self, other: &Decimal) -> Self {
+        let scale = self.scale.max(other.scale);
+        let (left, right) = (self.digits_at(scale), other.digits_at(scale));
+        let result = if self.negative == other.negative {
+            Decimal::new(self.negative, add(&left, &right), scale)
+        } else if compare(&left, &right) == Ordering::Less {
+            Decimal::new(other.negative, sub(&right, &left), scale)
+        } else {
+            Decimal::new(self.negative, sub(&left, &right), scale)
+        };
+        result.normalized()
+    }
+
+    pub fn sub(&self, other: &Decimal) -> Self {
+        self.add(&other.neg())
+    }
+
+    pub fn mul(&self, other: &Decimal) -> Self {
+        Decimal::new(
+            self.negative != other.negative,
+            mul(&self.digits, &other.digits),
+            self.scale + other.scale,
+        )
+        .round(SCALE)
+        .normalized()
+    }
+
+    /// Quotient rounded to `SCALE` fractional digits, `None` for zero divisor
+    pub fn div(&self, other: &Decimal) -> Option<Self> {
+        if other.is_zero() {
+            return None;
+        }
+
+        let (mut digits, mut divisor) = (self.digits.clone(), other.digits.clone());
+        let shift = SCALE as i64 + other.scale as i64 - self.scale as i64;
+        if shift >= 0 {
+            digits = mul(&digits, &pow10(shift as u32));
+        } else {
+            divisor = mul(&divisor, &pow10(-shift as u32));
+        }
+
+        let digits = div_rounded(&digits, &divisor);
+        Some(Decimal::new(self.negative != other.negative, digits, SCALE).normalized())
+    }
+
+    /// Multiplied by `10^exp`
+    pub fn scaled(&self, exp: i32) -> Self {
+        let scale = self.scale as i64 - exp as i64;
+        if scale >= 0 {
+            Decimal::new(self.negative, self.digits.clone(), scale as u32)
+        } else {
+            Decimal::new(self.negative, self.digits_at(self.scale + -scale as u32), 0)
+        }
+    }
+
+    /// Integral part
+    pub fn trunc(&self) -> Self {
+        let (digits, _) = divrem(&self.digits, &pow10(self.scale));
+        Decimal::new(self.negative, digits, 0)
+    }
+
+    /// Remainder of operands truncated to integers, with sign of dividend,
+    /// `None` for zero divisor
+    pub fn rem(&self, other: &Decimal) -> Option<Self> {
+        let divisor = other.trunc();
+        if divisor.is_zero() {
+            return None;
+        }
+
+        let (_, rem) = divrem(&self.trunc().digits, &divisor.digits);
+        Some(Decimal::new(self.negative, rem, 0))
+    }
+
+    /// Integer power, `None` if it is too big to compute or it is negative
+    /// power of zero
+    pub fn pow(&self, exp: i64) -> Option<Self> {
+        // Size of the result is known before it is computed, also of the
+        // power inverted for negative exponent
+        if exp as f64 * self.log10() > MAX_DIGITS {
+            return None;
+        }
+
+        let (mut result, mut base) = (Decimal::from(1), self.clone());
+        let mut remaining = exp.unsigned_abs();
+        while remaining > 0 {
+            if remaining & 1 == 1 {
+                result = result.mul(&base);
+            }
+            remaining >>= 1;
+            if remaining > 0 {
+                base = base.mul(&base);
+            }
+        }
+
+        if exp < 0 {
+            Decimal::from(1).div(&result)
+        } else {
+            Some(result)
+        }
+    }
+
+    /// Literal form, fractional digits are kept as they are, so `2.0` stays
+    /// `2.0`. Only non negative values have one.
+    pub fn source(&self) -> String {
+        let mut digits = vec![];
+        let mut rest = self.digits.clone();
+        while !rest.is_empty() {
+            let (quotient, rem) = divrem_small(&rest, 10);
+            digits.push(b'0' + rem as u8);
+            rest = quotient;
+        }
+        // At least one integral digit
+        digits.resize(digits.len().max(self.scale as usize + 1), b'0');
+        digits.reverse();
+
+        let digits = String::from_utf8(digits).unwrap_or_default();
+        let (integral, fraction) = digits.split_at(digits.len() - self.scale as usize);
+        let sign = if self.negative { "-" } else { "" };
+        if fraction.is_empty() {
+            format!("{}{}", sign, integral)
+        } else {
+            format!("{}{}.{}", sign, integral, fraction)
+        }
+    }
+}
+
+impl From<i64> for Decimal {
+    fn from(val: i64) -> Self {
+        let magnitude = val.unsigned_abs();
+        let digits = vec![magnitude as u32, (magnitude >> 32) as u32];
+        Decimal::new(val < 0, digits, 0)
+    }
+}
+
+/// Numeric equality, regardless of written fractional zeros
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        self.sub(other).is_zero()
+    }
+}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        let diff = self.sub(other);
+        if diff.is_zero() {
+            Some(Ordering::Equal)
+        } else if diff.negative {
+            Some(Ordering::Less)
+        } else {
+            Some(Ordering::Greater)
+        }
+    }
+}
+
+/// Without trailing fractional zeros
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.normalized().source())
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn decimal(src: &str) -> Decimal {
+        Decimal::parse(src).unwrap()
+    }
+
+    #[test]
+    fn parsing() {
+        assert_eq!("10.30", decimal("10.30").source());
+        assert_eq!("10.3", decimal("10.30").to_string());
+        assert_eq!("-0.05", decimal("-0.05").source());
+        assert_eq!("0", decimal("-0.0").to_string());
+        assert_eq!(
+            "123456789012345678901234567890",
+            decimal("123456789012345678901234567890").to_string()
+        );
+        assert_eq!(Some(decimal("0.1")), Decimal::from_f64(0.1));
+        assert_eq!(Some(-42), decimal("-42.00").to_i64());
+        assert_eq!(None, decimal("4.2").to_i64());
+        assert_eq!(None, Decimal::parse("1.2.3"));
+        assert_eq!(None, Decimal::parse("."));
+        assert_eq!(decimal("0.5"), decimal(".5"));
+        assert_eq!("1230", decimal("1.23").scaled(3).source());
+        assert_eq!("0.00123", decimal("1.23").scaled(-3).source());
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(decimal("0.3"), decimal("0.1").add(&decimal("0.2")));
+        assert_eq!(decimal("-1.5"), decimal("2").sub(&decimal("3.5")));
+        assert!(decimal("-1.5") < decimal("0.1"));
+        assert!(decimal("0.30") > decimal("0.2"));
+        assert_eq!(
+            "121932631137021795223746380111126352690",
+            decimal("12345678901234567890")
+                .mul(&decimal("9876543210987654321"))
+                .to_string()
+        );
+        assert_eq!(
+            "0.3333333333333333333333333333333333333333",
+            decimal("1").div(&decimal("3")).unwrap().to_string()
+        );
+        assert_eq!(
+            "-0.6666666666666666666666666666666666666667",
+            decimal("-2").div(&decimal("3")).unwrap().to_string()
+        );
+        assert_eq!(decimal("0.25"), decimal("1").div(&decimal("4")).unwrap());
+        assert_eq!(None, decimal("1").div(&decimal("0.0")));
+    }
+
+    #[test]
+    fn integers() {
+        assert_eq!(decimal("-1"), decimal("-7.5").rem(&decimal("3")).unwrap());
+        assert_eq!(None, decimal("7").rem(&decimal("0.5")));
+        assert_eq!(
+            "1267650600228229401496703205376",
+            decimal("2").pow(100).unwrap().to_string()
+        );
+        assert_eq!(decimal("0.125"), decimal("2").pow(-3).unwrap());
+        assert_eq!(None, decimal("2").pow(1 << 20));
+        assert_eq!(None, decimal("0.5").pow(-(1 << 20)));
+        assert_eq!(decimal("1"), decimal("1").pow(i64::MAX).unwrap());
+        assert_eq!(decimal("0"), decimal("0.5").pow(1 << 20).unwrap());
+
+        // Too big results are rejected before they are computed
+        let big = decimal("10").pow(4000).unwrap();
+        assert_eq!(4001, big.to_string().len());
+        assert_eq!(None, big.pow(4000));
+    }
+}
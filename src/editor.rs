@@ -0,0 +1,1009 @@
+use crate::repl::Input;
+use crate::{Error, Result};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+/// Key bindings of interactive line editing
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Keymap {
+    #[default]
+    Emacs,
+    Vi,
+}
+
+impl Keymap {
+    pub fn name(self) -> &'static str {
+        match self {
+            Keymap::Emacs => "emacs",
+            Keymap::Vi => "vi",
+        }
+    }
+
+    /// Shown in prompt, line is always read starting in vi insert mode
+    pub fn indicator(self, normal: bool) -> &'static str {
+        match self {
+            Keymap::Emacs => "",
+            Keymap::Vi if normal => "[N]",
+            Keymap::Vi => "[I]",
+        }
+    }
+}
+
+impl FromStr for Keymap {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "emacs" => Ok(Keymap::Emacs),
+            "vi" => Ok(Keymap::Vi),
+            _ => Err(format!("Unknown keymap: {}, expected `emacs` or `vi`", s).into()),
+        }
+    }
+}
+
+/// Consecutive read failures tolerated in interactive session
+pub const READ_RETRIES: usize = 3;
+
+/// Reads line without terminator, `None` at the end of input. Failed reads
+/// are retried in interactive session, as user may just had typed something
+/// invalid, with every failure reported; in batch mode the input cannot be
+/// trusted after the first failure.
+pub fn read_line(
+    input: &mut impl BufRead,
+    interactive: bool,
+    mut report: impl FnMut(&io::Error),
+) -> Result<Option<String>> {
+    let mut failures = 0;
+    loop {
+        let mut line = String::new();
+        match input.read_line(&mut line) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(line.trim_end_matches(&['\n', '\r'][..]).to_owned())),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => {
+                failures += 1;
+                if !interactive || failures > READ_RETRIES {
+                    return Err(Error::Io(format!("Cannot read input: {}", err)));
+                }
+                report(&err);
+            }
+        }
+    }
+}
+
+pub fn default_history_path() -> PathBuf {
+    env::var_os("HOME")
+        .map(PathBuf::from)
+        .unwrap_or_default()
+        .join(".toy-interpreter.history")
+}
+
+/// Lines entered in all sessions, oldest first
+#[derive(Default)]
+pub struct History {
+    entries: Vec<String>,
+    path: Option<PathBuf>,
+}
+
+impl History {
+    /// Missing file is just empty history
+    pub fn load(path: PathBuf) -> Self {
+        let entries = fs::read_to_string(&path)
+            .map(|content| content.lines().map(str::to_owned).collect())
+            .unwrap_or_default();
+
+        History {
+            entries,
+            path: Some(path),
+        }
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    /// Empty lines and repetitions of the previous line are not stored. If
+    /// the line cannot be written to the file, history is kept only in
+    /// memory from then on, so the failure is returned once.
+    pub fn push(&mut self, line: &str) -> Result<()> {
+        if line.trim().is_empty() || self.entries.last().map(String::as_str) == Some(line) {
+            return Ok(());
+        }
+
+        self.entries.push(line.to_owned());
+        if let Some(path) = &self.path {
+            let written = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| writeln!(file, "{}", line));
+            if let Err(err) = written {
+                let message = format!("Cannot write history {}: {}", path.display(), err);
+                self.path = None;
+                return Err(message.into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Newest entry older than `before` containing `query`
+    fn search(&self, query: &str, before: usize) -> Option<Match> {
+        self.entries[..before.min(self.entries.len())]
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(entry, line)| line.find(query).map(|start| Match { entry, start }))
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Match {
+    pub entry: usize,
+    pub start: usize, // Byte offset of the query in entry
+}
+
+/// Reverse incremental search state, as started with Ctrl-R
+pub struct ReverseSearch {
+    query: String,
+    found: Option<Match>,
+}
+
+impl ReverseSearch {
+    pub fn new() -> Self {
+        ReverseSearch {
+            query: String::new(),
+            found: None,
+        }
+    }
+
+    pub fn found(&self) -> Option<Match> {
+        self.found
+    }
+
+    /// Extending query keeps current match if it still matches
+    pub fn input(&mut self, history: &History, c: char) {
+        self.query.push(c);
+        let before = self
+            .found
+            .map_or(history.entries.len(), |found| found.entry + 1);
+        self.found = history.search(&self.query, before);
+    }
+
+    /// Shortening query starts search again from the newest entry
+    pub fn backspace(&mut self, history: &History) {
+        self.query.pop();
+        self.found = if self.query.is_empty() {
+            None
+        } else {
+            history.search(&self.query, history.entries.len())
+        };
+    }
+
+    /// Another Ctrl-R - moves to older match, staying at current if there is none
+    pub fn next(&mut self, history: &History) {
+        let before = self
+            .found
+            .map_or(history.entries.len(), |found| found.entry);
+        if let Some(found) = history.search(&self.query, before) {
+            self.found = Some(found);
+        }
+    }
+
+    /// Search line with matched part of entry in reverse video
+    pub fn render(&self, history: &History) -> String {
+        let line = match self.found {
+            Some(found) => {
+                let entry = &history.entries[found.entry];
+                let end = found.start + self.query.len();
+                format!(
+                    "{}\x1b[7m{}\x1b[0m{}",
+                    &entry[..found.start],
+                    &entry[found.start..end],
+                    &entry[end..]
+                )
+            }
+            None => "".to_owned(),
+        };
+
+        format!("(reverse-i-search)`{}': {}", self.query, line)
+    }
+}
+
+impl Default for ReverseSearch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Key pressed in terminal, as decoded from its bytes
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Key {
+    Char(char),
+    Tab,
+    /// Letter pressed with Ctrl, lowercase
+    Ctrl(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    Escape,
+    /// Key following escape, as terminals send keys pressed with Alt - also
+    /// any key pressed right after Escape
+    Alt(char),
+}
+
+fn byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut buf = [0];
+    loop {
+        match input.read(&mut buf) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(buf[0])),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Next key, `None` at the end of input. Unknown escape sequences and invalid
+/// characters are skipped.
+pub fn read_key(input: &mut impl Read) -> io::Result<Option<Key>> {
+    loop {
+        let first = match byte(input)? {
+            Some(first) => first,
+            None => return Ok(None),
+        };
+
+        let key = match first {
+            b'\r' | b'\n' => Some(Key::Enter),
+            b'\t' => Some(Key::Tab),
+            8 | 127 => Some(Key::Backspace),
+            1..=26 => Some(Key::Ctrl((b'a' + first - 1) as char)),
+            0x1b => escape(input)?,
+            _ => {
+                // Leading byte tells length of UTF-8 sequence
+                let len = match first.leading_ones() {
+                    0 => 1,
+                    len => len as usize,
+                };
+                let mut bytes = vec![first];
+                for _ in 1..len {
+                    bytes.extend(byte(input)?);
+                }
+                std::str::from_utf8(&bytes)
+                    .ok()
+                    .and_then(|s| s.chars().next())
+                    .map(Key::Char)
+            }
+        };
+
+        if key.is_some() {
+            return Ok(key);
+        }
+    }
+}
+
+/// Key of sequence following escape, as `ESC [ A` for up arrow
+fn escape(input: &mut impl Read) -> io::Result<Option<Key>> {
+    match byte(input)? {
+        Some(b'[') | Some(b'O') => (),
+        Some(next @ b' '..=b'~') => return Ok(Some(Key::Alt(next as char))),
+        Some(b'\r') | Some(b'\n') => return Ok(Some(Key::Alt('\n'))),
+        _ => return Ok(Some(Key::Escape)),
+    }
+
+    let mut params = vec![];
+    loop {
+        let key = match byte(input)? {
+            Some(b'A') => Key::Up,
+            Some(b'B') => Key::Down,
+            Some(b'C') => Key::Right,
+            Some(b'D') => Key::Left,
+            Some(b'H') => Key::Home,
+            Some(b'F') => Key::End,
+            Some(b'~') => match params.as_slice() {
+                b"1" | b"7" => Key::Home,
+                b"4" | b"8" => Key::End,
+                b"3" => Key::Delete,
+                _ => return Ok(None),
+            },
+            Some(param @ b'0'..=b'9') | Some(param @ b';') => {
+                params.push(param);
+                continue;
+            }
+            _ => return Ok(None),
+        };
+        return Ok(Some(key));
+    }
+}
+
+/// What the editor does after a key
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    Edit,
+    Submit(String),
+    /// Ctrl-C, the line is abandoned
+    Cancel,
+    /// Ctrl-D on empty line
+    Eof,
+    /// Tab completing more names, which are listed
+    Candidates(Vec<String>),
+}
+
+/// Line being edited, with its history browsed by arrows and searched with
+/// Ctrl-R, and names completed with Tab
+pub struct Editing<'a> {
+    chars: Vec<char>,
+    cursor: usize, // Index of char before which keys insert
+    keymap: Keymap,
+    normal: bool, // Vi normal mode, where keys are commands
+    history: &'a History,
+    browsed: usize,   // Entry shown, number of entries for the new line
+    draft: Vec<char>, // New line, kept while browsing history
+    search: Option<ReverseSearch>,
+    completions: &'a [String],
+}
+
+impl<'a> Editing<'a> {
+    pub fn new(history: &'a History, completions: &'a [String]) -> Self {
+        Editing {
+            chars: vec![],
+            cursor: 0,
+            keymap: Keymap::Emacs,
+            normal: false,
+            history,
+            browsed: history.entries.len(),
+            draft: vec![],
+            search: None,
+            completions,
+        }
+    }
+
+    /// Line is edited starting in vi insert mode
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keymap = keymap;
+        self.normal = false;
+    }
+
+    /// In vi normal mode
+    pub fn is_normal(&self) -> bool {
+        self.normal
+    }
+
+    pub fn line(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    fn replace(&mut self, chars: Vec<char>) {
+        self.cursor = chars.len();
+        self.chars = chars;
+    }
+
+    fn browse(&mut self, entry: usize) {
+        if self.browsed == self.history.entries.len() {
+            self.draft = self.chars.clone();
+        }
+        self.browsed = entry;
+        let chars = match self.history.entries.get(entry) {
+            Some(line) => line.chars().collect(),
+            None => self.draft.clone(),
+        };
+        self.replace(chars);
+    }
+
+    /// Completes name before the cursor up to the longest prefix shared by
+    /// all matching names, which are listed if it does not complete anything
+    fn complete(&mut self) -> Action {
+        let start = self.chars[..self.cursor]
+            .iter()
+            .rposition(|c| !(c.is_ascii_alphanumeric() || *c == '_' || *c == ':'))
+            .map_or(0, |idx| idx + 1);
+        let prefix: String = self.chars[start..self.cursor].iter().collect();
+        if prefix.is_empty() {
+            return Action::Edit;
+        }
+
+        let candidates: Vec<_> = self
+            .completions
+            .iter()
+            .filter(|name| name.starts_with(&prefix))
+            .collect();
+        let common = match candidates.split_first() {
+            Some((first, rest)) => rest.iter().fold(first.len(), |common, name| {
+                first
+                    .bytes()
+                    .zip(name.bytes())
+                    .take(common)
+                    .take_while(|(a, b)| a == b)
+                    .count()
+            }),
+            None => return Action::Edit,
+        };
+
+        if common == prefix.len() && candidates.len() > 1 {
+            return Action::Candidates(candidates.into_iter().cloned().collect());
+        }
+        for c in candidates[0][prefix.len()..common].chars() {
+            self.chars.insert(self.cursor, c);
+            self.cursor += 1;
+        }
+        Action::Edit
+    }
+
+    pub fn key(&mut self, key: Key) -> Action {
+        if let Some(search) = self.search.as_mut() {
+            match key {
+                Key::Char(c) => search.input(self.history, c),
+                Key::Backspace => search.backspace(self.history),
+                Key::Ctrl('r') => search.next(self.history),
+                Key::Ctrl('c') | Key::Ctrl('g') => self.search = None,
+                // Any other key takes the found line
+                key => {
+                    if let Some(found) = search.found() {
+                        let chars = self.history.entries[found.entry].chars().collect();
+                        self.replace(chars);
+                    }
+                    self.search = None;
+                    if key == Key::Enter {
+                        return Action::Submit(self.line());
+                    }
+                }
+            }
+            return Action::Edit;
+        }
+
+        if self.keymap == Keymap::Vi {
+            match key {
+                Key::Escape if !self.normal => {
+                    self.normal = true;
+                    self.cursor = self.cursor.saturating_sub(1);
+                    return Action::Edit;
+                }
+                Key::Alt(c) => {
+                    self.key(Key::Escape);
+                    return match c {
+                        '\n' => self.key(Key::Enter),
+                        c => self.key(Key::Char(c)),
+                    };
+                }
+                Key::Char(c) if self.normal => return self.command(c),
+                Key::Backspace if self.normal => return self.command('h'),
+                _ => (),
+            }
+        }
+
+        match key {
+            Key::Char(c) => {
+                self.chars.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            Key::Enter => return Action::Submit(self.line()),
+            Key::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.chars.remove(self.cursor);
+            }
+            Key::Ctrl('d') if self.chars.is_empty() => return Action::Eof,
+            Key::Delete | Key::Ctrl('d') if self.cursor < self.chars.len() => {
+                self.chars.remove(self.cursor);
+            }
+            Key::Left | Key::Ctrl('b') => self.cursor = self.cursor.saturating_sub(1),
+            Key::Right | Key::Ctrl('f') => self.cursor = (self.cursor + 1).min(self.chars.len()),
+            Key::Home | Key::Ctrl('a') => self.cursor = 0,
+            Key::End | Key::Ctrl('e') => self.cursor = self.chars.len(),
+            Key::Up | Key::Ctrl('p') if self.browsed > 0 => self.browse(self.browsed - 1),
+            Key::Down | Key::Ctrl('n') if self.browsed < self.history.entries.len() => {
+                self.browse(self.browsed + 1)
+            }
+            Key::Ctrl('k') => self.chars.truncate(self.cursor),
+            Key::Ctrl('u') => {
+                self.chars.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            Key::Ctrl('w') => {
+                let start = self.chars[..self.cursor]
+                    .iter()
+                    .rposition(|c| !c.is_whitespace())
+                    .and_then(|end| self.chars[..end].iter().rposition(|c| c.is_whitespace()))
+                    .map_or(0, |space| space + 1);
+                self.chars.drain(start..self.cursor);
+                self.cursor = start;
+            }
+            Key::Ctrl('r') => self.search = Some(ReverseSearch::new()),
+            Key::Ctrl('c') => return Action::Cancel,
+            Key::Tab => return self.complete(),
+            _ => (),
+        }
+        Action::Edit
+    }
+
+    /// Key of vi normal mode, cursor stays on the last char
+    fn command(&mut self, c: char) -> Action {
+        let last = self.chars.len().saturating_sub(1);
+        match c {
+            'h' => self.cursor = self.cursor.saturating_sub(1),
+            'l' | ' ' => self.cursor = (self.cursor + 1).min(last),
+            '0' | '^' => self.cursor = 0,
+            '$' => self.cursor = last,
+            'w' => {
+                let rest = &self.chars[self.cursor..];
+                let next = rest
+                    .iter()
+                    .position(|c| c.is_whitespace())
+                    .and_then(|space| {
+                        rest[space..]
+                            .iter()
+                            .position(|c| !c.is_whitespace())
+                            .map(|word| space + word)
+                    });
+                self.cursor = next.map_or(last, |next| self.cursor + next);
+            }
+            'b' => {
+                self.cursor = self.chars[..self.cursor]
+                    .iter()
+                    .rposition(|c| !c.is_whitespace())
+                    .and_then(|end| self.chars[..end].iter().rposition(|c| c.is_whitespace()))
+                    .map_or(0, |space| space + 1);
+            }
+            'k' | '-' if self.browsed > 0 => self.browse(self.browsed - 1),
+            'j' | '+' if self.browsed < self.history.entries.len() => self.browse(self.browsed + 1),
+            'x' if self.cursor < self.chars.len() => {
+                self.chars.remove(self.cursor);
+                self.cursor = self.cursor.min(self.chars.len().saturating_sub(1));
+            }
+            'X' if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.chars.remove(self.cursor);
+            }
+            'D' => {
+                self.chars.truncate(self.cursor);
+                self.cursor = self.cursor.min(self.chars.len().saturating_sub(1));
+            }
+            'i' => self.normal = false,
+            'a' => {
+                self.cursor = (self.cursor + 1).min(self.chars.len());
+                self.normal = false;
+            }
+            'I' => {
+                self.cursor = 0;
+                self.normal = false;
+            }
+            'A' => {
+                self.cursor = self.chars.len();
+                self.normal = false;
+            }
+            'C' => {
+                self.chars.truncate(self.cursor);
+                self.normal = false;
+            }
+            'S' => {
+                self.replace(vec![]);
+                self.normal = false;
+            }
+            _ => (),
+        }
+        // History entries are shown with cursor past their end
+        if self.normal {
+            self.cursor = self.cursor.min(self.chars.len().saturating_sub(1));
+        }
+        Action::Edit
+    }
+
+    /// Terminal output redrawing the whole line, with cursor where keys insert
+    pub fn render(&self, prompt: &str) -> String {
+        if let Some(search) = &self.search {
+            return format!("\r{}\x1b[K", search.render(self.history));
+        }
+
+        let back = self.chars.len() - self.cursor;
+        let mut rendered = format!("\r{}{}\x1b[K", prompt, self.line());
+        if back > 0 {
+            rendered += &format!("\x1b[{}D", back);
+        }
+        rendered
+    }
+}
+
+/// Settings of terminal in raw mode, restored when dropped. `stty` switches
+/// the mode, as there is no portable way to do it with the standard library.
+struct RawMode(String);
+
+fn stty(args: &[&str]) -> Option<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()
+        .ok()?;
+    if output.status.success() {
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+    } else {
+        None
+    }
+}
+
+impl RawMode {
+    fn enable() -> Option<Self> {
+        let saved = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+        Some(RawMode(saved))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        stty(&[&self.0]);
+    }
+}
+
+/// Interactive input with line editing: arrows and Emacs keys move over the
+/// line and history, Ctrl-R searches history, Tab completes names and Ctrl-C
+/// abandons the line. Entered lines are pushed to history. If terminal cannot
+/// be switched to raw mode, lines are read as typed.
+pub struct LineEditor {
+    history: History,
+    completions: Vec<String>,
+    keymap: Keymap,
+    normal_prompt: String, // Shown in vi normal mode
+    warnings: Vec<String>,
+}
+
+impl LineEditor {
+    pub fn new(history: History) -> Self {
+        LineEditor {
+            history,
+            completions: vec![],
+            keymap: Keymap::Emacs,
+            normal_prompt: String::new(),
+            warnings: vec![],
+        }
+    }
+
+    fn editing(&self) -> Editing<'_> {
+        let mut editing = Editing::new(&self.history, &self.completions);
+        editing.set_keymap(self.keymap);
+        editing
+    }
+
+    fn push(&mut self, line: &str) {
+        if let Err(err) = self.history.push(line) {
+            self.warnings.push(err.to_string());
+        }
+    }
+}
+
+impl Input for LineEditor {
+    fn read_line(&mut self, prompt: &str) -> Result<Option<String>> {
+        let mut stdout = io::stdout();
+        let raw = RawMode::enable();
+        if raw.is_none() {
+            print!("{}", prompt);
+            stdout.flush().ok();
+            let report = |err: &_| eprintln!("Error: Cannot read input: {}, retrying", err);
+            let line = read_line(&mut io::stdin().lock(), true, report)?;
+            if let Some(line) = &line {
+                self.push(line);
+            }
+            return Ok(line);
+        }
+
+        let mut stdin = io::stdin().lock();
+        let mut editing = self.editing();
+        let line = loop {
+            let prompt = match editing.is_normal() {
+                true => &self.normal_prompt,
+                false => prompt,
+            };
+            write!(stdout, "{}", editing.render(prompt)).ok();
+            stdout.flush().ok();
+
+            let key = read_key(&mut stdin)
+                .map_err(|err| Error::Io(format!("Cannot read input: {}", err)))?;
+            match key.map_or(Action::Eof, |key| editing.key(key)) {
+                Action::Edit => (),
+                Action::Submit(line) => break Some(line),
+                Action::Cancel => {
+                    write!(stdout, "^C\r\n").ok();
+                    editing = self.editing();
+                }
+                Action::Eof => break None,
+                Action::Candidates(names) => {
+                    write!(stdout, "\r\n{}\r\n", names.join("  ")).ok();
+                }
+            }
+        };
+        write!(stdout, "\r\n").ok();
+        drop(raw);
+
+        if let Some(line) = &line {
+            self.push(line);
+        }
+        Ok(line)
+    }
+
+    fn set_completions(&mut self, names: Vec<String>) {
+        self.completions = names;
+    }
+
+    fn set_keymap(&mut self, keymap: Keymap, normal_prompt: String) {
+        self.keymap = keymap;
+        self.normal_prompt = normal_prompt;
+    }
+
+    fn take_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.warnings)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(Ok(Keymap::Vi), "vi".parse());
+        assert_eq!(Ok(Keymap::Emacs), "emacs".parse());
+        "vim".parse::<Keymap>().unwrap_err();
+    }
+
+    fn history(entries: &[&str]) -> History {
+        History {
+            entries: entries.iter().map(|entry| entry.to_string()).collect(),
+            path: None,
+        }
+    }
+
+    /// Input which can never be read
+    struct Broken;
+
+    impl io::Read for Broken {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("broken"))
+        }
+    }
+
+    #[test]
+    fn read_errors() {
+        let src: &[u8] = b"a = 1\r\n\xff\nb\n";
+
+        let mut input = io::Cursor::new(src);
+        let mut reported = 0;
+        let mut read = || read_line(&mut input, true, |_| reported += 1).unwrap();
+        assert_eq!(Some("a = 1".to_owned()), read());
+        assert_eq!(Some("b".to_owned()), read());
+        assert_eq!(None, read());
+        assert_eq!(1, reported);
+
+        let mut input = io::Cursor::new(src);
+        read_line(&mut input, false, |_| ()).unwrap();
+        let err = read_line(&mut input, false, |_| ()).unwrap_err();
+        assert!(matches!(err, Error::Io(_)));
+
+        let mut reported = 0;
+        let mut input = io::BufReader::new(Broken);
+        read_line(&mut input, true, |_| reported += 1).unwrap_err();
+        assert_eq!(READ_RETRIES, reported);
+    }
+
+    #[test]
+    fn push() {
+        let mut history = history(&[]);
+        history.push("a = 1").unwrap();
+        history.push("a = 1").unwrap();
+        history.push("  ").unwrap();
+        history.push("a + 1").unwrap();
+        assert_eq!(&["a = 1", "a + 1"], history.entries());
+    }
+
+    #[test]
+    fn persistence() {
+        let path = env::temp_dir().join(format!("toy-history-{}", std::process::id()));
+        let mut history = History::load(path.clone());
+        history.push("a = 1").unwrap();
+        history.push("a + 1").unwrap();
+
+        assert_eq!(&["a = 1", "a + 1"], History::load(path.clone()).entries());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn write_failure() {
+        let path = env::temp_dir().join("toy-missing-dir").join("history");
+        let mut history = History::load(path);
+        history.push("a = 1").unwrap_err();
+        history.push("a + 1").unwrap();
+        assert_eq!(&["a = 1", "a + 1"], history.entries());
+    }
+
+    #[test]
+    fn reverse_search() {
+        let history = history(&["add x y => x + y", "a = 1", "add 1 2", "b = 2"]);
+        let mut search = ReverseSearch::new();
+
+        search.input(&history, 'a');
+        assert_eq!(Some(Match { entry: 2, start: 0 }), search.found());
+
+        search.input(&history, ' ');
+        assert_eq!(Some(Match { entry: 1, start: 0 }), search.found());
+
+        search.backspace(&history);
+        search.input(&history, 'd');
+        search.input(&history, 'd');
+        assert_eq!(Some(Match { entry: 2, start: 0 }), search.found());
+
+        search.next(&history);
+        assert_eq!(Some(Match { entry: 0, start: 0 }), search.found());
+        search.next(&history);
+        assert_eq!(Some(Match { entry: 0, start: 0 }), search.found());
+
+        search.input(&history, 'z');
+        assert_eq!(None, search.found());
+        assert_eq!("(reverse-i-search)`addz': ", search.render(&history));
+    }
+
+    #[test]
+    fn keys() {
+        let mut input =
+            io::Cursor::new("a\u{17c}\x1b[A\x1b[3~\x1bOH\x1b[4~\x01\x7f\r\x1b[5~\tz\x1bk\x1b\x1b");
+        let mut keys = vec![];
+        while let Some(key) = read_key(&mut input).unwrap() {
+            keys.push(key);
+        }
+        assert_eq!(
+            vec![
+                Key::Char('a'),
+                Key::Char('\u{17c}'),
+                Key::Up,
+                Key::Delete,
+                Key::Home,
+                Key::End,
+                Key::Ctrl('a'),
+                Key::Backspace,
+                Key::Enter,
+                Key::Tab,
+                Key::Char('z'),
+                Key::Alt('k'),
+                Key::Escape,
+            ],
+            keys
+        );
+    }
+
+    fn typed<'a>(editing: &mut Editing<'a>, keys: &[Key]) -> Action {
+        let mut action = Action::Edit;
+        for key in keys {
+            action = editing.key(*key);
+        }
+        action
+    }
+
+    #[test]
+    fn editing() {
+        let history = history(&["a = 1", "sq 2"]);
+        let mut editing = Editing::new(&history, &[]);
+        let chars = |s: &str| s.chars().map(Key::Char).collect::<Vec<_>>();
+
+        typed(&mut editing, &chars("1 + "));
+        typed(&mut editing, &[Key::Ctrl('a'), Key::Char('2'), Key::Right]);
+        assert_eq!("21 + ", editing.line());
+        assert_eq!("\r> 21 + \x1b[K\x1b[3D", editing.render("> "));
+        typed(
+            &mut editing,
+            &[Key::Ctrl('k'), Key::Backspace, Key::Ctrl('e')],
+        );
+        assert_eq!("2", editing.line());
+
+        typed(&mut editing, &[Key::Up, Key::Up, Key::Up]);
+        assert_eq!("a = 1", editing.line());
+        typed(&mut editing, &[Key::Down, Key::Ctrl('w')]);
+        assert_eq!("sq ", editing.line());
+        typed(&mut editing, &[Key::Down]);
+        assert_eq!("2", editing.line());
+        assert_eq!(Action::Submit("2".to_owned()), editing.key(Key::Enter));
+
+        let mut editing = Editing::new(&history, &[]);
+        typed(&mut editing, &[Key::Ctrl('r'), Key::Char('=')]);
+        assert_eq!(
+            "\r(reverse-i-search)`=': a \x1b[7m=\x1b[0m 1\x1b[K",
+            editing.render("> ")
+        );
+        assert_eq!(Action::Submit("a = 1".to_owned()), editing.key(Key::Enter));
+
+        let mut editing = Editing::new(&history, &[]);
+        typed(&mut editing, &chars("x"));
+        assert_eq!(Action::Cancel, editing.key(Key::Ctrl('c')));
+        assert_eq!(
+            Action::Eof,
+            typed(&mut editing, &[Key::Ctrl('u'), Key::Ctrl('d')])
+        );
+    }
+
+    #[test]
+    fn vi() {
+        let history = history(&["a = 1"]);
+        let mut editing = Editing::new(&history, &[]);
+        editing.set_keymap(Keymap::Vi);
+        let chars = |s: &str| s.chars().map(Key::Char).collect::<Vec<_>>();
+
+        typed(&mut editing, &chars("sq 12 + 3"));
+        typed(&mut editing, &[Key::Escape]);
+        assert!(editing.is_normal());
+        typed(&mut editing, &chars("bbx"));
+        assert_eq!("sq 2 + 3", editing.line());
+        typed(&mut editing, &chars("0wiy"));
+        assert_eq!("sq y2 + 3", editing.line());
+        assert!(!editing.is_normal());
+
+        // Alt is sent as escape followed by the key
+        typed(&mut editing, &[Key::Alt('$'), Key::Char('D')]);
+        assert_eq!("sq y2 + ", editing.line());
+        typed(&mut editing, &chars("A4"));
+        assert_eq!("sq y2 + 4", editing.line());
+        assert_eq!("\r> sq y2 + 4\x1b[K", editing.render("> "));
+
+        typed(&mut editing, &[Key::Escape, Key::Char('k')]);
+        assert_eq!("a = 1", editing.line());
+        typed(&mut editing, &chars("hxj"));
+        assert_eq!("sq y2 + 4", editing.line());
+        assert_eq!(
+            Action::Submit("sq y2 + 4".to_owned()),
+            editing.key(Key::Enter)
+        );
+
+        // Line starts in insert mode, as in emacs keymap
+        let mut editing = Editing::new(&history, &[]);
+        typed(&mut editing, &chars("x"));
+        editing.set_keymap(Keymap::Vi);
+        typed(&mut editing, &chars("k"));
+        assert_eq!("xk", editing.line());
+    }
+
+    #[test]
+    fn completion() {
+        let history = history(&[]);
+        let names: Vec<_> = [":set", "sin", "sinh", "sqrt", "square"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        let mut editing = Editing::new(&history, &names);
+
+        typed(
+            &mut editing,
+            &[Key::Tab, Key::Char('s'), Key::Char('q'), Key::Tab],
+        );
+        assert_eq!("sq", editing.line());
+        assert_eq!(
+            Action::Candidates(vec!["sqrt".to_owned(), "square".to_owned()]),
+            editing.key(Key::Tab)
+        );
+        typed(&mut editing, &[Key::Char('r'), Key::Tab]);
+        assert_eq!("sqrt", editing.line());
+
+        typed(
+            &mut editing,
+            &[Key::Ctrl('u'), Key::Char(':'), Key::Char('s'), Key::Tab],
+        );
+        assert_eq!(":set", editing.line());
+        typed(&mut editing, &[Key::Char(' '), Key::Char('x'), Key::Tab]);
+        assert_eq!(":set x", editing.line());
+    }
+
+    #[test]
+    fn highlight() {
+        let history = history(&["a = 1 + 2"]);
+        let mut search = ReverseSearch::new();
+        search.input(&history, '1');
+        assert_eq!(
+            "(reverse-i-search)`1': a = \x1b[7m1\x1b[0m + 2",
+            search.render(&history)
+        );
+    }
+}
@@ -0,0 +1,172 @@
+//! Versioned header of saved artifacts. It is the first line of the file,
+//! and it is a comment, so saved sessions are still valid source:
+//! ```text
+//! # toy-interpreter session, format 1, interpreter 0.1.0
+//! ```
+
+use crate::{Error, Result};
+use std::fmt;
+
+/// Version of saved artifacts, increased on every incompatible change
+pub const FORMAT: u32 = 1;
+
+/// Version of the interpreter, informative only
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+const MARKER: &str = "# toy-interpreter ";
+
+/// What the artifact holds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    /// Definitions of the session, as autosaved or exported
+    Session,
+    /// Compiled module
+    Module,
+    /// Dump of syntax tree
+    Ast,
+}
+
+impl Kind {
+    pub const ALL: &'static [Kind] = &[Kind::Session, Kind::Module, Kind::Ast];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Session => "session",
+            Kind::Module => "module",
+            Kind::Ast => "ast",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub kind: Kind,
+    pub format: u32,
+    pub interpreter: String,
+}
+
+impl Header {
+    /// Header written by this interpreter
+    pub fn current(kind: Kind) -> Self {
+        Header {
+            kind,
+            format: FORMAT,
+            interpreter: VERSION.to_owned(),
+        }
+    }
+
+    /// `None` if the line is not header at all
+    pub fn parse(line: &str) -> Option<Result<Header>> {
+        let fields = line.strip_prefix(MARKER)?;
+        let invalid = || Error::from(format!("Invalid header: {}", line));
+
+        let header = (|| {
+            let mut fields = fields.split(", ");
+            let kind = fields.next()?;
+            let kind = *Kind::ALL.iter().find(|k| k.name() == kind)?;
+            let format = fields.next()?.strip_prefix("format ")?.parse().ok()?;
+            let interpreter = fields.next()?.strip_prefix("interpreter ")?.to_owned();
+            Some(Header {
+                kind,
+                format,
+                interpreter,
+            })
+        })();
+        Some(header.ok_or_else(invalid))
+    }
+}
+
+impl fmt::Display for Header {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}{}, format {}, interpreter {}",
+            MARKER,
+            self.kind.name(),
+            self.format,
+            self.interpreter
+        )
+    }
+}
+
+/// Content with header of the current format
+pub fn wrap(kind: Kind, body: &str) -> String {
+    format!("{}\n{}", Header::current(kind), body)
+}
+
+/// Body of the artifact, if it is of given kind and format this interpreter
+/// reads
+pub fn open(kind: Kind, content: &str) -> Result<&str> {
+    let (line, body) = content.split_once('\n').unwrap_or((content, ""));
+    let header = Header::parse(line)
+        .ok_or_else(|| format!("Missing header, it is not toy-interpreter {}", kind.name()))??;
+
+    if header.kind != kind {
+        return Err(format!(
+            "Expected toy-interpreter {}, but it is {}",
+            kind.name(),
+            header.kind.name()
+        )
+        .into());
+    }
+
+    if header.format != FORMAT {
+        return Err(format!(
+            "Incompatible {}: saved in format {} by interpreter {}, interpreter {} reads only format {}",
+            kind.name(),
+            header.format,
+            header.interpreter,
+            VERSION,
+            FORMAT
+        )
+        .into());
+    }
+
+    Ok(body)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let content = wrap(Kind::Session, "a = 1\n");
+        assert_eq!(
+            format!(
+                "# toy-interpreter session, format 1, interpreter {}\na = 1\n",
+                VERSION
+            ),
+            content
+        );
+        assert_eq!(Ok("a = 1\n"), open(Kind::Session, &content));
+        assert_eq!(
+            Some(Ok(Header::current(Kind::Ast))),
+            Header::parse(&Header::current(Kind::Ast).to_string())
+        );
+    }
+
+    #[test]
+    fn incompatible() {
+        open(Kind::Session, "a = 1\n").unwrap_err();
+        open(Kind::Module, &wrap(Kind::Session, "")).unwrap_err();
+        assert_eq!(None, Header::parse("# toy-interpreter-like comment"));
+        Header::parse("# toy-interpreter session, format x")
+            .unwrap()
+            .unwrap_err();
+
+        let err = open(
+            Kind::Session,
+            "# toy-interpreter session, format 2, interpreter 9.0.0\na = 1",
+        )
+        .unwrap_err();
+        assert_eq!(
+            format!(
+                "Incompatible session: saved in format 2 by interpreter 9.0.0, interpreter {} reads only format 1",
+                VERSION
+            ),
+            err.to_string()
+        );
+    }
+}
@@ -0,0 +1,289 @@
+use crate::lexer::Span;
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// Invalid token, `span` is its byte range in source
+    Lex {
+        message: String,
+        span: Span,
+    },
+    /// Tokens not forming valid expression, `span` covers the offending
+    /// tokens, or is empty where missing token is expected
+    Parse {
+        message: String,
+        span: Span,
+    },
+    UndefinedSymbol(String),
+    ArityMismatch {
+        func: String,
+        expected: usize,
+        got: usize,
+    },
+    /// Symbol defined as something else than it is used for
+    NameConflict {
+        name: String,
+        existing: &'static str,
+    },
+    /// Symbol defined again in the same source, lines count from 1
+    DuplicateDefinition {
+        name: String,
+        first: usize,
+        second: usize,
+    },
+    /// Valid expression which cannot be evaluated, eg. assignment to constant
+    Eval(String),
+    /// Strings and lists created by statement take more bytes than allowed
+    MemoryLimit {
+        used: usize,
+        limit: usize,
+    },
+    /// Statement evaluates more expressions than allowed
+    StepLimit {
+        limit: u64,
+    },
+    /// Evaluation takes longer than given timeout
+    Timeout(Duration),
+    /// Reading input failed
+    Io(String),
+    /// Anything else not related to the language itself
+    Other(String),
+}
+
+impl Error {
+    /// Category name, stable for machine readable output
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Lex { .. } => "lex",
+            Error::Parse { .. } => "parse",
+            Error::UndefinedSymbol(_) => "undefined-symbol",
+            Error::ArityMismatch { .. } => "arity-mismatch",
+            Error::NameConflict { .. } => "name-conflict",
+            Error::DuplicateDefinition { .. } => "duplicate-definition",
+            Error::Eval(_) => "eval",
+            Error::MemoryLimit { .. } => "memory-limit",
+            Error::StepLimit { .. } => "step-limit",
+            Error::Timeout(_) => "timeout",
+            Error::Io(_) => "io",
+            Error::Other(_) => "other",
+        }
+    }
+
+    /// Stable code, explained in `CATALOG`
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Parse { .. } => "E001",
+            Error::Lex { .. } => "E002",
+            Error::UndefinedSymbol(_) => "E010",
+            Error::ArityMismatch { .. } => "E011",
+            Error::NameConflict { .. } => "E012",
+            Error::DuplicateDefinition { .. } => "E013",
+            Error::Eval(_) => "E020",
+            Error::MemoryLimit { .. } => "E021",
+            Error::StepLimit { .. } => "E022",
+            Error::Timeout(_) => "E023",
+            Error::Io(_) => "E030",
+            Error::Other(_) => "E090",
+        }
+    }
+
+    /// Range of the offending source fragment, if known
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Error::Lex { span, .. } | Error::Parse { span, .. } => Some(*span),
+            _ => None,
+        }
+    }
+
+    /// Position of the offending character in source, if known
+    pub fn offset(&self) -> Option<usize> {
+        self.span().map(|span| span.start)
+    }
+}
+
+/// Code, title and explanation of every error
+pub const CATALOG: &[(&str, &str, &str)] = &[
+    (
+        "E001",
+        "unexpected token",
+        "Tokens do not form valid expression, eg. operator misses its operand \
+         as in `1 +`, bracket is not closed, or something follows complete \
+         expression as in `2 + 2 foo`.",
+    ),
+    (
+        "E002",
+        "invalid token",
+        "Part of the source is not any token, eg. unknown character as `$`, or \
+         malformed number as `1.2.3`. Caret under the source points it.",
+    ),
+    (
+        "E010",
+        "undefined symbol",
+        "Name is neither variable, constant, argument nor function. Variables \
+         have to be assigned before they are used, and function bodies see only \
+         their arguments, functions and constants, unless variables are \
+         captured with `:set capture`.",
+    ),
+    (
+        "E011",
+        "arity mismatch",
+        "Function is called with different number of arguments than it is \
+         defined with, eg. `max(1)`. In `f a b` form every argument is single \
+         call or operand, so brackets are needed around expressions. Calls \
+         with fewer arguments are partial applications after \
+         `:set partial on`.",
+    ),
+    (
+        "E012",
+        "name conflict",
+        "Name is already used for different kind of symbol, eg. function is \
+         defined with name of variable. With `--force` the symbol is replaced.",
+    ),
+    (
+        "E013",
+        "duplicate definition",
+        "Symbol is defined twice in the same file, which is usually copy-paste \
+         mistake. Program given with `-` reports it as warning, or as error \
+         with `--strict`.",
+    ),
+    (
+        "E020",
+        "evaluation error",
+        "Expression is valid but cannot be evaluated, eg. assignment to \
+         constant, or the definition would break other functions.",
+    ),
+    (
+        "E021",
+        "memory limit",
+        "Strings and lists created while evaluating the statement take more \
+         memory than the embedding application allows, eg. string doubled in \
+         nested calls. Evaluation is stopped, and nothing is assigned.",
+    ),
+    (
+        "E022",
+        "step limit",
+        "Evaluation of the statement takes more steps than the embedding \
+         application allows, eg. long loop, or function calling itself \
+         through its argument. Evaluation is stopped, and nothing is assigned.",
+    ),
+    (
+        "E023",
+        "timeout",
+        "Evaluation takes longer than the embedding application allows. It is \
+         stopped, and nothing is assigned.",
+    ),
+    (
+        "E030",
+        "input or output error",
+        "Reading input or writing output failed, eg. input is not valid UTF-8. \
+         Interpreter exits with status 3.",
+    ),
+    (
+        "E090",
+        "other error",
+        "Failure not related to the language itself, eg. unknown command or \
+         invalid option value.",
+    ),
+];
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Lex { message, .. } | Error::Parse { message, .. } => write!(f, "{}", message),
+            Error::Eval(message) | Error::Io(message) | Error::Other(message) => {
+                write!(f, "{}", message)
+            }
+            Error::UndefinedSymbol(name) => write!(f, "Undefined symbol: {}", name),
+            Error::ArityMismatch {
+                func,
+                expected,
+                got,
+            } => write!(
+                f,
+                "Function `{}` expects {} arguments, got {}",
+                func, expected, got
+            ),
+            Error::NameConflict { name, existing } => write!(
+                f,
+                "{} is already defined as {}, use `--force` to redefine it",
+                name, existing
+            ),
+            Error::DuplicateDefinition {
+                name,
+                first,
+                second,
+            } => write!(
+                f,
+                "{} is defined twice, at lines {} and {}",
+                name, first, second
+            ),
+            Error::MemoryLimit { used, limit } => write!(
+                f,
+                "Evaluation takes over {} bytes, the limit is {}",
+                used, limit
+            ),
+            Error::StepLimit { limit } => {
+                write!(f, "Evaluation takes more steps than the limit of {}", limit)
+            }
+            Error::Timeout(timeout) => write!(f, "Evaluation takes longer than {:?}", timeout),
+        }
+    }
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error::Other(message)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Self {
+        Error::Other(message.to_owned())
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn catalog() {
+        let errors = [
+            Error::Parse {
+                message: "".to_owned(),
+                span: Span::default(),
+            },
+            Error::Lex {
+                message: "".to_owned(),
+                span: Span::default(),
+            },
+            Error::UndefinedSymbol("".to_owned()),
+            Error::ArityMismatch {
+                func: "".to_owned(),
+                expected: 0,
+                got: 0,
+            },
+            Error::NameConflict {
+                name: "".to_owned(),
+                existing: "",
+            },
+            Error::DuplicateDefinition {
+                name: "".to_owned(),
+                first: 0,
+                second: 0,
+            },
+            Error::Eval("".to_owned()),
+            Error::MemoryLimit { used: 0, limit: 0 },
+            Error::StepLimit { limit: 0 },
+            Error::Timeout(Duration::default()),
+            Error::Io("".to_owned()),
+            Error::Other("".to_owned()),
+        ];
+
+        let codes: Vec<_> = errors.iter().map(Error::code).collect();
+        let catalog: Vec<_> = CATALOG.iter().map(|(code, _, _)| *code).collect();
+        assert_eq!(catalog, codes);
+    }
+}
@@ -0,0 +1,282 @@
+//! Tree walking evaluation of expressions. Pending work is kept on explicit
+//! stack instead of the native one, so arbitrarily nested expressions cannot
+//! overflow it, and every evaluated expression is accounted by `step` in the
+//! single place.
+
+use crate::parser::{call_argument, Callee, Expr, Fold, Reference};
+use crate::{Context, Operator, Value};
+
+/// Work left to do, continuations take values of evaluated subexpressions
+/// from the top of value stack
+enum Task<'a> {
+    Eval(&'a Expr),
+    Assign(&'a str),
+    Const(&'a str),
+    /// Left operand is evaluated, right one is evaluated unless `&&` or `||`
+    /// is already decided
+    Binary(Operator, &'a Expr),
+    /// Both operands are evaluated
+    Apply(Operator),
+    Not,
+    /// Given number of arguments are evaluated
+    Call(&'a Callee, usize),
+    List(usize),
+    Partial(&'a Reference, usize),
+    Index,
+    Cond(&'a Expr, &'a Expr),
+    /// Bound value is evaluated, body is evaluated in scope seeing it
+    Let(&'a Expr),
+    /// Drops scope of `let` or `sum`
+    Leave,
+    /// Bounds are evaluated
+    Fold(&'a Fold),
+    /// Body is evaluated for the last value of loop variable, with result
+    /// of the previous steps
+    FoldStep(&'a Fold, f64, Option<Value>),
+}
+
+impl Expr {
+    /// `None` if evaluation is stopped, or the expression has no value
+    pub fn evaluate(&self, context: &mut Context, args: &[Value]) -> Option<Value> {
+        let mut tasks = vec![Task::Eval(self)];
+        let mut values: Vec<Value> = vec![];
+        // Arguments with locals of `let` and `sum`, the last one is current
+        let mut scopes: Vec<Vec<Value>> = vec![];
+
+        while let Some(task) = tasks.pop() {
+            let args = scopes.last().map_or(args, Vec::as_slice);
+            match task {
+                Task::Eval(expr) => {
+                    context.step()?;
+                    match expr {
+                        Expr::Unit => return None,
+                        Expr::Value(v) => values.push(v.clone()),
+                        Expr::Argument(arg) => values.push(args.get(*arg)?.clone()),
+                        // Variable may be replaced by function since it was captured
+                        Expr::Variable(var) => {
+                            values.push(context.get_var(var).unwrap_or(Value::Float(f64::NAN)))
+                        }
+                        Expr::Assign(var, val) => {
+                            tasks.push(Task::Assign(var));
+                            tasks.push(Task::Eval(val));
+                        }
+                        Expr::Const(var, val) => {
+                            tasks.push(Task::Const(var));
+                            tasks.push(Task::Eval(val));
+                        }
+                        Expr::Binary(op, left, right) => {
+                            tasks.push(Task::Binary(*op, right));
+                            tasks.push(Task::Eval(left));
+                        }
+                        Expr::Not(expr) => {
+                            tasks.push(Task::Not);
+                            tasks.push(Task::Eval(expr));
+                        }
+                        Expr::Call(callee, call_args) => {
+                            tasks.push(Task::Call(callee, call_args.len()));
+                            tasks.extend(call_args.iter().rev().map(Task::Eval));
+                        }
+                        Expr::List(items) => {
+                            tasks.push(Task::List(items.len()));
+                            tasks.extend(items.iter().rev().map(Task::Eval));
+                        }
+                        Expr::Partial(func, bound) => {
+                            tasks.push(Task::Partial(func, bound.len()));
+                            tasks.extend(bound.iter().rev().map(Task::Eval));
+                        }
+                        Expr::Index(list, idx) => {
+                            tasks.push(Task::Index);
+                            tasks.push(Task::Eval(idx));
+                            tasks.push(Task::Eval(list));
+                        }
+                        Expr::Cond(cond, then, other) => {
+                            tasks.push(Task::Cond(then, other));
+                            tasks.push(Task::Eval(cond));
+                        }
+                        Expr::Let(_, val, body) => {
+                            tasks.push(Task::Let(body));
+                            tasks.push(Task::Eval(val));
+                        }
+                        Expr::Fold(fold) => {
+                            tasks.push(Task::Fold(fold));
+                            tasks.push(Task::Eval(&fold.to));
+                            tasks.push(Task::Eval(&fold.from));
+                        }
+                        Expr::Define(func) => {
+                            context.update_func(func);
+                            return None;
+                        }
+                    }
+                }
+                Task::Assign(var) => {
+                    let val = values.last()?.clone();
+                    // Variable may become constant since function was defined
+                    if let Err(err) = context.update_var(var, val) {
+                        context.fail(err);
+                        return None;
+                    }
+                }
+                Task::Const(var) => {
+                    let val = values.last()?.clone();
+                    if let Err(err) = context.update_const(var, val) {
+                        context.fail(err);
+                        return None;
+                    }
+                }
+                Task::Binary(op, right) => {
+                    let left = values.pop()?;
+
+                    // Logical operators evaluate right side only if it matters for result
+                    match op {
+                        Operator::And if !left.is_true() => values.push(Value::from(false)),
+                        Operator::Or if left.is_true() => values.push(Value::from(true)),
+                        _ => {
+                            values.push(left);
+                            tasks.push(Task::Apply(op));
+                            tasks.push(Task::Eval(right));
+                        }
+                    }
+                }
+                Task::Apply(op) => {
+                    let right = values.pop()?;
+                    let left = values.pop()?;
+                    values.push(match op {
+                        Operator::Eq => Value::from(left.approx_eq(&right, context.eq_epsilon())),
+                        op => context.allocate(op.eval(left, right)?)?,
+                    });
+                }
+                Task::Not => {
+                    let val = values.pop()?;
+                    values.push(Value::from(!val.is_true()));
+                }
+                Task::Call(callee, count) => {
+                    let call_args = values.split_off(values.len() - count);
+                    values.push(match callee {
+                        Callee::Argument(idx) => {
+                            let func = args.get(*idx)?.clone();
+                            call_argument(context, &func, &call_args)?
+                        }
+                        callee => callee.call(context, &call_args)?,
+                    });
+                }
+                Task::List(count) => {
+                    let items = values.split_off(values.len() - count);
+                    values.push(context.allocate(Value::from(items))?);
+                }
+                Task::Partial(func, count) => {
+                    let bound = values.split_off(values.len() - count);
+                    values.push(Value::Func(func.bind(bound)));
+                }
+                Task::Index => {
+                    let idx = values.pop()?;
+                    let list = values.pop()?;
+                    values.push(list.index(&idx));
+                }
+                Task::Cond(then, other) => {
+                    let cond = values.pop()?;
+                    tasks.push(Task::Eval(if cond.is_true() { then } else { other }));
+                }
+                Task::Let(body) => {
+                    let mut scoped = args.to_vec();
+                    scoped.push(values.pop()?);
+                    scopes.push(scoped);
+                    tasks.push(Task::Leave);
+                    tasks.push(Task::Eval(body));
+                }
+                Task::Leave => {
+                    scopes.pop();
+                }
+                Task::Fold(fold) => {
+                    let to = values.pop()?.as_f64();
+                    let from = values.pop()?;
+                    // `NaN` if bounds are not finite numbers
+                    if !(to - from.as_f64()).is_finite() {
+                        values.push(Value::Float(f64::NAN));
+                    } else if from.as_f64() > to {
+                        values.push(fold.identity());
+                    } else {
+                        let mut scoped = args.to_vec();
+                        scoped.push(from);
+                        scopes.push(scoped);
+                        tasks.push(Task::FoldStep(fold, to, None));
+                        tasks.push(Task::Eval(&fold.body));
+                    }
+                }
+                Task::FoldStep(fold, to, result) => {
+                    let val = values.pop()?;
+                    let result = match result {
+                        Some(result) => context.allocate(fold.op.eval(result, val)?)?,
+                        None => val,
+                    };
+                    let scoped = scopes.last_mut()?;
+                    let var = scoped.last_mut()?;
+                    *var = var.clone() + Value::Int(1);
+                    if var.as_f64() <= to {
+                        tasks.push(Task::FoldStep(fold, to, Some(result)));
+                        tasks.push(Task::Eval(&fold.body));
+                    } else {
+                        scopes.pop();
+                        values.push(result);
+                    }
+                }
+            }
+        }
+        values.pop()
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::Interpreter;
+    use std::thread;
+
+    /// `x + 1 + 1 ...` nested given times, as parser would build it
+    fn nested(depth: usize) -> Expr {
+        (0..depth).fold(Expr::Argument(0), |expr, _| {
+            Expr::Binary(
+                Operator::Add,
+                Box::new(expr),
+                Box::new(Expr::Value(Value::Int(1))),
+            )
+        })
+    }
+
+    #[test]
+    fn deep_nesting() {
+        let expr = nested(20_000);
+        // Small stack, recursive evaluation or dropping would overflow it
+        let result = thread::Builder::new()
+            .stack_size(64 * 1024)
+            .spawn(move || {
+                let mut context = Context::new();
+                expr.evaluate(&mut context, &[Value::Int(1)])
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(Some(Value::Int(20_001)), result);
+    }
+
+    #[test]
+    fn scopes() {
+        let mut interpreter = Interpreter::new();
+        assert_eq!(
+            Some(Value::Int(12)),
+            interpreter
+                .eval("(let a = 2 in a * (sum i = 1 to 3 of i)) + (let b = 0 in b)")
+                .unwrap()
+        );
+        assert_eq!(
+            Some(Value::Int(36)),
+            interpreter
+                .eval("sum i = 1 to 3 of sum j = 1 to 3 of i * j")
+                .unwrap()
+        );
+        assert_eq!(
+            Some(Value::Int(1)),
+            interpreter.eval("prod i = 3 to 1 of i").unwrap()
+        );
+    }
+}
@@ -0,0 +1,125 @@
+//! Language capabilities, which embedders can disable to offer simpler (and
+//! safer) formula language - eg. without imports reading files.
+
+use crate::{Error, Result};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    /// `x = ...` in expressions
+    Assignments,
+    /// `f x => ...` statements
+    Definitions,
+    /// `import "file"` statements
+    Imports,
+    /// `"text"` literals
+    Strings,
+    /// `[a, b]` literals and indexing
+    Lists,
+    /// `sum i = a to b of ...` and `prod` expressions
+    Loops,
+    /// `let x = ... in ...` expressions
+    Bindings,
+    /// `cond ? a : b` expressions
+    Conditionals,
+    /// `x |> f` calls
+    Pipes,
+}
+
+impl Feature {
+    pub const ALL: &'static [Feature] = &[
+        Feature::Assignments,
+        Feature::Definitions,
+        Feature::Imports,
+        Feature::Strings,
+        Feature::Lists,
+        Feature::Loops,
+        Feature::Bindings,
+        Feature::Conditionals,
+        Feature::Pipes,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::Assignments => "assignments",
+            Feature::Definitions => "definitions",
+            Feature::Imports => "imports",
+            Feature::Strings => "strings",
+            Feature::Lists => "lists",
+            Feature::Loops => "loops",
+            Feature::Bindings => "bindings",
+            Feature::Conditionals => "conditionals",
+            Feature::Pipes => "pipes",
+        }
+    }
+
+    /// Error reported when disabled feature is used
+    pub(crate) fn disabled(self) -> String {
+        let mut name = self.name().to_owned();
+        name[..1].make_ascii_uppercase();
+        format!("{} are disabled in this interpreter", name)
+    }
+}
+
+impl FromStr for Feature {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Feature::ALL
+            .iter()
+            .copied()
+            .find(|feature| feature.name() == s)
+            .ok_or_else(|| format!("Unknown feature: {}", s).into())
+    }
+}
+
+/// Enabled features, all of them by default
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Features {
+    disabled: Vec<Feature>,
+}
+
+impl Features {
+    /// Only evaluation of expressions, with symbols defined by the host
+    pub fn minimal() -> Self {
+        Features {
+            disabled: Feature::ALL.to_vec(),
+        }
+    }
+
+    pub fn is_enabled(&self, feature: Feature) -> bool {
+        !self.disabled.contains(&feature)
+    }
+
+    pub fn set(&mut self, feature: Feature, enabled: bool) {
+        self.disabled.retain(|&disabled| disabled != feature);
+        if !enabled {
+            self.disabled.push(feature);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn toggling() {
+        let mut features = Features::minimal();
+        assert!(!features.is_enabled(Feature::Imports));
+        features.set(Feature::Imports, true);
+        features.set(Feature::Imports, true);
+        assert!(features.is_enabled(Feature::Imports));
+        features.set(Feature::Definitions, false);
+        assert!(!features.is_enabled(Feature::Definitions));
+
+        assert_eq!(Ok(Feature::Assignments), "assignments".parse());
+        assert_eq!(Ok(Feature::Loops), "loops".parse());
+        "macros".parse::<Feature>().unwrap_err();
+        assert_eq!(
+            "Imports are disabled in this interpreter",
+            Feature::Imports.disabled()
+        );
+    }
+}
@@ -0,0 +1,159 @@
+//! C interface of the interpreter, declared in `include/toy_interpreter.h`.
+//! Interpreter is opaque handle created by `ti_new` and released by
+//! `ti_free`. Strings are NUL terminated UTF-8, returned strings are owned
+//! by the handle and valid until its next call:
+//! ```text
+//! ti_interpreter *ti = ti_new();
+//! const char *result = ti_eval(ti, "sq x => x * x; sq 4");
+//! if (result == NULL)
+//!     fprintf(stderr, "%s\n", ti_last_error(ti));
+//! ti_free(ti);
+//! ```
+
+use crate::{Error, Interpreter, Result};
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+
+/// Opaque handle of `ti_interpreter`
+pub struct Handle {
+    interpreter: Interpreter,
+    result: Option<CString>,
+    error: Option<CString>,
+}
+
+/// Text never holding NUL, which would end C string early
+fn c_string(text: String) -> CString {
+    CString::new(text.replace('\0', "\\0")).unwrap_or_default()
+}
+
+impl Handle {
+    fn eval(&mut self, src: *const c_char) -> Result<CString> {
+        if src.is_null() {
+            return Err(Error::Other("Source is NULL".to_owned()));
+        }
+        // Safety: the caller passes NUL terminated string
+        let src = unsafe { CStr::from_ptr(src) }
+            .to_str()
+            .map_err(|_| Error::Io("Source is not valid UTF-8".to_owned()))?;
+
+        let interpreter = &mut self.interpreter;
+        let val = panic::catch_unwind(AssertUnwindSafe(|| interpreter.eval(src)))
+            .map_err(|_| Error::Other("Interpreter panicked".to_owned()))??;
+        let val = val.map(|val| self.interpreter.context().rounded(val));
+        Ok(c_string(val.map_or(String::new(), |val| val.to_string())))
+    }
+}
+
+/// New interpreter with builtin functions and constants
+#[no_mangle]
+pub extern "C" fn ti_new() -> *mut Handle {
+    Box::into_raw(Box::new(Handle {
+        interpreter: Interpreter::new(),
+        result: None,
+        error: None,
+    }))
+}
+
+/// Evaluates all statements of `src`, giving result of the last one -
+/// empty string if it has none (eg. definition), or NULL on error
+///
+/// # Safety
+///
+/// `handle` has to come from `ti_new`, `src` has to be NUL terminated.
+#[no_mangle]
+pub unsafe extern "C" fn ti_eval(handle: *mut Handle, src: *const c_char) -> *const c_char {
+    let handle = match handle.as_mut() {
+        Some(handle) => handle,
+        None => return std::ptr::null(),
+    };
+
+    match handle.eval(src) {
+        Ok(result) => {
+            handle.error = None;
+            handle.result = Some(result);
+        }
+        Err(err) => {
+            handle.error = Some(c_string(err.to_string()));
+            handle.result = None;
+        }
+    }
+    handle
+        .result
+        .as_ref()
+        .map_or(std::ptr::null(), |result| result.as_ptr())
+}
+
+/// Message of error of the last `ti_eval`, NULL if it succeeded
+///
+/// # Safety
+///
+/// `handle` has to come from `ti_new`.
+#[no_mangle]
+pub unsafe extern "C" fn ti_last_error(handle: *const Handle) -> *const c_char {
+    handle
+        .as_ref()
+        .and_then(|handle| handle.error.as_ref())
+        .map_or(std::ptr::null(), |error| error.as_ptr())
+}
+
+/// Releases the interpreter, NULL is ignored
+///
+/// # Safety
+///
+/// `handle` has to come from `ti_new`, and it cannot be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn ti_free(handle: *mut Handle) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    unsafe fn text(ptr: *const c_char) -> Option<String> {
+        ptr.as_ref()
+            .map(|_| CStr::from_ptr(ptr).to_str().unwrap().to_owned())
+    }
+
+    #[test]
+    fn session() {
+        unsafe {
+            let ti = ti_new();
+            let eval = |src: &str| {
+                let src = CString::new(src).unwrap();
+                text(ti_eval(ti, src.as_ptr()))
+            };
+
+            assert_eq!(Some("16".to_owned()), eval("sq x => x * x; sq 4"));
+            assert_eq!(None, text(ti_last_error(ti)));
+            assert_eq!(Some("".to_owned()), eval("sq x => x"));
+            assert_eq!(Some("0.3".to_owned()), eval("0.1 + 0.2"));
+            assert_eq!(Some("\"a\\0b\"".to_owned()), eval("\"a\\0b\""));
+
+            assert_eq!(None, eval("y + 1"));
+            assert_eq!(
+                Some("Undefined symbol: y".to_owned()),
+                text(ti_last_error(ti))
+            );
+            assert_eq!(None, text(ti_eval(ti, std::ptr::null())));
+            assert_eq!(Some("Source is NULL".to_owned()), text(ti_last_error(ti)));
+
+            let invalid = [0xff, 0];
+            assert_eq!(None, text(ti_eval(ti, invalid.as_ptr().cast())));
+            eval("1").unwrap();
+            assert_eq!(None, text(ti_last_error(ti)));
+
+            ti_free(ti);
+            assert_eq!(
+                None,
+                text(ti_eval(std::ptr::null_mut(), invalid.as_ptr().cast()))
+            );
+            assert_eq!(None, text(ti_last_error(std::ptr::null())));
+            ti_free(std::ptr::null_mut());
+        }
+    }
+}
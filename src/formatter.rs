@@ -0,0 +1,165 @@
+//! Canonical layout of source, as `toy-interpreter fmt file`. Tokens are
+//! separated by single spaces, except inside brackets, before commas and
+//! after `!` - calls become `f(1, 2)`. Comments, commands and imports are
+//! kept, and lines continuing statement of the previous one are indented.
+//! Unlike `printer`, nothing is parsed, so names are never substituted.
+
+use crate::lexer::{self, tokenize, Span};
+use crate::{import, Result, Token};
+
+/// Indentation of lines continuing statement
+const INDENT: &str = "    ";
+
+/// Space between two tokens, `glued` tells whether they touch in the source
+fn spaced(left: &Token, right: &Token, glued: bool) -> bool {
+    match (left, right) {
+        (Token::LBracket, _) | (Token::LSquare, _) | (Token::Not, _) | (Token::Ellipsis, _) => {
+            false
+        }
+        (_, Token::RBracket) | (_, Token::RSquare) | (_, Token::Comma) | (_, Token::Semicolon) => {
+            false
+        }
+        (Token::Id(_), Token::LBracket) => false,
+        (Token::RBracket, Token::LSquare) | (Token::RSquare, Token::LSquare) => false,
+        // Indexed variable and function called with list look the same,
+        // so the source decides
+        (Token::Id(_), Token::LSquare) => !glued,
+        _ => true,
+    }
+}
+
+/// Tokens in canonical layout, which tokenizes to the same tokens
+pub fn render(tokens: &[(Token, Span)]) -> String {
+    let mut result = String::new();
+    for (idx, (token, span)) in tokens.iter().enumerate() {
+        if let Some((previous, previous_span)) = idx.checked_sub(1).map(|idx| &tokens[idx]) {
+            if spaced(previous, token, previous_span.end == span.start) {
+                result.push(' ');
+            }
+        }
+        result += &token.to_string();
+    }
+
+    // Never output something tokenizing differently
+    let tokens: Vec<_> = tokens.iter().map(|(token, _)| token.clone()).collect();
+    let rendered: Option<Vec<_>> = tokenize(&result)
+        .map(|token| token.ok().map(|(token, _)| token))
+        .collect();
+    if rendered.as_ref() == Some(&tokens) {
+        result
+    } else {
+        lexer::render(&tokens)
+    }
+}
+
+/// Splits line into code and comment, `#` inside strings starts no comment
+fn comment(line: &str) -> (&str, Option<&str>) {
+    let mut string = false;
+    let mut escaped = false;
+    for (idx, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if string => escaped = true,
+            '"' => string = !string,
+            '#' if !string => return (&line[..idx], Some(line[idx..].trim_end())),
+            _ => (),
+        }
+    }
+    (line, None)
+}
+
+/// Line in canonical layout, and the change of bracket depth it leaves
+fn format_line(line: &str) -> Result<(String, isize)> {
+    let trimmed = line.trim();
+    if trimmed.starts_with(':') || import::parse(trimmed).is_some() {
+        return Ok((trimmed.to_owned(), 0));
+    }
+
+    let (code, comment) = comment(trimmed);
+    let (code, continued) = match code.trim_end().strip_suffix('\\') {
+        Some(code) => (code, true),
+        None => (code, false),
+    };
+    let tokens: Vec<_> = tokenize(code).collect::<Result<_>>()?;
+    let depth = tokens
+        .iter()
+        .map(|(token, _)| match token {
+            Token::LBracket | Token::LSquare => 1,
+            Token::RBracket | Token::RSquare => -1,
+            _ => 0,
+        })
+        .sum();
+
+    let parts: Vec<_> = vec![
+        Some(render(&tokens)).filter(|code| !code.is_empty()),
+        continued.then(|| "\\".to_owned()),
+        comment.map(str::to_owned),
+    ];
+    let parts: Vec<_> = parts.into_iter().flatten().collect();
+    Ok((parts.join(" "), depth))
+}
+
+/// Every line of the source in canonical layout, formatting the same source
+/// again changes nothing
+pub fn format(src: &str) -> Result<String> {
+    let mut result = String::new();
+    let mut depth = 0;
+    let mut continued = false;
+
+    for (idx, line) in src.lines().enumerate() {
+        if idx == 0 && line.starts_with("#!") {
+            result += line;
+            result.push('\n');
+            continue;
+        }
+
+        let (formatted, change) =
+            format_line(line).map_err(|err| format!("Line {}: {}", idx + 1, err))?;
+        if (continued || depth > 0) && !formatted.is_empty() {
+            result += INDENT;
+        }
+        result += &formatted;
+        result.push('\n');
+
+        depth = (depth + change).max(0);
+        continued = formatted.ends_with('\\');
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn layout(src: &str) -> String {
+        render(&tokenize(src).collect::<Result<Vec<_>>>().unwrap())
+    }
+
+    #[test]
+    fn spacing() {
+        assert_eq!("x = 3 + 4 * y", layout("x=3+4*y"));
+        assert_eq!("f(1, g(2)) - !a", layout("f ( 1,g (2) ) -! a"));
+        assert_eq!("xs[0] + len [1, 2][1]", layout("xs[0]+len [1 ,2] [1]"));
+        assert_eq!(
+            "total ...xs => a ? 1 : 2; y = \"a  b\"",
+            layout("total ... xs=>a?1:2 ;y=\"a  b\"")
+        );
+        assert_eq!(
+            "sum i = 1 to 3 of let t = i in t |> sq",
+            layout("sum i=1 to 3 of let t=i in t|>sq")
+        );
+    }
+
+    #[test]
+    fn lines() {
+        let src = "#!/usr/bin/env toy\n  a=1 # one\n\n# note\n:vars\nf x=>max(x,\n1)\ns = \"#\"+a \\\n+1\n";
+        let expected = "#!/usr/bin/env toy\na = 1 # one\n\n# note\n:vars\nf x => max(x,\n    1)\ns = \"#\" + a \\\n    + 1\n";
+        assert_eq!(Ok(expected.to_owned()), format(src));
+        assert_eq!(Ok(expected.to_owned()), format(expected));
+        assert!(format("a = 1\n$")
+            .unwrap_err()
+            .to_string()
+            .starts_with("Line 2"));
+    }
+}
@@ -0,0 +1,212 @@
+use crate::{Error, Result};
+
+/// Symbol of dependency graph
+#[derive(Debug, PartialEq)]
+pub struct Node {
+    pub name: String,
+    pub function: bool,
+    pub uses: Vec<String>,
+}
+
+impl Node {
+    fn kind(&self) -> &'static str {
+        if self.function {
+            "function"
+        } else {
+            "variable"
+        }
+    }
+}
+
+/// Orders nodes so every node comes after nodes it uses, otherwise keeping
+/// the original order. Cycles are possible if function is redefined after
+/// other function used it.
+pub fn topological(nodes: &[Node]) -> Result<Vec<&Node>> {
+    let known = |name: &String| nodes.iter().any(|node| &node.name == name);
+    let mut pending: Vec<_> = nodes.iter().collect();
+    let mut ordered: Vec<&Node> = vec![];
+
+    while !pending.is_empty() {
+        let ready = pending.iter().position(|node| {
+            node.uses
+                .iter()
+                .all(|used| !known(used) || ordered.iter().any(|node| &node.name == used))
+        });
+
+        match ready {
+            Some(idx) => ordered.push(pending.remove(idx)),
+            None => {
+                let names: Vec<_> = pending.iter().map(|node| node.name.as_str()).collect();
+                return Err(Error::Eval(format!(
+                    "Cyclic dependencies between definitions: {}",
+                    names.join(", ")
+                )));
+            }
+        }
+    }
+
+    Ok(ordered)
+}
+
+/// Graphviz format, functions are ellipses and variables boxes
+pub fn to_dot(nodes: &[Node]) -> String {
+    let mut result = "digraph symbols {\n".to_owned();
+
+    for node in nodes {
+        let shape = if node.function { "ellipse" } else { "box" };
+        result += &format!("    \"{}\" [shape={}];\n", node.name, shape);
+    }
+
+    for node in nodes {
+        for used in &node.uses {
+            result += &format!("    \"{}\" -> \"{}\";\n", node.name, used);
+        }
+    }
+
+    result + "}"
+}
+
+/// Symbol names are identifiers, so they never need escaping
+pub fn to_json(nodes: &[Node]) -> String {
+    let symbols: Vec<_> = nodes
+        .iter()
+        .map(|node| {
+            format!(
+                "{{\"name\":\"{}\",\"kind\":\"{}\"}}",
+                node.name,
+                node.kind()
+            )
+        })
+        .collect();
+
+    let edges: Vec<_> = nodes
+        .iter()
+        .flat_map(|node| {
+            node.uses
+                .iter()
+                .map(move |used| format!("{{\"from\":\"{}\",\"to\":\"{}\"}}", node.name, used))
+        })
+        .collect();
+
+    format!(
+        "{{\"nodes\":[{}],\"edges\":[{}]}}",
+        symbols.join(","),
+        edges.join(",")
+    )
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::Context;
+
+    fn context(lines: &[&str]) -> Context {
+        let mut context = Context::new();
+        for line in lines {
+            let tokens = crate::lexer::tokenize(line).map(|t| t.unwrap()).collect();
+            let statement = crate::parser::statements(tokens).remove(0);
+            context
+                .parse(statement)
+                .unwrap()
+                .evaluate(&mut context, &[]);
+        }
+        context
+    }
+
+    #[test]
+    fn dependencies() {
+        let context = context(&[
+            "a = 4",
+            "add x y => x + y",
+            "add3 x y z => add add x y z",
+            "eq x y => approx x y 0.1",
+        ]);
+
+        let expected = vec![
+            Node {
+                name: "a".to_owned(),
+                function: false,
+                uses: vec![],
+            },
+            Node {
+                name: "add".to_owned(),
+                function: true,
+                uses: vec![],
+            },
+            Node {
+                name: "add3".to_owned(),
+                function: true,
+                uses: vec!["add".to_owned()],
+            },
+            Node {
+                name: "eq".to_owned(),
+                function: true,
+                uses: vec![],
+            },
+        ];
+
+        assert_eq!(expected, context.graph());
+    }
+
+    fn node(name: &str, uses: &[&str]) -> Node {
+        Node {
+            name: name.to_owned(),
+            function: true,
+            uses: uses.iter().map(|used| used.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn ordering() {
+        let nodes = vec![
+            node("c", &["b"]),
+            node("a", &[]),
+            node("b", &["a", "native"]),
+            node("d", &[]),
+        ];
+        let ordered: Vec<_> = topological(&nodes)
+            .unwrap()
+            .into_iter()
+            .map(|node| node.name.as_str())
+            .collect();
+        assert_eq!(vec!["a", "b", "c", "d"], ordered);
+
+        let nodes = vec![node("f", &["g"]), node("g", &["f"]), node("h", &[])];
+        let err = topological(&nodes).unwrap_err();
+        assert!(err.to_string().ends_with("f, g"));
+
+        topological(&[node("f", &["f"])]).unwrap_err();
+    }
+
+    #[test]
+    fn redefinition_cycle() {
+        let cyclic = context(&["f x => x", "g x => f x", "f x => g x"]);
+        cyclic.definitions().unwrap_err();
+
+        let context = context(&["g x => x", "f x => g x", "g x => x + 1"]);
+        let expected = vec!["g x => x + 1", "f x => g x"];
+        assert_eq!(expected, context.definitions().unwrap());
+    }
+
+    #[test]
+    fn formats() {
+        let context = context(&["a = 4", "inc x => x + 1", "inc2 x => inc inc x"]);
+
+        let expected = "digraph symbols {
+    \"a\" [shape=box];
+    \"inc\" [shape=ellipse];
+    \"inc2\" [shape=ellipse];
+    \"inc2\" -> \"inc\";
+}";
+        assert_eq!(expected, to_dot(&context.graph()));
+
+        let expected = concat!(
+            "{\"nodes\":[{\"name\":\"a\",\"kind\":\"variable\"},",
+            "{\"name\":\"inc\",\"kind\":\"function\"},",
+            "{\"name\":\"inc2\",\"kind\":\"function\"}],",
+            "\"edges\":[{\"from\":\"inc2\",\"to\":\"inc\"}]}"
+        );
+        assert_eq!(expected, to_json(&context.graph()));
+    }
+}
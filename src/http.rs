@@ -0,0 +1,460 @@
+//! JSON evaluation endpoint, as `toy-interpreter --http 127.0.0.1:8000`.
+//! `POST /eval` with `{"session": "abc", "src": "x = 2"}` evaluates the
+//! source in named session, and responds with the result as `--json`
+//! writes it, plus warnings of the evaluation:
+//! ```text
+//! {"ok":2,"warnings":[]}
+//! ```
+//! Sessions are created on first use from copy of the same context, and
+//! dropped after they are unused for `ttl`. Request without session is
+//! evaluated in fresh one, which is not kept. Every connection is read in
+//! its own thread, while evaluations take turns - every one is stopped
+//! after `timeout`, and may allocate `DEFAULT_MEMORY` bytes unless the
+//! context limits it already. Imports are disabled, as in `server`.
+
+use crate::feature::Feature;
+use crate::json::{self, Json};
+use crate::sink::{json_string, Event};
+use crate::{Context, Error, Interpreter, Result};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Time unused session is kept for, unless set otherwise
+pub const DEFAULT_TTL: Duration = Duration::from_secs(600);
+
+/// Time evaluation of single request may take, unless set otherwise
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Bytes of strings and lists single evaluation may create, unless the
+/// context limits them already
+pub const DEFAULT_MEMORY: usize = 16 << 20;
+
+/// Sessions kept at once, new ones are refused above it
+pub const MAX_SESSIONS: usize = 1000;
+
+/// Bytes of request line and headers together, larger ones are refused
+const MAX_HEAD: u64 = 8 << 10;
+
+/// Bytes of request body, larger ones are refused
+const MAX_BODY: usize = 1 << 20;
+
+/// Time client has to send the request
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Named sessions kept between requests
+pub struct Sessions {
+    template: Context,
+    ttl: Duration,
+    timeout: Duration,
+    sessions: HashMap<String, (Context, Instant)>,
+}
+
+impl Sessions {
+    pub fn new(mut template: Context, ttl: Duration) -> Self {
+        template.set_feature(Feature::Imports, false);
+        let limits = template.limits();
+        if limits.memory.is_none() {
+            template.set_limits(limits.memory(DEFAULT_MEMORY));
+        }
+        Sessions {
+            template,
+            ttl,
+            timeout: DEFAULT_TIMEOUT,
+            sessions: HashMap::new(),
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    /// Drops sessions unused for longer than ttl
+    pub fn evict(&mut self, now: Instant) {
+        let ttl = self.ttl;
+        self.sessions
+            .retain(|_, (_, used)| now.saturating_duration_since(*used) <= ttl);
+    }
+
+    /// Evaluates source in the session, giving body of the response. Error
+    /// if the session would be created above `MAX_SESSIONS`.
+    pub fn eval(&mut self, session: Option<&str>, src: &str, now: Instant) -> Result<String> {
+        self.evict(now);
+        if let Some(name) = session {
+            if !self.sessions.contains_key(name) && self.sessions.len() >= MAX_SESSIONS {
+                return Err(Error::Other("Too many sessions".to_owned()));
+            }
+        }
+        // Interpreter is not `Send`, so sessions keep only their contexts
+        let context = match session.and_then(|name| self.sessions.remove(name)) {
+            Some((context, _)) => context,
+            None => self.template.clone(),
+        };
+        let mut interpreter = Interpreter::with_context(context);
+
+        let event = match interpreter.eval_with_timeout(src, self.timeout) {
+            Ok(val) => Event::Result(val.map(|val| interpreter.context().rounded(val))),
+            Err(error) => Event::Error {
+                error,
+                line: src.to_owned(),
+            },
+        };
+        let warnings: Vec<_> = interpreter
+            .context_mut()
+            .take_warnings()
+            .iter()
+            .map(|warning| json_string(warning))
+            .collect();
+
+        // Event is single JSON object, extended with warnings
+        let mut body = event.json();
+        body.pop();
+        if let Some(name) = session {
+            self.sessions
+                .insert(name.to_owned(), (interpreter.into_context(), now));
+        }
+        Ok(format!("{},\"warnings\":[{}]}}", body, warnings.join(",")))
+    }
+}
+
+/// Failed request, without code of evaluation error
+fn error_body(message: &str) -> String {
+    format!("{{\"error\":{{\"message\":{}}}}}", json_string(message))
+}
+
+/// Request to evaluate `src` in the session
+#[derive(Debug, PartialEq)]
+pub struct Request {
+    pub session: Option<String>,
+    pub src: String,
+}
+
+/// Reads single request, failure is status and body of the response
+pub fn read_request(reader: &mut impl BufRead) -> std::result::Result<Request, (u16, String)> {
+    let mut head = reader.take(MAX_HEAD);
+    let mut line = String::new();
+    match head.read_line(&mut line) {
+        Ok(_) if line.ends_with('\n') => (),
+        Ok(_) if head.limit() == 0 => return Err((431, error_body("Request line is too large"))),
+        _ => return Err((400, error_body("Invalid request"))),
+    }
+    let mut parts = line.split_whitespace();
+    let (method, path) = (parts.next(), parts.next());
+
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        match head.read_line(&mut header) {
+            Ok(_) if header.trim().is_empty() && header.ends_with('\n') => break,
+            Ok(_) if header.ends_with('\n') => (),
+            Ok(_) if head.limit() == 0 => return Err((431, error_body("Headers are too large"))),
+            _ => return Err((400, error_body("Invalid request"))),
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                match value.trim().parse() {
+                    Ok(value) => length = value,
+                    Err(_) => return Err((400, error_body("Invalid Content-Length"))),
+                }
+            }
+        }
+    }
+
+    match (method, path) {
+        (Some("POST"), Some("/eval")) => (),
+        (Some(_), Some("/eval")) => return Err((405, error_body("Only POST is allowed"))),
+        _ => return Err((404, error_body("Unknown endpoint, expected POST /eval"))),
+    }
+    if length > MAX_BODY {
+        return Err((413, error_body("Request is too large")));
+    }
+
+    let mut body = vec![0; length];
+    if reader.read_exact(&mut body).is_err() {
+        return Err((
+            400,
+            error_body("Request body is shorter than Content-Length"),
+        ));
+    }
+    let request = String::from_utf8(body)
+        .map_err(|_| Error::Other("Body is not UTF-8".to_owned()))
+        .and_then(|body| json::parse(&body))
+        .map_err(|err| (400, error_body(&err.to_string())))?;
+
+    let session = match request.field("session") {
+        Some(Json::Str(session)) => Some(session.clone()),
+        None | Some(Json::Null) => None,
+        Some(_) => return Err((400, error_body("`session` has to be string"))),
+    };
+    match request.field("src") {
+        Some(Json::Str(src)) => Ok(Request {
+            session,
+            src: src.clone(),
+        }),
+        _ => Err((400, error_body("`src` has to be string"))),
+    }
+}
+
+/// Status and body of response to the request
+pub fn answer(sessions: &mut Sessions, request: &Request, now: Instant) -> (u16, String) {
+    match sessions.eval(request.session.as_deref(), &request.src, now) {
+        Ok(body) => (200, body),
+        Err(err) => (503, error_body(&err.to_string())),
+    }
+}
+
+/// Status and body of response to single request
+pub fn respond(sessions: &mut Sessions, reader: &mut impl BufRead, now: Instant) -> (u16, String) {
+    match read_request(reader) {
+        Ok(request) => answer(sessions, &request, now),
+        Err(response) => response,
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        _ => "Service Unavailable",
+    }
+}
+
+/// Serves requests forever, every connection in its own thread. Failures
+/// of connections are reported on stderr, and do not stop the server.
+pub fn serve(listener: TcpListener, sessions: Sessions) -> Result<()> {
+    let sessions = Arc::new(Mutex::new(sessions));
+    for stream in listener.incoming() {
+        let mut stream =
+            stream.map_err(|err| Error::Io(format!("Cannot accept connection: {}", err)))?;
+        stream.set_read_timeout(Some(READ_TIMEOUT)).ok();
+        let reader = match stream.try_clone() {
+            Ok(reader) => reader,
+            Err(err) => {
+                eprintln!("Cannot open connection: {}", err);
+                continue;
+            }
+        };
+
+        let sessions = Arc::clone(&sessions);
+        thread::spawn(move || {
+            // Slow client holds only its own thread, the lock is taken for
+            // evaluation alone
+            let (status, body) = match read_request(&mut BufReader::new(reader)) {
+                Ok(request) => {
+                    // Panic of evaluation loses only its own session
+                    let mut sessions = sessions.lock().unwrap_or_else(PoisonError::into_inner);
+                    answer(&mut sessions, &request, Instant::now())
+                }
+                Err(response) => response,
+            };
+            let response = format!(
+                "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                reason(status),
+                body.len(),
+                body
+            );
+            if let Err(err) = stream.write_all(response.as_bytes()) {
+                eprintln!("Cannot write response: {}", err);
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::limits::Limits;
+    use std::io::{Cursor, Read};
+    use std::net::TcpStream;
+
+    fn post(sessions: &mut Sessions, body: &str, now: Instant) -> (u16, String) {
+        let request = format!(
+            "POST /eval HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        respond(sessions, &mut Cursor::new(request), now)
+    }
+
+    #[test]
+    fn sessions() {
+        let mut context = Context::new();
+        context.set_force(true);
+        let mut sessions = Sessions::new(context, Duration::from_secs(60));
+        let now = Instant::now();
+        let ok = |body: &str| (200, body.to_owned());
+
+        assert_eq!(
+            ok("{\"ok\":2,\"warnings\":[]}"),
+            post(
+                &mut sessions,
+                "{\"session\": \"a\", \"src\": \"x = 2\"}",
+                now
+            )
+        );
+        assert_eq!(
+            ok("{\"ok\":4,\"warnings\":[]}"),
+            post(
+                &mut sessions,
+                "{\"session\": \"a\", \"src\": \"x * 2\"}",
+                now
+            )
+        );
+        assert_eq!(
+            ok("{\"error\":{\"message\":\"Undefined symbol: x\",\"code\":\"E010\",\"kind\":\"undefined-symbol\",\"offset\":null},\"warnings\":[]}"),
+            post(&mut sessions, "{\"session\": \"b\", \"src\": \"x\"}", now)
+        );
+        assert_eq!(
+            ok("{\"ok\":null,\"warnings\":[\"Builtin function sqrt is shadowed\"]}"),
+            post(&mut sessions, "{\"src\": \"sqrt x => x\"}", now)
+        );
+        assert_eq!(2, sessions.len());
+
+        // Every use keeps the session alive
+        let later = now + Duration::from_secs(50);
+        post(&mut sessions, "{\"session\": \"a\", \"src\": \"1\"}", later);
+        sessions.evict(now + Duration::from_secs(100));
+        assert_eq!(1, sessions.len());
+        assert_eq!(
+            ok("{\"ok\":2,\"warnings\":[]}"),
+            post(&mut sessions, "{\"session\": \"a\", \"src\": \"x\"}", later)
+        );
+    }
+
+    #[test]
+    fn timeout() {
+        let mut sessions =
+            Sessions::new(Context::new(), DEFAULT_TTL).with_timeout(Duration::from_millis(50));
+        let now = Instant::now();
+        let (status, body) = post(
+            &mut sessions,
+            "{\"session\": \"a\", \"src\": \"sum i = 1 to 1e12 of i\"}",
+            now,
+        );
+        assert_eq!(200, status);
+        assert!(body.contains("\"code\":\"E023\""), "{}", body);
+        assert_eq!(
+            (200, "{\"ok\":2,\"warnings\":[]}".to_owned()),
+            post(
+                &mut sessions,
+                "{\"session\": \"a\", \"src\": \"1 + 1\"}",
+                now
+            )
+        );
+    }
+
+    #[test]
+    fn invalid_requests() {
+        let mut sessions = Sessions::new(Context::new(), DEFAULT_TTL);
+        let now = Instant::now();
+        let status = |request: &str, sessions: &mut Sessions| {
+            respond(sessions, &mut Cursor::new(request.to_owned()), now).0
+        };
+
+        assert_eq!(404, status("GET / HTTP/1.1\r\n\r\n", &mut sessions));
+        assert_eq!(405, status("GET /eval HTTP/1.1\r\n\r\n", &mut sessions));
+        assert_eq!(
+            400,
+            status(
+                "POST /eval HTTP/1.1\r\nContent-Length: 9\r\n\r\n{}",
+                &mut sessions
+            )
+        );
+        assert_eq!(
+            413,
+            status(
+                &format!(
+                    "POST /eval HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+                    MAX_BODY + 1
+                ),
+                &mut sessions
+            )
+        );
+        assert_eq!(400, post(&mut sessions, "{\"src\": 1}", now).0);
+        assert_eq!(
+            400,
+            post(&mut sessions, "{\"session\": 1, \"src\": \"1\"}", now).0
+        );
+        assert_eq!(400, post(&mut sessions, "src=1", now).0);
+        let long = format!("POST /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_HEAD as usize));
+        assert_eq!(431, status(&long, &mut sessions));
+        let many = format!("POST /eval HTTP/1.1\r\n{}\r\n", "X-A: b\r\n".repeat(2000));
+        assert_eq!(431, status(&many, &mut sessions));
+        assert_eq!(400, status("POST /eval HTTP/1.1\r\nHost: a", &mut sessions));
+
+        let nested = format!("{{\"src\": {}}}", "[".repeat(200_000));
+        assert_eq!(400, post(&mut sessions, &nested, now).0);
+
+        for idx in 0..MAX_SESSIONS {
+            sessions.eval(Some(&idx.to_string()), "1", now).unwrap();
+        }
+        assert_eq!(
+            503,
+            post(&mut sessions, "{\"session\": \"new\", \"src\": \"1\"}", now).0
+        );
+    }
+
+    #[test]
+    fn memory() {
+        let sessions = Sessions::new(Context::new(), DEFAULT_TTL);
+        assert_eq!(Some(DEFAULT_MEMORY), sessions.template.limits().memory);
+
+        let mut context = Context::new();
+        context.set_limits(Limits::default().memory(100));
+        let mut sessions = Sessions::new(context, DEFAULT_TTL);
+        let (_, body) = post(
+            &mut sessions,
+            "{\"src\": \"d s => s + s; d(d(d(d(d \\\"abcdefgh\\\"))))\"}",
+            Instant::now(),
+        );
+        assert!(body.contains("\"code\":\"E021\""), "{}", body);
+    }
+
+    #[test]
+    fn concurrent() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || serve(listener, Sessions::new(Context::new(), DEFAULT_TTL)));
+
+        // Request which is never finished doesn't hold the others
+        let mut slow = TcpStream::connect(addr).unwrap();
+        slow.write_all(b"POST /eval HTTP/1.1\r\n").unwrap();
+
+        let body = "{\"src\": \"1 + 1\"}";
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "POST /eval HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(
+            response.ends_with("{\"ok\":2,\"warnings\":[]}"),
+            "{}",
+            response
+        );
+    }
+}
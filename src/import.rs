@@ -0,0 +1,296 @@
+//! `import "lib.calc"` statements, which evaluate other file and define its
+//! symbols in the session. `import "stats.calc" (mean, stddev)` imports only
+//! listed symbols, and `import "geo.calc" as geo` prefixes imported names,
+//! so `area` becomes `geo_area`.
+
+use crate::envelope::{self, Header, Kind};
+use crate::graph;
+use crate::lexer::{self, render, Span, Token};
+use crate::{strip_shebang, Error, Interpreter, Result};
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Debug, PartialEq)]
+pub struct Import {
+    pub path: String,
+    pub symbols: Option<Vec<String>>, // Only listed symbols, `None` for all
+    pub prefix: Option<String>,
+}
+
+impl Import {
+    /// Name of imported symbol in the importing session
+    fn rename(&self, name: &str) -> String {
+        match &self.prefix {
+            Some(prefix) => format!("{}_{}", prefix, name),
+            None => name.to_owned(),
+        }
+    }
+}
+
+/// Import statement in the line, `None` if it is not import. Quotes are not
+/// valid in expressions, so the path distinguishes it from using `import`
+/// symbol.
+pub fn parse(line: &str) -> Option<Result<Import>> {
+    let rest = line.trim_start().strip_prefix("import")?.trim_start();
+    let rest = rest.strip_prefix('"')?;
+    Some(parse_import(line, rest))
+}
+
+/// Tokens of the line starting at `tail`, with spans relative to the line
+fn tokenize(line: &str, tail: &str) -> Result<Vec<(Token, Span)>> {
+    let offset = line.len() - tail.len();
+    lexer::tokenize(tail)
+        .map(|token| match token {
+            Ok((token, span)) => Ok((token, span.shift(offset))),
+            Err(Error::Lex { message, span }) => Err(Error::Lex {
+                message,
+                span: span.shift(offset),
+            }),
+            Err(err) => Err(err),
+        })
+        .collect()
+}
+
+/// `rest` follows the opening quote of the path
+fn parse_import(line: &str, rest: &str) -> Result<Import> {
+    let (path, tail) = rest.split_once('"').ok_or_else(|| Error::Parse {
+        message: "Import path is not closed with `\"`".to_owned(),
+        span: Span::at(line.len()),
+    })?;
+
+    let tokens = tokenize(line, tail.trim_start())?;
+    let end = Span::at(
+        tokens
+            .last()
+            .map_or(line.len() - tail.len(), |(_, span)| span.end),
+    );
+    let mut tokens = tokens.into_iter().peekable();
+    let unexpected = |expected: &str, token: Option<(Token, Span)>| {
+        let (got, span) = match token {
+            Some((token, span)) => (format!("`{}`", token), span),
+            None => ("end of line".to_owned(), end),
+        };
+        Error::Parse {
+            message: format!("Expected {} in import, got {}", expected, got),
+            span,
+        }
+    };
+
+    let mut symbols = None;
+    if let Some((Token::LBracket, _)) = tokens.peek() {
+        tokens.next();
+        let mut names = vec![];
+        loop {
+            match tokens.next() {
+                Some((Token::Id(name), _)) => names.push(name),
+                token => return Err(unexpected("symbol name", token)),
+            }
+            match tokens.next() {
+                Some((Token::Comma, _)) => (),
+                Some((Token::RBracket, _)) => break,
+                token => return Err(unexpected("`,` or `)`", token)),
+            }
+        }
+        symbols = Some(names);
+    }
+
+    let mut prefix = None;
+    match tokens.next() {
+        None => (),
+        Some((Token::Id(keyword), _)) if keyword == "as" => match tokens.next() {
+            Some((Token::Id(name), _)) => prefix = Some(name),
+            token => return Err(unexpected("prefix", token)),
+        },
+        token => return Err(unexpected("symbol list or `as`", token)),
+    }
+
+    if let Some(token) = tokens.next() {
+        return Err(unexpected("end of line", Some(token)));
+    }
+
+    Ok(Import {
+        path: path.to_owned(),
+        symbols,
+        prefix,
+    })
+}
+
+/// Evaluates imported file in separate interpreter, and defines selected
+/// symbols with functions they call by evaluating their definitions, so
+/// the usual redefinition rules apply. Returns number of defined symbols.
+pub fn load(interpreter: &mut Interpreter, import: &Import) -> Result<usize> {
+    // Nested imports are relative to the importing file
+    let path = match interpreter.importing.last().and_then(|file| file.parent()) {
+        Some(dir) => dir.join(&import.path),
+        None => PathBuf::from(&import.path),
+    };
+    if interpreter.importing.contains(&path) {
+        return Err(Error::Eval(format!("Cyclic import of {}", path.display())));
+    }
+
+    let src = fs::read_to_string(&path)
+        .map_err(|err| Error::Io(format!("Cannot read {}: {}", path.display(), err)))?;
+    // Exported sessions are checked to be compatible, plain sources have no header
+    let src = match src.lines().next().and_then(Header::parse) {
+        Some(_) => envelope::open(Kind::Session, &src)
+            .map_err(|err| Error::Eval(format!("{}: {}", path.display(), err)))?,
+        None => &src,
+    };
+
+    let mut library = Interpreter::new();
+    library.importing = interpreter.importing.clone();
+    library.importing.push(path.clone());
+    for (idx, line) in strip_shebang(src).lines().enumerate() {
+        if line.trim_start().starts_with(':') {
+            continue;
+        }
+        library
+            .eval(line)
+            .map_err(|err| Error::Eval(format!("{}:{}: {}", path.display(), idx + 1, err)))?;
+    }
+
+    let context = library.context();
+    let nodes = context.graph();
+    let mut selected: Vec<&str> = match &import.symbols {
+        Some(symbols) => symbols.iter().map(String::as_str).collect(),
+        None => nodes.iter().map(|node| node.name.as_str()).collect(),
+    };
+
+    // Called functions are inlined, but exported session has to define them
+    let mut idx = 0;
+    while idx < selected.len() {
+        let node = nodes
+            .iter()
+            .find(|node| node.name == selected[idx])
+            .ok_or_else(|| {
+                Error::UndefinedSymbol(format!("{} in {}", selected[idx], path.display()))
+            })?;
+        for used in &node.uses {
+            if !selected.contains(&used.as_str()) {
+                selected.push(used);
+            }
+        }
+        idx += 1;
+    }
+
+    for node in graph::topological(&nodes)? {
+        if !selected.contains(&node.name.as_str()) {
+            continue;
+        }
+
+        let definition = context.definition(&node.name).unwrap_or_default();
+        let tokens: Vec<_> = lexer::tokenize(&definition)
+            .map(|token| {
+                token.map(|(token, _)| match token {
+                    Token::Id(id) if selected.contains(&id.as_str()) => {
+                        Token::Id(import.rename(&id))
+                    }
+                    Token::Assign(var) if selected.contains(&var.as_str()) => {
+                        Token::Assign(import.rename(&var))
+                    }
+                    token => token,
+                })
+            })
+            .collect::<Result<_>>()?;
+        interpreter.eval(&render(&tokens))?;
+    }
+
+    Ok(selected.len())
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::Value;
+    use std::env;
+
+    fn import(path: &str, symbols: Option<&[&str]>, prefix: Option<&str>) -> Import {
+        Import {
+            path: path.to_owned(),
+            symbols: symbols.map(|symbols| symbols.iter().map(|s| s.to_string()).collect()),
+            prefix: prefix.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn statements() {
+        assert!(parse("important + 1").is_none());
+        assert!(parse("import = 2").is_none());
+        assert_eq!(
+            Some(Ok(import("lib.calc", None, None))),
+            parse("import \"lib.calc\" # all of it")
+        );
+        assert_eq!(
+            Some(Ok(import("stats.calc", Some(&["mean", "stddev"]), None))),
+            parse("import \"stats.calc\" (mean, stddev)")
+        );
+        assert_eq!(
+            Some(Ok(import("geo.calc", Some(&["area"]), Some("geo")))),
+            parse("  import \"geo.calc\" (area) as geo")
+        );
+
+        let span = |line| parse(line).unwrap().unwrap_err().span();
+        assert_eq!(Some(Span::at(11)), span("import \"lib"));
+        assert_eq!(
+            Some(Span { start: 19, end: 20 }),
+            span("import \"lib.calc\" (1)")
+        );
+        assert_eq!(Some(Span::at(20)), span("import \"lib.calc\" as"));
+        assert_eq!(
+            Some(Span { start: 25, end: 26 }),
+            span("import \"lib.calc\" as geo x")
+        );
+    }
+
+    #[test]
+    fn symbols() {
+        let dir = env::temp_dir().join(format!("toy-import-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let stats = "sum a b => a + b\nmean a b => sum(a, b) / 2\nn = 2\n:set force on\n";
+        fs::write(dir.join("stats.calc"), stats).unwrap();
+        fs::write(dir.join("lib.calc"), "import \"stats.calc\" (mean) as s\n").unwrap();
+        let lib = dir.join("lib.calc").display().to_string();
+
+        let mut interpreter = Interpreter::new();
+        let line = format!("import \"{}\" (s_mean) as lib", lib);
+        assert_eq!(Ok(None), interpreter.eval(&line));
+        assert_eq!(Ok(Some(Value::Int(3))), interpreter.eval("lib_s_mean 2 4"));
+        assert_eq!(Ok(Some(Value::Int(6))), interpreter.eval("lib_s_sum 2 4"));
+        interpreter.eval("n").unwrap_err();
+
+        let mut interpreter = Interpreter::new();
+        let stats = dir.join("stats.calc").display().to_string();
+        interpreter.eval(&format!("import \"{}\"", stats)).unwrap();
+        assert_eq!(Some(Value::Int(2)), interpreter.get_var("n"));
+        assert_eq!(
+            vec!["sum a b => a + b", "mean a b => sum(a, b) / 2", "n = 2"],
+            interpreter.context().definitions().unwrap()
+        );
+
+        let missing = interpreter.eval(&format!("import \"{}\" (median)", stats));
+        assert!(matches!(missing, Err(Error::UndefinedSymbol(_))));
+
+        fs::write(dir.join("cycle.calc"), "import \"cycle.calc\"\n").unwrap();
+        let cycle = dir.join("cycle.calc").display().to_string();
+        interpreter
+            .eval(&format!("import \"{}\"", cycle))
+            .unwrap_err();
+
+        fs::write(
+            dir.join("saved.calc"),
+            envelope::wrap(Kind::Session, "k = 7\n"),
+        )
+        .unwrap();
+        let saved = dir.join("saved.calc").display().to_string();
+        interpreter.eval(&format!("import \"{}\"", saved)).unwrap();
+        assert_eq!(Some(Value::Int(7)), interpreter.get_var("k"));
+        let future = "# toy-interpreter session, format 9, interpreter 9.0.0\nk = 8\n";
+        fs::write(dir.join("saved.calc"), future).unwrap();
+        interpreter
+            .eval(&format!("import \"{}\"", saved))
+            .unwrap_err();
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
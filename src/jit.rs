@@ -0,0 +1,406 @@
+//! Native code of hot functions, with `jit` feature. Bytecode of function
+//! called `HOT_CALLS` times is compiled with cranelift, if it is straight
+//! arithmetic of arguments, variables and number literals, with calls of
+//! float builtins - no branches, lists, strings or calls of user functions.
+//! Such bytecode gives float whenever all arguments and variables it reads
+//! are floats, and only these calls run native code, every other call runs
+//! the bytecode.
+//!
+//! Native function takes pointer to arguments and pointer to values of
+//! variables loaded by the bytecode, by their indices. Variables are read
+//! from the context before the call, which gives the same values as loading
+//! them while running, since the code cannot assign them. Steps of the
+//! bytecode are counted before the call too, so step limits and timeouts
+//! stop the call exactly where they would stop the bytecode.
+
+use crate::builtins::Func;
+use crate::parser::Callee;
+use crate::vm::{Op, Program};
+use crate::{Context, Operator, Value};
+use cranelift_codegen::ir::{
+    types, AbiParam, InstBuilder, MemFlags, StackSlotData, StackSlotKind, Type,
+};
+use cranelift_codegen::{ir, settings};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Module};
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::thread;
+
+/// Calls of function after which it is compiled
+pub const HOT_CALLS: usize = 100;
+
+type Entry = unsafe extern "C" fn(*const f64, *const f64) -> f64;
+
+/// Calls of program, with its native code once it is hot
+#[derive(Default)]
+pub struct Hot {
+    calls: AtomicUsize,
+    /// `None` inside if bytecode cannot be compiled
+    code: OnceLock<Option<Code>>,
+}
+
+/// Compiled function, with module owning its memory
+struct Code {
+    module: Option<JITModule>,
+    entry: Entry,
+}
+
+// SAFETY: the code is never modified once finalized, and it reads only the
+// memory passed to it, so it can be called from any thread
+unsafe impl Send for Code {}
+unsafe impl Sync for Code {}
+
+impl Drop for Code {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            // SAFETY: the entry is dropped together with the module
+            unsafe { module.free_memory() }
+        }
+    }
+}
+
+impl Hot {
+    /// Result of native code, `None` if this call runs the bytecode
+    pub fn call(
+        &self,
+        program: &Program,
+        context: &mut Context,
+        args: &[Value],
+    ) -> Option<Option<Value>> {
+        let code = match self.code.get() {
+            Some(code) => code.as_ref()?,
+            None if self.calls.fetch_add(1, Ordering::Relaxed) + 1 < HOT_CALLS => return None,
+            // Call may be nested deep in recursion, and compilation takes a lot of stack
+            None => self
+                .code
+                .get_or_init(|| {
+                    thread::scope(|scope| scope.spawn(|| compile(program)).join().ok()?)
+                })
+                .as_ref()?,
+        };
+
+        let args = floats(args.iter().cloned())?;
+        // Variables bound by host are read by calling it, as many times as
+        // bytecode loads them
+        let vars = program.names().iter().map(|name| {
+            if context.is_external(name) {
+                None
+            } else {
+                Some(context.get_var(name).unwrap_or(Value::Float(f64::NAN)))
+            }
+        });
+        let vars = floats(vars.collect::<Option<Vec<_>>>()?)?;
+
+        for _ in program.ops() {
+            if context.step().is_none() {
+                return Some(None);
+            }
+        }
+        // SAFETY: the code reads as many arguments and variables as the
+        // bytecode does, and calls only builtins it was compiled with
+        let result = unsafe { (code.entry)(args.as_ptr(), vars.as_ptr()) };
+        Some(Some(Value::Float(result)))
+    }
+
+    pub fn is_compiled(&self) -> bool {
+        matches!(self.code.get(), Some(Some(_)))
+    }
+}
+
+/// Hot programs are equal to cold ones
+impl PartialEq for Hot {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl fmt::Debug for Hot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Hot")
+            .field("calls", &self.calls.load(Ordering::Relaxed))
+            .field("compiled", &self.is_compiled())
+            .finish()
+    }
+}
+
+fn floats(values: impl IntoIterator<Item = Value>) -> Option<Vec<f64>> {
+    values
+        .into_iter()
+        .map(|val| match val {
+            Value::Float(val) => Some(val),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Operations on floats with the same results as on values
+extern "C" fn rem(left: f64, right: f64) -> f64 {
+    left.trunc() % right.trunc()
+}
+
+extern "C" fn pow(left: f64, right: f64) -> f64 {
+    left.powf(right)
+}
+
+extern "C" fn builtin(func: *const (), args: *const f64, len: usize) -> f64 {
+    // SAFETY: only builtins are passed as `func`, and `args` points to `len`
+    // arguments on the stack of the native code
+    let (func, args) = unsafe {
+        let func: fn(&[f64]) -> f64 = std::mem::transmute(func);
+        (func, std::slice::from_raw_parts(args, len))
+    };
+    func(args)
+}
+
+/// Native code of bytecode, `None` if it may give something else than float
+fn compile(program: &Program) -> Option<Code> {
+    let isa = cranelift_native::builder()
+        .ok()?
+        .finish(settings::Flags::new(settings::builder()))
+        .ok()?;
+    let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+    let ptr = module.target_config().pointer_type();
+    let mut ctx = module.make_context();
+    ctx.func.signature.params = vec![AbiParam::new(ptr), AbiParam::new(ptr)];
+    ctx.func.signature.returns = vec![AbiParam::new(types::F64)];
+    let mut func_ctx = FunctionBuilderContext::new();
+    let mut builder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+    let block = builder.create_block();
+    builder.append_block_params_for_function_params(block);
+    builder.switch_to_block(block);
+    builder.seal_block(block);
+    let (args, vars) = (
+        builder.block_params(block)[0],
+        builder.block_params(block)[1],
+    );
+    let call_conv = module.isa().default_call_conv();
+
+    // Calls function at given address, taking arguments of given types
+    let call =
+        |builder: &mut FunctionBuilder, addr: *const (), params: &[Type], args: &[ir::Value]| {
+            let mut sig = ir::Signature::new(call_conv);
+            sig.params = params.iter().map(|ty| AbiParam::new(*ty)).collect();
+            sig.returns = vec![AbiParam::new(types::F64)];
+            let sig = builder.import_signature(sig);
+            let addr = builder.ins().iconst(ptr, addr as i64);
+            let inst = builder.ins().call_indirect(sig, addr, args);
+            builder.inst_results(inst)[0]
+        };
+
+    // Operands, with whether they are floats - integers are only literals
+    let mut stack: Vec<(ir::Value, bool)> = vec![];
+    for op in program.ops() {
+        let operand = match *op {
+            Op::Push(Value::Int(val)) => (builder.ins().f64const(val as f64), false),
+            Op::Push(Value::Float(val)) => (builder.ins().f64const(val), true),
+            Op::Arg(idx) => {
+                let offset = i32::try_from(idx * 8).ok()?;
+                (
+                    builder
+                        .ins()
+                        .load(types::F64, MemFlags::trusted(), args, offset),
+                    true,
+                )
+            }
+            Op::Load(idx) => {
+                let offset = i32::try_from(idx * 8).ok()?;
+                (
+                    builder
+                        .ins()
+                        .load(types::F64, MemFlags::trusted(), vars, offset),
+                    true,
+                )
+            }
+            Op::Binop(op) => {
+                let (right, right_float) = stack.pop()?;
+                let (left, left_float) = stack.pop()?;
+                // Operation on integers gives integer, or decimal in decimal mode
+                if !left_float && !right_float {
+                    return None;
+                }
+                let val = match op {
+                    Operator::Add => builder.ins().fadd(left, right),
+                    Operator::Sub => builder.ins().fsub(left, right),
+                    Operator::Mul => builder.ins().fmul(left, right),
+                    Operator::Div => builder.ins().fdiv(left, right),
+                    Operator::Mod => call(
+                        &mut builder,
+                        rem as *const (),
+                        &[types::F64, types::F64],
+                        &[left, right],
+                    ),
+                    Operator::Pow => call(
+                        &mut builder,
+                        pow as *const (),
+                        &[types::F64, types::F64],
+                        &[left, right],
+                    ),
+                    _ => return None,
+                };
+                (val, true)
+            }
+            Op::Call { callee, args } => {
+                let func = match &program.callees()[callee] {
+                    Callee::Native(native) => match native.func {
+                        Func::Float(func) => func,
+                        _ => return None,
+                    },
+                    _ => return None,
+                };
+                let size = u32::try_from(args * 8).ok()?;
+                let slot = builder.create_sized_stack_slot(StackSlotData::new(
+                    StackSlotKind::ExplicitSlot,
+                    size,
+                    3,
+                ));
+                let start = stack.len().checked_sub(args)?;
+                for (idx, (arg, _)) in stack.drain(start..).enumerate() {
+                    builder.ins().stack_store(arg, slot, idx as i32 * 8);
+                }
+                let addr = builder.ins().stack_addr(ptr, slot, 0);
+                let len = builder.ins().iconst(ptr, args as i64);
+                let params = [ptr, ptr, ptr];
+                let func = builder.ins().iconst(ptr, func as *const () as i64);
+                (
+                    call(
+                        &mut builder,
+                        builtin as *const (),
+                        &params,
+                        &[func, addr, len],
+                    ),
+                    true,
+                )
+            }
+            _ => return None,
+        };
+        stack.push(operand);
+    }
+    match stack[..] {
+        [(result, true)] => builder.ins().return_(&[result]),
+        _ => return None,
+    };
+    builder.finalize();
+
+    let id = module
+        .declare_anonymous_function(&ctx.func.signature)
+        .ok()?;
+    module.define_function(id, &mut ctx).ok()?;
+    module.finalize_definitions().ok()?;
+    // SAFETY: the function was declared with the signature of `Entry`
+    let entry =
+        unsafe { std::mem::transmute::<*const u8, Entry>(module.get_finalized_function(id)) };
+    Some(Code {
+        module: Some(module),
+        entry,
+    })
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::limits::Limits;
+    use crate::parser::Body;
+    use crate::vm;
+    use crate::{Capture, Interpreter};
+    use std::sync::Arc;
+
+    /// Interpreter with `f` reading variable `k`
+    fn interpreter() -> Interpreter {
+        let mut interpreter = Interpreter::new();
+        interpreter.context_mut().set_capture(Capture::Reference);
+        interpreter.eval("k = 1.5").unwrap();
+        interpreter
+            .eval("f x y => x * 2.5 + sqrt(y) - x % 3 ^ 2 / y * k")
+            .unwrap();
+        interpreter
+    }
+
+    fn body(interpreter: &Interpreter, func: &str) -> Arc<Body> {
+        match interpreter.context().get_func(func) {
+            Some(Callee::Function(body)) => body,
+            callee => panic!("Not user function: {:?}", callee),
+        }
+    }
+
+    fn compiled(interpreter: &Interpreter, func: &str) -> bool {
+        body(interpreter, func)
+            .program
+            .as_ref()
+            .unwrap()
+            .is_compiled()
+    }
+
+    /// Result of fresh bytecode of function, which is never hot
+    fn cold(interpreter: &mut Interpreter, func: &str, args: &[Value]) -> Option<Value> {
+        let program = vm::compile(&body(interpreter, func).expr).unwrap();
+        program.run(interpreter.context_mut(), args)
+    }
+
+    /// Calls function enough times to compile it
+    fn warm(interpreter: &mut Interpreter, call: &str) {
+        for _ in 0..HOT_CALLS {
+            interpreter.eval(call).unwrap();
+        }
+    }
+
+    #[test]
+    fn results() {
+        let mut interpreter = interpreter();
+        warm(&mut interpreter, "f(1.5, 2.5)");
+        assert!(compiled(&interpreter, "f"));
+
+        let f = body(&interpreter, "f");
+        let special = [0.0, -0.0, 0.5, -7.25, 1e300, f64::INFINITY, f64::NAN];
+        for x in special.iter() {
+            for y in special.iter() {
+                let args = [Value::Float(*x), Value::Float(*y)];
+                let native = f.call(interpreter.context_mut(), &args);
+                let bytecode = cold(&mut interpreter, "f", &args);
+                assert_eq!(format!("{:?}", bytecode), format!("{:?}", native));
+            }
+        }
+    }
+
+    #[test]
+    fn fallback() {
+        let mut interpreter = interpreter();
+        interpreter.eval("g x => x == 0 ? 1 : x / 2").unwrap();
+        warm(&mut interpreter, "f(1.5, 2.5)");
+        warm(&mut interpreter, "g(1.5)");
+        assert!(!compiled(&interpreter, "g"));
+
+        // Integer arguments and variables run the bytecode
+        interpreter.eval("h x => x * 2 + 1").unwrap();
+        warm(&mut interpreter, "h(1.5)");
+        assert!(compiled(&interpreter, "h"));
+        assert_eq!(Some(Value::Int(5)), interpreter.eval("h(2)").unwrap());
+        assert_eq!(Some(Value::Float(4.0)), interpreter.eval("h(1.5)").unwrap());
+        interpreter.eval("k = 1").unwrap();
+        let args = [Value::Float(1.5), Value::Int(4)];
+        assert_eq!(
+            cold(&mut interpreter, "f", &args),
+            interpreter.eval("f(1.5, 4)").unwrap()
+        );
+    }
+
+    #[test]
+    fn step_limit() {
+        let mut hot = interpreter();
+        warm(&mut hot, "f(1.5, 2.5)");
+        for steps in 1..30 {
+            let mut cold = interpreter();
+            cold.context_mut()
+                .set_limits(Limits::default().steps(steps));
+            hot.context_mut().set_limits(Limits::default().steps(steps));
+            assert_eq!(
+                cold.eval("f(0.5, 4.5)").map_err(|err| err.code()),
+                hot.eval("f(0.5, 4.5)").map_err(|err| err.code())
+            );
+        }
+    }
+}
@@ -0,0 +1,191 @@
+//! Reading of JSON documents given by other programs - saved state and
+//! requests. Writing is simpler, and it is done where the output is built.
+
+use crate::{Error, Result};
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Arrays and objects nested deeper are rejected, as parsing is recursive
+const MAX_DEPTH: usize = 128;
+
+/// Whole document, nothing but whitespace may follow the value
+pub(crate) fn parse(src: &str) -> Result<Json> {
+    let mut chars = src.chars().peekable();
+    let json = Json::parse(&mut chars, 0)?;
+    skip_whitespace(&mut chars);
+    if chars.next().is_some() {
+        return Err(invalid("trailing characters"));
+    }
+    Ok(json)
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::Other(format!("Invalid JSON: {}", reason))
+}
+
+/// Parsed JSON value, fields of object are in order
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+        chars.next();
+    }
+}
+
+impl Json {
+    pub(crate) fn field(&self, name: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields
+                .iter()
+                .find(|(field, _)| field == name)
+                .map(|(_, val)| val),
+            _ => None,
+        }
+    }
+
+    /// Value nested in given number of arrays and objects
+    fn parse(chars: &mut Peekable<Chars>, depth: usize) -> Result<Json> {
+        skip_whitespace(chars);
+        if matches!(chars.peek(), Some('{' | '[')) && depth >= MAX_DEPTH {
+            return Err(invalid(&format!("nested deeper than {}", MAX_DEPTH)));
+        }
+        match chars.peek() {
+            Some('{') => {
+                chars.next();
+                let fields = Self::items(chars, '}', |chars| {
+                    skip_whitespace(chars);
+                    let name = Self::string(chars)?;
+                    skip_whitespace(chars);
+                    if chars.next() != Some(':') {
+                        return Err(invalid("expected `:`"));
+                    }
+                    Ok((name, Self::parse(chars, depth + 1)?))
+                })?;
+                Ok(Json::Object(fields))
+            }
+            Some('[') => {
+                chars.next();
+                let items = Self::items(chars, ']', |chars| Self::parse(chars, depth + 1))?;
+                Ok(Json::Array(items))
+            }
+            Some('"') => Ok(Json::Str(Self::string(chars)?)),
+            Some(_) => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_alphanumeric() || "+-.".contains(c)) {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                match word.as_str() {
+                    "null" => Ok(Json::Null),
+                    "true" => Ok(Json::Bool(true)),
+                    "false" => Ok(Json::Bool(false)),
+                    _ => word
+                        .parse()
+                        .map(Json::Number)
+                        .map_err(|_| invalid(&format!("unexpected `{}`", word))),
+                }
+            }
+            None => Err(invalid("unexpected end")),
+        }
+    }
+
+    /// Comma separated items, up to the closing character
+    fn items<T>(
+        chars: &mut Peekable<Chars>,
+        close: char,
+        mut item: impl FnMut(&mut Peekable<Chars>) -> Result<T>,
+    ) -> Result<Vec<T>> {
+        let mut items = vec![];
+        skip_whitespace(chars);
+        if chars.peek() == Some(&close) {
+            chars.next();
+            return Ok(items);
+        }
+
+        loop {
+            items.push(item(chars)?);
+            skip_whitespace(chars);
+            match chars.next() {
+                Some(',') => (),
+                Some(c) if c == close => return Ok(items),
+                _ => return Err(invalid(&format!("expected `,` or `{}`", close))),
+            }
+        }
+    }
+
+    fn string(chars: &mut Peekable<Chars>) -> Result<String> {
+        if chars.next() != Some('"') {
+            return Err(invalid("expected string"));
+        }
+
+        let mut text = String::new();
+        loop {
+            match chars.next().ok_or_else(|| invalid("unterminated string"))? {
+                '"' => return Ok(text),
+                '\\' => match chars.next() {
+                    Some('n') => text.push('\n'),
+                    Some('t') => text.push('\t'),
+                    Some('r') => text.push('\r'),
+                    Some('u') => {
+                        let code: String = chars.by_ref().take(4).collect();
+                        let c = u32::from_str_radix(&code, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| invalid(&format!("invalid escape `\\u{}`", code)))?;
+                        text.push(c);
+                    }
+                    Some(c @ ('"' | '\\' | '/')) => text.push(c),
+                    _ => return Err(invalid("invalid escape")),
+                },
+                c => text.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn values() {
+        let json = parse(" {\"a\": [1, -2.5e1, true, null], \"b\": \"\\u0041\\n\"} ").unwrap();
+        assert_eq!(
+            Some(&Json::Array(vec![
+                Json::Number(1.0),
+                Json::Number(-25.0),
+                Json::Bool(true),
+                Json::Null
+            ])),
+            json.field("a")
+        );
+        assert_eq!(Some(&Json::Str("A\n".to_owned())), json.field("b"));
+        assert_eq!(None, json.field("c"));
+
+        parse("[1,]").unwrap_err();
+        parse("{\"a\" 1}").unwrap_err();
+        parse("\"\\x\"").unwrap_err();
+        parse("nope").unwrap_err();
+    }
+
+    #[test]
+    fn nesting() {
+        let nested = |depth| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        parse(&nested(MAX_DEPTH)).unwrap();
+        parse(&nested(MAX_DEPTH + 1)).unwrap_err();
+        parse(&"{\"a\":".repeat(MAX_DEPTH + 1)).unwrap_err();
+        parse(&"[".repeat(200_000)).unwrap_err();
+    }
+}
@@ -1,7 +1,15 @@
 use crate::combinators::next_token;
-use crate::Result;
+use crate::{Error, Result, Value};
+use std::cmp::Ordering;
+use std::fmt;
 use std::iter;
 
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Associativity {
+    Left,
+    Right,
+}
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Operator {
     Add,
@@ -9,40 +17,241 @@ pub enum Operator {
     Mul,
     Div,
     Mod,
+    Pow,
+    And,
+    Or,
+    Eq,
+    ExactEq,
+    Less,
+    Greater,
 }
 
 impl Operator {
-    pub fn eval(self, left: f32, right: f32) -> f32 {
-        match self {
+    /// Result of `==` depends on tolerance configured in context, so it is
+    /// not known here
+    pub fn eval(self, left: Value, right: Value) -> Option<Value> {
+        let result = match self {
             Operator::Add => left + right,
             Operator::Sub => left - right,
             Operator::Mul => left * right,
             Operator::Div => left / right,
-            Operator::Mod => ((left as i64) % (right as i64)) as f32,
+            Operator::Mod => left % right,
+            Operator::Pow => left.pow(right),
+            Operator::And => Value::from(left.is_true() && right.is_true()),
+            Operator::Or => Value::from(left.is_true() || right.is_true()),
+            Operator::Eq => return None,
+            Operator::ExactEq => Value::from(left.exact_eq(&right)),
+            Operator::Less => Value::from(left.compare(&right) == Some(Ordering::Less)),
+            Operator::Greater => Value::from(left.compare(&right) == Some(Ordering::Greater)),
+        };
+        Some(result)
+    }
+
+    /// Operators with higher precedence are applied first
+    pub fn precedence(self) -> u8 {
+        match self {
+            Operator::Or => 1,
+            Operator::And => 2,
+            Operator::Eq | Operator::ExactEq | Operator::Less | Operator::Greater => 3,
+            Operator::Add | Operator::Sub => 4,
+            Operator::Mul | Operator::Div | Operator::Mod => 5,
+            Operator::Pow => 6,
         }
     }
+
+    /// Order of applying chained operators of the same precedence
+    pub fn associativity(self) -> Associativity {
+        match self {
+            Operator::Pow => Associativity::Right,
+            _ => Associativity::Left,
+        }
+    }
+}
+
+/// Words which will gain meaning in future syntax, so they cannot name
+/// symbols already
+pub const RESERVED: &[&str] = &[
+    "const", "delete", "else", "false", "for", "fn", "if", "import", "in", "let", "of", "return",
+    "then", "to", "true", "while",
+];
+
+/// Error if the word is reserved, `span` points the name
+pub fn not_reserved(name: &str, span: Span) -> Result<()> {
+    if RESERVED.contains(&name) {
+        Err(Error::Parse {
+            message: format!("`{}` is reserved word, it cannot be used as a name", name),
+            span,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// Tolerance is relative for big numbers, but absolute for numbers smaller than 1
+pub fn approx_eq(left: f64, right: f64, epsilon: f64) -> bool {
+    left == right || (left - right).abs() <= epsilon * left.abs().max(right.abs()).max(1.0)
+}
+
+/// Logical values are numbers: `0` is false, and everything else is true
+pub fn truth(val: bool) -> f64 {
+    if val {
+        1.0
+    } else {
+        0.0
+    }
 }
 
-#[derive(Debug, PartialEq)]
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self {
+            Operator::Add => "+",
+            Operator::Sub => "-",
+            Operator::Mul => "*",
+            Operator::Div => "/",
+            Operator::Mod => "%",
+            Operator::Pow => "^",
+            Operator::And => "&&",
+            Operator::Or => "||",
+            Operator::Eq => "==",
+            Operator::ExactEq => "===",
+            Operator::Less => "<",
+            Operator::Greater => ">",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum Token {
     Id(String),
-    Number(f32),
+    Number(Value),
+    Str(String),
     Operator(Operator),
     LBracket,
     RBracket,
+    LSquare, // `[` of list literal or indexing
+    RSquare,
     Assign(String), // Assignment is actually bitoken including variable which is assigned to
     Func,           // =>
+    Let,            // `let` of `let x = ... in ...`
+    Const,          // `const` of `const x = ...`
+    In,
+    To, // `to` of `sum i = 1 to n of ...`
+    Of,
+    Question, // `?` of `cond ? a : b`
+    Colon,
+    Pipe,     // `|>`
+    Ellipsis, // `...` before arguments list of variadic function
+    Not,
+    Semicolon,
+    Comma,
 }
 
-pub fn tokenize<'a>(mut src: &'a str) -> impl Iterator<Item = Result<Token>> + 'a {
-    iter::from_fn(move || match next_token(src) {
-        Ok(progress) => {
-            src = progress.tail.trim_start();
-            progress.token.map(Ok)
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Id(id) => write!(f, "{}", id),
+            Token::Number(x) => write!(f, "{}", x.source()),
+            Token::Str(text) => write!(f, "{:?}", text),
+            Token::Operator(op) => write!(f, "{}", op),
+            Token::LBracket => write!(f, "("),
+            Token::RBracket => write!(f, ")"),
+            Token::LSquare => write!(f, "["),
+            Token::RSquare => write!(f, "]"),
+            Token::Assign(var) => write!(f, "{} =", var),
+            Token::Func => write!(f, "=>"),
+            Token::Let => write!(f, "let"),
+            Token::Const => write!(f, "const"),
+            Token::In => write!(f, "in"),
+            Token::To => write!(f, "to"),
+            Token::Of => write!(f, "of"),
+            Token::Question => write!(f, "?"),
+            Token::Colon => write!(f, ":"),
+            Token::Pipe => write!(f, "|>"),
+            Token::Ellipsis => write!(f, "..."),
+            Token::Not => write!(f, "!"),
+            Token::Semicolon => write!(f, ";"),
+            Token::Comma => write!(f, ","),
         }
-        Err(err) => {
-            src = "";
-            Some(Err(err))
+    }
+}
+
+impl Token {
+    /// Reserved word with its own token
+    pub fn is_keyword(&self) -> bool {
+        matches!(
+            self,
+            Token::Let | Token::Const | Token::In | Token::To | Token::Of
+        )
+    }
+}
+
+/// Renders tokens back to the source form, which tokenizes to the same tokens
+pub fn render(tokens: &[Token]) -> String {
+    tokens
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Byte range of source fragment, eg. token in line
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// Empty span at given position, eg. at the end of line
+    pub fn at(offset: usize) -> Self {
+        Span {
+            start: offset,
+            end: offset,
+        }
+    }
+
+    /// Span moved `offset` bytes forward
+    pub fn shift(self, offset: usize) -> Self {
+        Span {
+            start: self.start + offset,
+            end: self.end + offset,
+        }
+    }
+
+    /// Span from the beginning of this one to the end of `other`
+    pub fn to(self, other: Span) -> Self {
+        Span {
+            start: self.start,
+            end: other.end,
+        }
+    }
+}
+
+/// Tokens with their spans, lexer error spans are also bytes from the
+/// beginning of `line`
+pub fn tokenize<'a>(line: &'a str) -> impl Iterator<Item = Result<(Token, Span)>> + 'a {
+    let mut src = line;
+    iter::from_fn(move || {
+        let offset = line.len() - src.len();
+        match next_token(src) {
+            Ok(progress) => {
+                src = progress.tail.trim_start();
+                progress
+                    .token
+                    .map(|(token, span)| Ok((token, span.shift(offset))))
+            }
+            Err(Error::Lex { message, span }) => {
+                src = "";
+                Some(Err(Error::Lex {
+                    message,
+                    span: span.shift(offset),
+                }))
+            }
+            Err(err) => {
+                src = "";
+                Some(Err(err))
+            }
         }
     })
 }
@@ -51,6 +260,13 @@ pub fn tokenize<'a>(mut src: &'a str) -> impl Iterator<Item = Result<Token>> + '
 mod test {
 
     use super::*;
+    use crate::decimal::Decimal;
+
+    fn tokens(src: &str) -> Result<Vec<Token>> {
+        tokenize(src)
+            .map(|token| token.map(|(token, _)| token))
+            .collect()
+    }
 
     #[test]
     fn empty() {
@@ -59,27 +275,106 @@ mod test {
 
     #[test]
     fn all_tokens() {
-        let src = "x 10.3 + - * / % () x = =>";
+        let src = "x 10.3 \"a\" + - * / % ^ && || ! == === < > () [] x = => ; , ? : |> ...";
         let expected = vec![
             Token::Id("x".to_owned()),
-            Token::Number(10.3),
+            Token::Number(Value::Decimal(Decimal::parse("10.3").unwrap())),
+            Token::Str("a".to_owned()),
             Token::Operator(Operator::Add),
             Token::Operator(Operator::Sub),
             Token::Operator(Operator::Mul),
             Token::Operator(Operator::Div),
             Token::Operator(Operator::Mod),
+            Token::Operator(Operator::Pow),
+            Token::Operator(Operator::And),
+            Token::Operator(Operator::Or),
+            Token::Not,
+            Token::Operator(Operator::Eq),
+            Token::Operator(Operator::ExactEq),
+            Token::Operator(Operator::Less),
+            Token::Operator(Operator::Greater),
             Token::LBracket,
             Token::RBracket,
+            Token::LSquare,
+            Token::RSquare,
             Token::Assign("x".to_owned()),
             Token::Func,
+            Token::Semicolon,
+            Token::Comma,
+            Token::Question,
+            Token::Colon,
+            Token::Pipe,
+            Token::Ellipsis,
         ];
 
-        assert_eq!(Ok(expected), tokenize(src).collect());
+        assert_eq!(Ok(expected), tokens(src));
+    }
+
+    #[test]
+    fn comments() {
+        let expected = vec![
+            Token::Id("x".to_owned()),
+            Token::Operator(Operator::Add),
+            Token::Number(Value::Int(1)),
+        ];
+        assert_eq!(Ok(expected), tokens("x + 1 # x + 2 ^"));
+        assert_eq!(None, tokenize("# just comment").next());
     }
 
     #[test]
     fn invalid() {
-        tokenize("^").collect::<Result<Vec<_>, _>>().unwrap_err();
+        tokenize("$").collect::<Result<Vec<_>>>().unwrap_err();
+
+        let err = tokenize("a = 1 +  $ 2").collect::<Result<Vec<_>>>();
+        assert_eq!(Some(9), err.unwrap_err().offset());
+        let err = tokenize("x = 1.2.3").collect::<Result<Vec<_>>>();
+        assert_eq!(Some(Span { start: 4, end: 9 }), err.unwrap_err().span());
+    }
+
+    #[test]
+    fn spans() {
+        let spans: Vec<_> = tokenize("x = 10 +  max(2) # comment")
+            .map(|token| token.unwrap().1)
+            .collect();
+        let expected: Vec<_> = [
+            (0, 3),
+            (4, 6),
+            (7, 8),
+            (10, 13),
+            (13, 14),
+            (14, 15),
+            (15, 16),
+        ]
+        .iter()
+        .map(|&(start, end)| Span { start, end })
+        .collect();
+        assert_eq!(expected, spans);
+    }
+
+    #[test]
+    fn render_tokens() {
+        let src = "add x y => x * ( y ^ 2.5 ) % 3 - z = ! 1 && 2 || 0 == 1 === max ( 2 , 3 )";
+        assert_eq!(src, render(&tokens(src).unwrap()));
+        let src = "xs = [ 1 , \"a\" ] ; xs [ 0 ]";
+        assert_eq!(src, render(&tokens(src).unwrap()));
+        let src = "s = \"tab\\t \\\"quoted\\\"\" + \"#\"";
+        assert_eq!(src, render(&tokens(src).unwrap()));
+    }
+
+    #[test]
+    fn reserved() {
+        not_reserved("iff", Span::at(0)).unwrap();
+        let err = not_reserved("while", Span { start: 2, end: 7 }).unwrap_err();
+        assert_eq!(Some(Span { start: 2, end: 7 }), err.span());
+    }
+
+    #[test]
+    fn tolerance() {
+        assert!(approx_eq(1.0, 1.0, 0.0));
+        assert!(approx_eq(0.1, 0.1001, 0.001));
+        assert!(!approx_eq(0.1, 0.102, 0.001));
+        assert!(approx_eq(10000.0, 10001.0, 0.001));
+        assert!(!approx_eq(10000.0, 10020.0, 0.001));
     }
 
     #[test]
@@ -95,7 +390,6 @@ mod test {
             Token::Id("y".to_owned()),
         ];
 
-        assert_eq!(Ok(expected), tokenize(src).collect());
+        assert_eq!(Ok(expected), tokens(src));
     }
-
 }
@@ -0,0 +1,783 @@
+pub mod analysis;
+#[cfg(feature = "cli")]
+pub mod annotate;
+#[cfg(any(feature = "cli", feature = "wasm"))]
+pub mod autosave;
+#[cfg(any(feature = "cli", feature = "wasm"))]
+pub mod bench;
+pub mod builder;
+mod builtins;
+pub mod check;
+mod clock;
+pub mod codegen;
+mod combinators;
+pub mod context;
+pub mod cost;
+pub mod decimal;
+#[cfg(any(feature = "cli", feature = "wasm"))]
+pub mod editor;
+pub mod envelope;
+mod error;
+mod eval;
+pub mod feature;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod formatter;
+pub mod graph;
+#[cfg(feature = "cli")]
+pub mod http;
+pub mod import;
+#[cfg(feature = "jit")]
+pub mod jit;
+mod json;
+pub mod lexer;
+pub mod limits;
+pub mod locale;
+#[cfg(feature = "cli")]
+pub mod lsp;
+#[cfg(feature = "cli")]
+pub mod md;
+#[cfg(feature = "cli")]
+pub mod minify;
+pub mod optimize;
+pub mod parser;
+pub mod printer;
+#[cfg(any(feature = "cli", feature = "wasm"))]
+pub mod prompt;
+#[cfg(any(feature = "cli", feature = "wasm"))]
+pub mod repl;
+#[cfg(feature = "cli")]
+pub mod script;
+#[cfg(feature = "cli")]
+pub mod server;
+pub mod sink;
+pub mod state;
+pub mod trace;
+#[cfg(any(feature = "cli", feature = "wasm"))]
+pub mod transcript;
+#[cfg(feature = "cli")]
+pub mod tutorial;
+mod value;
+pub mod vm;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+pub use builtins::Prelude;
+pub use context::{Capture, Context};
+pub use error::{Error, CATALOG};
+pub use lexer::{Operator, Span, Token};
+pub use locale::Lang;
+pub use value::{Mode, Value};
+
+use feature::Feature;
+use sink::{Event, OutputSink};
+use std::io::BufRead;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use trace::{Level, Phase, Record, Tracer};
+
+/// Evaluates every line of program in fresh interpreter, results are in
+/// order of lines
+pub fn run_program(src: &str) -> Vec<Result<Option<Value>>> {
+    let mut interpreter = Interpreter::new();
+    src.lines().map(|line| interpreter.eval(line)).collect()
+}
+
+#[cfg(feature = "tracing")]
+fn default_tracer() -> Option<Box<dyn Tracer>> {
+    Some(Box::new(trace::Tracing))
+}
+
+#[cfg(not(feature = "tracing"))]
+fn default_tracer() -> Option<Box<dyn Tracer>> {
+    None
+}
+
+/// Skips `#!` line, so scripts can be executable
+pub fn strip_shebang(src: &str) -> &str {
+    if src.starts_with("#!") {
+        src.split_once('\n').map_or("", |(_, tail)| tail)
+    } else {
+        src
+    }
+}
+
+/// Result as shown to the user
+pub fn format_result(result: Result<Option<Value>>) -> String {
+    match result {
+        Ok(Some(val)) => format!("= {}", val),
+        Ok(None) => "()".to_owned(),
+        Err(err) => Lang::En.error(&err),
+    }
+}
+
+/// Error message followed by the source line with carets under the offending
+/// fragment, if its position is known
+pub fn format_error(err: &Error, src: &str, lang: Lang) -> String {
+    match columns(err, src) {
+        Some((column, width)) => format!(
+            "{}\n  {}\n  {}{}",
+            lang.error(err),
+            src,
+            " ".repeat(column),
+            "^".repeat(width)
+        ),
+        None => lang.error(err),
+    }
+}
+
+/// Column and width in characters of the fragment of `src` which error points,
+/// at least one character wide
+pub(crate) fn columns(err: &Error, src: &str) -> Option<(usize, usize)> {
+    let span = err.span()?;
+    let column = src.get(..span.start)?.chars().count();
+    let width = src.get(span.start..span.end)?.chars().count();
+    Some((column, width.max(1)))
+}
+
+/// Evaluator to be embedded in other applications
+///
+/// ```
+/// use interpreter::{Interpreter, Value};
+///
+/// let mut interpreter = Interpreter::new();
+/// interpreter.set_var("x", 2i64).unwrap();
+/// interpreter.eval("sq a => a * a").unwrap();
+/// assert_eq!(Ok(Some(Value::Int(4))), interpreter.eval("sq x"));
+/// ```
+pub struct Interpreter {
+    context: Context,
+    importing: Vec<PathBuf>, // Files being imported, the innermost last
+    output: Option<Box<dyn OutputSink>>, // Receives warnings instead of context
+    selfcheck: bool,         // Every statement is printed and reparsed before evaluation
+    tracer: Option<Box<dyn Tracer>>,
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self::with_context(Context::new())
+    }
+
+    /// Interpreter evaluating in given context. Contexts are `Send` and
+    /// `Sync`, so one prepared context may be shared by threads, each
+    /// evaluating in its own clone.
+    pub fn with_context(context: Context) -> Self {
+        Interpreter {
+            context,
+            importing: vec![],
+            output: None,
+            selfcheck: false,
+            tracer: default_tracer(),
+        }
+    }
+
+    /// Configuration of new interpreter
+    pub fn builder() -> builder::Builder {
+        builder::Builder::new()
+    }
+
+    /// Interpreter with copy of the context, isolated from this one. Copy
+    /// is cheap, as function bodies and values are shared. Output and
+    /// tracer are not copied.
+    pub fn fork(&self) -> Self {
+        Interpreter {
+            selfcheck: self.selfcheck,
+            ..Self::with_context(self.context.clone())
+        }
+    }
+
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+
+    /// Context with everything evaluated so far, to be kept without the
+    /// interpreter and evaluated in again with `with_context`
+    pub fn into_context(self) -> Context {
+        self.context
+    }
+
+    pub fn context_mut(&mut self) -> &mut Context {
+        &mut self.context
+    }
+
+    /// Evaluates all statements in source, returning result of the last one.
+    /// Import statement takes the whole line. Warnings are emitted to the
+    /// output if there is one, otherwise they are kept in the context.
+    pub fn eval(&mut self, src: &str) -> Result<Option<Value>> {
+        let result = self.eval_line(src);
+        if let Some(output) = self.output.as_mut() {
+            for warning in self.context.take_warnings() {
+                output.emit(Event::Warning(warning))?;
+            }
+        }
+        result
+    }
+
+    /// Evaluates every line read from `reader` as it is read, yielding its
+    /// result. Failure of reading is the last item.
+    pub fn eval_stream<'a>(
+        &'a mut self,
+        reader: impl BufRead + 'a,
+    ) -> impl Iterator<Item = Result<Option<Value>>> + 'a {
+        let mut lines = reader.lines();
+        let mut failed = false;
+        std::iter::from_fn(move || {
+            if failed {
+                return None;
+            }
+            match lines.next()? {
+                Ok(line) => Some(self.eval(&line)),
+                Err(err) => {
+                    failed = true;
+                    Some(Err(Error::Io(format!("Cannot read input: {}", err))))
+                }
+            }
+        })
+    }
+
+    /// Evaluates source as `eval`, but evaluation still running after
+    /// `timeout` is stopped with `Error::Timeout`. Without clock, as on
+    /// `wasm32-unknown-unknown`, it is not stopped.
+    pub fn eval_with_timeout(&mut self, src: &str, timeout: Duration) -> Result<Option<Value>> {
+        let deadline = clock::now().map(|now| (now + timeout, timeout));
+        self.context.set_deadline(deadline);
+        let result = self.eval(src);
+        self.context.set_deadline(None);
+        result
+    }
+
+    fn eval_line(&mut self, src: &str) -> Result<Option<Value>> {
+        if let Some(import) = import::parse(src) {
+            if !self.context.is_enabled(Feature::Imports) {
+                return Err(Error::Parse {
+                    message: Feature::Imports.disabled(),
+                    span: Span {
+                        start: 0,
+                        end: src.len(),
+                    },
+                });
+            }
+            import::load(self, &import?)?;
+            return Ok(None);
+        }
+
+        let mut result = None;
+        for (idx, statement) in self.lex_line(src)?.into_iter().enumerate() {
+            let expr = self.parse_traced(idx, statement)?;
+            if self.selfcheck {
+                if let Err(err) = round_trip(&self.context, &expr) {
+                    self.context.warn(format!("selfcheck: {}", err));
+                }
+            }
+            result = self.eval_traced(idx, &expr)?;
+        }
+        Ok(result)
+    }
+
+    /// Statements of the line, lexed in `lex` span
+    fn lex_line(&mut self, src: &str) -> Result<Vec<parser::Tokens>> {
+        let _span = trace::enter(Phase::Lex);
+        let started = self.started();
+        let tokens: Result<Vec<_>> = lexer::tokenize(src).collect();
+        let tokens = match tokens {
+            Ok(tokens) => tokens,
+            Err(err) => {
+                self.trace(Level::Info, Phase::Lex, started, || "failed".to_owned());
+                return Err(err);
+            }
+        };
+        self.trace(Level::Debug, Phase::Lex, started, || {
+            let tokens: Vec<_> = tokens.iter().map(|(token, _)| token.clone()).collect();
+            format!("tokens: {}", lexer::render(&tokens))
+        });
+        let count = tokens.len();
+        let statements = parser::statements(tokens);
+        self.trace(Level::Info, Phase::Lex, started, || {
+            format!("{} tokens in {} statements", count, statements.len())
+        });
+        Ok(statements)
+    }
+
+    /// Statement of given index in the line, parsed in `parse` span
+    fn parse_traced(&mut self, idx: usize, statement: parser::Tokens) -> Result<parser::Expr> {
+        let _span = trace::enter(Phase::Parse);
+        let started = self.started();
+        let expr = self.context.parse(statement);
+        self.trace(Level::Info, Phase::Parse, started, || {
+            let failed = if expr.is_err() { " failed" } else { "" };
+            format!("statement {}{}", idx + 1, failed)
+        });
+        let expr = expr?;
+        self.trace(Level::Debug, Phase::Parse, started, || {
+            format!("{:?}", expr)
+        });
+        Ok(expr)
+    }
+
+    /// Statement of given index in the line, evaluated in `eval` span
+    fn eval_traced(&mut self, idx: usize, expr: &parser::Expr) -> Result<Option<Value>> {
+        let _span = trace::enter(Phase::Eval);
+        let started = self.started();
+        let result = self.eval_expr(expr)?;
+        self.trace(Level::Info, Phase::Eval, started, || {
+            format!("statement {}", idx + 1)
+        });
+        self.trace(Level::Debug, Phase::Eval, started, || match &result {
+            Some(val) => format!("result: {}", val),
+            None => "result: ()".to_owned(),
+        });
+        Ok(result)
+    }
+
+    /// Evaluates parsed statement, so it can be evaluated again without
+    /// parsing it
+    pub fn eval_expr(&mut self, expr: &parser::Expr) -> Result<Option<Value>> {
+        let context = &mut self.context;
+        context.limits().check(expr)?;
+        let result = expr.evaluate(context, &[]);
+        if let Some(err) = context.take_failure() {
+            return Err(err);
+        }
+        if let Some(val) = &result {
+            context.set_last_result(val.clone());
+        }
+        Ok(result)
+    }
+
+    /// Start of traced phase, the clock is read only if there is tracer
+    fn started(&self) -> Option<Instant> {
+        self.tracer.as_ref().and_then(|_| clock::now())
+    }
+
+    /// Records phase which started at `started`, if tracer records `level`.
+    /// Phases have no duration without clock.
+    fn trace(
+        &mut self,
+        level: Level,
+        phase: Phase,
+        started: Option<Instant>,
+        message: impl FnOnce() -> String,
+    ) {
+        if let Some(tracer) = self.tracer.as_mut() {
+            if level <= tracer.level() {
+                tracer.record(Record {
+                    level,
+                    phase,
+                    elapsed: started.map_or(Duration::ZERO, |started| started.elapsed()),
+                    message: message(),
+                });
+            }
+        }
+    }
+
+    /// Parses single statement without evaluating it
+    pub fn parse(&self, src: &str) -> Result<parser::Expr> {
+        parse_statement(&self.context, src)
+    }
+
+    /// Trees, types, symbols and problems of the source, as far as it can be
+    /// parsed - see `analysis`. The session is not changed.
+    pub fn analyze(&self, src: &str) -> analysis::Analysis {
+        analysis::analyze(&mut Interpreter::with_context(self.context.clone()), src)
+    }
+
+    /// Source parsing back to equivalent expression, see `printer`
+    pub fn print(&self, expr: &parser::Expr) -> Result<String> {
+        printer::print(expr, &self.context)
+    }
+
+    /// Reports statements which do not parse back to the same expression
+    /// after they are printed, as warnings
+    pub fn set_selfcheck(&mut self, selfcheck: bool) {
+        self.selfcheck = selfcheck;
+    }
+
+    pub fn set_var(&mut self, name: &str, val: impl Into<Value>) -> Result<()> {
+        identifier(name)?;
+        if !self.context.is_var(name) {
+            return Err(Error::Eval(format!("{} is not a variable", name)));
+        }
+        self.context.update_var(name, val.into())
+    }
+
+    pub fn get_var(&self, name: &str) -> Option<Value> {
+        self.context.get_var(name)
+    }
+
+    /// Function callable from evaluated code, it gets exactly `arity` arguments
+    pub fn define_native_fn(
+        &mut self,
+        name: &str,
+        arity: usize,
+        func: fn(&[f64]) -> f64,
+    ) -> Result<()> {
+        identifier(name)?;
+        self.context.define_native(name, arity, func)
+    }
+
+    /// Closure callable from evaluated code, as `define_native_fn`. Error it
+    /// returns stops evaluation, and is returned by `eval`.
+    ///
+    /// ```
+    /// use interpreter::{Interpreter, Value};
+    ///
+    /// let mut interpreter = Interpreter::new();
+    /// let (lo, hi) = (0.0, 10.0);
+    /// interpreter
+    ///     .register_fn("limit", 1, move |args| Ok(args[0].max(lo).min(hi)))
+    ///     .unwrap();
+    /// assert_eq!(Ok(Some(Value::Float(10.0))), interpreter.eval("limit 12"));
+    /// ```
+    pub fn register_fn(
+        &mut self,
+        name: &str,
+        arity: usize,
+        func: impl Fn(&[f64]) -> Result<f64> + Send + Sync + 'static,
+    ) -> Result<()> {
+        identifier(name)?;
+        self.context.register_fn(name, arity, func)
+    }
+
+    /// User definitions as JSON, see `state`
+    pub fn save_state(&self) -> Result<String> {
+        state::to_json(&self.context)
+    }
+
+    /// Evaluates definitions saved by `save_state`, returns how many there
+    /// were
+    pub fn restore_state(&mut self, json: &str) -> Result<usize> {
+        state::restore(self, json)
+    }
+
+    /// Variable of the host application - reading it in evaluated code
+    /// calls `get`, and assigning to it calls `set`, which may reject the
+    /// value.
+    ///
+    /// ```
+    /// use interpreter::{Interpreter, Value};
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let mut interpreter = Interpreter::new();
+    /// let temperature = Arc::new(Mutex::new(21.5));
+    /// let (get, set) = (Arc::clone(&temperature), Arc::clone(&temperature));
+    /// let set = move |val| {
+    ///     *set.lock().unwrap() = val;
+    ///     Ok(())
+    /// };
+    /// interpreter
+    ///     .bind_var("temperature", move || *get.lock().unwrap(), set)
+    ///     .unwrap();
+    /// assert_eq!(Ok(Some(Value::Float(23.5))), interpreter.eval("temperature + 2"));
+    /// interpreter.eval("temperature = 18").unwrap();
+    /// assert_eq!(18.0, *temperature.lock().unwrap());
+    /// ```
+    pub fn bind_var(
+        &mut self,
+        name: &str,
+        get: impl Fn() -> f64 + Send + Sync + 'static,
+        set: impl Fn(f64) -> Result<()> + Send + Sync + 'static,
+    ) -> Result<()> {
+        identifier(name)?;
+        self.context.bind_external(name, get, set)
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn parse_statement(context: &Context, src: &str) -> Result<parser::Expr> {
+    let tokens: Result<Vec<_>> = lexer::tokenize(src).collect();
+    let mut statements = parser::statements(tokens?);
+    if statements.len() > 1 {
+        return Err(Error::Parse {
+            message: "Expected single statement".to_owned(),
+            span: statements[1].span(),
+        });
+    }
+    context.parse(statements.remove(0))
+}
+
+/// Error if printed expression parses to different one
+fn round_trip(context: &Context, expr: &parser::Expr) -> Result<()> {
+    let printed = printer::print(expr, context)?;
+    let reparsed = parse_statement(context, &printed)
+        .map_err(|err| format!("`{}` does not parse: {}", printed, err))?;
+
+    if printer::equivalent(expr, &reparsed) {
+        Ok(())
+    } else {
+        Err(format!("`{}` parses to different expression", printed).into())
+    }
+}
+
+/// Host given names have to be usable in source
+fn identifier(name: &str) -> Result<()> {
+    let tokens: Vec<_> = lexer::tokenize(name).collect();
+    match tokens.as_slice() {
+        [Ok((Token::Id(id), span))] if id == name => lexer::not_reserved(id, *span),
+        _ => Err(Error::Parse {
+            message: format!("Invalid identifier: {}", name),
+            span: Span {
+                start: 0,
+                end: name.len(),
+            },
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn eval() {
+        let mut interpreter = Interpreter::new();
+        assert_eq!(Ok(Some(Value::Int(3))), interpreter.eval("a = 1; a + 2"));
+        assert_eq!(Some(Value::Int(1)), interpreter.get_var("a"));
+        assert_eq!(Ok(None), interpreter.eval("add x y => x + y"));
+        assert_eq!(Ok(Some(Value::Int(5))), interpreter.eval("add 2 3"));
+        interpreter.eval("a +").unwrap_err();
+        assert_eq!(
+            Err(Error::UndefinedSymbol("b".to_owned())),
+            interpreter.eval("a + b")
+        );
+    }
+
+    #[test]
+    fn shebang() {
+        assert_eq!(
+            "a = 1\n",
+            strip_shebang("#!/usr/bin/env toy-interpreter\na = 1\n")
+        );
+        assert_eq!("", strip_shebang("#!/usr/bin/env toy-interpreter"));
+        assert_eq!("a = 1\n#!", strip_shebang("a = 1\n#!"));
+    }
+
+    #[test]
+    fn caret() {
+        let err = Interpreter::new().eval("a = 1 + $").unwrap_err();
+        assert_eq!(
+            "Error[E002]: Invalid token: $\n  a = 1 + $\n          ^",
+            format_error(&err, "a = 1 + $", Lang::En)
+        );
+        let err = Interpreter::new().eval("2 + 2 foo bar").unwrap_err();
+        assert_eq!(
+            "Error[E001]: Unexpected trailing tokens: foo bar\n  2 + 2 foo bar\n        ^^^^^^^",
+            format_error(&err, "2 + 2 foo bar", Lang::En)
+        );
+        assert_eq!(
+            "Błąd[E010]: Niezdefiniowany symbol: b",
+            format_error(&Error::UndefinedSymbol("b".to_owned()), "b", Lang::Pl)
+        );
+    }
+
+    #[test]
+    fn set_var() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_var("x", 4.0).unwrap();
+        assert_eq!(Ok(Some(Value::Float(8.0))), interpreter.eval("x * 2"));
+        interpreter.set_var("x", 4i64).unwrap();
+        assert_eq!(Ok(Some(Value::Int(8))), interpreter.eval("x * 2"));
+
+        interpreter.set_var("pi", 3.0).unwrap_err();
+        interpreter.set_var("sqrt", 3.0).unwrap_err();
+        interpreter.set_var("1x", 3.0).unwrap_err();
+        interpreter.set_var("x y", 3.0).unwrap_err();
+    }
+
+    #[test]
+    fn selfcheck() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_selfcheck(true);
+        interpreter
+            .eval("sq x => x * x; a = 0 - 2; b = (a - sq(a)) ^ 2 ^ 0.5 || !a")
+            .unwrap();
+        assert!(interpreter.context_mut().take_warnings().is_empty());
+
+        let expr = interpreter.parse("sq(a) * (1 + a)").unwrap();
+        assert_eq!("sq(0 - 2) * (0 - 1)", interpreter.print(&expr).unwrap());
+        interpreter.eval("sq x => x").unwrap();
+        interpreter.print(&expr).unwrap_err();
+    }
+
+    #[test]
+    fn native_fn() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .define_native_fn("clamp", 3, |args| args[0].max(args[1]).min(args[2]))
+            .unwrap();
+        assert_eq!(Ok(Some(Value::Float(5.0))), interpreter.eval("clamp 7 0 5"));
+        assert_eq!(
+            Ok(Some(Value::Float(1.0))),
+            interpreter.eval("unit x => clamp x 0 1; unit 3")
+        );
+
+        interpreter.define_native_fn("sqrt", 1, |_| 0.0).unwrap();
+        assert_eq!(Ok(Some(Value::Float(0.0))), interpreter.eval("sqrt 4"));
+
+        interpreter.eval("v = 1").unwrap();
+        interpreter.define_native_fn("v", 0, |_| 0.0).unwrap_err();
+        interpreter.define_native_fn("if", 0, |_| 0.0).unwrap_err();
+        interpreter.define_native_fn("a b", 0, |_| 0.0).unwrap_err();
+    }
+
+    #[test]
+    fn registered_fn() {
+        let mut interpreter = Interpreter::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        interpreter
+            .register_fn("inverse", 1, move |args| {
+                counter.fetch_add(1, Ordering::Relaxed);
+                match args[0] {
+                    0.0 => Err(Error::Eval("Zero has no inverse".to_owned())),
+                    x => Ok(1.0 / x),
+                }
+            })
+            .unwrap();
+        assert_eq!(Ok(Some(Value::Float(0.5))), interpreter.eval("inverse 2"));
+        assert_eq!(
+            Ok(Some(Value::Float(0.25))),
+            interpreter.eval("half x => inverse(x) / 2; half 2")
+        );
+        assert_eq!(
+            Err(Error::Eval("Zero has no inverse".to_owned())),
+            interpreter.eval("y = inverse 0")
+        );
+        assert_eq!(None, interpreter.get_var("y"));
+        assert_eq!(3, calls.load(Ordering::Relaxed));
+
+        interpreter.eval("v = 1").unwrap();
+        interpreter.register_fn("v", 0, |_| Ok(0.0)).unwrap_err();
+    }
+
+    #[test]
+    fn stream() {
+        let mut interpreter = Interpreter::new();
+        let src = std::io::Cursor::new("x = 2\n\nsq a => a * a\nsq x\ny\nx + 1");
+        let results: Vec<_> = interpreter.eval_stream(src).collect();
+        assert_eq!(
+            vec![
+                Ok(Some(Value::Int(2))),
+                Ok(None),
+                Ok(None),
+                Ok(Some(Value::Int(4))),
+                Err(Error::UndefinedSymbol("y".to_owned())),
+                Ok(Some(Value::Int(3))),
+            ],
+            results
+        );
+
+        // Lines are evaluated lazily, as they are read
+        let mut results = interpreter.eval_stream(&b"x = 5\nx = 6\n"[..]);
+        results.next().unwrap().unwrap();
+        drop(results);
+        assert_eq!(Some(Value::Int(5)), interpreter.get_var("x"));
+
+        let results: Vec<_> = interpreter.eval_stream(&b"x\n\xff\nx\n"[..]).collect();
+        assert_eq!(2, results.len());
+        assert!(matches!(results[1], Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn threads() {
+        fn shared<T: Send + Sync>(val: T) -> T {
+            val
+        }
+
+        let mut interpreter = Interpreter::new();
+        interpreter.eval("r = 2; area x => pi * x ^ 2").unwrap();
+        let context = Arc::new(shared(interpreter.context().clone()));
+        let results: Vec<_> = (1..=4)
+            .map(|n| {
+                let context = Arc::clone(&context);
+                std::thread::spawn(move || {
+                    let mut interpreter = Interpreter::with_context((*context).clone());
+                    interpreter.eval(&format!("r = r * {}", n)).unwrap();
+                    interpreter.eval("area r").unwrap()
+                })
+            })
+            .map(|thread| thread.join().unwrap())
+            .collect();
+        assert_eq!(Some(Value::Float(std::f64::consts::PI * 64.0)), results[3]);
+
+        let mut fork = interpreter.fork();
+        fork.eval("r = 3").unwrap();
+        assert_eq!(Some(Value::Int(2)), interpreter.get_var("r"));
+        assert_eq!(Some(Value::Int(3)), fork.get_var("r"));
+    }
+
+    #[test]
+    fn timeout() {
+        let mut interpreter = Interpreter::new();
+        let timeout = Duration::from_millis(20);
+        assert_eq!(
+            Ok(Some(Value::Int(55))),
+            interpreter.eval_with_timeout("s = sum i = 1 to 10 of i", timeout)
+        );
+
+        let started = Instant::now();
+        assert_eq!(
+            Err(Error::Timeout(timeout)),
+            interpreter.eval_with_timeout("s = sum i = 1 to 1000000000 of i", timeout)
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(Some(Value::Int(55)), interpreter.get_var("s"));
+
+        // Evaluation without timeout is not stopped
+        interpreter.eval("sum i = 1 to 100000 of i").unwrap();
+    }
+
+    #[test]
+    fn host_var() {
+        let mut interpreter = Interpreter::new();
+        let level = Arc::new(Mutex::new(1.0));
+        let (get, set) = (Arc::clone(&level), Arc::clone(&level));
+        interpreter
+            .bind_var(
+                "level",
+                move || *get.lock().unwrap(),
+                move |val| {
+                    if val < 0.0 {
+                        return Err(Error::Eval("Level cannot be negative".to_owned()));
+                    }
+                    *set.lock().unwrap() = val;
+                    Ok(())
+                },
+            )
+            .unwrap();
+
+        interpreter.eval("twice => level * 2").unwrap();
+        assert_eq!(Ok(Some(Value::Float(2.0))), interpreter.eval("twice"));
+        *level.lock().unwrap() = 4.0;
+        assert_eq!(Ok(Some(Value::Float(8.0))), interpreter.eval("twice"));
+        assert_eq!(
+            Ok(Some(Value::Float(10.0))),
+            interpreter.eval("level = level + 1; level * 2")
+        );
+        assert_eq!(5.0, *level.lock().unwrap());
+
+        interpreter.set_var("level", 3).unwrap();
+        assert_eq!(Some(Value::Float(3.0)), interpreter.get_var("level"));
+        assert_eq!(
+            Err(Error::Eval("Level cannot be negative".to_owned())),
+            interpreter.eval("level = 0 - 1")
+        );
+        interpreter.eval("level = \"high\"").unwrap_err();
+        assert_eq!(3.0, *level.lock().unwrap());
+
+        interpreter.analyze("level = 7");
+        assert_eq!(3.0, *level.lock().unwrap());
+        interpreter
+            .bind_var("twice", || 0.0, |_| Ok(()))
+            .unwrap_err();
+    }
+}
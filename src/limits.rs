@@ -0,0 +1,161 @@
+//! Bounds of evaluated statements, so embedding application is not blocked
+//! by expensive input. Statements are checked with their estimated `Cost`
+//! before they are evaluated, and memory and steps they take are accounted
+//! while they are evaluated.
+
+use crate::cost::cost;
+use crate::parser::Expr;
+use crate::{Error, Result};
+
+/// Unlimited by default
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Limits {
+    /// Operations and calls of single statement in the worst case
+    pub operations: Option<u64>,
+    /// Nesting of calls
+    pub depth: Option<usize>,
+    /// Bytes of strings and lists created by single statement
+    pub memory: Option<usize>,
+    /// Expressions and instructions evaluated by single statement, also
+    /// those which cost estimate cannot foresee, as recursion through
+    /// function arguments
+    pub steps: Option<u64>,
+}
+
+impl Limits {
+    pub fn operations(self, operations: u64) -> Self {
+        Limits {
+            operations: Some(operations),
+            ..self
+        }
+    }
+
+    pub fn depth(self, depth: usize) -> Self {
+        Limits {
+            depth: Some(depth),
+            ..self
+        }
+    }
+
+    pub fn memory(self, bytes: usize) -> Self {
+        Limits {
+            memory: Some(bytes),
+            ..self
+        }
+    }
+
+    pub fn steps(self, steps: u64) -> Self {
+        Limits {
+            steps: Some(steps),
+            ..self
+        }
+    }
+
+    /// Error if statement could exceed any limit, definition exceeds it if
+    /// single call of the function does
+    pub fn check(&self, expr: &Expr) -> Result<()> {
+        let cost = cost(expr);
+        match (self.operations, self.depth) {
+            (Some(limit), _) if cost.operations > limit => Err(Error::Eval(format!(
+                "Statement takes up to {} operations, the limit is {}",
+                cost.operations, limit
+            ))),
+            (_, Some(limit)) if cost.depth > limit => Err(Error::Eval(format!(
+                "Statement nests calls {} deep, the limit is {}",
+                cost.depth, limit
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::{Interpreter, Mode, Value};
+
+    #[test]
+    fn check() {
+        let mut interpreter = Interpreter::new();
+        interpreter.eval("sq x => x * x").unwrap();
+        interpreter.eval("quad x => sq(sq x)").unwrap();
+        let expr = interpreter.parse("quad 2").unwrap();
+
+        Limits::default().check(&expr).unwrap();
+        Limits::default()
+            .operations(6)
+            .depth(2)
+            .check(&expr)
+            .unwrap();
+        let err = Limits::default().operations(4).check(&expr).unwrap_err();
+        assert_eq!(
+            "Statement takes up to 5 operations, the limit is 4",
+            err.to_string()
+        );
+        Limits::default().depth(1).check(&expr).unwrap_err();
+    }
+
+    #[test]
+    fn decimals() {
+        let mut interpreter = Interpreter::builder()
+            .with_mode(Mode::Decimal)
+            .with_limits(Limits::default().memory(1000))
+            .build()
+            .unwrap();
+        interpreter.eval("p x => x ^ 4000").unwrap();
+        interpreter.eval("p 2").unwrap();
+        let err = interpreter.eval("p 10").unwrap_err();
+        assert!(matches!(err, Error::MemoryLimit { .. }), "{:?}", err);
+    }
+
+    #[test]
+    fn memory() {
+        let mut interpreter = Interpreter::builder()
+            .with_limits(Limits::default().memory(1000))
+            .build()
+            .unwrap();
+        interpreter.eval("d s => s + s; s = \"\"").unwrap();
+        interpreter.eval("s = d(d(d(d \"abcdefgh\")))").unwrap();
+        assert_eq!(
+            Some(Value::from("abcdefgh".repeat(16))),
+            interpreter.get_var("s")
+        );
+
+        let err = interpreter.eval("s = d(d(d(d s)))").unwrap_err();
+        assert_eq!(
+            Error::MemoryLimit {
+                used: 1792,
+                limit: 1000
+            },
+            err
+        );
+        assert_eq!(128, interpreter.get_var("s").unwrap().length().unwrap());
+
+        // Every statement has its own budget
+        interpreter.eval("d(d s)").unwrap();
+        interpreter.eval("[s, s, s] + [s]").unwrap();
+    }
+
+    #[test]
+    fn steps() {
+        let mut interpreter = Interpreter::builder()
+            .with_limits(Limits::default().steps(100))
+            .build()
+            .unwrap();
+        interpreter.eval("n = 10").unwrap();
+        interpreter.eval("s = sum i = 1 to n of i").unwrap();
+
+        let err = interpreter
+            .eval("s = sum i = 1 to n * 1000 of i")
+            .unwrap_err();
+        assert_eq!(Error::StepLimit { limit: 100 }, err);
+        assert_eq!(Some(Value::Int(55)), interpreter.get_var("s"));
+
+        // Recursion through function arguments is not estimated
+        interpreter.eval("loop f x => f(f, x)").unwrap();
+        let err = interpreter.eval("loop(loop, 1)").unwrap_err();
+        assert_eq!(Error::StepLimit { limit: 100 }, err);
+        interpreter.eval("s + 1").unwrap();
+    }
+}
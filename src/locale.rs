@@ -0,0 +1,253 @@
+use crate::error::CATALOG;
+use crate::{Error, Result};
+use std::str::FromStr;
+
+/// Language of messages shown to the user. Details carried as text, like
+/// parse errors and warnings, are not translated.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Lang {
+    #[default]
+    En,
+    Pl,
+}
+
+const CATALOG_PL: &[(&str, &str, &str)] = &[
+    (
+        "E001",
+        "nieoczekiwany token",
+        "Tokeny nie tworzą poprawnego wyrażenia, np. operatorowi brakuje \
+         argumentu jak w `1 +`, nawias nie jest zamknięty, albo po pełnym \
+         wyrażeniu coś następuje, jak w `2 + 2 foo`.",
+    ),
+    (
+        "E002",
+        "niepoprawny token",
+        "Fragment kodu nie jest żadnym tokenem, np. nieznany znak jak `$` albo \
+         błędnie zapisana liczba jak `1.2.3`. Wskazuje go znak `^` pod kodem.",
+    ),
+    (
+        "E010",
+        "niezdefiniowany symbol",
+        "Nazwa nie jest zmienną, stałą, argumentem ani funkcją. Zmiennym trzeba \
+         przypisać wartość przed użyciem, a ciała funkcji widzą tylko swoje \
+         argumenty, funkcje i stałe, chyba że zmienne są przechwytywane po \
+         `:set capture`.",
+    ),
+    (
+        "E011",
+        "niezgodna liczba argumentów",
+        "Funkcja jest wywołana z inną liczbą argumentów niż została \
+         zdefiniowana, np. `max(1)`. W postaci `f a b` każdy argument jest \
+         pojedynczym wywołaniem lub operandem, więc wyrażenia trzeba ująć w \
+         nawiasy. Wywołania z mniejszą liczbą argumentów są częściowym \
+         zastosowaniem po `:set partial on`.",
+    ),
+    (
+        "E012",
+        "konflikt nazw",
+        "Nazwa jest już użyta dla innego rodzaju symbolu, np. funkcja jest \
+         definiowana z nazwą zmiennej. Z `--force` symbol jest zastępowany.",
+    ),
+    (
+        "E013",
+        "podwójna definicja",
+        "Symbol jest zdefiniowany dwukrotnie w tym samym pliku, co zwykle jest \
+         błędem kopiowania. Program podany przez `-` zgłasza to jako \
+         ostrzeżenie, albo jako błąd z `--strict`.",
+    ),
+    (
+        "E020",
+        "błąd obliczenia",
+        "Wyrażenie jest poprawne, ale nie można go obliczyć, np. przypisanie \
+         do stałej, albo definicja zepsułaby inne funkcje.",
+    ),
+    (
+        "E021",
+        "limit pamięci",
+        "Napisy i listy utworzone podczas obliczania wyrażenia zajmują więcej \
+         pamięci, niż pozwala aplikacja, np. napis podwajany w zagnieżdżonych \
+         wywołaniach. Obliczenie jest przerwane i nic nie jest przypisane.",
+    ),
+    (
+        "E022",
+        "limit kroków",
+        "Obliczenie wyrażenia wymaga więcej kroków, niż pozwala aplikacja, np. \
+         długa pętla albo funkcja wywołująca samą siebie przez argument. \
+         Obliczenie jest przerwane i nic nie jest przypisane.",
+    ),
+    (
+        "E023",
+        "przekroczony czas",
+        "Obliczenie trwa dłużej, niż pozwala aplikacja. Jest przerwane i nic \
+         nie jest przypisane.",
+    ),
+    (
+        "E030",
+        "błąd wejścia lub wyjścia",
+        "Odczyt wejścia lub zapis wyjścia się nie powiódł, np. wejście nie \
+         jest poprawnym UTF-8. Interpreter kończy pracę z kodem 3.",
+    ),
+    (
+        "E090",
+        "inny błąd",
+        "Błąd niezwiązany z samym językiem, np. nieznane polecenie albo \
+         niepoprawna wartość opcji.",
+    ),
+];
+
+impl Lang {
+    /// Codes, titles and explanations of errors
+    pub fn catalog(self) -> &'static [(&'static str, &'static str, &'static str)] {
+        match self {
+            Lang::En => CATALOG,
+            Lang::Pl => CATALOG_PL,
+        }
+    }
+
+    /// Explanation of given code, `None` if there is no such code
+    pub fn explain(self, code: &str) -> Option<String> {
+        self.catalog()
+            .iter()
+            .find(|(known, _, _)| known.eq_ignore_ascii_case(code))
+            .map(|(code, title, text)| format!("{}: {}\n{}", code, title, text))
+    }
+
+    /// Name of symbol kind as given by `Context::kind`
+    fn kind(self, kind: &'static str) -> &'static str {
+        match (self, kind) {
+            (Lang::Pl, "variable") => "zmienna",
+            (Lang::Pl, "function") => "funkcja",
+            (Lang::Pl, "builtin function") => "funkcja wbudowana",
+            (Lang::Pl, "constant") => "stała",
+            (_, kind) => kind,
+        }
+    }
+
+    /// Message of the error, without its code
+    pub fn message(self, err: &Error) -> String {
+        match (self, err) {
+            (Lang::Pl, Error::UndefinedSymbol(name)) => format!("Niezdefiniowany symbol: {}", name),
+            (
+                Lang::Pl,
+                Error::ArityMismatch {
+                    func,
+                    expected,
+                    got,
+                },
+            ) => format!(
+                "Funkcja `{}` oczekuje {} argumentów, otrzymała {}",
+                func, expected, got
+            ),
+            (Lang::Pl, Error::NameConflict { name, existing }) => format!(
+                "{} jest już zdefiniowane jako {}, użyj `--force`, aby je przedefiniować",
+                name,
+                self.kind(existing)
+            ),
+            (
+                Lang::Pl,
+                Error::DuplicateDefinition {
+                    name,
+                    first,
+                    second,
+                },
+            ) => format!(
+                "{} jest zdefiniowane dwukrotnie, w liniach {} i {}",
+                name, first, second
+            ),
+            (Lang::Pl, Error::MemoryLimit { used, limit }) => format!(
+                "Obliczenie zajmuje ponad {} bajtów, limit wynosi {}",
+                used, limit
+            ),
+            (Lang::Pl, Error::StepLimit { limit }) => {
+                format!("Obliczenie wymaga więcej kroków niż limit {}", limit)
+            }
+            (Lang::Pl, Error::Timeout(timeout)) => {
+                format!("Obliczenie trwa dłużej niż {:?}", timeout)
+            }
+            _ => err.to_string(),
+        }
+    }
+
+    /// Error with its code, as shown to the user
+    pub fn error(self, err: &Error) -> String {
+        let label = match self {
+            Lang::En => "Error",
+            Lang::Pl => "Błąd",
+        };
+        format!("{}[{}]: {}", label, err.code(), self.message(err))
+    }
+
+    pub fn warning(self, warning: &str) -> String {
+        match self {
+            Lang::En => format!("Warning: {}", warning),
+            Lang::Pl => format!("Ostrzeżenie: {}", warning),
+        }
+    }
+
+    pub fn mismatch(self, line: &str, expected: &str) -> String {
+        match self {
+            Lang::En => format!("Mismatch for `{}`: expected `{}`", line, expected),
+            Lang::Pl => format!("Niezgodność dla `{}`: oczekiwano `{}`", line, expected),
+        }
+    }
+
+    /// Summary of `--check` run
+    pub fn mismatches(self, count: usize) -> String {
+        match self {
+            Lang::En => format!("{} output(s) did not match the transcript", count),
+            Lang::Pl => format!("Wyniki niezgodne z zapisem sesji: {}", count),
+        }
+    }
+}
+
+impl FromStr for Lang {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "en" => Ok(Lang::En),
+            "pl" => Ok(Lang::Pl),
+            _ => Err(format!("Unknown language: {}, expected `en` or `pl`", s).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn catalogs() {
+        assert!(Lang::En
+            .explain("e010")
+            .unwrap()
+            .starts_with("E010: undefined symbol\n"));
+        assert_eq!(None, Lang::En.explain("E999"));
+
+        let codes = |lang: Lang| -> Vec<_> { lang.catalog().iter().map(|e| e.0).collect() };
+        assert_eq!(codes(Lang::En), codes(Lang::Pl));
+        assert!(Lang::Pl
+            .explain("E010")
+            .unwrap()
+            .starts_with("E010: niezdefiniowany symbol\n"));
+    }
+
+    #[test]
+    fn messages() {
+        let conflict = Error::NameConflict {
+            name: "a".to_owned(),
+            existing: "variable",
+        };
+        assert_eq!(conflict.to_string(), Lang::En.message(&conflict));
+        assert_eq!(
+            "Błąd[E012]: a jest już zdefiniowane jako zmienna, użyj `--force`, aby je przedefiniować",
+            Lang::Pl.error(&conflict)
+        );
+
+        let other = Error::Other("Unknown command: :nope".to_owned());
+        assert_eq!("Błąd[E090]: Unknown command: :nope", Lang::Pl.error(&other));
+        assert_eq!(Ok(Lang::Pl), "pl".parse());
+        assert!("de".parse::<Lang>().is_err());
+    }
+}
@@ -0,0 +1,527 @@
+//! Language server for editors, as `toy-interpreter --lsp`. Messages are
+//! JSON-RPC over stdin and stdout, every one after `Content-Length` header.
+//! Documents are synchronized as whole text, and every change publishes
+//! diagnostics given by `analysis`. Hover and completion evaluate the
+//! document up to the cursor, so they show values the script has there -
+//! evaluation of every line is stopped after `EVAL_TIMEOUT`.
+
+use crate::analysis::Usage;
+use crate::check::{Diagnostic, Problem};
+use crate::json::{self, Json};
+use crate::sink::json_string;
+use crate::{Context, Error, Interpreter, Result};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::time::Duration;
+
+/// Time single line of document is evaluated for hover or completion
+pub const EVAL_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Error code of JSON-RPC for unknown request
+const METHOD_NOT_FOUND: i32 = -32601;
+
+const CAPABILITIES: &str =
+    "{\"capabilities\":{\"textDocumentSync\":1,\"hoverProvider\":true,\"completionProvider\":{}},\
+     \"serverInfo\":{\"name\":\"toy-interpreter\"}}";
+
+/// Open documents, evaluated from copy of the same context
+pub struct Server {
+    template: Context,
+    documents: HashMap<String, String>,
+    exited: bool,
+}
+
+/// Character of LSP position counts UTF-16 units
+fn units(line: &str, offset: usize) -> usize {
+    line.get(..offset).unwrap_or(line).encode_utf16().count()
+}
+
+/// Byte offset of LSP character in the line
+fn offset(line: &str, character: usize) -> usize {
+    let mut units = 0;
+    for (offset, c) in line.char_indices() {
+        if units >= character {
+            return offset;
+        }
+        units += c.len_utf16();
+    }
+    line.len()
+}
+
+fn range(line: usize, start: usize, end: usize) -> String {
+    format!(
+        "{{\"start\":{{\"line\":{},\"character\":{}}},\"end\":{{\"line\":{},\"character\":{}}}}}",
+        line, start, line, end
+    )
+}
+
+fn diagnostic(src: &str, diagnostic: &Diagnostic) -> String {
+    let line = src.lines().nth(diagnostic.line - 1).unwrap_or_default();
+    let (severity, code, message, span) = match &diagnostic.problem {
+        Problem::Error(err) => (1, json_string(err.code()), err.to_string(), err.span()),
+        Problem::Warning(warning) => (2, "null".to_owned(), warning.clone(), None),
+    };
+    // Problems without span are reported on whole line
+    let (start, end) = span.map_or((0, line.len()), |span| (span.start, span.end));
+    format!(
+        "{{\"range\":{},\"severity\":{},\"code\":{},\"source\":\"toy-interpreter\",\"message\":{}}}",
+        range(
+            diagnostic.line - 1,
+            units(line, start),
+            units(line, end.max(start))
+        ),
+        severity,
+        code,
+        json_string(&message)
+    )
+}
+
+fn arguments(arity: usize) -> String {
+    match arity {
+        1 => "1 argument".to_owned(),
+        _ => format!("{} arguments", arity),
+    }
+}
+
+/// Id of request, echoed in the response
+fn id(id: &Json) -> String {
+    match id {
+        Json::Number(id) => id.to_string(),
+        Json::Str(id) => json_string(id),
+        _ => "null".to_owned(),
+    }
+}
+
+/// Position of request, as (uri, line, character)
+fn position(params: &Json) -> Option<(&str, usize, usize)> {
+    let uri = match params.field("textDocument")?.field("uri")? {
+        Json::Str(uri) => uri,
+        _ => return None,
+    };
+    let position = params.field("position")?;
+    match (position.field("line")?, position.field("character")?) {
+        (Json::Number(line), Json::Number(character)) => {
+            Some((uri, *line as usize, *character as usize))
+        }
+        _ => None,
+    }
+}
+
+impl Server {
+    pub fn new(template: Context) -> Self {
+        Server {
+            template,
+            documents: HashMap::new(),
+            exited: false,
+        }
+    }
+
+    /// Server got `exit`, and no more messages should be handled
+    pub fn exited(&self) -> bool {
+        self.exited
+    }
+
+    /// Messages to send in response to the message, responses and
+    /// notifications
+    pub fn handle(&mut self, message: &str) -> Vec<String> {
+        let message = match json::parse(message) {
+            Ok(message) => message,
+            Err(err) => {
+                let error = format!(
+                    "{{\"code\":-32700,\"message\":{}}}",
+                    json_string(&err.to_string())
+                );
+                return vec![format!(
+                    "{{\"jsonrpc\":\"2.0\",\"id\":null,\"error\":{}}}",
+                    error
+                )];
+            }
+        };
+        let method = match message.field("method") {
+            Some(Json::Str(method)) => method.as_str(),
+            _ => "",
+        };
+        let params = message.field("params").unwrap_or(&Json::Null);
+
+        let result = match method {
+            "initialize" => Some(CAPABILITIES.to_owned()),
+            "shutdown" => Some("null".to_owned()),
+            "textDocument/hover" => Some(self.hover(params)),
+            "textDocument/completion" => Some(self.completion(params)),
+            _ => None,
+        };
+        let notification = match method {
+            "textDocument/didOpen" => self.open(params),
+            "textDocument/didChange" => self.change(params),
+            "textDocument/didClose" => {
+                if let Some(Json::Str(uri)) = params
+                    .field("textDocument")
+                    .and_then(|doc| doc.field("uri"))
+                {
+                    self.documents.remove(uri);
+                }
+                None
+            }
+            "exit" => {
+                self.exited = true;
+                None
+            }
+            _ => None,
+        };
+
+        // Notifications have no id, and are never answered
+        let mut messages: Vec<_> = notification.into_iter().collect();
+        if let Some(request) = message.field("id") {
+            let body = match result {
+                Some(result) => format!("\"result\":{}", result),
+                None => format!(
+                    "\"error\":{{\"code\":{},\"message\":{}}}",
+                    METHOD_NOT_FOUND,
+                    json_string(&format!("Unknown method {}", method))
+                ),
+            };
+            messages.push(format!(
+                "{{\"jsonrpc\":\"2.0\",\"id\":{},{}}}",
+                id(request),
+                body
+            ));
+        }
+        messages
+    }
+
+    fn open(&mut self, params: &Json) -> Option<String> {
+        let document = params.field("textDocument")?;
+        match (document.field("uri")?, document.field("text")?) {
+            (Json::Str(uri), Json::Str(text)) => self.update(uri, text.clone()),
+            _ => None,
+        }
+    }
+
+    /// Only full text of document is synchronized, so the last change has
+    /// all of it
+    fn change(&mut self, params: &Json) -> Option<String> {
+        let uri = match params.field("textDocument")?.field("uri")? {
+            Json::Str(uri) => uri,
+            _ => return None,
+        };
+        let text = match params.field("contentChanges")? {
+            Json::Array(changes) => match changes.last()?.field("text")? {
+                Json::Str(text) => text.clone(),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        self.update(uri, text)
+    }
+
+    /// Keeps the text, giving notification with its diagnostics
+    fn update(&mut self, uri: &str, text: String) -> Option<String> {
+        let analysis = Interpreter::with_context(self.template.clone()).analyze(&text);
+        let diagnostics: Vec<_> = analysis
+            .diagnostics
+            .iter()
+            .map(|found| diagnostic(&text, found))
+            .collect();
+        self.documents.insert(uri.to_owned(), text);
+        Some(format!(
+            "{{\"jsonrpc\":\"2.0\",\"method\":\"textDocument/publishDiagnostics\",\"params\":{{\"uri\":{},\"diagnostics\":[{}]}}}}",
+            json_string(uri),
+            diagnostics.join(",")
+        ))
+    }
+
+    /// Interpreter after the first `lines` lines of the document, failing
+    /// lines and commands are skipped
+    fn evaluated(&self, src: &str, lines: usize) -> Interpreter {
+        let mut interpreter = Interpreter::with_context(self.template.clone());
+        for line in src.lines().take(lines) {
+            if !line.trim_start().starts_with(':') {
+                interpreter.eval_with_timeout(line, EVAL_TIMEOUT).ok();
+            }
+        }
+        interpreter.context_mut().take_warnings();
+        interpreter
+    }
+
+    /// Description of symbol under the cursor, with value it has after the
+    /// line is evaluated
+    fn hover(&self, params: &Json) -> String {
+        let describe = || -> Option<String> {
+            let (uri, line, character) = position(params)?;
+            let src = self.documents.get(uri)?;
+            let text = src.lines().nth(line)?;
+            let cursor = offset(text, character);
+
+            let analysis = Interpreter::with_context(self.template.clone()).analyze(src);
+            let usage: &Usage = analysis.usages.iter().find(|usage| {
+                usage.line == line + 1 && usage.span.start <= cursor && cursor <= usage.span.end
+            })?;
+            let kind = usage.kind?;
+
+            let interpreter = self.evaluated(src, line + 1);
+            let context = interpreter.context();
+            let name = &usage.name;
+            let description = match kind {
+                "argument" | "local" => format!("{} {}", kind, name),
+                "function" | "builtin function" => {
+                    let arity = context.get_arity(name)?;
+                    let mut description = format!("{} {} of {}", kind, name, arguments(arity));
+                    if let Some(source) = context.definition(name) {
+                        description = format!("{}\n\n{}", description, source);
+                    }
+                    description
+                }
+                _ => match context.get_var(name) {
+                    Some(val) => format!("{} {} = {}", kind, name, context.rounded(val)),
+                    None => format!("{} {}", kind, name),
+                },
+            };
+            Some(format!(
+                "{{\"contents\":{{\"kind\":\"plaintext\",\"value\":{}}},\"range\":{}}}",
+                json_string(&description),
+                range(
+                    line,
+                    units(text, usage.span.start),
+                    units(text, usage.span.end)
+                )
+            ))
+        };
+        describe().unwrap_or_else(|| "null".to_owned())
+    }
+
+    /// Symbols starting with the name before the cursor, defined by the
+    /// preceding lines
+    fn completion(&self, params: &Json) -> String {
+        let items = || -> Option<Vec<String>> {
+            let (uri, line, character) = position(params)?;
+            let src = self.documents.get(uri)?;
+            let text = src.lines().nth(line).unwrap_or_default();
+            let before = &text[..offset(text, character)];
+            let prefix = before
+                .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .map_or(before, |idx| &before[idx + 1..]);
+
+            let interpreter = self.evaluated(src, line);
+            let context = interpreter.context();
+            Some(
+                context
+                    .complete(prefix)
+                    .iter()
+                    .map(|name| {
+                        // Kinds of LSP completion items
+                        let (kind, detail) = match context.get_arity(name) {
+                            Some(arity) => (3, arguments(arity)),
+                            None => (6, context.kind(name).unwrap_or_default().to_owned()),
+                        };
+                        format!(
+                            "{{\"label\":{},\"kind\":{},\"detail\":{}}}",
+                            json_string(name),
+                            kind,
+                            json_string(&detail)
+                        )
+                    })
+                    .collect(),
+            )
+        };
+        format!("[{}]", items().unwrap_or_default().join(","))
+    }
+}
+
+/// Body of the next message, `None` at the end of input
+pub fn read_message(reader: &mut impl BufRead) -> Result<Option<String>> {
+    let io = |err: std::io::Error| Error::Io(format!("Cannot read message: {}", err));
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).map_err(io)? == 0 {
+            return Ok(None);
+        }
+        if header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                let value = value.trim();
+                length = Some(
+                    value
+                        .parse()
+                        .map_err(|_| Error::Io(format!("Invalid Content-Length: {}", value)))?,
+                );
+            }
+        }
+    }
+
+    let length = length.ok_or_else(|| Error::Io("Message without Content-Length".to_owned()))?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).map_err(io)?;
+    String::from_utf8(body)
+        .map(Some)
+        .map_err(|_| Error::Io("Message is not UTF-8".to_owned()))
+}
+
+pub fn write_message(writer: &mut impl Write, message: &str) -> Result<()> {
+    write!(
+        writer,
+        "Content-Length: {}\r\n\r\n{}",
+        message.len(),
+        message
+    )
+    .and_then(|_| writer.flush())
+    .map_err(|err| Error::Io(format!("Cannot write message: {}", err)))
+}
+
+/// Handles messages until `exit` or the end of input
+pub fn run(template: Context, reader: &mut impl BufRead, writer: &mut impl Write) -> Result<()> {
+    let mut server = Server::new(template);
+    while !server.exited() {
+        let message = match read_message(reader)? {
+            Some(message) => message,
+            None => break,
+        };
+        for response in server.handle(&message) {
+            write_message(writer, &response)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use std::io::Cursor;
+
+    fn open(server: &mut Server, text: &str) -> String {
+        let message = format!(
+            "{{\"jsonrpc\":\"2.0\",\"method\":\"textDocument/didOpen\",\"params\":{{\"textDocument\":{{\"uri\":\"file:///a.calc\",\"languageId\":\"calc\",\"version\":1,\"text\":{}}}}}}}",
+            json_string(text)
+        );
+        server.handle(&message).remove(0)
+    }
+
+    fn request(server: &mut Server, method: &str, line: usize, character: usize) -> String {
+        let message = format!(
+            "{{\"jsonrpc\":\"2.0\",\"id\":7,\"method\":{},\"params\":{{\"textDocument\":{{\"uri\":\"file:///a.calc\"}},\"position\":{{\"line\":{},\"character\":{}}}}}}}",
+            json_string(method),
+            line,
+            character
+        );
+        server.handle(&message).remove(0)
+    }
+
+    #[test]
+    fn diagnostics() {
+        let mut server = Server::new(Context::new());
+        let published = open(&mut server, "x = 1\n\"ż\" + (1 +\nsq x => x * y");
+        assert!(published.starts_with(
+            "{\"jsonrpc\":\"2.0\",\"method\":\"textDocument/publishDiagnostics\",\"params\":{\"uri\":\"file:///a.calc\""
+        ));
+        let diagnostics = json::parse(&published).unwrap();
+        let diagnostics = match diagnostics.field("params").unwrap().field("diagnostics") {
+            Some(Json::Array(diagnostics)) => diagnostics.clone(),
+            _ => panic!("No diagnostics in {}", published),
+        };
+        assert_eq!(2, diagnostics.len());
+
+        // Columns count UTF-16 units
+        let start = diagnostics[0]
+            .field("range")
+            .unwrap()
+            .field("start")
+            .unwrap();
+        assert_eq!(Some(&Json::Number(1.0)), start.field("line"));
+        assert_eq!(Some(&Json::Number(10.0)), start.field("character"));
+        assert_eq!(Some(&Json::Number(1.0)), diagnostics[0].field("severity"));
+        let end = diagnostics[1].field("range").unwrap().field("end").unwrap();
+        assert_eq!(Some(&Json::Number(13.0)), end.field("character"));
+
+        let change = "{\"jsonrpc\":\"2.0\",\"method\":\"textDocument/didChange\",\"params\":{\"textDocument\":{\"uri\":\"file:///a.calc\",\"version\":2},\"contentChanges\":[{\"text\":\"x = 2\"}]}}";
+        assert!(server.handle(change)[0].ends_with("\"diagnostics\":[]}}"));
+    }
+
+    #[test]
+    fn hover() {
+        let mut server = Server::new(Context::new());
+        open(
+            &mut server,
+            "x = 2\nsq a => a * a\nx = sq x\nsq x + sqrt(x)\ny",
+        );
+
+        let contents = |response: String| {
+            let response = json::parse(&response).unwrap();
+            match response.field("result").unwrap().field("contents") {
+                Some(contents) => match contents.field("value") {
+                    Some(Json::Str(value)) => Some(value.clone()),
+                    _ => None,
+                },
+                None => None,
+            }
+        };
+        let hover = |server: &mut Server, line, character| {
+            contents(request(server, "textDocument/hover", line, character))
+        };
+        assert_eq!(Some("variable x = 2".to_owned()), hover(&mut server, 0, 0));
+        assert_eq!(Some("variable x = 4".to_owned()), hover(&mut server, 2, 7));
+        assert_eq!(Some("argument a".to_owned()), hover(&mut server, 1, 9));
+        assert_eq!(
+            Some("function sq of 1 argument\n\nsq a => a * a".to_owned()),
+            hover(&mut server, 3, 1)
+        );
+        assert_eq!(
+            Some("builtin function sqrt of 1 argument".to_owned()),
+            hover(&mut server, 3, 9)
+        );
+        assert_eq!(
+            "{\"jsonrpc\":\"2.0\",\"id\":7,\"result\":null}",
+            request(&mut server, "textDocument/hover", 4, 0)
+        );
+    }
+
+    #[test]
+    fn completion() {
+        let mut server = Server::new(Context::new());
+        open(&mut server, "square = 2\nsq x => x * x\n1 + sq");
+
+        let response = request(&mut server, "textDocument/completion", 2, 6);
+        assert_eq!(
+            "{\"jsonrpc\":\"2.0\",\"id\":7,\"result\":[\
+             {\"label\":\"sq\",\"kind\":3,\"detail\":\"1 argument\"},\
+             {\"label\":\"sqrt\",\"kind\":3,\"detail\":\"1 argument\"},\
+             {\"label\":\"square\",\"kind\":6,\"detail\":\"variable\"}]}",
+            response
+        );
+    }
+
+    #[test]
+    fn session() {
+        let messages = [
+            "{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"initialize\",\"params\":{}}",
+            "{\"jsonrpc\":\"2.0\",\"method\":\"initialized\",\"params\":{}}",
+            "{\"jsonrpc\":\"2.0\",\"id\":\"a\",\"method\":\"workspace/symbol\",\"params\":{}}",
+            "{\"jsonrpc\":\"2.0\",\"id\":2,\"method\":\"shutdown\"}",
+            "{\"jsonrpc\":\"2.0\",\"method\":\"exit\"}",
+            "{\"jsonrpc\":\"2.0\",\"id\":3,\"method\":\"shutdown\"}",
+        ];
+        let mut input = vec![];
+        for message in &messages {
+            write_message(&mut input, message).unwrap();
+        }
+        let mut output = vec![];
+        run(Context::new(), &mut Cursor::new(input), &mut output).unwrap();
+
+        let mut output = Cursor::new(output);
+        let initialized = read_message(&mut output).unwrap().unwrap();
+        assert!(initialized.contains("\"hoverProvider\":true"));
+        assert_eq!(
+            Some("{\"jsonrpc\":\"2.0\",\"id\":\"a\",\"error\":{\"code\":-32601,\"message\":\"Unknown method workspace/symbol\"}}".to_owned()),
+            read_message(&mut output).unwrap()
+        );
+        assert_eq!(
+            Some("{\"jsonrpc\":\"2.0\",\"id\":2,\"result\":null}".to_owned()),
+            read_message(&mut output).unwrap()
+        );
+        assert_eq!(None, read_message(&mut output).unwrap());
+
+        read_message(&mut Cursor::new("Content-Type: x\r\n\r\n{}")).unwrap_err();
+    }
+}
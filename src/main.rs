@@ -1,31 +1,547 @@
-mod combinators;
-mod context;
-mod lexer;
-mod parser;
+use std::env;
+use std::fs::File;
+use std::io::{stdin, stdout, BufReader, IsTerminal, Read};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process;
 
-use std;
-use std::io::{stdin, BufRead};
+use interpreter::autosave::{self, Autosave};
+use interpreter::editor::{self, History, LineEditor};
+use interpreter::repl::{LineReader, Repl};
+use interpreter::sink::{Colored, Event, JsonLines, OutputSink, Terminal};
+use interpreter::trace::{Level, Stderr};
+use interpreter::transcript::{self, Entry, Recorder};
+use interpreter::{
+    annotate, check, format_error, formatter, http, lsp, md, minify, script, server, strip_shebang,
+    tutorial, Error, Interpreter, Lang, Mode, Result,
+};
 
-type Result<T> = std::result::Result<T, String>;
+#[derive(Default)]
+struct Args {
+    record: Option<String>,
+    replay: Option<String>,
+    check: bool,
+    autosave: Option<PathBuf>,
+    config: Option<PathBuf>,
+    history: Option<PathBuf>,
+    force: bool,
+    strict: bool,    // Duplicate definitions in program or script are errors
+    selfcheck: bool, // Printed statements are checked to parse back
+    no_color: bool,
+    json: bool, // Events are written as JSON lines
+    precision: Option<usize>,
+    mode: Mode,
+    lang: Lang,
+    program: bool, // Whole program is read from stdin
+    script: Option<PathBuf>,
+    serve: Option<String>, // Address sessions are served on
+    http: Option<String>,  // Address of JSON evaluation endpoint
+    lsp: bool,             // Language server over stdin and stdout
+    dump_ast: bool,        // Script is parsed, not evaluated
+    exprs: Vec<String>,    // Expressions of `-e` flags, evaluated in order
+    verbose: usize,        // Number of `v` in `-v` flags
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut result = Args::default();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--record" => {
+                    result.record = Some(args.next().ok_or("Missing `--record` file")?);
+                }
+                "--replay" => {
+                    result.replay = Some(args.next().ok_or("Missing `--replay` file")?);
+                }
+                "-" => result.program = true,
+                "-e" => result
+                    .exprs
+                    .push(args.next().ok_or("Missing `-e` expression")?),
+                "-v" | "-vv" => result.verbose += arg.len() - 1,
+                "--check" => result.check = true,
+                "--force" => result.force = true,
+                "--strict" => result.strict = true,
+                "--selfcheck" => result.selfcheck = true,
+                "--no-color" => result.no_color = true,
+                "--json" => result.json = true,
+                "--lsp" => result.lsp = true,
+                "--dump-ast" => result.dump_ast = true,
+                "--autosave" => {
+                    let path = args.next().ok_or("Missing `--autosave` file")?;
+                    result.autosave = Some(path.into());
+                }
+                "--history" => {
+                    let path = args.next().ok_or("Missing `--history` file")?;
+                    result.history = Some(path.into());
+                }
+                "--precision" => {
+                    let digits = args.next().ok_or("Missing `--precision` digits")?;
+                    let digits = digits
+                        .parse()
+                        .map_err(|err| format!("Invalid precision: {}, {}", digits, err))?;
+                    result.precision = Some(digits);
+                }
+                "--mode" => {
+                    result.mode = args.next().ok_or("Missing `--mode` mode")?.parse()?;
+                }
+                "--lang" => {
+                    result.lang = args.next().ok_or("Missing `--lang` language")?.parse()?;
+                }
+                "--serve" => {
+                    result.serve = Some(args.next().ok_or("Missing `--serve` address")?);
+                }
+                "--http" => {
+                    result.http = Some(args.next().ok_or("Missing `--http` address")?);
+                }
+                "--config" => {
+                    let path = args.next().ok_or("Missing `--config` file")?;
+                    result.config = Some(path.into());
+                }
+                _ if !arg.starts_with('-') && result.script.is_none() => {
+                    result.script = Some(arg.into());
+                }
+                _ => return Err(format!("Unknown argument: {}", arg).into()),
+            }
+        }
+
+        if result.script.is_some() && (result.program || result.replay.is_some()) {
+            return Err("Script cannot be used with `-` or `--replay`".into());
+        }
+
+        let other_input = result.script.is_some() || result.program || result.replay.is_some();
+        if !result.exprs.is_empty() && other_input {
+            return Err("`-e` cannot be used with script, `-` or `--replay`".into());
+        }
+
+        if result.json && (result.script.is_some() || !result.exprs.is_empty()) {
+            return Err("`--json` cannot be used with script or `-e`".into());
+        }
+
+        if result.serve.is_some() && (other_input || !result.exprs.is_empty() || result.json) {
+            return Err(
+                "`--serve` cannot be used with script, `-`, `-e`, `--replay` or `--json`".into(),
+            );
+        }
+
+        if result.http.is_some()
+            && (other_input || !result.exprs.is_empty() || result.json || result.serve.is_some())
+        {
+            return Err(
+                "`--http` cannot be used with script, `-`, `-e`, `--replay`, `--json` or `--serve`"
+                    .into(),
+            );
+        }
+
+        if result.lsp
+            && (other_input
+                || !result.exprs.is_empty()
+                || result.json
+                || result.serve.is_some()
+                || result.http.is_some())
+        {
+            return Err(
+                "`--lsp` cannot be used with script, `-`, `-e`, `--replay`, `--json`, `--serve` or `--http`"
+                    .into(),
+            );
+        }
+
+        if result.dump_ast && result.script.is_none() {
+            return Err("`--dump-ast` requires script".into());
+        }
+
+        if result.program && result.replay.is_some() {
+            return Err("`-` cannot be used with `--replay`".into());
+        }
+
+        if result.check && result.replay.is_none() {
+            return Err("`--check` requires `--replay`".into());
+        }
+
+        if result.strict && !result.program && result.script.is_none() {
+            return Err("`--strict` requires `-` or script".into());
+        }
+
+        Ok(result)
+    }
+}
+
+/// Input line with output expected from it, if it is known
+type Input = (String, Option<String>);
+
+fn replay_inputs(path: &str) -> Result<Vec<Input>> {
+    let file = File::open(path).map_err(|err| format!("Cannot open {}: {}", path, err))?;
+    let mut inputs: Vec<Input> = vec![];
+
+    for entry in transcript::read(BufReader::new(file))? {
+        match entry {
+            Entry::Input(line) => inputs.push((line, None)),
+            Entry::Output(output) => match inputs.last_mut() {
+                Some((_, Some(expected))) => *expected = format!("{}\n{}", expected, output),
+                Some((_, expected)) => *expected = Some(output),
+                None => (),
+            },
+        }
+    }
+
+    Ok(inputs)
+}
+
+fn default_config() -> PathBuf {
+    env::var_os("HOME")
+        .map(PathBuf::from)
+        .unwrap_or_default()
+        .join(".toy-interpreter.conf")
+}
+
+fn start(args: Args) -> Result<usize> {
+    let mut builder = Interpreter::builder()
+        .force(args.force)
+        .selfcheck(args.selfcheck)
+        .with_mode(args.mode);
+    if let Some(digits) = args.precision {
+        builder = builder.with_precision(digits);
+    }
+    if let Some(level) = Level::verbosity(args.verbose) {
+        builder = builder.with_tracer(Stderr(level));
+    }
+    let mut interpreter = builder.build()?;
+
+    if let Some(addr) = args.serve {
+        let listener = TcpListener::bind(&addr)
+            .map_err(|err| format!("Cannot listen on {}: {}", addr, err))?;
+        eprintln!("Serving sessions on {}", addr);
+        server::serve(listener, interpreter.context().clone(), args.lang)?;
+        return Ok(0);
+    }
+    if let Some(addr) = args.http {
+        let listener = TcpListener::bind(&addr)
+            .map_err(|err| format!("Cannot listen on {}: {}", addr, err))?;
+        eprintln!("Serving POST /eval on {}", addr);
+        let sessions = http::Sessions::new(interpreter.context().clone(), http::DEFAULT_TTL);
+        http::serve(listener, sessions)?;
+        return Ok(0);
+    }
+    if args.lsp {
+        lsp::run(
+            interpreter.context().clone(),
+            &mut stdin().lock(),
+            &mut stdout().lock(),
+        )?;
+        return Ok(0);
+    }
+    if let Some(path) = args.script {
+        return run_script(
+            &mut interpreter,
+            &path,
+            args.dump_ast,
+            args.strict,
+            args.lang,
+        );
+    }
+    if !args.exprs.is_empty() {
+        let exprs = args.exprs.iter().map(String::as_str);
+        let failure = script::run_lines(&mut interpreter, exprs, &mut Terminal(args.lang))?;
+        if let Some(failure) = failure {
+            eprintln!(
+                "{}",
+                format_error(&failure.error, &failure.source, args.lang)
+            );
+            process::exit(1);
+        }
+        return Ok(0);
+    }
+
+    let mut repl = Repl::new(interpreter);
+    repl.set_check(args.check);
+    repl.set_lang(args.lang);
+    if let Some(path) = args.record {
+        let file = File::create(&path).map_err(|err| format!("Cannot create {}: {}", path, err))?;
+        repl.set_recorder(Recorder::new(Box::new(file)));
+    }
+
+    // Colors would only clutter piped output
+    let color = !args.no_color && stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+    let mut output: Box<dyn OutputSink> = if args.json {
+        Box::new(JsonLines(stdout()))
+    } else if color {
+        Box::new(Colored(args.lang))
+    } else {
+        Box::new(Terminal(args.lang))
+    };
+
+    if let Some(path) = args.replay {
+        for (line, expected) in replay_inputs(&path)? {
+            repl.line(&line, expected, &mut output)?;
+        }
+    } else if args.program {
+        let mut src = String::new();
+        stdin()
+            .read_to_string(&mut src)
+            .map_err(|err| Error::Io(format!("Cannot read program: {}", err)))?;
+        let src = strip_shebang(&src);
+
+        if !report_duplicates(src, args.strict, &mut output)? {
+            process::exit(1);
+        }
+
+        for line in src.lines() {
+            repl.line(line, None, &mut output)?;
+        }
+    } else {
+        // Only explicitly given config has to exist
+        match args.config {
+            Some(config) => repl.configure(&config)?,
+            None if default_config().exists() => repl.configure(&default_config())?,
+            None => (),
+        }
+
+        // Piped input is not a session to recover or to remember, and
+        // prompt would only clutter piped output
+        let result = if stdin().is_terminal() {
+            let path = args.autosave.unwrap_or_else(autosave::default_path);
+            if path.exists() {
+                output.emit(Event::Message(format!(
+                    "Recovery file {} found, type `:recover` to restore previous session",
+                    path.display()
+                )))?;
+            }
+            repl.set_autosave(Autosave::new(path));
+            let history = History::load(args.history.unwrap_or_else(editor::default_history_path));
 
-use context::Context;
-use lexer::{Operator, Token};
+            // Lines are edited only for output shown in terminal
+            if args.json {
+                repl.set_history(history);
+                repl.run(&mut LineReader::new(stdin().lock(), false), &mut output)
+            } else {
+                repl.run(&mut LineEditor::new(history), &mut output)
+            }
+        } else {
+            repl.run(&mut LineReader::new(stdin().lock(), false), &mut output)
+        };
+        if let Err(err) = result {
+            // Definitions are still saved, so session can be recovered
+            repl.finish(&mut output)?;
+            return Err(err);
+        }
+    }
 
-fn run(line: &str, context: &mut Context) -> Result<Option<f32>> {
-    let tokens: Result<Vec<_>> = lexer::tokenize(line).collect();
-    let tokens = tokens?.into_iter();
-    Ok(context.parse(tokens)?.evaluate(context, &[]))
+    repl.finish(&mut output)
+}
+
+/// Reports functions and constants defined twice in the program, as
+/// warnings or as errors with `--strict`. `false` if the program should not
+/// run.
+fn report_duplicates(src: &str, strict: bool, output: &mut dyn OutputSink) -> Result<bool> {
+    let duplicates = check::duplicates(src);
+    for error in &duplicates {
+        output.emit(if strict {
+            Event::Error {
+                error: error.clone(),
+                line: String::new(),
+            }
+        } else {
+            Event::Warning(error.to_string())
+        })?;
+    }
+    Ok(!strict || duplicates.is_empty())
+}
+
+/// `script.calc` - evaluates the file, exiting with status 1 on the first
+/// failed line, or on duplicate definition with `--strict`. With
+/// `--dump-ast` trees of statements are written instead.
+fn run_script(
+    interpreter: &mut Interpreter,
+    path: &Path,
+    dump_ast: bool,
+    strict: bool,
+    lang: Lang,
+) -> Result<usize> {
+    let src = std::fs::read_to_string(path)
+        .map_err(|err| Error::Io(format!("Cannot read {}: {}", path.display(), err)))?;
+
+    let failure = if dump_ast {
+        script::dump_ast(interpreter, &src, &mut stdout().lock())?
+    } else {
+        let mut output = Terminal(lang);
+        if !report_duplicates(strip_shebang(&src), strict, &mut output)? {
+            process::exit(1);
+        }
+        script::run(interpreter, &src, &mut output)?
+    };
+    if let Some(failure) = failure {
+        eprintln!(
+            "{}:{}: {}",
+            path.display(),
+            failure.line,
+            format_error(&failure.error, &failure.source, lang)
+        );
+        process::exit(1);
+    }
+    Ok(0)
+}
+
+/// `minify [--rename] file` - prints shortened equivalent of the file
+fn minify(args: impl Iterator<Item = String>) -> Result<usize> {
+    let mut rename = false;
+    let mut path = None;
+
+    for arg in args {
+        match arg.as_str() {
+            "--rename" => rename = true,
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(format!("Unknown argument: {}", arg).into()),
+        }
+    }
+
+    let path = path.ok_or("Missing file to minify")?;
+    let src =
+        std::fs::read_to_string(&path).map_err(|err| format!("Cannot read {}: {}", path, err))?;
+    println!("{}", minify::minify(&src, rename)?);
+    Ok(0)
+}
+
+/// `fmt [--write] file` - prints the file in canonical layout, or puts it
+/// into the file
+fn fmt(args: impl Iterator<Item = String>) -> Result<usize> {
+    let mut write = false;
+    let mut path = None;
+
+    for arg in args {
+        match arg.as_str() {
+            "--write" => write = true,
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(format!("Unknown argument: {}", arg).into()),
+        }
+    }
+
+    let path = path.ok_or("Missing file to format")?;
+    let src =
+        std::fs::read_to_string(&path).map_err(|err| format!("Cannot read {}: {}", path, err))?;
+    let formatted = formatter::format(&src)?;
+
+    if write {
+        std::fs::write(&path, formatted)
+            .map_err(|err| format!("Cannot write {}: {}", path, err))?;
+    } else {
+        print!("{}", formatted);
+    }
+    Ok(0)
+}
+
+/// `annotate file` - prints the file with results appended to its lines
+fn annotate(mut args: impl Iterator<Item = String>) -> Result<usize> {
+    let path = args.next().ok_or("Missing file to annotate")?;
+    if let Some(arg) = args.next() {
+        return Err(format!("Unknown argument: {}", arg).into());
+    }
+
+    let src =
+        std::fs::read_to_string(&path).map_err(|err| format!("Cannot read {}: {}", path, err))?;
+    print!("{}", annotate::annotate(&src));
+    Ok(0)
+}
+
+/// `md [--write] file` - evaluates calc code blocks of Markdown document,
+/// printing their results or putting them into the document
+fn md(args: impl Iterator<Item = String>) -> Result<usize> {
+    let mut write = false;
+    let mut path = None;
+
+    for arg in args {
+        match arg.as_str() {
+            "--write" => write = true,
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(format!("Unknown argument: {}", arg).into()),
+        }
+    }
+
+    let path = path.ok_or("Missing Markdown file")?;
+    let src =
+        std::fs::read_to_string(&path).map_err(|err| format!("Cannot read {}: {}", path, err))?;
+    let (document, outputs) = md::evaluate(&src)?;
+
+    if write {
+        std::fs::write(&path, document).map_err(|err| format!("Cannot write {}: {}", path, err))?;
+    } else {
+        for output in outputs {
+            println!("{}:{}: {}", path, output.line, output.output);
+        }
+    }
+    Ok(0)
+}
+
+/// `tutorial [lesson]` - interactive lessons, from the first one or from
+/// given one
+fn tutorial(mut args: impl Iterator<Item = String>) -> Result<usize> {
+    let first = match args.next() {
+        Some(lesson) => lesson
+            .parse()
+            .map_err(|err| format!("Invalid lesson: {}, {}", lesson, err))?,
+        None => 1,
+    };
+    if let Some(arg) = args.next() {
+        return Err(format!("Unknown argument: {}", arg).into());
+    }
+
+    let mut input = LineReader::new(stdin().lock(), stdin().is_terminal());
+    tutorial::run(first, &mut input, &mut Terminal(Lang::default()))?;
+    Ok(0)
+}
+
+/// `check file...` - reports problems found by lexing and parsing the files,
+/// exiting with status 1 if there are any
+fn check(args: impl Iterator<Item = String>) -> Result<usize> {
+    let mut found = false;
+    let mut paths = args.peekable();
+    if paths.peek().is_none() {
+        return Err("Missing file to check".into());
+    }
+
+    for path in paths {
+        let src = std::fs::read_to_string(&path)
+            .map_err(|err| format!("Cannot read {}: {}", path, err))?;
+        for diagnostic in check::check(&src) {
+            println!("{}:{}", path, diagnostic);
+            found = true;
+        }
+    }
+
+    if found {
+        process::exit(1);
+    }
+    Ok(0)
 }
 
 fn main() {
-    let mut context = Context::new();
-    stdin()
-        .lock()
-        .lines()
-        .filter_map(|line| line.ok()) // Actually ignoring iostream errors
-        .for_each(|line| match run(&line, &mut context) {
-            Ok(Some(val)) => println!("= {}", val),
-            Ok(None) => println!("()"),
-            Err(err) => println!("Error: {}", err),
-        });
+    let mut args = env::args().skip(1).peekable();
+    let mut lang = Lang::default();
+    let result = match args.peek().map(String::as_str) {
+        Some("minify") => minify(args.skip(1)),
+        Some("annotate") => annotate(args.skip(1)),
+        Some("fmt") => fmt(args.skip(1)),
+        Some("md") => md(args.skip(1)),
+        Some("check") => check(args.skip(1)),
+        Some("tutorial") => tutorial(args.skip(1)),
+        _ => Args::parse(args).and_then(|args| {
+            lang = args.lang;
+            start(args)
+        }),
+    };
+
+    match result {
+        Ok(0) => (),
+        Ok(mismatches) => {
+            eprintln!("{}", lang.mismatches(mismatches));
+            process::exit(1);
+        }
+        Err(err @ Error::Io(_)) => {
+            eprintln!("{}", lang.error(&err));
+            process::exit(3);
+        }
+        Err(err) => {
+            eprintln!("{}", lang.error(&err));
+            process::exit(2);
+        }
+    }
 }
@@ -0,0 +1,119 @@
+use crate::{format_result, Interpreter, Result};
+
+const CALC: &str = "```calc";
+const OUTPUT: &str = "```output";
+const FENCE: &str = "```";
+
+/// Result of code block line, `line` is counted from 1
+#[derive(Debug, PartialEq)]
+pub struct Output {
+    pub line: usize,
+    pub output: String,
+}
+
+enum State {
+    Text,
+    Calc(usize), // Line where block starts
+    AfterCalc,
+    OldOutput,
+}
+
+/// Evaluates all `calc` code blocks in one session, returning document with
+/// `output` block after every block producing any results, and the results
+/// themselves. Output blocks of previous run are replaced.
+pub fn evaluate(src: &str) -> Result<(String, Vec<Output>)> {
+    let mut interpreter = Interpreter::new();
+    let mut document = vec![];
+    let mut outputs = vec![];
+    let mut block = vec![];
+    let mut state = State::Text;
+
+    for (idx, line) in src.lines().enumerate() {
+        let fence = line.trim();
+        state = match state {
+            State::Calc(_) if fence == FENCE => {
+                document.push(line.to_owned());
+                if !block.is_empty() {
+                    document.push(OUTPUT.to_owned());
+                    document.append(&mut block);
+                    document.push(FENCE.to_owned());
+                }
+                State::AfterCalc
+            }
+            State::Calc(start) => {
+                document.push(line.to_owned());
+                let result = interpreter.eval(line);
+                let context = interpreter.context();
+                let output = format_result(result.map(|val| val.map(|val| context.rounded(val))));
+                // Definitions, empty lines and comments have nothing to show
+                if output != "()" {
+                    block.push(output.clone());
+                    outputs.push(Output {
+                        line: idx + 1,
+                        output,
+                    });
+                }
+                State::Calc(start)
+            }
+            State::AfterCalc if fence == OUTPUT => State::OldOutput,
+            State::OldOutput if fence == FENCE => State::Text,
+            State::OldOutput => State::OldOutput,
+            State::Text | State::AfterCalc => {
+                document.push(line.to_owned());
+                if fence == CALC {
+                    State::Calc(idx + 1)
+                } else {
+                    State::Text
+                }
+            }
+        };
+    }
+
+    match state {
+        State::Calc(start) => Err(format!("Unclosed calc block at line {}", start).into()),
+        State::OldOutput => Err("Unclosed output block".into()),
+        State::Text | State::AfterCalc => {
+            let document: String = document.iter().map(|line| format!("{}\n", line)).collect();
+            Ok((document, outputs))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn blocks() {
+        let src = "# Doc\n```calc\nr = 2\nsq x => x * x\n```\nText\n```rust\nr = 1\n```\n```calc\nsq r\n```\n";
+        let (document, outputs) = evaluate(src).unwrap();
+
+        assert_eq!(
+            "# Doc\n```calc\nr = 2\nsq x => x * x\n```\n```output\n= 2\n```\nText\n```rust\nr = 1\n```\n```calc\nsq r\n```\n```output\n= 4\n```\n",
+            document
+        );
+        assert_eq!(
+            vec![
+                Output {
+                    line: 3,
+                    output: "= 2".to_owned()
+                },
+                Output {
+                    line: 11,
+                    output: "= 4".to_owned()
+                },
+            ],
+            outputs
+        );
+
+        // Old outputs are replaced
+        assert_eq!(document, evaluate(&document).unwrap().0);
+    }
+
+    #[test]
+    fn unclosed() {
+        evaluate("```calc\n1\n").unwrap_err();
+        evaluate("```calc\n1\n```\n```output\n= 1\n").unwrap_err();
+    }
+}
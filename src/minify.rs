@@ -0,0 +1,182 @@
+use crate::import;
+use crate::lexer::{render, tokenize};
+use crate::{Result, Token};
+use std::collections::HashMap;
+
+fn lex(src: &str) -> Result<Vec<Token>> {
+    tokenize(src)
+        .map(|token| token.map(|(token, _)| token))
+        .collect()
+}
+
+/// Token text without any optional whitespace
+fn compact(token: &Token) -> String {
+    match token {
+        Token::Assign(var) => format!("{}=", var),
+        token => token.to_string(),
+    }
+}
+
+/// Tokens may be glued together only if it doesn't change how they are tokenized
+fn needs_space(left: &Token, right: &Token) -> bool {
+    let (left, right) = (compact(left), compact(right));
+    let glued = format!("{}{}", left, right);
+    let separate = format!("{} {}", left, right);
+    lex(&glued).ok() != lex(&separate).ok()
+}
+
+/// Shortest identifier of given index: `a`, `b`, ..., `z`, `aa`, `ab`, ...
+fn short_name(mut idx: usize) -> String {
+    let mut name = vec![];
+    loop {
+        name.push(b'a' + (idx % 26) as u8);
+        if idx < 26 {
+            break;
+        }
+        idx = idx / 26 - 1;
+    }
+    name.reverse();
+    String::from_utf8(name).unwrap()
+}
+
+/// Renames arguments of function definition to shortest names not colliding
+/// with any other identifier used in definition
+fn rename_args(tokens: Vec<Token>) -> Vec<Token> {
+    let args: Vec<_> = tokens
+        .iter()
+        .skip(1)
+        .take_while(|token| **token != Token::Func)
+        .filter_map(|token| match token {
+            Token::Id(id) => Some(id.clone()),
+            _ => None,
+        })
+        .collect();
+
+    // Function name and all called functions have to stay untouched
+    let reserved: Vec<_> = tokens
+        .iter()
+        .filter_map(|token| match token {
+            Token::Id(id) if !args.contains(id) => Some(id.clone()),
+            _ => None,
+        })
+        .collect();
+
+    let mut renames = HashMap::new();
+    let mut candidates = (0..)
+        .map(short_name)
+        .filter(|name| !reserved.contains(name));
+    for arg in args {
+        renames
+            .entry(arg)
+            .or_insert_with(|| candidates.next().unwrap());
+    }
+
+    let mut tokens = tokens.into_iter();
+    let name = tokens.next();
+    name.into_iter()
+        .chain(tokens.map(|token| match token {
+            Token::Id(id) => Token::Id(renames.get(&id).cloned().unwrap_or(id)),
+            token => token,
+        }))
+        .collect()
+}
+
+/// Shortens single line, the result tokenizes to the same tokens as original
+/// (modulo argument renaming)
+pub fn minify_line(src: &str, rename: bool) -> Result<String> {
+    // Import statement is not made of tokens
+    if import::parse(src).is_some() {
+        return Ok(src.trim().to_owned());
+    }
+
+    let mut tokens = lex(src.trim())?;
+    if rename && tokens.contains(&Token::Func) {
+        tokens = rename_args(tokens);
+    }
+
+    let mut result = String::new();
+    for (idx, token) in tokens.iter().enumerate() {
+        if idx > 0 && needs_space(&tokens[idx - 1], token) {
+            result.push(' ');
+        }
+        result += &compact(token);
+    }
+
+    // Pairwise check should be enough, but never output something invalid
+    if lex(&result).ok().as_ref() == Some(&tokens) {
+        Ok(result)
+    } else {
+        Ok(render(&tokens))
+    }
+}
+
+/// Minifies every line of the source, skipping the empty ones and comments
+pub fn minify(src: &str, rename: bool) -> Result<String> {
+    let lines: Result<Vec<_>> = src
+        .lines()
+        .enumerate()
+        .map(|(idx, line)| {
+            minify_line(line, rename).map_err(|err| format!("Line {}: {}", idx + 1, err).into())
+        })
+        .filter(|line| !matches!(line, Ok(line) if line.is_empty()))
+        .collect();
+
+    Ok(lines?.join("\n"))
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert_eq!("a", short_name(0));
+        assert_eq!("z", short_name(25));
+        assert_eq!("aa", short_name(26));
+        assert_eq!("az", short_name(51));
+        assert_eq!("ba", short_name(52));
+    }
+
+    #[test]
+    fn whitespace() {
+        assert_eq!(
+            Ok("a=3+4*b".to_owned()),
+            minify_line("  a = 3 + 4 * b ", false)
+        );
+        assert_eq!(Ok("x=y=!1".to_owned()), minify_line("x = y = ! 1", false));
+        assert_eq!(Ok("add 1 2".to_owned()), minify_line("add   1   2", false));
+        assert_eq!(
+            Ok("a==b===c".to_owned()),
+            minify_line("a == b === c", false)
+        );
+        assert_eq!(
+            Ok("add x y=>x+y".to_owned()),
+            minify_line("add x y => x + y", false)
+        );
+        minify_line("a = $", false).unwrap_err();
+    }
+
+    #[test]
+    fn rename() {
+        assert_eq!(
+            Ok("add a b=>a+b".to_owned()),
+            minify_line("add first second => first + second", true)
+        );
+        assert_eq!(
+            Ok("a3 b c d=>a b c d".to_owned()),
+            minify_line("a3 x y z => a x y z", true)
+        );
+        assert_eq!(Ok("x=y+1".to_owned()), minify_line("x = y + 1", true));
+    }
+
+    #[test]
+    fn lines() {
+        let src = "a = 1\n\n# sum\nadd x y => x + y # of two\n";
+        assert_eq!(Ok("a=1\nadd x y=>x+y".to_owned()), minify(src, false));
+        assert!(minify("a = 1\n\n$", false)
+            .unwrap_err()
+            .to_string()
+            .starts_with("Line 3"));
+    }
+}
@@ -0,0 +1,296 @@
+//! Simplification of parsed expressions, run on every statement and function
+//! body before they are evaluated or compiled. Subexpressions which values
+//! are known without any context - literals and substituted variables and
+//! constants - are replaced with their values, and operations which don't
+//! change numeric operand are dropped.
+
+use crate::analysis::{infer, Type};
+use crate::parser::{Callee, Expr, Fold, Reference};
+use crate::{Operator, Value};
+
+/// Work left to do, as in evaluation - continuations take optimized
+/// subexpressions from the top of the stack, so deep trees cannot overflow
+/// the native one
+enum Task {
+    Optimize(Expr),
+    Assign(String),
+    Const(String),
+    Binary(Operator),
+    Not,
+    Call(Callee, usize),
+    List(usize),
+    Partial(Box<Reference>, usize),
+    Index,
+    /// Condition is optimized, branches are not yet
+    Cond(Expr, Expr),
+    /// Condition and both branches are optimized
+    Branches,
+    Let(String),
+    Fold(Operator, String),
+}
+
+/// Equivalent expression, evaluating to the same value with the same effects
+pub fn optimize(expr: Expr) -> Expr {
+    walk(expr).unwrap_or(Expr::Unit)
+}
+
+fn walk(expr: Expr) -> Option<Expr> {
+    let mut tasks = vec![Task::Optimize(expr)];
+    let mut done: Vec<Expr> = vec![];
+
+    while let Some(task) = tasks.pop() {
+        let expr = match task {
+            // Function bodies are optimized when they are parsed
+            Task::Optimize(expr) if expr.is_leaf() => expr,
+            Task::Optimize(mut expr) => {
+                match &mut expr {
+                    Expr::Assign(var, val) => {
+                        tasks.push(Task::Assign(std::mem::take(var)));
+                        tasks.push(Task::Optimize(val.take()));
+                    }
+                    Expr::Const(var, val) => {
+                        tasks.push(Task::Const(std::mem::take(var)));
+                        tasks.push(Task::Optimize(val.take()));
+                    }
+                    Expr::Binary(op, left, right) => {
+                        tasks.push(Task::Binary(*op));
+                        tasks.push(Task::Optimize(right.take()));
+                        tasks.push(Task::Optimize(left.take()));
+                    }
+                    Expr::Not(expr) => {
+                        tasks.push(Task::Not);
+                        tasks.push(Task::Optimize(expr.take()));
+                    }
+                    Expr::Call(callee, args) => {
+                        tasks.push(Task::Call(callee.clone(), args.len()));
+                        tasks.extend(args.drain(..).rev().map(Task::Optimize));
+                    }
+                    Expr::List(items) => {
+                        tasks.push(Task::List(items.len()));
+                        tasks.extend(items.drain(..).rev().map(Task::Optimize));
+                    }
+                    Expr::Partial(func, args) => {
+                        tasks.push(Task::Partial(func.clone(), args.len()));
+                        tasks.extend(args.drain(..).rev().map(Task::Optimize));
+                    }
+                    Expr::Index(list, idx) => {
+                        tasks.push(Task::Index);
+                        tasks.push(Task::Optimize(idx.take()));
+                        tasks.push(Task::Optimize(list.take()));
+                    }
+                    Expr::Cond(cond, then, other) => {
+                        tasks.push(Task::Cond(then.take(), other.take()));
+                        tasks.push(Task::Optimize(cond.take()));
+                    }
+                    Expr::Let(var, val, body) => {
+                        tasks.push(Task::Let(std::mem::take(var)));
+                        tasks.push(Task::Optimize(body.take()));
+                        tasks.push(Task::Optimize(val.take()));
+                    }
+                    Expr::Fold(fold) => {
+                        tasks.push(Task::Fold(fold.op, std::mem::take(&mut fold.var)));
+                        tasks.push(Task::Optimize(fold.body.take()));
+                        tasks.push(Task::Optimize(fold.to.take()));
+                        tasks.push(Task::Optimize(fold.from.take()));
+                    }
+                    Expr::Unit
+                    | Expr::Value(_)
+                    | Expr::Argument(_)
+                    | Expr::Variable(_)
+                    | Expr::Define(_) => (),
+                }
+                continue;
+            }
+            Task::Assign(var) => Expr::Assign(var, Box::new(done.pop()?)),
+            Task::Const(var) => Expr::Const(var, Box::new(done.pop()?)),
+            Task::Binary(op) => {
+                let right = done.pop()?;
+                let left = done.pop()?;
+                binary(op, left, right)
+            }
+            Task::Not => Expr::Not(Box::new(done.pop()?)),
+            Task::Call(callee, count) => Expr::Call(callee, done.split_off(done.len() - count)),
+            Task::List(count) => Expr::List(done.split_off(done.len() - count)),
+            Task::Partial(func, count) => Expr::Partial(func, done.split_off(done.len() - count)),
+            Task::Index => {
+                let idx = done.pop()?;
+                let list = done.pop()?;
+                Expr::Index(Box::new(list), Box::new(idx))
+            }
+            // Only the chosen branch would be evaluated
+            Task::Cond(then, other) => {
+                let cond = done.pop()?;
+                match known(&cond) {
+                    Some(cond) if cond.is_true() => tasks.push(Task::Optimize(then)),
+                    Some(_) => tasks.push(Task::Optimize(other)),
+                    None => {
+                        done.push(cond);
+                        tasks.push(Task::Branches);
+                        tasks.push(Task::Optimize(other));
+                        tasks.push(Task::Optimize(then));
+                    }
+                }
+                continue;
+            }
+            Task::Branches => {
+                let other = done.pop()?;
+                let then = done.pop()?;
+                let cond = done.pop()?;
+                Expr::Cond(Box::new(cond), Box::new(then), Box::new(other))
+            }
+            Task::Let(var) => {
+                let body = done.pop()?;
+                let val = done.pop()?;
+                Expr::Let(var, Box::new(val), Box::new(body))
+            }
+            Task::Fold(op, var) => {
+                let body = done.pop()?;
+                let to = done.pop()?;
+                let from = done.pop()?;
+                Expr::Fold(Box::new(Fold {
+                    op,
+                    var,
+                    from,
+                    to,
+                    body,
+                }))
+            }
+        };
+
+        done.push(match folded(&expr) {
+            Some(val) => Expr::Value(val),
+            None => expr,
+        });
+    }
+    done.pop()
+}
+
+/// Value of optimized expression, subexpressions with known values are
+/// already replaced with them
+fn known(expr: &Expr) -> Option<Value> {
+    match expr {
+        Expr::Value(val) => Some(val.clone()),
+        _ => None,
+    }
+}
+
+/// Value of expression with optimized subexpressions, it is looked for only
+/// if they all are values - the others are not known, however deep they are
+fn folded(expr: &Expr) -> Option<Value> {
+    let is_value = |expr: &Expr| matches!(expr, Expr::Value(_));
+    let operands = match expr {
+        Expr::Binary(_, left, right) | Expr::Index(left, right) => {
+            is_value(left) && is_value(right)
+        }
+        Expr::Not(expr) => is_value(expr),
+        Expr::List(items) | Expr::Partial(_, items) => items.iter().all(is_value),
+        expr => is_value(expr),
+    };
+    if operands {
+        expr.value()
+    } else {
+        None
+    }
+}
+
+/// Operation on optimized operands. `x - 0` and `x * 1` are simplified only
+/// if `x` is known to be number - they give `x` for every number, also for
+/// infinities, `NaN` and `-0`. `x + 0` is not, as `-0 + 0` is `0`.
+fn binary(op: Operator, left: Expr, right: Expr) -> Expr {
+    // Right side is not evaluated at all if left one decides the result
+    match (op, known(&left)) {
+        (Operator::And, Some(val)) if !val.is_true() => return Expr::Value(Value::from(false)),
+        (Operator::Or, Some(val)) if val.is_true() => return Expr::Value(Value::from(true)),
+        _ => (),
+    }
+
+    // Operands are folded first, so identities never hide their value
+    let expr = Expr::Binary(op, Box::new(left), Box::new(right));
+    match folded(&expr) {
+        Some(val) => Expr::Value(val),
+        None => simplified(expr),
+    }
+}
+
+fn simplified(mut expr: Expr) -> Expr {
+    let (zero, one) = (Expr::Value(Value::Int(0)), Expr::Value(Value::Int(1)));
+    if let Expr::Binary(op, left, right) = &mut expr {
+        match op {
+            Operator::Sub if **right == zero && is_number(left) => return left.take(),
+            Operator::Mul if **right == one && is_number(left) => return left.take(),
+            Operator::Mul if **left == one && is_number(right) => return right.take(),
+            _ => (),
+        }
+    }
+    expr
+}
+
+fn is_number(expr: &Expr) -> bool {
+    infer(expr) == Some(Type::Number)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::Interpreter;
+
+    #[test]
+    fn constants() {
+        let interpreter = Interpreter::new();
+        assert_eq!(
+            Expr::Value(Value::Float(std::f64::consts::TAU)),
+            interpreter.parse("pi * 2").unwrap()
+        );
+        assert_eq!(
+            Expr::Value(Value::Int(3)),
+            interpreter.parse("[1, 2, 3][1 + 1]").unwrap()
+        );
+    }
+
+    #[test]
+    fn identities() {
+        let mut interpreter = Interpreter::new();
+        interpreter.eval("f x => x; x = 2").unwrap();
+        let parse = |src| interpreter.parse(src).unwrap();
+        assert_eq!(parse("sin(x)"), parse("sin(x) * 1 - 0"));
+        assert_eq!(parse("sin(x)"), parse("1 * (sin(x) - 0)"));
+
+        // `-0 + 0` is `0`, and `x - x` is `NaN` for infinities
+        assert_ne!(parse("sin(x)"), parse("sin(x) + 0"));
+        assert_ne!(Expr::Value(Value::Int(0)), parse("sin(x) - sin(x)"));
+        // Type of `f(x)` is not known, and strings may be joined
+        assert_ne!(parse("f(x)"), parse("f(x) * 1"));
+    }
+
+    #[test]
+    fn special_values() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .eval("f x => x - x; g x => sin(x) - sin(x)")
+            .unwrap();
+        let mut eval = |src: &str| match interpreter.eval(src).unwrap() {
+            Some(Value::Float(val)) => val,
+            val => panic!("{} gives {:?}", src, val),
+        };
+        assert!(eval("1e308 * 10 - 1e308 * 10").is_nan());
+        assert!(eval("(1e308 * 10) * 0 - 0").is_nan());
+        assert!(eval("f(1e308 * 10)").is_nan());
+        assert!(eval("g(1e308 * 10)").is_nan());
+
+        let zero = "(0 - 1) / (1e308 * 10)";
+        assert_eq!(f64::INFINITY, eval(&format!("1 / ({} + 0)", zero)));
+        assert_eq!(f64::NEG_INFINITY, eval(&format!("1 / ({} * 1 - 0)", zero)));
+    }
+
+    #[test]
+    fn branches() {
+        let mut interpreter = Interpreter::new();
+        interpreter.eval("f x => x").unwrap();
+        let parse = |src| interpreter.parse(src).unwrap();
+        assert_eq!(parse("f(1)"), parse("2 - 1 ? f(1) : f(2)"));
+        assert_eq!(parse("f(2)"), parse("0 ? f(1) : f(2)"));
+        assert_eq!(Expr::Value(Value::from(false)), parse("0 && f(1)"));
+        assert_eq!(Expr::Value(Value::from(true)), parse("1 || f(1)"));
+    }
+}
@@ -1,314 +1,807 @@
-use crate::{Context, Operator, Result, Token};
-use std::any::Any;
-use std::iter::Peekable;
-use std::rc::Rc;
+use crate::builtins::Func;
+use crate::feature::Feature;
+use crate::formatter;
+use crate::lexer::{not_reserved, render, Associativity, Span};
+use crate::optimize::optimize;
+use crate::vm::{self, Program};
+use crate::{Context, Error, Operator, Result, Token, Value};
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+/// Expression tree. Variables are substituted with their values, and calls
+/// are bound to called functions while parsing.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Unit,             // Empty expression, eg. comment only
+    Value(Value),     // Literal or substituted variable value
+    Argument(usize),  // Function argument of given index
+    Variable(String), // Variable captured by reference, read when evaluated
+    Assign(String, Box<Expr>),
+    Binary(Operator, Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Call(Callee, Vec<Expr>),
+    List(Vec<Expr>),             // List literal, `[a, b]`
+    Index(Box<Expr>, Box<Expr>), // Indexing, `xs[0]`
+    /// `cond ? a : b`, only the chosen branch is evaluated
+    Cond(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// Function with fewer arguments than its arity, evaluated to reference
+    /// with them bound
+    Partial(Box<Reference>, Vec<Expr>),
+    /// `const name = value`, evaluated to the value
+    Const(String, Box<Expr>),
+    /// `let name = value in body`, body sees the value as local of the next
+    /// free argument index
+    Let(String, Box<Expr>, Box<Expr>),
+    /// `sum i = 1 to n of body` or `prod ...`
+    Fold(Box<Fold>),
+    /// Function definition, evaluating it defines the function
+    Define(Function),
+}
+
+/// Sum or product of body evaluated for every integer step from `from` up
+/// to `to`, the loop variable is local as in `let`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fold {
+    pub op: Operator,
+    pub var: String,
+    pub from: Expr,
+    pub to: Expr,
+    pub body: Expr,
+}
 
-pub trait AST: std::fmt::Debug {
-    fn as_any(&self) -> &dyn Any;
-    fn is_same(&self, other: &dyn AST) -> bool;
-    fn arity(&self) -> usize {
-        0
+impl Fold {
+    /// Result of empty range
+    pub fn identity(&self) -> Value {
+        match self.op {
+            Operator::Mul => Value::Int(1),
+            _ => Value::Int(0),
+        }
     }
+}
 
-    /// Used to return value if known without any context
-    fn value(&self) -> Option<f32>;
+/// Operator of fold started by given name, `sum` and `prod` are keywords
+/// only when loop variable follows them, so they can still name symbols
+fn fold_operator(name: &str, tokens: &Tokens) -> Option<Operator> {
+    let op = match name {
+        "sum" => Operator::Add,
+        "prod" => Operator::Mul,
+        _ => return None,
+    };
+    match tokens.peek() {
+        Some(Token::Assign(_)) => Some(op),
+        _ => None,
+    }
+}
 
-    fn evaluate(&self, context: &mut Context, args: &[f32]) -> Option<f32>;
+/// Argument of call given by name, with position of its parameter and span
+/// of the name
+type Named = (usize, Expr, Span);
+
+/// Builtin function implemented in Rust
+#[derive(Clone)]
+pub struct Native {
+    pub name: Arc<str>,
+    pub func: Func,
+}
+
+impl std::fmt::Debug for Native {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Native({})", self.name)
+    }
 }
 
-#[derive(Debug)]
-enum Terminal {
-    Value(f32), // Literal or substituted variable value
-    Assign(String, Box<dyn AST>),
-    Argument(usize), // Function argument of given index
+/// Body of user function, with its bytecode if it can be compiled
+#[derive(Debug, PartialEq)]
+pub struct Body {
+    pub expr: Expr,
+    pub program: Option<Program>,
 }
 
-#[derive(Debug)]
-struct OpExpr {
-    op: Operator,
-    left: Box<dyn AST>,
-    right: Box<dyn AST>,
+#[derive(Debug, Clone)]
+pub enum Callee {
+    Native(Native),
+    Function(Arc<Body>),
+    /// Function passed as argument of given index, known only when called
+    Argument(usize),
 }
 
-#[derive(Debug)]
-struct CallExpr {
-    func: Rc<dyn AST>,
-    args: Vec<Box<dyn AST>>,
+/// Function as value, it can be passed to other functions
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reference {
+    pub name: Arc<str>,
+    /// Number of arguments still to be given
+    pub arity: usize,
+    pub callee: Callee,
+    /// Arguments of partial application, passed before the given ones
+    pub bound: Vec<Value>,
 }
 
-#[derive(Debug)]
+impl Reference {
+    /// The same function with more arguments bound
+    pub fn bind(&self, args: Vec<Value>) -> Self {
+        let mut bound = self.bound.clone();
+        bound.extend(args);
+        Reference {
+            name: Arc::clone(&self.name),
+            arity: self.arity.saturating_sub(bound.len() - self.bound.len()),
+            callee: self.callee.clone(),
+            bound,
+        }
+    }
+
+    /// Name with bound arguments, as `add(1)`
+    pub fn source(&self) -> String {
+        if self.bound.is_empty() {
+            return self.name.to_string();
+        }
+        let args: Vec<_> = self.bound.iter().map(Value::source).collect();
+        format!("{}({})", self.name, args.join(", "))
+    }
+}
+
+impl std::fmt::Display for Reference {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        if !self.bound.is_empty() {
+            let args: Vec<_> = self.bound.iter().map(ToString::to_string).collect();
+            write!(f, "({})", args.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Function {
     pub name: String,
     pub arity: usize,
-    pub expr: Rc<dyn AST>,
+    pub body: Arc<Body>,
+    pub source: String,     // Definition as typed, used for dumping the session
+    pub calls: Vec<String>, // User functions called from body and captured variables
+    /// Values of trailing arguments which may be omitted, evaluated when
+    /// the function is called
+    pub defaults: Vec<Expr>,
+    /// The last argument is list of arguments after the others
+    pub variadic: bool,
+    /// Names of arguments, for calls naming them
+    pub params: Vec<String>,
 }
 
-impl AST for Terminal {
-    fn as_any(&self) -> &dyn Any {
-        self
+impl PartialEq for Callee {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Callee::Native(left), Callee::Native(right)) => left.name == right.name,
+            (Callee::Function(left), Callee::Function(right)) => left == right,
+            (Callee::Argument(left), Callee::Argument(right)) => left == right,
+            _ => false,
+        }
     }
+}
 
-    fn is_same(&self, other: &dyn AST) -> bool {
-        other
-            .as_any()
-            .downcast_ref::<Self>()
-            .map_or(false, |o| match (self, o) {
-                (Terminal::Value(x), Terminal::Value(y)) => (x - y).abs() < 0.001,
-                (Terminal::Assign(v1, val1), Terminal::Assign(v2, val2)) => {
-                    v1 == v2 && val1.is_same(val2.as_ref())
+impl Native {
+    /// Most builtins compute with floats. Failure of host function stops
+    /// evaluation.
+    pub fn call(&self, context: &mut Context, args: &[Value]) -> Option<Value> {
+        let floats = || args.iter().map(|arg| arg.as_f64()).collect::<Vec<_>>();
+        match &self.func {
+            Func::Float(func) => Some(Value::Float(func(&floats()))),
+            Func::Generic(func) => Some(func(args)),
+            Func::Host(func) => match func(&floats()) {
+                Ok(val) => Some(Value::Float(val)),
+                Err(err) => {
+                    context.fail(err);
+                    None
                 }
-                _ => false,
-            })
+            },
+        }
     }
+}
 
-    fn value(&self) -> Option<f32> {
-        match self {
-            Terminal::Value(v) => Some(*v),
-            Terminal::Assign(_, _) => None,
-            Terminal::Argument(_) => None,
+impl Body {
+    pub fn new(expr: Expr) -> Self {
+        let program = vm::compile(&expr);
+        Body { expr, program }
+    }
+
+    pub fn call(&self, context: &mut Context, args: &[Value]) -> Option<Value> {
+        match &self.program {
+            Some(program) => program.run(context, args),
+            None => self.expr.evaluate(context, args),
         }
     }
+}
 
-    fn evaluate(&self, context: &mut Context, args: &[f32]) -> Option<f32> {
+impl Callee {
+    /// Argument callees are resolved by the caller, with `call_argument`
+    pub fn call(&self, context: &mut Context, args: &[Value]) -> Option<Value> {
         match self {
-            Terminal::Value(v) => Some(*v),
-            Terminal::Assign(var, val) => {
-                let val = val.evaluate(context, args)?;
-                context.update_var(var, val);
-                Some(val)
-            }
-            Terminal::Argument(arg) => args.get(*arg).cloned(),
+            Callee::Native(native) => native.call(context, args),
+            Callee::Function(body) => body.call(context, args),
+            Callee::Argument(_) => None,
         }
     }
 }
 
-impl AST for OpExpr {
-    fn as_any(&self) -> &dyn Any {
-        self
+/// Calls function passed as argument, `NaN` if it is not a function of
+/// matching arity. Such calls may recurse, so they stop evaluation once they
+/// are nested too deep.
+pub fn call_argument(context: &mut Context, func: &Value, args: &[Value]) -> Option<Value> {
+    let func = match func {
+        Value::Func(func) if func.arity == args.len() => func,
+        _ => return Some(Value::Float(f64::NAN)),
+    };
+
+    if !context.enter() {
+        return None;
     }
+    let args = [&func.bound[..], args].concat();
+    let result = func.callee.call(context, &args);
+    context.leave();
+    result
+}
 
-    fn is_same(&self, other: &dyn AST) -> bool {
-        if let Some(other) = other.as_any().downcast_ref::<Self>() {
-            self.op == other.op
-                && self.left.is_same(other.left.as_ref())
-                && self.right.is_same(other.right.as_ref())
-        } else {
-            false
+impl Drop for Expr {
+    /// Subtrees are moved out to explicit stack and dropped one by one, so
+    /// dropping arbitrarily deep tree cannot overflow the native stack
+    fn drop(&mut self) {
+        let mut subtrees = vec![];
+        self.detach(&mut subtrees);
+        while let Some(mut expr) = subtrees.pop() {
+            expr.detach(&mut subtrees);
         }
     }
+}
 
-    fn value(&self) -> Option<f32> {
-        let (left, right) = (self.left.value(), self.right.value());
-        if let (Some(left), Some(right)) = (left, right) {
-            Some(self.op.eval(left, right))
-        } else {
-            None
-        }
+impl Expr {
+    /// Moves the expression out, leaving `Unit` in its place
+    pub fn take(&mut self) -> Expr {
+        std::mem::replace(self, Expr::Unit)
     }
 
-    fn evaluate(&self, context: &mut Context, args: &[f32]) -> Option<f32> {
-        let (left, right) = (
-            self.left.evaluate(context, args)?,
-            self.right.evaluate(context, args)?,
-        );
+    /// Expression without subexpressions
+    pub fn is_leaf(&self) -> bool {
+        matches!(
+            self,
+            Expr::Unit | Expr::Value(_) | Expr::Argument(_) | Expr::Variable(_) | Expr::Define(_)
+        )
+    }
 
-        Some(self.op.eval(left, right))
+    /// Moves subexpressions which are not leaves out to `subtrees`
+    fn detach(&mut self, subtrees: &mut Vec<Expr>) {
+        let mut detach = |expr: &mut Expr| {
+            if !expr.is_leaf() {
+                subtrees.push(expr.take());
+            }
+        };
+        match self {
+            Expr::Assign(_, expr) | Expr::Const(_, expr) | Expr::Not(expr) => detach(expr),
+            Expr::Binary(_, left, right) | Expr::Index(left, right) | Expr::Let(_, left, right) => {
+                detach(left);
+                detach(right);
+            }
+            Expr::Cond(cond, then, other) => {
+                detach(cond);
+                detach(then);
+                detach(other);
+            }
+            Expr::Call(_, exprs) | Expr::List(exprs) | Expr::Partial(_, exprs) => {
+                exprs.iter_mut().for_each(detach)
+            }
+            Expr::Fold(fold) => {
+                detach(&mut fold.from);
+                detach(&mut fold.to);
+                detach(&mut fold.body);
+            }
+            Expr::Unit
+            | Expr::Value(_)
+            | Expr::Argument(_)
+            | Expr::Variable(_)
+            | Expr::Define(_) => (),
+        }
     }
-}
 
-impl AST for CallExpr {
-    fn as_any(&self) -> &dyn Any {
-        self
+    /// Value if known without any context
+    pub fn value(&self) -> Option<Value> {
+        match self {
+            Expr::Value(v) => Some(v.clone()),
+            Expr::Binary(op, left, right) => op.eval(left.value()?, right.value()?),
+            Expr::Not(expr) => expr.value().map(|v| Value::from(!v.is_true())),
+            Expr::List(items) => {
+                let items: Option<Vec<_>> = items.iter().map(Expr::value).collect();
+                items.map(Value::from)
+            }
+            Expr::Index(list, idx) => Some(list.value()?.index(&idx.value()?)),
+            Expr::Cond(cond, then, other) => {
+                if cond.value()?.is_true() {
+                    then.value()
+                } else {
+                    other.value()
+                }
+            }
+            Expr::Partial(func, args) => {
+                let args: Option<Vec<_>> = args.iter().map(Expr::value).collect();
+                Some(Value::Func(func.bind(args?)))
+            }
+            _ => None,
+        }
     }
 
-    fn is_same(&self, other: &dyn AST) -> bool {
-        other.as_any().downcast_ref::<Self>().is_some()
+    /// Whether argument of given index is called as function, also by
+    /// functions it is passed to
+    pub fn calls_argument(&self, idx: usize) -> bool {
+        self.walk().any(|expr| match expr {
+            Expr::Call(callee, args) => {
+                let passed = match callee {
+                    Callee::Function(body) => args.iter().enumerate().any(|(param, arg)| {
+                        *arg == Expr::Argument(idx) && body.expr.calls_argument(param)
+                    }),
+                    _ => false,
+                };
+                *callee == Callee::Argument(idx) || passed
+            }
+            _ => false,
+        })
     }
 
-    fn value(&self) -> Option<f32> {
-        None
+    /// The expression and all its subexpressions, every one before its
+    /// subexpressions. Bodies of defined functions are not entered.
+    pub fn walk(&self) -> Walk<'_> {
+        Walk(vec![self])
     }
+}
 
-    fn evaluate(&self, context: &mut Context, args: &[f32]) -> Option<f32> {
-        let args: Option<Vec<_>> = self
-            .args
-            .iter()
-            .map(|arg| arg.evaluate(context, args))
-            .collect();
-        let args = args?;
+/// Iterator of `Expr::walk`, expressions still to be visited are kept on
+/// explicit stack, so deep trees can be walked
+pub struct Walk<'a>(Vec<&'a Expr>);
+
+impl<'a> Iterator for Walk<'a> {
+    type Item = &'a Expr;
 
-        self.func.evaluate(context, &args)
+    fn next(&mut self) -> Option<&'a Expr> {
+        let expr = self.0.pop()?;
+        match expr {
+            Expr::Assign(_, val) | Expr::Const(_, val) | Expr::Not(val) => self.0.push(val),
+            Expr::Binary(_, left, right) | Expr::Index(left, right) | Expr::Let(_, left, right) => {
+                self.0.push(right);
+                self.0.push(left);
+            }
+            Expr::Cond(cond, then, other) => {
+                self.0.push(other);
+                self.0.push(then);
+                self.0.push(cond);
+            }
+            Expr::Call(_, exprs) | Expr::List(exprs) | Expr::Partial(_, exprs) => {
+                self.0.extend(exprs.iter().rev())
+            }
+            Expr::Fold(fold) => {
+                self.0.push(&fold.body);
+                self.0.push(&fold.to);
+                self.0.push(&fold.from);
+            }
+            Expr::Unit
+            | Expr::Value(_)
+            | Expr::Argument(_)
+            | Expr::Variable(_)
+            | Expr::Define(_) => (),
+        }
+        Some(expr)
     }
 }
 
-impl AST for Function {
-    fn as_any(&self) -> &dyn Any {
-        self
+/// Levels of nesting - brackets, arguments of calls and operands of `!` -
+/// deeper expressions are rejected while they are parsed, as the parser
+/// recurses into them. Chains of operators, indexes and pipes are parsed in
+/// loops, so they are not limited.
+pub const MAX_DEPTH: usize = 256;
+
+/// Tokens of single statement with their spans, parsing takes them from the
+/// front. Cloning is cheap, so it can be used to look ahead.
+#[derive(Debug, Clone)]
+pub struct Tokens {
+    tokens: Arc<[(Token, Span)]>,
+    next: usize,
+    end: usize,   // Where the statement ends, errors at the end point there
+    depth: usize, // Level of the tree being parsed
+}
+
+impl Tokens {
+    fn new(tokens: Vec<(Token, Span)>, end: usize) -> Self {
+        Tokens {
+            tokens: tokens.into(),
+            next: 0,
+            end,
+            depth: 0,
+        }
     }
 
-    fn is_same(&self, other: &dyn AST) -> bool {
-        if let Some(other) = other.as_any().downcast_ref::<Self>() {
-            self.name == other.name
-                && self.arity == other.arity
-                && self.expr.is_same(other.expr.as_ref())
-        } else {
-            false
+    /// Goes level deeper in the parsed tree, giving the previous level so
+    /// it can be restored once the subtree is parsed
+    fn nest(&mut self) -> Result<usize> {
+        if self.depth >= MAX_DEPTH {
+            return Err(self.error(format!(
+                "Expression is nested deeper than {} levels",
+                MAX_DEPTH
+            )));
         }
+        self.depth += 1;
+        Ok(self.depth - 1)
+    }
+
+    pub fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(token, _)| token)
     }
 
-    fn value(&self) -> Option<f32> {
-        None
+    /// Span of the next token, or empty span at the end of the statement
+    pub fn span(&self) -> Span {
+        self.tokens
+            .get(self.next)
+            .map_or(Span::at(self.end), |(_, span)| *span)
     }
 
-    fn evaluate(&self, context: &mut Context, _args: &[f32]) -> Option<f32> {
-        context.update_func(self);
-        None
+    /// Tokens not taken yet, with their spans
+    fn rest(&self) -> &[(Token, Span)] {
+        &self.tokens[self.next..]
+    }
+
+    /// Parse error pointing the next token
+    fn error(&self, message: String) -> Error {
+        Error::Parse {
+            message,
+            span: self.span(),
+        }
     }
 }
 
-impl Terminal {
-    fn parse(
-        tokens: &mut Peekable<impl Iterator<Item = Token>>,
-        context: &Context,
-    ) -> Result<Box<dyn AST>> {
+impl Iterator for Tokens {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        let (token, _) = self.tokens.get(self.next)?;
+        self.next += 1;
+        Some(token.clone())
+    }
+}
+
+impl Expr {
+    /// Terminal with indexing applied, `xs[0][1]`
+    fn parse_terminal(tokens: &mut Tokens, context: &Context) -> Result<Expr> {
+        let depth = tokens.nest()?;
+        let mut expr = Self::parse_atom(tokens, context)?;
+        while tokens.peek() == Some(&Token::LSquare) {
+            if !context.is_enabled(Feature::Lists) {
+                return Err(tokens.error(Feature::Lists.disabled()));
+            }
+            tokens.next();
+            let idx = Self::parse_call(tokens, context)?;
+            if tokens.peek() != Some(&Token::RSquare) {
+                return Err(
+                    tokens.error(format!("Invalid token {:?}, expected `]`", tokens.peek()))
+                );
+            }
+            tokens.next();
+            expr = Expr::Index(Box::new(expr), Box::new(idx));
+        }
+        tokens.depth = depth;
+        Ok(expr)
+    }
+
+    fn parse_atom(tokens: &mut Tokens, context: &Context) -> Result<Expr> {
+        let span = tokens.span();
         match tokens.next() {
-            Some(Token::Number(x)) => Ok(Box::new(Terminal::Value(x))),
+            Some(Token::Number(x)) => match context.mode().literal(x) {
+                Ok(val) => Ok(Expr::Value(val)),
+                Err(message) => Err(Error::Lex { message, span }),
+            },
+            Some(Token::Str(_)) if !context.is_enabled(Feature::Strings) => Err(Error::Parse {
+                message: Feature::Strings.disabled(),
+                span,
+            }),
+            Some(Token::Str(text)) => Ok(Expr::Value(Value::from(text))),
             Some(Token::LBracket) => {
-                tokens.next();
-                let expr = OpExpr::parse(tokens, context)?;
+                let expr = Self::parse_operation(tokens, context)?;
                 if let Some(Token::RBracket) = tokens.peek() {
                     tokens.next();
                     Ok(expr)
                 } else {
-                    Err(format!("Invalid token {:?}, expected `)`", tokens.next()))
+                    Err(tokens.error(format!("Invalid token {:?}, expected `)`", tokens.peek())))
                 }
             }
+            Some(Token::LSquare) if !context.is_enabled(Feature::Lists) => Err(Error::Parse {
+                message: Feature::Lists.disabled(),
+                span,
+            }),
+            Some(Token::LSquare) => {
+                let items = Self::parse_list(tokens, context, Token::RSquare)?;
+                Ok(Expr::List(items))
+            }
             Some(Token::Assign(var)) => {
-                if context.is_var(&var) {
-                    let expr = CallExpr::parse(tokens, context)?;
-                    Ok(Box::new(Terminal::Assign(var, expr)))
+                if !context.is_enabled(Feature::Assignments) {
+                    return Err(Error::Parse {
+                        message: Feature::Assignments.disabled(),
+                        span,
+                    });
+                }
+
+                let name = Span {
+                    start: span.start,
+                    end: span.start + var.len(),
+                };
+                not_reserved(&var, name)?;
+                if context.is_const(&var) {
+                    Err(Error::Eval(format!("Cannot assign to constant: {}", var)))
+                } else if context.is_var(&var) || context.force() && context.is_func(&var) {
+                    let expr = Self::parse_call(tokens, context)?;
+                    Ok(Expr::Assign(var, Box::new(expr)))
                 } else {
-                    Err(format!(
-                        "Assigning to symbol which is not variable: {}",
-                        var
-                    ))
+                    Err(Error::NameConflict {
+                        existing: context.kind(&var).unwrap_or("symbol"),
+                        name: var,
+                    })
                 }
             }
+            Some(Token::Let) => Self::parse_let(tokens, context),
+            Some(Token::Const) => Self::parse_const(tokens, context),
+            Some(Token::Not) => {
+                let expr = Self::parse_terminal(tokens, context)?;
+                Ok(Expr::Not(Box::new(expr)))
+            }
             Some(Token::Id(var)) => {
-                if let Some(var) = context.get_var(&var) {
-                    Ok(Box::new(Terminal::Value(var)))
-                } else if let Some(var) = context.get_arg(&var) {
-                    Ok(Box::new(Terminal::Argument(var)))
+                if let Some(op) = fold_operator(&var, tokens) {
+                    Self::parse_fold(op, tokens, context)
+                } else if context.is_captured(&var) || context.is_external(&var) {
+                    Ok(Expr::Variable(var))
+                } else if let Some(var) = context.get_var(&var) {
+                    Ok(Expr::Value(var))
+                } else if let Some(idx) = context.get_arg(&var) {
+                    if tokens.peek() == Some(&Token::LBracket) {
+                        tokens.next();
+                        let args = Self::parse_list(tokens, context, Token::RBracket)?;
+                        Ok(Expr::Call(Callee::Argument(idx), args))
+                    } else {
+                        Ok(Expr::Argument(idx))
+                    }
+                } else if context.get_arity(&var).is_none() {
+                    Err(Error::UndefinedSymbol(var))
+                } else if tokens.peek() == Some(&Token::LBracket) {
+                    Self::parse_bracketed(var, tokens, context)
                 } else {
-                    Err(format!(
-                        "Non variable symbol as terminal token occured: {}",
-                        var
-                    ))
+                    Err(Error::Parse {
+                        message: format!("Non variable symbol as terminal token occured: {}", var),
+                        span,
+                    })
                 }
             }
-            Some(token) => Err(format!(
-                "Unexpected token while parsing terminal expression: {:?}",
-                token
-            )),
-            None => {
-                Err("Unexpected end of tokens list while parsing terminal expression".to_owned())
-            }
+            Some(token) => Err(Error::Parse {
+                message: format!(
+                    "Unexpected token while parsing terminal expression: {:?}",
+                    token
+                ),
+                span,
+            }),
+            None => Err(Error::Parse {
+                message: "Unexpected end of tokens list while parsing terminal expression"
+                    .to_owned(),
+                span,
+            }),
         }
     }
-}
 
-impl OpExpr {
-    fn get_next_multiplicative(
-        tokens: &mut Peekable<impl Iterator<Item = Token>>,
-    ) -> Option<Operator> {
-        match tokens.peek() {
-            Some(Token::Operator(Operator::Mul)) => {
-                tokens.next();
-                Some(Operator::Mul)
+    /// Name bound by `let`, `sum` or `prod`, with `=` following it
+    fn parse_binding(keyword: &str, tokens: &mut Tokens) -> Result<String> {
+        let span = tokens.span();
+        let var = match tokens.next() {
+            Some(Token::Assign(var)) => var,
+            token => {
+                return Err(Error::Parse {
+                    message: format!("Expected `name =` after `{}`, got: {:?}", keyword, token),
+                    span,
+                })
             }
-            Some(Token::Operator(Operator::Div)) => {
-                tokens.next();
-                Some(Operator::Div)
-            }
-            Some(Token::Operator(Operator::Mod)) => {
-                tokens.next();
-                Some(Operator::Mod)
-            }
-            _ => None,
+        };
+        let name = Span {
+            start: span.start,
+            end: span.start + var.len(),
+        };
+        not_reserved(&var, name)?;
+        Ok(var)
+    }
+
+    /// Complete expression followed by given keyword, which is skipped
+    fn parse_until(keyword: Token, tokens: &mut Tokens, context: &Context) -> Result<Expr> {
+        let expr = Self::parse_call(tokens, context)?;
+        if tokens.peek() != Some(&keyword) {
+            return Err(tokens.error(format!(
+                "Invalid token {:?}, expected `{}`",
+                tokens.peek(),
+                keyword
+            )));
         }
+        tokens.next();
+        Ok(expr)
     }
 
-    fn parse_multiplicative(
-        tokens: &mut Peekable<impl Iterator<Item = Token>>,
-        context: &Context,
-    ) -> Result<Box<dyn AST>> {
-        let mut result = Terminal::parse(tokens, context)?;
-
-        while let Some(op) = Self::get_next_multiplicative(tokens) {
-            let right = Terminal::parse(tokens, context)?;
-            result = Box::new(OpExpr {
-                op,
-                left: result,
-                right,
+    /// `const name = value` after `const`, like assignment it takes
+    /// everything to the end of expression. Variable becomes constant.
+    fn parse_const(tokens: &mut Tokens, context: &Context) -> Result<Expr> {
+        if !context.is_enabled(Feature::Assignments) {
+            return Err(tokens.error(Feature::Assignments.disabled()));
+        }
+
+        let var = Self::parse_binding("const", tokens)?;
+        if context.is_const(&var) {
+            return Err(Error::Eval(format!("Cannot assign to constant: {}", var)));
+        }
+        if !(context.is_var(&var) || context.force() && context.is_func(&var)) {
+            return Err(Error::NameConflict {
+                existing: context.kind(&var).unwrap_or("symbol"),
+                name: var,
             });
+        }
+        let val = Self::parse_call(tokens, context)?;
+        Ok(Expr::Const(var, Box::new(val)))
+    }
 
-            if let Some(val) = result.value() {
-                result = Box::new(Terminal::Value(val))
-            }
+    /// `let name = value in body` after `let`, like assignment it takes
+    /// everything to the end of expression
+    fn parse_let(tokens: &mut Tokens, context: &Context) -> Result<Expr> {
+        if !context.is_enabled(Feature::Bindings) {
+            return Err(tokens.error(Feature::Bindings.disabled()));
         }
 
-        Ok(result)
+        let var = Self::parse_binding("let", tokens)?;
+        let val = Self::parse_until(Token::In, tokens, context)?;
+        let body = Self::parse_call(tokens, &context.scoped(&var))?;
+        Ok(Expr::Let(var, Box::new(val), Box::new(body)))
     }
 
-    fn get_next_additive(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Option<Operator> {
-        match tokens.peek() {
-            Some(Token::Operator(Operator::Add)) => {
-                tokens.next();
-                Some(Operator::Add)
-            }
-            Some(Token::Operator(Operator::Sub)) => {
-                tokens.next();
-                Some(Operator::Sub)
+    /// `i = from to to of body` after `sum` or `prod`, body takes
+    /// everything to the end of expression
+    fn parse_fold(op: Operator, tokens: &mut Tokens, context: &Context) -> Result<Expr> {
+        if !context.is_enabled(Feature::Loops) {
+            return Err(tokens.error(Feature::Loops.disabled()));
+        }
+
+        let keyword = if op == Operator::Mul { "prod" } else { "sum" };
+        let var = Self::parse_binding(keyword, tokens)?;
+        let from = Self::parse_until(Token::To, tokens, context)?;
+        let to = Self::parse_until(Token::Of, tokens, context)?;
+        let body = Self::parse_call(tokens, &context.scoped(&var))?;
+        Ok(Expr::Fold(Box::new(Fold {
+            op,
+            var,
+            from,
+            to,
+            body,
+        })))
+    }
+
+    /// Operator precedence parsing - operands waiting for their right
+    /// operands are kept with their operators on explicit stack, so chains
+    /// of any length are parsed without recursion
+    fn parse_binary(tokens: &mut Tokens, context: &Context) -> Result<Expr> {
+        let mut pending: Vec<(Expr, Operator)> = vec![];
+        let mut operand = Self::parse_terminal(tokens, context)?;
+
+        while let Some(&Token::Operator(op)) = tokens.peek() {
+            tokens.next();
+            // Pending operators binding stronger are applied first, and
+            // those binding as strong if the operator is left associative
+            while let Some((left, prev)) = pending.pop() {
+                let applied = match prev.precedence().cmp(&op.precedence()) {
+                    Ordering::Greater => true,
+                    Ordering::Equal => op.associativity() == Associativity::Left,
+                    Ordering::Less => false,
+                };
+                if !applied {
+                    pending.push((left, prev));
+                    break;
+                }
+                operand = Expr::Binary(prev, Box::new(left), Box::new(operand));
             }
-            _ => None,
+            pending.push((operand, op));
+            operand = Self::parse_terminal(tokens, context)?;
+        }
+
+        while let Some((left, op)) = pending.pop() {
+            operand = Expr::Binary(op, Box::new(left), Box::new(operand));
         }
+        Ok(operand)
     }
 
-    fn parse_additive(
-        tokens: &mut Peekable<impl Iterator<Item = Token>>,
-        context: &Context,
-    ) -> Result<Box<dyn AST>> {
-        let mut result = Self::parse_multiplicative(tokens, context)?;
-
-        while let Some(op) = Self::get_next_additive(tokens) {
-            let right = Self::parse_multiplicative(tokens, context)?;
-            result = Box::new(OpExpr {
-                op,
-                left: result,
-                right,
-            });
+    /// Call right of `|>`, the piped value is passed as its last argument
+    /// after the bracketed ones
+    fn parse_pipe(piped: Expr, tokens: &mut Tokens, context: &Context) -> Result<Expr> {
+        let span = tokens.span();
+        let (name, callee, arity) = match tokens.next() {
+            Some(Token::Id(name)) => match (context.get_arg(&name), context.get_func(&name)) {
+                // Arity of passed function is not known
+                (Some(idx), _) => (name, Callee::Argument(idx), None),
+                (None, Some(func)) => {
+                    let arity = context.get_arity(&name);
+                    (name, func, arity)
+                }
+                (None, None) if context.kind(&name).is_none() => {
+                    return Err(Error::UndefinedSymbol(name))
+                }
+                (None, None) => {
+                    return Err(Error::Parse {
+                        message: format!("Expected function after `|>`, got: {}", name),
+                        span,
+                    })
+                }
+            },
+            token => {
+                return Err(Error::Parse {
+                    message: format!("Expected function after `|>`, got: {:?}", token),
+                    span,
+                })
+            }
+        };
+
+        let mut args = if tokens.peek() == Some(&Token::LBracket) {
+            tokens.next();
+            Self::parse_list(tokens, context, Token::RBracket)?
+        } else {
+            vec![]
+        };
+        args.push(piped);
+        match arity {
+            Some(arity) => Self::applied(name, arity, callee, args, context),
+            None => Ok(Expr::Call(callee, args)),
+        }
+    }
 
-            if let Some(val) = result.value() {
-                result = Box::new(Terminal::Value(val))
+    /// Binary operations, possibly piped with `|>` binding weaker than any
+    /// operator, and possibly as condition of `cond ? a : b` - it binds the
+    /// weakest, and the other branch takes everything to the end of
+    /// expression
+    fn parse_operation(tokens: &mut Tokens, context: &Context) -> Result<Expr> {
+        let mut cond = Self::parse_binary(tokens, context)?;
+        while tokens.peek() == Some(&Token::Pipe) {
+            if !context.is_enabled(Feature::Pipes) {
+                return Err(tokens.error(Feature::Pipes.disabled()));
             }
+            tokens.next();
+            cond = Self::parse_pipe(cond, tokens, context)?;
+        }
+        if tokens.peek() != Some(&Token::Question) {
+            return Ok(cond);
+        }
+        if !context.is_enabled(Feature::Conditionals) {
+            return Err(tokens.error(Feature::Conditionals.disabled()));
         }
 
-        Ok(result)
+        tokens.next();
+        let then = Self::parse_until(Token::Colon, tokens, context)?;
+        let other = Self::parse_call(tokens, context)?;
+        Ok(Expr::Cond(Box::new(cond), Box::new(then), Box::new(other)))
     }
 
-    fn parse(
-        tokens: &mut Peekable<impl Iterator<Item = Token>>,
-        context: &Context,
-    ) -> Result<Box<dyn AST>> {
-        Self::parse_additive(tokens, context)
+    /// Comma separated expressions, after the opening bracket - arguments of
+    /// `f(a, b)` form, or elements of `[a, b]`
+    fn parse_list(tokens: &mut Tokens, context: &Context, close: Token) -> Result<Vec<Expr>> {
+        let mut args = vec![];
+        if tokens.peek() == Some(&close) {
+            tokens.next();
+            return Ok(args);
+        }
+
+        loop {
+            args.push(Self::parse_call(tokens, context)?);
+            let span = tokens.span();
+            match tokens.next() {
+                Some(Token::Comma) => (),
+                Some(token) if token == close => return Ok(args),
+                token => {
+                    return Err(Error::Parse {
+                        message: format!("Invalid token {:?}, expected `,` or `{}`", token, close),
+                        span,
+                    })
+                }
+            }
+        }
     }
-}
 
-impl CallExpr {
-    fn get_func(
-        tokens: &mut Peekable<impl Iterator<Item = Token>>,
-        context: &Context,
-    ) -> Option<String> {
+    fn get_func(tokens: &mut Tokens, context: &Context) -> Option<String> {
         if let Some(Token::Id(f)) = tokens.peek() {
             if context.is_func(f) {
                 let name = f.clone();
@@ -322,82 +815,509 @@ impl CallExpr {
         }
     }
 
-    fn parse(
-        tokens: &mut Peekable<impl Iterator<Item = Token>>,
+    /// Call of `f(a, b)` form is complete operand, which may be followed
+    /// by operators. Single argument in brackets of function taking more of
+    /// them, followed by another operand, is the first argument of `f (a) b`
+    /// form instead.
+    fn is_bracketed(tokens: &Tokens, context: &Context) -> bool {
+        let mut ahead = tokens.clone();
+        let name = match (ahead.next(), ahead.next()) {
+            (Some(Token::Id(f)), Some(Token::LBracket)) if context.is_func(&f) => f,
+            _ => return false,
+        };
+        let single = context.get_arity(&name) == Some(1) && !context.is_variadic(&name);
+        if single || ahead.peek() == Some(&Token::RBracket) {
+            return true;
+        }
+
+        // Commas and names are only in arguments list of bracketed call
+        let mut depth = 0;
+        for token in &mut ahead {
+            match token {
+                Token::LBracket | Token::LSquare => depth += 1,
+                Token::RBracket | Token::RSquare if depth == 0 => break,
+                Token::RBracket | Token::RSquare => depth -= 1,
+                Token::Comma | Token::Assign(_) if depth == 0 => return true,
+                _ => (),
+            }
+        }
+        !matches!(
+            ahead.peek(),
+            Some(Token::Number(_))
+                | Some(Token::Str(_))
+                | Some(Token::Id(_))
+                | Some(Token::LBracket)
+                | Some(Token::LSquare)
+                | Some(Token::Not)
+        )
+    }
+
+    /// Call of `f(a, b)` form, after the function name
+    fn parse_bracketed(name: String, tokens: &mut Tokens, context: &Context) -> Result<Expr> {
+        let arity = context.get_arity(&name).unwrap_or(0);
+        let func = context
+            .get_func(&name)
+            .ok_or_else(|| Error::UndefinedSymbol(name.clone()))?;
+
+        tokens.next();
+        let params = context.get_params(&name);
+        let (args, named) = Self::parse_arguments(tokens, context, params)?;
+        if named.is_empty() {
+            return Self::applied(name, arity, func, args, context);
+        }
+        if args.len() + named.len() > arity {
+            return Err(Error::ArityMismatch {
+                func: name,
+                expected: arity,
+                got: args.len() + named.len(),
+            });
+        }
+
+        // Arguments which are not given take defaults, the rest of variadic
+        // function is empty
+        let defaults = context.get_defaults(&name);
+        let required = arity - defaults.len();
+        let mut slots: Vec<_> = args.into_iter().map(Some).collect();
+        slots.resize(arity, None);
+        for (idx, arg, span) in named {
+            if slots[idx].is_some() {
+                return Err(Error::Parse {
+                    message: format!("Argument {} of {} is given twice", params[idx], name),
+                    span,
+                });
+            }
+            slots[idx] = Some(arg);
+        }
+        let variadic = context.is_variadic(&name);
+        let args: Result<Vec<_>> = slots
+            .into_iter()
+            .enumerate()
+            .map(|(idx, slot)| match slot {
+                Some(arg) => Ok(arg),
+                None if variadic && idx + 1 == arity => Ok(Expr::List(vec![])),
+                None if idx >= required => Ok(defaults[idx - required].clone()),
+                None => {
+                    Err(tokens.error(format!("Argument {} of {} is not given", params[idx], name)))
+                }
+            })
+            .collect();
+        Ok(Expr::Call(func, args?))
+    }
+
+    /// Arguments of `f(a, name=b)` form after the opening bracket. Names of
+    /// arguments are given with their positions, and have to follow the
+    /// arguments without them. Other assignments are not names.
+    fn parse_arguments(
+        tokens: &mut Tokens,
         context: &Context,
-    ) -> Result<Box<dyn AST>> {
-        if let Some(name) = Self::get_func(tokens, context) {
+        params: &[String],
+    ) -> Result<(Vec<Expr>, Vec<Named>)> {
+        let (mut args, mut named) = (vec![], vec![]);
+        if tokens.peek() == Some(&Token::RBracket) {
+            tokens.next();
+            return Ok((args, named));
+        }
+
+        loop {
+            let span = tokens.span();
+            let param = match tokens.peek() {
+                Some(Token::Assign(var)) => params.iter().position(|param| param == var),
+                _ => None,
+            };
+            match param {
+                Some(idx) => {
+                    tokens.next();
+                    named.push((idx, Self::parse_call(tokens, context)?, span));
+                }
+                None if !named.is_empty() => {
+                    return Err(Error::Parse {
+                        message: "Argument without name after named one".to_owned(),
+                        span,
+                    })
+                }
+                None => args.push(Self::parse_call(tokens, context)?),
+            }
+
+            let span = tokens.span();
+            match tokens.next() {
+                Some(Token::Comma) => (),
+                Some(Token::RBracket) => return Ok((args, named)),
+                token => {
+                    return Err(Error::Parse {
+                        message: format!("Invalid token {:?}, expected `,` or `)`", token),
+                        span,
+                    })
+                }
+            }
+        }
+    }
+
+    /// Call with all arguments, or partial application of fewer ones if it
+    /// is enabled
+    fn applied(
+        name: String,
+        arity: usize,
+        func: Callee,
+        args: Vec<Expr>,
+        context: &Context,
+    ) -> Result<Expr> {
+        // Variadic function takes the rest of arguments as the last one, so
+        // it has no defaults
+        let mut args = args;
+        let variadic = context.is_variadic(&name);
+        let defaults = context.get_defaults(&name);
+        let required = arity - defaults.len() - usize::from(variadic);
+        if variadic && args.len() >= required {
+            let rest = args.split_off(required);
+            args.push(Expr::List(rest));
+        }
+        if (required..arity).contains(&args.len()) {
+            args.extend_from_slice(&defaults[args.len() - required..]);
+        }
+
+        if args.len() == arity {
+            return Ok(Expr::Call(func, args));
+        }
+        if args.len() < required && context.partial() {
+            let func = Reference {
+                name: name.into(),
+                arity,
+                callee: func,
+                bound: vec![],
+            };
+            return Ok(Expr::Partial(Box::new(func), args));
+        }
+        Err(Error::ArityMismatch {
+            func: name,
+            expected: arity - usize::from(variadic),
+            got: args.len(),
+        })
+    }
+
+    /// End of arguments, out of brackets or call arguments list
+    fn is_end(tokens: &Tokens) -> bool {
+        matches!(
+            tokens.peek(),
+            None | Some(Token::RBracket)
+                | Some(Token::RSquare)
+                | Some(Token::Comma)
+                | Some(Token::In)
+                | Some(Token::To)
+                | Some(Token::Of)
+                | Some(Token::Question)
+                | Some(Token::Colon)
+                | Some(Token::Pipe)
+        )
+    }
+
+    /// Function named by the next token as value, if it takes arguments, or
+    /// argument passed further
+    fn parse_reference(tokens: &mut Tokens, context: &Context) -> Option<Expr> {
+        let name = match tokens.peek() {
+            Some(Token::Id(name)) => name.clone(),
+            _ => return None,
+        };
+        if let Some(idx) = context.get_arg(&name) {
+            tokens.next();
+            return Some(Expr::Argument(idx));
+        }
+        let arity = context.get_arity(&name).filter(|arity| *arity > 0)?;
+        let callee = context.get_func(&name)?;
+        tokens.next();
+        Some(Expr::Value(Value::Func(Reference {
+            name: name.into(),
+            arity,
+            callee,
+            bound: vec![],
+        })))
+    }
+
+    /// Argument called as `f a b`, it takes all operands up to the end of
+    /// arguments. It has to be complete expression, as otherwise arguments
+    /// of other calls, as in `g a b`, would be taken as calls.
+    fn parse_argument_call(tokens: &mut Tokens, context: &Context) -> Result<Option<Expr>> {
+        let mut ahead = tokens.clone();
+        let idx = match ahead.next() {
+            Some(Token::Id(var)) => context.get_arg(&var),
+            _ => None,
+        };
+        let idx = match (idx, ahead.peek()) {
+            (
+                Some(idx),
+                Some(Token::Number(_))
+                | Some(Token::Str(_))
+                | Some(Token::Id(_))
+                | Some(Token::Not),
+            ) => idx,
+            _ => return Ok(None),
+        };
+
+        tokens.next();
+        let mut args = vec![];
+        while !Self::is_end(tokens) {
+            args.push(Self::parse_operand(tokens, context)?);
+        }
+        Ok(Some(Expr::Call(Callee::Argument(idx), args)))
+    }
+
+    /// Complete expression, eg. in brackets or assigned
+    fn parse_call(tokens: &mut Tokens, context: &Context) -> Result<Expr> {
+        match Self::parse_argument_call(tokens, context)? {
+            Some(call) => Ok(call),
+            None => Self::parse_operand(tokens, context),
+        }
+    }
+
+    /// Argument of call, in which arguments are not called
+    fn parse_operand(tokens: &mut Tokens, context: &Context) -> Result<Expr> {
+        let mut ahead = tokens.clone();
+        let fold = match ahead.next() {
+            Some(Token::Id(name)) => fold_operator(&name, &ahead).is_some(),
+            _ => false,
+        };
+
+        if fold || Self::is_bracketed(tokens, context) {
+            Self::parse_operation(tokens, context)
+        } else if let Some(name) = Self::get_func(tokens, context) {
             let arity = context.get_arity(&name).unwrap_or(0);
             let func = context
                 .get_func(&name)
-                .ok_or_else(|| format!("No function named {}", name))?;
+                .ok_or_else(|| Error::UndefinedSymbol(name.clone()))?;
+
+            // Function not followed by arguments is passed as value
+            if arity > 0 && Self::is_end(tokens) {
+                return Ok(Expr::Value(Value::Func(Reference {
+                    name: name.into(),
+                    arity,
+                    callee: func,
+                    bound: vec![],
+                })));
+            }
 
+            // Variadic function takes all operands up to the end of arguments
+            let count = if context.is_variadic(&name) {
+                usize::MAX
+            } else {
+                arity
+            };
+            let depth = tokens.nest()?;
             let mut args = vec![];
-            for _ in 0..arity {
-                let arg = CallExpr::parse(tokens, context)?;
+            for got in 0..count {
+                if Self::is_end(tokens) {
+                    break;
+                }
+
+                // Arguments called as functions take functions without
+                // brackets, as in `twice sq 3`
+                let callable = match &func {
+                    Callee::Function(body) if got < arity => body.expr.calls_argument(got),
+                    _ => false,
+                };
+                let reference = if callable {
+                    Self::parse_reference(tokens, context)
+                } else {
+                    None
+                };
+                let arg = match reference {
+                    Some(reference) => reference,
+                    None => Self::parse_operand(tokens, context)?,
+                };
                 args.push(arg);
             }
 
-            Ok(Box::new(CallExpr { func, args }))
+            tokens.depth = depth;
+            Self::applied(name, arity, func, args, context)
         } else {
-            OpExpr::parse(tokens, context)
+            Self::parse_operation(tokens, context)
         }
     }
 }
 
 impl Function {
-    fn get_id(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Option<String> {
+    fn get_id(tokens: &mut Tokens) -> Result<Option<String>> {
+        let span = tokens.span();
         match tokens.peek() {
             Some(Token::Id(id)) => {
                 let id = id.clone();
                 tokens.next();
-                Some(id)
+                not_reserved(&id, span)?;
+                Ok(Some(id))
             }
-            _ => None,
+            // Keywords have their own tokens, but they are reserved names too
+            Some(keyword) if keyword.is_keyword() => {
+                not_reserved(&keyword.to_string(), span).map(|_| None)
+            }
+            _ => Ok(None),
         }
     }
 
-    fn parse(
-        tokens: &mut Peekable<impl Iterator<Item = Token>>,
-        context: &Context,
-    ) -> Result<Box<dyn AST>> {
-        let name = Self::get_id(tokens).ok_or_else(|| format!(
-            "Expected function name, but got: {:?}",
-            tokens.peek()
-        ))?;
-
-        if !context.is_func(&name) {
-            return Err(format!(
-                "Expected function name, but got not function id: {}",
+    fn parse(tokens: &mut Tokens, context: &Context, source: String) -> Result<Expr> {
+        if !context.is_enabled(Feature::Definitions) {
+            return Err(tokens.error(Feature::Definitions.disabled()));
+        }
+
+        let name = Self::get_id(tokens)?.ok_or_else(|| {
+            tokens.error(format!(
+                "Expected function name, but got: {:?}",
+                tokens.peek()
+            ))
+        })?;
+
+        if context.strict() && context.get_arity(&name).is_some() {
+            return Err(Error::Eval(format!(
+                "{} is already defined, functions cannot be redefined in strict mode",
+                name
+            )));
+        }
+
+        match context.kind(&name) {
+            Some("variable") if context.force() => (),
+            Some(existing @ "variable") | Some(existing @ "constant") => {
+                return Err(Error::NameConflict { name, existing })
+            }
+            _ => (),
+        }
+
+        if context.is_native(&name) && !context.force() {
+            return Err(Error::Eval(format!(
+                "Cannot redefine builtin function: {}, use `--force` to shadow it",
                 name
-            ));
+            )));
         }
 
+        let head = tokens.clone();
         let mut args = vec![];
-        while let Some(arg) = Self::get_id(tokens) {
-            args.push(arg.clone());
+        while let Some(arg) = Self::get_id(tokens)? {
+            args.push(arg);
+        }
+
+        // Defaults see what the body sees, but not the arguments
+        let mut defaults = vec![];
+        let outer = Context::function_ctx(vec![], context);
+        while let Some(Token::Assign(arg)) = tokens.peek() {
+            let arg = arg.clone();
+            let span = tokens.span();
+            tokens.next();
+            not_reserved(
+                &arg,
+                Span {
+                    start: span.start,
+                    end: span.start + arg.len(),
+                },
+            )?;
+            defaults.push(optimize(Expr::parse_operand(tokens, &outer)?));
+            args.push(arg);
+        }
+        if !defaults.is_empty() && matches!(tokens.peek(), Some(Token::Id(_))) {
+            return Err(
+                tokens.error("Arguments after one with default value need defaults too".to_owned())
+            );
         }
 
-        if tokens.next() != Some(Token::Func) {
-            return Err("Expected => token".to_string());
+        let variadic = tokens.peek() == Some(&Token::Ellipsis);
+        if variadic {
+            if !defaults.is_empty() {
+                return Err(tokens.error(
+                    "Variadic function cannot have default values of arguments".to_owned(),
+                ));
+            }
+            tokens.next();
+            let rest = Self::get_id(tokens)?
+                .ok_or_else(|| tokens.error("Expected name of arguments list".to_owned()))?;
+            args.push(rest);
         }
 
+        if tokens.peek() != Some(&Token::Func) {
+            return Err(tokens.error("Expected => token".to_owned()));
+        }
+        tokens.next();
+
         let arity = args.len();
+        if let Some(previous) = context.get_arity(&name) {
+            let dependents = context.dependents(&name);
+            if previous != arity && !dependents.is_empty() && !context.force() {
+                return Err(Error::Eval(format!(
+                    "Changing arity of {} from {} to {} breaks calls in {}, use `--force` to redefine anyway",
+                    name,
+                    previous,
+                    arity,
+                    dependents.join(", ")
+                )));
+            }
+        }
+
+        let params = args.clone();
         let ctx = Context::function_ctx(args, context);
-        let expr = CallExpr::parse(tokens, &ctx)?.into();
 
-        Ok(Box::new(Function { name, arity, expr }))
+        let mut body = tokens.clone();
+        let mut calls: Vec<String> = vec![];
+        for token in head {
+            if let Token::Id(id) = token {
+                let used = ctx.get_arity(&id).is_some() && !ctx.is_native(&id)
+                    || ctx.kind(&id) == Some("variable");
+                if used && !calls.contains(&id) {
+                    calls.push(id);
+                }
+            }
+        }
+
+        let expr = optimize(complete(&mut body, &ctx)?);
+
+        Ok(Expr::Define(Function {
+            name,
+            arity,
+            body: Arc::new(Body::new(expr)),
+            source,
+            calls,
+            defaults,
+            variadic,
+            params,
+        }))
     }
 }
 
-impl Context {
-    pub fn parse(&self, tokens: impl Iterator<Item = Token>) -> Result<Box<dyn AST>> {
-        let tokens: Vec<_> = tokens.collect();
+/// Splits tokens into `;` separated statements. Statements have to be parsed
+/// one by one, after evaluating previous ones, as variables are substituted
+/// while parsing.
+pub fn statements(tokens: Vec<(Token, Span)>) -> Vec<Tokens> {
+    let mut result = vec![];
+    let mut statement = vec![];
+    let mut end = 0;
+    for (token, span) in tokens {
+        match token {
+            Token::Semicolon => result.push(Tokens::new(std::mem::take(&mut statement), end)),
+            token => statement.push((token, span)),
+        }
+        end = span.end;
+    }
+    result.push(Tokens::new(statement, end));
+    result
+}
+
+/// Parses expression which has to take all the tokens
+fn complete(tokens: &mut Tokens, context: &Context) -> Result<Expr> {
+    let expr = Expr::parse_call(tokens, context)?;
+    let start = tokens.span();
+    let trailing: Vec<_> = tokens.by_ref().collect();
+    if trailing.is_empty() {
+        Ok(expr)
+    } else {
+        Err(Error::Parse {
+            message: format!("Unexpected trailing tokens: {}", render(&trailing)),
+            span: start.to(tokens.span()),
+        })
+    }
+}
 
-        if tokens.contains(&Token::Func) {
-            Function::parse(&mut tokens.into_iter().peekable(), self)
+impl Context {
+    pub fn parse(&self, mut tokens: Tokens) -> Result<Expr> {
+        if tokens.peek().is_none() {
+            Ok(Expr::Unit)
+        } else if tokens.clone().any(|token| token == Token::Func) {
+            let source = formatter::render(tokens.rest());
+            Function::parse(&mut tokens, self, source)
         } else {
-            CallExpr::parse(&mut tokens.into_iter().peekable(), self)
+            complete(&mut tokens, self).map(optimize)
         }
     }
 }
@@ -406,91 +1326,700 @@ impl Context {
 mod test {
 
     use super::*;
+    use crate::Mode;
 
-    fn tokenize<'a>(src: &'a str) -> Peekable<impl Iterator<Item = Token> + 'a> {
-        use crate::lexer::tokenize;
-
-        tokenize(src).map(|t| t.unwrap()).peekable()
+    fn tokenize(src: &str) -> Tokens {
+        let tokens = crate::lexer::tokenize(src).map(|t| t.unwrap()).collect();
+        statements(tokens).remove(0)
     }
 
     #[test]
     fn test_terminal_number() {
-        let number = Terminal::parse(&mut tokenize("10"), &Context::new()).unwrap();
-        let expected = Terminal::Value(10.0);
-        assert!(expected.is_same(number.as_ref()));
+        let number = optimize(Expr::parse_terminal(&mut tokenize("10"), &Context::new()).unwrap());
+        let expected = Expr::Value(Value::Int(10));
+        assert_eq!(expected, number);
+    }
+
+    #[test]
+    fn test_empty() {
+        let mut context = Context::new();
+        let expr = context.parse(tokenize("# nothing here")).unwrap();
+        assert_eq!(Expr::Unit, expr);
+        assert_eq!(None, expr.evaluate(&mut context, &[]));
+    }
+
+    #[test]
+    fn test_statements() {
+        let src = crate::lexer::tokenize("a = 1; f x => x;; a")
+            .map(|t| t.unwrap())
+            .collect();
+        let expected = vec![
+            tokenize("a = 1").collect::<Vec<_>>(),
+            tokenize("f x => x").collect(),
+            vec![],
+            tokenize("a").collect(),
+        ];
+        let statements: Vec<Vec<_>> = statements(src).into_iter().map(Iterator::collect).collect();
+        assert_eq!(expected, statements);
+    }
+
+    #[test]
+    fn test_trailing_tokens() {
+        let context = Context::new();
+        let err = context.parse(tokenize("2 + 2 foo bar")).unwrap_err();
+        assert_eq!(
+            Error::Parse {
+                message: "Unexpected trailing tokens: foo bar".to_owned(),
+                span: Span { start: 6, end: 13 }
+            },
+            err
+        );
+        context.parse(tokenize("a = 1 2")).unwrap_err();
+        context.parse(tokenize("f x => 1 x")).unwrap_err();
+        context.parse(tokenize("max 1 2 3")).unwrap_err();
+    }
+
+    #[test]
+    fn error_spans() {
+        let context = Context::new();
+        let span = |src| context.parse(tokenize(src)).unwrap_err().span();
+        assert_eq!(Some(Span::at(6)), span("(1 + 2"));
+        assert_eq!(Some(Span::at(3)), span("1 + # comment"));
+        assert_eq!(Some(Span { start: 6, end: 7 }), span("max(1 2)"));
+        assert_eq!(Some(Span { start: 2, end: 3 }), span("f 1 => 1"));
+        assert_eq!(None, span("1 + b"));
+    }
+
+    #[test]
+    fn reserved_names() {
+        let context = Context::new();
+        let span = |src| context.parse(tokenize(src)).unwrap_err().span();
+        assert_eq!(Some(Span { start: 4, end: 6 }), span("a = if = 1"));
+        assert_eq!(Some(Span { start: 0, end: 3 }), span("let x => x"));
+        assert_eq!(Some(Span { start: 4, end: 9 }), span("f x while => x"));
+        context.parse(tokenize("iff = 1")).unwrap();
+    }
+
+    #[test]
+    fn literal_range() {
+        let mut context = Context::new();
+        let huge = format!("1{}", "0".repeat(400));
+        let tiny = format!("0.{}1", "0".repeat(400));
+        for literal in &[&huge, &tiny] {
+            let err = context
+                .parse(tokenize(&format!("2 * {}", literal)))
+                .unwrap_err();
+            let span = Span {
+                start: 4,
+                end: 4 + literal.len(),
+            };
+            assert_eq!(Some(span), err.span());
+        }
+
+        context.set_mode(Mode::Decimal);
+        let expr = context.parse(tokenize(&format!("{} * 2", huge))).unwrap();
+        assert_eq!(
+            format!("2{}", "0".repeat(400)),
+            expr.value().unwrap().to_string()
+        );
     }
 
     #[test]
     fn test_terminal_assignment() {
-        let assign = Terminal::parse(&mut tokenize("a = 10 + 2"), &Context::new()).unwrap();
-        let expected = Terminal::Assign("a".to_string(), Box::new(Terminal::Value(12.0)));
-        assert!(expected.is_same(assign.as_ref()));
-
-        let assign = OpExpr::parse(&mut tokenize("2 + a = 10"), &Context::new()).unwrap();
-        let expected = OpExpr {
-            op: Operator::Add,
-            left: Box::new(Terminal::Value(2.0)),
-            right: Box::new(Terminal::Assign(
+        let assign =
+            optimize(Expr::parse_terminal(&mut tokenize("a = 10 + 2"), &Context::new()).unwrap());
+        let expected = Expr::Assign("a".to_string(), Box::new(Expr::Value(Value::Int(12))));
+        assert_eq!(expected, assign);
+
+        let assign =
+            optimize(Expr::parse_operation(&mut tokenize("2 + a = 10"), &Context::new()).unwrap());
+        let expected = Expr::Binary(
+            Operator::Add,
+            Box::new(Expr::Value(Value::Int(2))),
+            Box::new(Expr::Assign(
                 "a".to_string(),
-                Box::new(Terminal::Value(10.0)),
+                Box::new(Expr::Value(Value::Int(10))),
             )),
-        };
-        assert!(expected.is_same(assign.as_ref()));
+        );
+        assert_eq!(expected, assign);
     }
 
     #[test]
     fn text_op_expr_mul() {
-        let expr = OpExpr::parse_multiplicative(&mut tokenize("10"), &Context::new()).unwrap();
-        let expected = Terminal::Value(10.0);
-        assert!(expected.is_same(expr.as_ref()));
+        let expr = optimize(Expr::parse_operation(&mut tokenize("10"), &Context::new()).unwrap());
+        let expected = Expr::Value(Value::Int(10));
+        assert_eq!(expected, expr);
 
-        let expr = OpExpr::parse_multiplicative(&mut tokenize("10 * 2"), &Context::new()).unwrap();
+        let expr =
+            optimize(Expr::parse_operation(&mut tokenize("10 * 2"), &Context::new()).unwrap());
 
-        let expected = Terminal::Value(20.0);
-        assert!(expected.is_same(expr.as_ref()));
+        let expected = Expr::Value(Value::Int(20));
+        assert_eq!(expected, expr);
 
-        let expr = OpExpr::parse_multiplicative(&mut tokenize("10 / 2"), &Context::new()).unwrap();
+        let expr =
+            optimize(Expr::parse_operation(&mut tokenize("10 / 2"), &Context::new()).unwrap());
 
-        let expected = Terminal::Value(5.0);
-        assert!(expected.is_same(expr.as_ref()));
+        let expected = Expr::Value(Value::Int(5));
+        assert_eq!(expected, expr);
 
-        let expr = OpExpr::parse_multiplicative(&mut tokenize("10 % 2"), &Context::new()).unwrap();
+        let expr =
+            optimize(Expr::parse_operation(&mut tokenize("10 % 2"), &Context::new()).unwrap());
 
-        let expected = Terminal::Value(0.0);
-        assert!(expected.is_same(expr.as_ref()));
+        let expected = Expr::Value(Value::Int(0));
+        assert_eq!(expected, expr);
 
-        let expr =
-            OpExpr::parse_multiplicative(&mut tokenize("11 % 2 * 5 / 3"), &Context::new()).unwrap();
+        let expr = optimize(
+            Expr::parse_operation(&mut tokenize("11 % 2 * 5 / 3"), &Context::new()).unwrap(),
+        );
 
-        let expected = Terminal::Value(5.0f32 / 3.0f32);
-        assert!(expected.is_same(expr.as_ref()));
+        let expected = Expr::Value(Value::Float(5.0 / 3.0));
+        assert_eq!(expected, expr);
     }
 
     #[test]
     fn text_op_expr_add() {
-        let expr = OpExpr::parse_additive(&mut tokenize("10"), &Context::new()).unwrap();
-        let expected = Terminal::Value(10.0);
-        assert!(expected.is_same(expr.as_ref()));
+        let expr = optimize(Expr::parse_operation(&mut tokenize("10"), &Context::new()).unwrap());
+        let expected = Expr::Value(Value::Int(10));
+        assert_eq!(expected, expr);
+
+        let expr =
+            optimize(Expr::parse_operation(&mut tokenize("10 + 2"), &Context::new()).unwrap());
+
+        let expected = Expr::Value(Value::Int(12));
+        assert_eq!(expected, expr);
+
+        let expr =
+            optimize(Expr::parse_operation(&mut tokenize("10 - 2"), &Context::new()).unwrap());
 
-        let expr = OpExpr::parse_additive(&mut tokenize("10 + 2"), &Context::new()).unwrap();
+        let expected = Expr::Value(Value::Int(8));
+        assert_eq!(expected, expr);
 
-        let expected = Terminal::Value(12.0);
-        assert!(expected.is_same(expr.as_ref()));
+        let expr =
+            optimize(Expr::parse_operation(&mut tokenize("11 + 2 - 5"), &Context::new()).unwrap());
 
-        let expr = OpExpr::parse_additive(&mut tokenize("10 - 2"), &Context::new()).unwrap();
+        let expected = Expr::Value(Value::Int(8));
+        assert_eq!(expected, expr);
 
-        let expected = Terminal::Value(8.0);
-        assert!(expected.is_same(expr.as_ref()));
+        let expr = optimize(
+            Expr::parse_operation(&mut tokenize("10 * 3 - 6 / 2"), &Context::new()).unwrap(),
+        );
 
-        let expr = OpExpr::parse_additive(&mut tokenize("11 + 2 - 5"), &Context::new()).unwrap();
+        let expected = Expr::Value(Value::Int(27));
+        assert_eq!(expected, expr);
+    }
 
-        let expected = Terminal::Value(8.0f32);
-        assert!(expected.is_same(expr.as_ref()));
+    #[test]
+    fn text_op_expr_pow() {
+        let expr =
+            optimize(Expr::parse_operation(&mut tokenize("2 ^ 3"), &Context::new()).unwrap());
+        let expected = Expr::Value(Value::Int(8));
+        assert_eq!(expected, expr);
 
         let expr =
-            OpExpr::parse_additive(&mut tokenize("10 * 3 - 6 / 2"), &Context::new()).unwrap();
+            optimize(Expr::parse_operation(&mut tokenize("2 ^ 3 ^ 2"), &Context::new()).unwrap());
+        let expected = Expr::Value(Value::Int(512));
+        assert_eq!(expected, expr);
+
+        let expr = optimize(
+            Expr::parse_operation(&mut tokenize("3 * 2 ^ 2 - 4"), &Context::new()).unwrap(),
+        );
+        let expected = Expr::Value(Value::Int(8));
+        assert_eq!(expected, expr);
+    }
+
+    #[test]
+    fn associativity() {
+        let context =
+            Context::function_ctx(vec!["a".into(), "b".into(), "c".into()], &Context::new());
+        let parse = |src| optimize(Expr::parse_operation(&mut tokenize(src), &context).unwrap());
+        let binary = |op, left, right| Expr::Binary(op, Box::new(left), Box::new(right));
+        let (a, b, c) = (Expr::Argument(0), Expr::Argument(1), Expr::Argument(2));
+
+        assert_eq!(
+            binary(
+                Operator::Sub,
+                binary(Operator::Sub, a.clone(), b.clone()),
+                c.clone()
+            ),
+            parse("a - b - c")
+        );
+        assert_eq!(
+            binary(
+                Operator::Pow,
+                a.clone(),
+                binary(Operator::Pow, b.clone(), c.clone())
+            ),
+            parse("a ^ b ^ c")
+        );
+        assert_eq!(
+            binary(
+                Operator::Or,
+                a.clone(),
+                binary(
+                    Operator::And,
+                    b.clone(),
+                    binary(Operator::Eq, c, binary(Operator::Mul, a, b))
+                )
+            ),
+            parse("a || b && c == a * b")
+        );
+    }
+
+    #[test]
+    fn text_op_expr_logic() {
+        let expr = optimize(
+            Expr::parse_operation(&mut tokenize("1 + 1 && 0 || 2"), &Context::new()).unwrap(),
+        );
+        let expected = Expr::Value(Value::Int(1));
+        assert_eq!(expected, expr);
+
+        let expr =
+            optimize(Expr::parse_operation(&mut tokenize("1 || 0 && 0"), &Context::new()).unwrap());
+        let expected = Expr::Value(Value::Int(1));
+        assert_eq!(expected, expr);
+
+        let expr =
+            optimize(Expr::parse_operation(&mut tokenize("!0 && !3"), &Context::new()).unwrap());
+        let expected = Expr::Value(Value::Int(0));
+        assert_eq!(expected, expr);
+
+        let expr =
+            optimize(Expr::parse_operation(&mut tokenize("!!5 * 2"), &Context::new()).unwrap());
+        let expected = Expr::Value(Value::Int(2));
+        assert_eq!(expected, expr);
+    }
+
+    #[test]
+    fn short_circuit() {
+        let mut context = Context::new();
+
+        let expr = optimize(Expr::parse_operation(&mut tokenize("0 && a = 5"), &context).unwrap());
+        assert_eq!(Some(Value::Int(0)), expr.evaluate(&mut context, &[]));
+        assert_eq!(None, context.get_var("a"));
+
+        let expr = optimize(Expr::parse_operation(&mut tokenize("1 || a = 5"), &context).unwrap());
+        assert_eq!(Some(Value::Int(1)), expr.evaluate(&mut context, &[]));
+        assert_eq!(None, context.get_var("a"));
+
+        let expr = optimize(Expr::parse_operation(&mut tokenize("1 && a = 5"), &context).unwrap());
+        assert_eq!(Some(Value::Int(1)), expr.evaluate(&mut context, &[]));
+        assert_eq!(Some(Value::Int(5)), context.get_var("a"));
+    }
+
+    #[test]
+    fn text_op_expr_comparison() {
+        let expr = optimize(
+            Expr::parse_operation(&mut tokenize("1 + 2 === 3 && 1"), &Context::new()).unwrap(),
+        );
+        let expected = Expr::Value(Value::Int(1));
+        assert_eq!(expected, expr);
+
+        let expr = optimize(
+            Expr::parse_operation(&mut tokenize("1 === 1.0000001"), &Context::new()).unwrap(),
+        );
+        let expected = Expr::Value(Value::Int(0));
+        assert_eq!(expected, expr);
+
+        let mut context = Context::new();
+        let expr =
+            optimize(Expr::parse_operation(&mut tokenize("1 == 1.0000001"), &context).unwrap());
+        assert_eq!(Some(Value::Int(1)), expr.evaluate(&mut context, &[]));
+
+        context.set_eq_epsilon(0.0).unwrap();
+        assert_eq!(Some(Value::Int(0)), expr.evaluate(&mut context, &[]));
+    }
+
+    #[test]
+    fn arity_change() {
+        let mut context = Context::new();
+        for line in &["f x => x", "g x => f x", "f x => x + 1"] {
+            let expr = context.parse(tokenize(line)).unwrap();
+            expr.evaluate(&mut context, &[]);
+        }
+        assert!(context.take_warnings().is_empty());
+
+        context.parse(tokenize("f x y => x + y")).unwrap_err();
+        context.parse(tokenize("g x y => x + y")).unwrap();
+
+        context.set_force(true);
+        let expr = context.parse(tokenize("f x y => x + y")).unwrap();
+        expr.evaluate(&mut context, &[]);
+        assert_eq!(1, context.take_warnings().len());
+        assert_eq!(Some(2), context.get_arity("f"));
+    }
+
+    #[test]
+    fn name_conflicts() {
+        let mut context = Context::new();
+        for src in &["x = 5", "f a => a"] {
+            let expr = context.parse(tokenize(src)).unwrap();
+            expr.evaluate(&mut context, &[]);
+        }
+
+        let conflict = |name: &str, existing| Error::NameConflict {
+            name: name.to_owned(),
+            existing,
+        };
+        assert_eq!(
+            conflict("x", "variable"),
+            context.parse(tokenize("x a => a")).unwrap_err()
+        );
+        assert_eq!(
+            conflict("f", "function"),
+            context.parse(tokenize("f = 1")).unwrap_err()
+        );
+        assert_eq!(
+            conflict("pi", "constant"),
+            context.parse(tokenize("pi a => a")).unwrap_err()
+        );
+        assert_eq!(
+            conflict("sqrt", "builtin function"),
+            context.parse(tokenize("sqrt = 1")).unwrap_err()
+        );
+        context.parse(tokenize("x 1 2")).unwrap_err();
+
+        context.set_force(true);
+        let expr = context.parse(tokenize("x a => a * 2")).unwrap();
+        expr.evaluate(&mut context, &[]);
+        let expr = context.parse(tokenize("f = 3")).unwrap();
+        expr.evaluate(&mut context, &[]);
+        assert_eq!(
+            vec![
+                "Variable x is replaced by function",
+                "Function f is replaced by variable"
+            ],
+            context.take_warnings()
+        );
+
+        let expr = context.parse(tokenize("x f")).unwrap();
+        assert_eq!(Some(Value::Int(6)), expr.evaluate(&mut context, &[]));
+        context.parse(tokenize("pi a => a")).unwrap_err();
+    }
+
+    #[test]
+    fn missing_arguments() {
+        let mut context = Context::new();
+        let expr = context
+            .parse(tokenize("avg a b c => (a + b + c) / 3"))
+            .unwrap();
+        expr.evaluate(&mut context, &[]);
+
+        let mismatch = |func: &str, expected, got| Error::ArityMismatch {
+            func: func.to_owned(),
+            expected,
+            got,
+        };
+        assert_eq!(
+            mismatch("avg", 3, 1),
+            context.parse(tokenize("avg 1")).unwrap_err()
+        );
+        assert_eq!(
+            mismatch("max", 2, 1),
+            context.parse(tokenize("avg 1 2 max 3")).unwrap_err()
+        );
+        assert_eq!(
+            mismatch("avg", 3, 2),
+            context.parse(tokenize("max(avg 1 2, 3)")).unwrap_err()
+        );
+        assert!(matches!(
+            context.parse(tokenize("avg")),
+            Ok(Expr::Value(Value::Func(_)))
+        ));
+    }
+
+    #[test]
+    fn bracketed_calls() {
+        let mut context = Context::new();
+        let expr = context.parse(tokenize("f x y => x - y")).unwrap();
+        expr.evaluate(&mut context, &[]);
+
+        let eval = |src| {
+            let mut context = Context::function_ctx(vec![], &context);
+            let expr = context.parse(tokenize(src))?;
+            Ok(expr.evaluate(&mut context, &[]))
+        };
+        assert_eq!(Ok(Some(Value::Float(2.0))), eval("max(1, 2)"));
+        assert_eq!(Ok(Some(Value::Float(4.0))), eval("f(sqrt(16) + 4, 2 * 2)"));
+        assert_eq!(Ok(Some(Value::Int(7))), eval("1 + f(5, 2) * 2"));
+        assert_eq!(Ok(Some(Value::Int(9))), eval("(1 + 2) * 3"));
+        assert_eq!(Ok(Some(Value::Float(1.0))), eval("f max(1, 2) 1"));
+        // Single bracketed argument followed by another one is not the list
+        assert_eq!(Ok(Some(Value::Int(0))), eval("f (1 + 2) 3"));
+        assert_eq!(Ok(Some(Value::Int(-1))), eval("f (1) (2)"));
+        assert_eq!(Ok(Some(Value::Float(5.0))), eval("f (max(4, 8)) (3)"));
+        assert_eq!(
+            Err(Error::ArityMismatch {
+                func: "f".to_owned(),
+                expected: 2,
+                got: 1
+            }),
+            eval("f(1)")
+        );
+        eval("f()").unwrap_err();
+        eval("f(1 2)").unwrap_err();
+        eval("f(1, 2").unwrap_err();
+    }
+
+    #[test]
+    fn argument_calls() {
+        let mut context = Context::new();
+        for src in &[
+            "twice f x => f (f x)",
+            "apply f x => f x",
+            "pair f a b => a + f(b)",
+        ] {
+            let expr = context.parse(tokenize(src)).unwrap();
+            expr.evaluate(&mut context, &[]);
+        }
+
+        let body = |func: &str| match context.get_func(func) {
+            Some(Callee::Function(body)) => body,
+            _ => panic!("{} is not user function", func),
+        };
+        assert!(body("twice").expr.calls_argument(0));
+        assert!(!body("twice").expr.calls_argument(1));
+        assert!(!body("pair").expr.calls_argument(1));
+
+        let expr = context
+            .parse(tokenize("g h x => apply h (apply(h, x))"))
+            .unwrap();
+        match expr {
+            Expr::Define(ref func) => assert!(func.body.expr.calls_argument(0)),
+            expr => panic!("{:?} is not definition", expr),
+        }
+
+        let expr = context.parse(tokenize("twice sqrt 16")).unwrap();
+        assert_eq!(Some(Value::Float(2.0)), expr.evaluate(&mut context, &[]));
+        let expr = context.parse(tokenize("apply(pair, 1)")).unwrap();
+        assert!(expr.evaluate(&mut context, &[]).unwrap().as_f64().is_nan());
+        let expr = context.parse(tokenize("pair sqrt 1 4")).unwrap();
+        assert_eq!(Some(Value::Float(3.0)), expr.evaluate(&mut context, &[]));
+        context.parse(tokenize("apply sqrt")).unwrap_err();
+    }
+
+    #[test]
+    fn let_in() {
+        let mut context = Context::new();
+        let expr = context.parse(tokenize("let t = 2 in t * 3")).unwrap();
+        let expected = Expr::Let(
+            "t".to_owned(),
+            Box::new(Expr::Value(Value::Int(2))),
+            Box::new(Expr::Binary(
+                Operator::Mul,
+                Box::new(Expr::Argument(0)),
+                Box::new(Expr::Value(Value::Int(3))),
+            )),
+        );
+        assert_eq!(expected, expr);
+        assert_eq!(Some(Value::Int(6)), expr.evaluate(&mut context, &[]));
+        assert_eq!(None, context.kind("t"));
+
+        let expr = context
+            .parse(tokenize("f a => let b = a + 1 in let a = b * 2 in a + b"))
+            .unwrap();
+        expr.evaluate(&mut context, &[]);
+        let expr = context.parse(tokenize("f 1")).unwrap();
+        assert_eq!(Some(Value::Int(6)), expr.evaluate(&mut context, &[]));
+
+        let expr = context.parse(tokenize("let g = max in g 1 2")).unwrap();
+        assert_eq!(Some(Value::Float(2.0)), expr.evaluate(&mut context, &[]));
+
+        context.parse(tokenize("let t = 1")).unwrap_err();
+        context.parse(tokenize("let 1 in 2")).unwrap_err();
+        context.parse(tokenize("let t = 1 in t = 2")).unwrap_err();
+    }
+
+    #[test]
+    fn folds() {
+        let mut context = Context::new();
+        let mut eval = |src| {
+            let expr = context.parse(tokenize(src)).unwrap();
+            expr.evaluate(&mut context, &[])
+        };
+        assert_eq!(Some(Value::Int(338350)), eval("sum i = 1 to 100 of i * i"));
+        assert_eq!(Some(Value::Int(120)), eval("prod i = 1 to 5 of i"));
+        assert_eq!(Some(Value::Int(0)), eval("sum i = 2 to 1 of i"));
+        assert_eq!(Some(Value::Int(1)), eval("prod i = 2 to 1 of i"));
+        assert_eq!(
+            Some(Value::Int(20)),
+            eval("sum i = 1 to 4 of sum j = 1 to i of j")
+        );
+        assert!(eval("sum i = 1 to 1 / 0 of i").unwrap().as_f64().is_nan());
+
+        eval("fact n => prod i = 1 to n of i");
+        assert_eq!(Some(Value::Int(3628800)), eval("fact 10"));
+        assert_eq!(None, context.kind("i"));
+
+        context.parse(tokenize("sum i = 1 of i")).unwrap_err();
+        context.parse(tokenize("sum 1 to 2 of 3")).unwrap_err();
+    }
+
+    #[test]
+    fn conditions() {
+        let mut context = Context::new();
+        let mut eval = |src| {
+            let expr = context.parse(tokenize(src)).unwrap();
+            expr.evaluate(&mut context, &[])
+        };
+        eval("x = 0");
+        eval("sign a => a == 0 ? 0 : a === abs(a) ? 1 : 0 - 1");
+        assert_eq!(Some(Value::Int(1)), eval("sign 3"));
+        assert_eq!(Some(Value::Int(-1)), eval("sign(0 - 2)"));
+        assert_eq!(Some(Value::Int(0)), eval("sign 0"));
+        assert_eq!(Some(Value::Int(4)), eval("1 + 1 == 2 ? 2 * 2 : 3"));
+        assert_eq!(Some(Value::Int(2)), eval("0 ? 1 : 1 ? 2 : 3"));
+        assert_eq!(Some(Value::Int(2)), eval("1 ? 0 ? 1 : 2 : 3"));
+
+        // Not chosen branch is not evaluated
+        eval("set a => a ? a : x = 1");
+        assert_eq!(Some(Value::Int(2)), eval("set 2"));
+        assert_eq!(Some(Value::Int(0)), eval("x"));
+
+        let expr = context.parse(tokenize("1 ? x : 2")).unwrap();
+        assert_eq!(Expr::Value(Value::Int(0)), expr);
+        context.parse(tokenize("1 ? 2")).unwrap_err();
+        context.parse(tokenize("1 ? 2 : ")).unwrap_err();
+    }
+
+    #[test]
+    fn defaults() {
+        let mut context = Context::new();
+        let mut eval = |src| {
+            let expr = context.parse(tokenize(src)).unwrap();
+            expr.evaluate(&mut context, &[])
+        };
+        eval("sq x => x * x");
+        eval("f x y=sq 3 z=0 - 1 => x + y * z");
+        assert_eq!(Some(Value::Int(-8)), eval("f 1"));
+        assert_eq!(Some(Value::Int(-1)), eval("f(1, 2)"));
+        assert_eq!(Some(Value::Int(7)), eval("f 1 2 3"));
+        eval("g a=2 => a");
+        assert_eq!(Some(Value::Int(2)), eval("g()"));
+
+        context.parse(tokenize("h x=1 y => x")).unwrap_err();
+        context.parse(tokenize("h x=1 => y")).unwrap_err();
+        context.parse(tokenize("h x=y => x")).unwrap_err();
+        context.parse(tokenize("h in=1 => 2")).unwrap_err();
+        let err = context.parse(tokenize("f()")).unwrap_err();
+        assert_eq!(
+            Error::ArityMismatch {
+                func: "f".to_owned(),
+                expected: 3,
+                got: 0
+            },
+            err
+        );
+    }
+
+    #[test]
+    fn named_arguments() {
+        let mut context = Context::new();
+        let mut eval = |src| {
+            let expr = context.parse(tokenize(src)).unwrap();
+            expr.evaluate(&mut context, &[])
+        };
+        eval("x = 0");
+        eval("f a b c=10 => a - b * c");
+        assert_eq!(Some(Value::Int(-19)), eval("f(b=2, a=1)"));
+        assert_eq!(Some(Value::Int(3)), eval("f(5, c=1, b=2)"));
+        // Other assignments are not names
+        assert_eq!(Some(Value::Int(-29)), eval("f(1, x = 3)"));
+        assert_eq!(Some(Value::Int(3)), eval("x"));
+        eval("g a ...xs => a + len(xs)");
+        assert_eq!(Some(Value::Int(1)), eval("g(a=1)"));
+        assert_eq!(Some(Value::Int(3)), eval("g(xs=[1, 2], a=1)"));
+
+        context.parse(tokenize("f(a=1)")).unwrap_err();
+        context.parse(tokenize("f(a=1, 2)")).unwrap_err();
+        context.parse(tokenize("f(1, a=2)")).unwrap_err();
+        context.parse(tokenize("f(1, 2, 3, c=4)")).unwrap_err();
+    }
+
+    #[test]
+    fn pipes() {
+        let mut context = Context::new();
+        let mut eval = |src| {
+            let expr = context.parse(tokenize(src)).unwrap();
+            expr.evaluate(&mut context, &[])
+        };
+        eval("sq x => x * x");
+        eval("sub a b => a - b");
+        assert_eq!(Some(Value::Int(81)), eval("1 + 2 |> sq |> sq"));
+        assert_eq!(Some(Value::Int(6)), eval("4 |> sub(10)"));
+        assert_eq!(Some(Value::Int(2)), eval("0 |> sq ? 1 : 2"));
+        assert_eq!(Some(Value::Int(16)), eval("let g = sq in 2 |> g |> g"));
+
+        context.parse(tokenize("2 |> sub")).unwrap_err();
+        context.parse(tokenize("2 |> sub(1, 2)")).unwrap_err();
+        context.parse(tokenize("2 |> 3")).unwrap_err();
+        context.parse(tokenize("2 |> pi")).unwrap_err();
+        context.parse(tokenize("2 |> sq + 1")).unwrap_err();
+    }
+
+    #[test]
+    fn builtins() {
+        let mut context = Context::new();
+        let expr = context.parse(tokenize("max sqrt 16 2 * 3")).unwrap();
+        assert_eq!(Some(Value::Float(6.0)), expr.evaluate(&mut context, &[]));
+
+        context.parse(tokenize("sqrt x => x")).unwrap_err();
+        context.set_force(true);
+        let expr = context.parse(tokenize("sqrt x => x")).unwrap();
+        expr.evaluate(&mut context, &[]);
+        assert_eq!(1, context.take_warnings().len());
+        assert!(!context.is_native("sqrt"));
+
+        let expr = context.parse(tokenize("sqrt 16")).unwrap();
+        assert_eq!(Some(Value::Int(16)), expr.evaluate(&mut context, &[]));
+    }
+
+    #[test]
+    fn constants() {
+        let mut context = Context::new();
+        let expr = context.parse(tokenize("tau / pi")).unwrap();
+        assert_eq!(Expr::Value(Value::Float(2.0)), expr);
+
+        let expr = context.parse(tokenize("circle r => tau * r")).unwrap();
+        expr.evaluate(&mut context, &[]);
+        let expr = context.parse(tokenize("circle 1")).unwrap();
+        assert_eq!(
+            Some(Value::Float(std::f64::consts::TAU)),
+            expr.evaluate(&mut context, &[])
+        );
+
+        let err = context.parse(tokenize("pi = 3")).unwrap_err();
+        assert_eq!(Error::Eval("Cannot assign to constant: pi".to_owned()), err);
+        context.parse(tokenize("f x => e = x")).unwrap_err();
+        context.update_var("e", Value::Int(1)).unwrap_err();
+
+        let expr = context.parse(tokenize("const g = 9.5 * 2")).unwrap();
+        assert_eq!(Some(Value::Float(19.0)), expr.evaluate(&mut context, &[]));
+        assert_eq!(Some("constant"), context.kind("g"));
+        context.parse(tokenize("g = 1")).unwrap_err();
+        context.parse(tokenize("const g = 1")).unwrap_err();
+        context.parse(tokenize("const circle = 1")).unwrap_err();
+        context.parse(tokenize("const = 1")).unwrap_err();
+
+        // Assigned in function before it became constant
+        let expr = context.parse(tokenize("k = 1; set x => k = x")).unwrap();
+        expr.evaluate(&mut context, &[]);
+        let expr = context.parse(tokenize("set x => k = x")).unwrap();
+        expr.evaluate(&mut context, &[]);
+        let expr = context.parse(tokenize("const k = 2")).unwrap();
+        expr.evaluate(&mut context, &[]);
+        let expr = context.parse(tokenize("set 3")).unwrap();
+        assert_eq!(None, expr.evaluate(&mut context, &[]));
+        assert!(matches!(context.take_failure(), Some(Error::Eval(_))));
+        assert_eq!(Some(Value::Int(2)), context.get_var("k"));
+    }
+
+    #[test]
+    fn last_result() {
+        let mut context = Context::new();
+        context.set_last_result(Value::Int(5));
 
-        let expected = Terminal::Value(27.0);;
-        assert!(expected.is_same(expr.as_ref()));
+        let expr = context.parse(tokenize("_ * 2")).unwrap();
+        assert_eq!(Expr::Value(Value::Int(10)), expr);
+        context.parse(tokenize("_ = 2")).unwrap_err();
     }
 }
@@ -0,0 +1,494 @@
+//! Canonical source of expression trees - printed expression parses back to
+//! equivalent tree, as long as called functions are not redefined
+
+use crate::context::literal;
+use crate::lexer::Associativity;
+use crate::parser::{Callee, Expr};
+use crate::sink::json_string;
+use crate::{Context, Error, Operator, Result, Value};
+
+/// Binding of terminals, which are never bracketed
+const TERMINAL: u8 = u8::MAX;
+
+fn local(locals: &[String], idx: usize) -> Result<String> {
+    locals.get(idx).cloned().ok_or_else(|| {
+        Error::Eval(format!(
+            "Argument {} cannot be printed out of its function",
+            idx
+        ))
+    })
+}
+
+fn callee_name(callee: &Callee, context: &Context, locals: &[String]) -> Result<String> {
+    match callee {
+        Callee::Native(native) => Ok(native.name.to_string()),
+        Callee::Function(body) => context
+            .function_name(body)
+            .map(str::to_owned)
+            .ok_or_else(|| Error::Eval("Called function is no longer defined".to_owned())),
+        Callee::Argument(idx) => local(locals, *idx),
+    }
+}
+
+/// Source with binding of its top level, operands binding weaker than their
+/// position needs are bracketed. Names bound by `let` are indexed as the
+/// arguments they are passed as.
+fn source(expr: &Expr, context: &Context, locals: &[String]) -> Result<(String, u8)> {
+    let print = |expr| source(expr, context, locals).map(|(source, _)| source);
+    let operand = |expr, binding| operand(expr, context, locals, binding);
+
+    let printed = match expr {
+        Expr::Unit => (String::new(), TERMINAL),
+        Expr::Value(val) => {
+            // Negative and infinite values are expressions, but lists are
+            // bracketed anyway
+            let literal = literal(val);
+            let binding = match val {
+                Value::List(_) => TERMINAL,
+                _ if literal.contains(' ') => 0,
+                _ => TERMINAL,
+            };
+            (literal, binding)
+        }
+        Expr::Argument(idx) => (local(locals, *idx)?, TERMINAL),
+        Expr::Variable(var) => (var.clone(), TERMINAL),
+        Expr::Assign(var, val) => (format!("{} = {}", var, print(val)?), 0),
+        Expr::Const(var, val) => (format!("const {} = {}", var, print(val)?), 0),
+        Expr::Binary(op, left, right) => {
+            let (left_binding, right_binding) = match op.associativity() {
+                Associativity::Left => (op.precedence(), op.precedence() + 1),
+                Associativity::Right => (op.precedence() + 1, op.precedence()),
+            };
+            (
+                format!(
+                    "{} {} {}",
+                    operand(left, left_binding)?,
+                    op,
+                    operand(right, right_binding)?
+                ),
+                op.precedence(),
+            )
+        }
+        Expr::Not(val) => (format!("!{}", operand(val, TERMINAL)?), TERMINAL),
+        Expr::Call(callee, args) => {
+            let name = callee_name(callee, context, locals)?;
+            // Assignment would name the argument
+            let arg = |arg: &Expr| {
+                let (printed, _) = source(arg, context, locals)?;
+                match arg {
+                    Expr::Assign(..) => Ok(format!("({})", printed)),
+                    _ => Ok(printed),
+                }
+            };
+            // Rest of arguments of variadic function is collected to list,
+            // unless it is given by name
+            let printed: Result<Vec<_>> = match args.split_last() {
+                Some((rest, fixed)) if context.is_variadic(&name) => {
+                    let rest = match rest {
+                        Expr::List(items) => items.iter().map(arg).collect(),
+                        rest => {
+                            let params = context.get_params(&name);
+                            let param = params.last().map_or("", String::as_str);
+                            vec![arg(rest).map(|rest| format!("{}={}", param, rest))]
+                        }
+                    };
+                    fixed.iter().map(arg).chain(rest).collect()
+                }
+                _ => args.iter().map(arg).collect(),
+            };
+            (format!("{}({})", name, printed?.join(", ")), TERMINAL)
+        }
+        Expr::List(items) => {
+            let items: Result<Vec<_>> = items.iter().map(print).collect();
+            (format!("[{}]", items?.join(", ")), TERMINAL)
+        }
+        // Parses back to partial application only if it is enabled
+        Expr::Partial(func, args) => {
+            let args: Result<Vec<_>> = args.iter().map(print).collect();
+            (format!("{}({})", func.name, args?.join(", ")), TERMINAL)
+        }
+        Expr::Index(list, idx) => (
+            format!("{}[{}]", operand(list, TERMINAL)?, print(idx)?),
+            TERMINAL,
+        ),
+        Expr::Let(var, val, body) => {
+            let mut scoped = locals.to_vec();
+            scoped.push(var.clone());
+            let (body, _) = source(body, context, &scoped)?;
+            (format!("let {} = {} in {}", var, print(val)?, body), 0)
+        }
+        Expr::Fold(fold) => {
+            let mut scoped = locals.to_vec();
+            scoped.push(fold.var.clone());
+            let (body, _) = source(&fold.body, context, &scoped)?;
+            let keyword = if fold.op == Operator::Mul {
+                "prod"
+            } else {
+                "sum"
+            };
+            let (from, to) = (print(&fold.from)?, print(&fold.to)?);
+            (
+                format!("{} {} = {} to {} of {}", keyword, fold.var, from, to, body),
+                0,
+            )
+        }
+        Expr::Cond(cond, then, other) => (
+            format!(
+                "{} ? {} : {}",
+                operand(cond, 1)?,
+                print(then)?,
+                print(other)?
+            ),
+            0,
+        ),
+        Expr::Define(func) => (func.source.clone(), 0),
+    };
+    Ok(printed)
+}
+
+fn operand(expr: &Expr, context: &Context, locals: &[String], binding: u8) -> Result<String> {
+    let (source, actual) = source(expr, context, locals)?;
+    if actual < binding {
+        Ok(format!("({})", source))
+    } else {
+        Ok(source)
+    }
+}
+
+/// Source parsing to equivalent expression in the same context
+pub fn print(expr: &Expr, context: &Context) -> Result<String> {
+    source(expr, context, &[]).map(|(source, _)| source)
+}
+
+/// Visits labels of node and its children, with depth of every one
+fn node(
+    expr: &Expr,
+    context: &Context,
+    locals: &[String],
+    depth: usize,
+    visit: &mut dyn FnMut(usize, String),
+) -> Result<()> {
+    let mut scoped = locals.to_vec();
+    let (label, children): (String, Vec<&Expr>) = match expr {
+        Expr::Unit => ("Unit".to_owned(), vec![]),
+        Expr::Value(val) => (format!("Value {}", literal(val)), vec![]),
+        Expr::Argument(idx) => (format!("Argument {}", local(locals, *idx)?), vec![]),
+        Expr::Variable(var) => (format!("Variable {}", var), vec![]),
+        Expr::Assign(var, val) => (format!("Assign {}", var), vec![val]),
+        Expr::Const(var, val) => (format!("Const {}", var), vec![val]),
+        Expr::Binary(op, left, right) => (format!("Binary {}", op), vec![left, right]),
+        Expr::Not(val) => ("Not".to_owned(), vec![val]),
+        Expr::Call(callee, args) => (
+            format!("Call {}", callee_name(callee, context, locals)?),
+            args.iter().collect(),
+        ),
+        Expr::List(items) => ("List".to_owned(), items.iter().collect()),
+        Expr::Partial(func, args) => (format!("Partial {}", func), args.iter().collect()),
+        Expr::Index(list, idx) => ("Index".to_owned(), vec![list, idx]),
+        Expr::Cond(cond, then, other) => ("Cond".to_owned(), vec![cond, then, other]),
+        Expr::Let(var, val, body) => {
+            // Value is printed before the name is bound
+            visit(depth, format!("Let {}", var));
+            node(val, context, locals, depth + 1, visit)?;
+            scoped.push(var.clone());
+            return node(body, context, &scoped, depth + 1, visit);
+        }
+        Expr::Fold(fold) => {
+            let keyword = if fold.op == Operator::Mul {
+                "Prod"
+            } else {
+                "Sum"
+            };
+            visit(depth, format!("{} {}", keyword, fold.var));
+            node(&fold.from, context, locals, depth + 1, visit)?;
+            node(&fold.to, context, locals, depth + 1, visit)?;
+            scoped.push(fold.var.clone());
+            return node(&fold.body, context, &scoped, depth + 1, visit);
+        }
+        Expr::Define(func) => {
+            scoped = func.params.clone();
+            (
+                format!("Define {} {}", func.name, func.params.join(" ")),
+                vec![&func.body.expr],
+            )
+        }
+    };
+
+    visit(depth, label.trim_end().to_owned());
+    for child in children {
+        node(child, context, &scoped, depth + 1, visit)?;
+    }
+    Ok(())
+}
+
+/// Tree of expression, one node per line with its children indented
+pub fn tree(expr: &Expr, context: &Context) -> Result<String> {
+    let mut lines = vec![];
+    node(expr, context, &[], 0, &mut |depth, label| {
+        lines.push(format!("{}{}", "  ".repeat(depth), label))
+    })?;
+    Ok(lines.join("\n"))
+}
+
+/// Tree of expression in Graphviz format, nodes are numbered in order they
+/// are visited and edges go from parents to children
+pub fn to_dot(expr: &Expr, context: &Context) -> Result<String> {
+    let mut result = "digraph ast {\n".to_owned();
+    // Numbers of ancestors of the visited node
+    let mut parents: Vec<usize> = vec![];
+    let mut count = 0;
+    node(expr, context, &[], 0, &mut |depth, label| {
+        let label = label.replace('\\', "\\\\").replace('"', "\\\"");
+        result += &format!("    n{} [label=\"{}\"];\n", count, label);
+        parents.truncate(depth);
+        if let Some(parent) = parents.last() {
+            result += &format!("    n{} -> n{};\n", parent, count);
+        }
+        parents.push(count);
+        count += 1;
+    })?;
+    Ok(result + "}")
+}
+
+/// Labels of nodes in order they are visited, with their depths
+fn nodes(expr: &Expr, context: &Context) -> Result<Vec<(usize, String)>> {
+    let mut nodes = vec![];
+    node(expr, context, &[], 0, &mut |depth, label| {
+        nodes.push((depth, label))
+    })?;
+    Ok(nodes)
+}
+
+/// Tree of expression as S-expression, every node is its label followed
+/// by its children: `(Binary * (Value 2) (Variable x))`
+pub fn to_sexpr(expr: &Expr, context: &Context) -> Result<String> {
+    let mut result = String::new();
+    let mut open = 0;
+    for (depth, label) in nodes(expr, context)? {
+        for _ in depth..open {
+            result.push(')');
+        }
+        if !result.is_empty() {
+            result.push(' ');
+        }
+        result += &format!("({}", label);
+        open = depth + 1;
+    }
+    Ok(result + &")".repeat(open))
+}
+
+/// Tree of expression as JSON, every node has its kind, the rest of its
+/// label (or null) and children:
+/// `{"node":"Binary","label":"*","children":[...]}`
+pub fn to_json(expr: &Expr, context: &Context) -> Result<String> {
+    let mut result = String::new();
+    let mut open = 0;
+    for (depth, label) in nodes(expr, context)? {
+        for _ in depth..open {
+            result += "]}";
+        }
+        if depth < open {
+            result.push(',');
+        }
+        let (kind, label) = match label.split_once(' ') {
+            Some((kind, label)) => (kind, json_string(label)),
+            None => (label.as_str(), "null".to_owned()),
+        };
+        result += &format!(
+            "{{\"node\":{},\"label\":{},\"children\":[",
+            json_string(kind),
+            label
+        );
+        open = depth + 1;
+    }
+    Ok(result + &"]}".repeat(open))
+}
+
+/// The same trees, but values are compared numerically - literals may
+/// become integers or decimals when they are parsed
+pub fn equivalent(left: &Expr, right: &Expr) -> bool {
+    match (left, right) {
+        (Expr::Value(left), Expr::Value(right)) => {
+            left.exact_eq(right) || left.as_f64().is_nan() && right.as_f64().is_nan()
+        }
+        (Expr::Assign(left_var, left), Expr::Assign(right_var, right))
+        | (Expr::Const(left_var, left), Expr::Const(right_var, right)) => {
+            left_var == right_var && equivalent(left, right)
+        }
+        (Expr::Binary(left_op, left_l, left_r), Expr::Binary(right_op, right_l, right_r)) => {
+            left_op == right_op && equivalent(left_l, right_l) && equivalent(left_r, right_r)
+        }
+        (Expr::Not(left), Expr::Not(right)) => equivalent(left, right),
+        (Expr::List(left), Expr::List(right)) => all_equivalent(left, right),
+        (Expr::Index(left_list, left), Expr::Index(right_list, right)) => {
+            equivalent(left_list, right_list) && equivalent(left, right)
+        }
+        (Expr::Let(left_var, left_val, left), Expr::Let(right_var, right_val, right)) => {
+            left_var == right_var && equivalent(left_val, right_val) && equivalent(left, right)
+        }
+        (Expr::Cond(left_cond, left_then, left), Expr::Cond(right_cond, right_then, right)) => {
+            equivalent(left_cond, right_cond)
+                && equivalent(left_then, right_then)
+                && equivalent(left, right)
+        }
+        (Expr::Fold(left), Expr::Fold(right)) => {
+            left.op == right.op
+                && left.var == right.var
+                && equivalent(&left.from, &right.from)
+                && equivalent(&left.to, &right.to)
+                && equivalent(&left.body, &right.body)
+        }
+        (Expr::Call(left, left_args), Expr::Call(right, right_args)) => {
+            left == right && all_equivalent(left_args, right_args)
+        }
+        (Expr::Partial(left, left_args), Expr::Partial(right, right_args)) => {
+            left == right && all_equivalent(left_args, right_args)
+        }
+        (left, right) => left == right,
+    }
+}
+
+fn all_equivalent(left: &[Expr], right: &[Expr]) -> bool {
+    left.len() == right.len()
+        && left
+            .iter()
+            .zip(right)
+            .all(|(left, right)| equivalent(left, right))
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::Interpreter;
+
+    fn printed(interpreter: &Interpreter, src: &str) -> String {
+        print(&interpreter.parse(src).unwrap(), interpreter.context()).unwrap()
+    }
+
+    #[test]
+    fn brackets() {
+        let mut interpreter = Interpreter::new();
+        interpreter.eval("x = 0 - 2; f a b => a - b").unwrap();
+        assert_eq!(
+            "(0 - 2) * f(1, 0 - 5)",
+            printed(&interpreter, "x * f(1, x - 3)")
+        );
+        assert_eq!(
+            "f(1, 2) - (3 - f(4, 5))",
+            printed(&interpreter, "f(1, 2) - (3 - f(4, 5))")
+        );
+        assert_eq!(
+            "(sqrt(2) ^ sqrt(3)) ^ sqrt(4)",
+            printed(&interpreter, "(sqrt(2) ^ sqrt(3)) ^ sqrt(4)")
+        );
+        assert_eq!(
+            "!(sqrt(2) && (y = 3))",
+            printed(&interpreter, "!(sqrt(2) && y = 3)")
+        );
+        assert_eq!("f a b => a - b", printed(&interpreter, "f a b => a - b"));
+        assert_eq!("", printed(&interpreter, "# comment"));
+        assert_eq!(
+            "[0 - 2, 3][f(1, 0)]",
+            printed(&interpreter, "[x, 3][f(1, 0)]")
+        );
+        assert_eq!(
+            "(1 - f(0 - 2, 1))[0]",
+            printed(&interpreter, "(1 - f(x, 1))[0]")
+        );
+        assert_eq!(
+            "1 + (let t = 2 in let g = f in g(t, 1))",
+            printed(&interpreter, "1 + let t = 2 in let g = f in g t 1")
+        );
+    }
+
+    #[test]
+    fn trees() {
+        let mut interpreter = Interpreter::new();
+        interpreter.eval("sq x => x * x").unwrap();
+        let tree = |src| tree(&interpreter.parse(src).unwrap(), interpreter.context()).unwrap();
+        assert_eq!(
+            "Binary *\n  Value 2\n  Binary +\n    Value 3\n    Call sq\n      Value 4",
+            tree("2 * (3 + sq(4))")
+        );
+        assert_eq!(
+            "Define f a b\n  Cond\n    Argument a\n    Let t\n      Argument b\n      Argument t\n    Value []",
+            tree("f a b => a ? let t = b in t : []")
+        );
+    }
+
+    #[test]
+    fn dot() {
+        let interpreter = Interpreter::new();
+        let expr = interpreter.parse(r#"2 * (x = 3 + "a\"")"#).unwrap();
+        let expected = r#"digraph ast {
+    n0 [label="Binary *"];
+    n1 [label="Value 2"];
+    n0 -> n1;
+    n2 [label="Assign x"];
+    n0 -> n2;
+    n3 [label="Value \"3a\\\"\""];
+    n2 -> n3;
+}"#;
+        assert_eq!(expected, to_dot(&expr, interpreter.context()).unwrap());
+    }
+
+    #[test]
+    fn exports() {
+        let mut interpreter = Interpreter::new();
+        interpreter.eval("sq x => x * x").unwrap();
+        let expr = interpreter.parse("f a => sq(a) + !a").unwrap();
+        assert_eq!(
+            "(Define f a (Binary + (Call sq (Argument a)) (Not (Argument a))))",
+            to_sexpr(&expr, interpreter.context()).unwrap()
+        );
+        assert_eq!(
+            concat!(
+                r#"{"node":"Define","label":"f a","children":[{"node":"Binary","label":"+","children":["#,
+                r#"{"node":"Call","label":"sq","children":[{"node":"Argument","label":"a","children":[]}]},"#,
+                r#"{"node":"Not","label":null,"children":[{"node":"Argument","label":"a","children":[]}]}]}]}"#
+            ),
+            to_json(&expr, interpreter.context()).unwrap()
+        );
+
+        let expr = interpreter.parse("\"a b\"").unwrap();
+        assert_eq!(
+            "(Value \"a b\")",
+            to_sexpr(&expr, interpreter.context()).unwrap()
+        );
+        assert_eq!(
+            r#"{"node":"Value","label":"\"a b\"","children":[]}"#,
+            to_json(&expr, interpreter.context()).unwrap()
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .eval("sq x => x * x; total ...xs => len xs; f a b => a - b; z = 0")
+            .unwrap();
+        for src in &[
+            "sq 2 * 3 + 1",
+            "2 ^ sq(3) ^ 2",
+            "sq(2) - sq(3) - sq(4)",
+            "z = !sq(0) || sq(1) == 1.5",
+            "0 / 0 + sq(1)",
+            "max(sq 2, sq sq 3)",
+            "[sq 2, 0 - 1][1] + len([sq 1])",
+            "let t = sq 2 in t * (let u = t + 1 in u - t)",
+            "1 + (sum i = 1 to sq 3 of prod j = i to 2 * i of sq(j) - i)",
+            "(z = sq 2) ? sq(3) == 9 ? 1 : 2 : 2 * (sq(1) ? 3 : 4)",
+            "total(1, [2], total()) + total(3, 4)",
+            "f(b=1, a=z = 2) + total(xs=[sq 2]) + f(1, (z = 3))",
+        ] {
+            let expr = interpreter.parse(src).unwrap();
+            let reparsed = interpreter.parse(&printed(&interpreter, src)).unwrap();
+            assert!(equivalent(&expr, &reparsed), "{}", src);
+        }
+
+        let expr = interpreter.parse("1 + sq(2)").unwrap();
+        let other = interpreter.parse("1 + sq(3)").unwrap();
+        assert!(!equivalent(&expr, &other));
+    }
+}
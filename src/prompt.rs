@@ -0,0 +1,148 @@
+use crate::editor::Keymap;
+use crate::Result;
+
+pub const DEFAULT: &str = "> ";
+
+/// Prompt of lines continuing unfinished statement, unless template shows
+/// it with `{cont}`
+pub const CONTINUATION: &str = "... ";
+
+/// Session state which may be shown in prompt
+pub struct State {
+    pub count: usize, // Number of line being read, starting from 1
+    pub force: bool,
+    pub keymap: Keymap,
+    pub normal: bool,  // Line is edited in vi normal mode
+    pub pending: bool, // Line continues unfinished statement
+}
+
+#[derive(Debug)]
+enum Part {
+    Text(String),
+    Count,
+    Mode,
+    Keymap,
+    Cont,
+}
+
+/// Prompt template, eg. `"{count}:{mode}> "`
+#[derive(Debug)]
+pub struct Prompt {
+    parts: Vec<Part>,
+}
+
+impl Prompt {
+    pub fn new(template: &str) -> Result<Self> {
+        let mut parts = vec![];
+        let mut tail = template;
+
+        while let Some(start) = tail.find('{') {
+            if start > 0 {
+                parts.push(Part::Text(tail[..start].to_owned()));
+            }
+
+            let end = tail[start..]
+                .find('}')
+                .ok_or_else(|| format!("Unclosed placeholder in prompt: {}", template))?;
+            let part = match &tail[start + 1..start + end] {
+                "count" => Part::Count,
+                "mode" => Part::Mode,
+                "keymap" => Part::Keymap,
+                "cont" => Part::Cont,
+                name => return Err(format!("Unknown prompt placeholder: {{{}}}", name).into()),
+            };
+            parts.push(part);
+            tail = &tail[start + end + 1..];
+        }
+
+        if !tail.is_empty() {
+            parts.push(Part::Text(tail.to_owned()));
+        }
+
+        Ok(Prompt { parts })
+    }
+
+    pub fn render(&self, state: &State) -> String {
+        if state.pending && !self.parts.iter().any(|part| matches!(part, Part::Cont)) {
+            return CONTINUATION.to_owned();
+        }
+
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Count => state.count.to_string(),
+                Part::Mode if state.force => "force".to_owned(),
+                Part::Mode => "".to_owned(),
+                Part::Keymap => state.keymap.indicator(state.normal).to_owned(),
+                Part::Cont if state.pending => "...".to_owned(),
+                Part::Cont => "".to_owned(),
+            })
+            .collect()
+    }
+}
+
+impl Default for Prompt {
+    fn default() -> Self {
+        Prompt {
+            parts: vec![Part::Text(DEFAULT.to_owned())],
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn render() {
+        let state = State {
+            count: 3,
+            force: true,
+            keymap: Keymap::Vi,
+            normal: false,
+            pending: false,
+        };
+
+        assert_eq!("> ", Prompt::default().render(&state));
+        assert_eq!(
+            "3:force> ",
+            Prompt::new("{count}:{mode}> ").unwrap().render(&state)
+        );
+        assert_eq!("[3]", Prompt::new("[{count}]").unwrap().render(&state));
+        assert_eq!("[I]> ", Prompt::new("{keymap}> ").unwrap().render(&state));
+        let normal = State {
+            normal: true,
+            ..state
+        };
+        assert_eq!("[N]> ", Prompt::new("{keymap}> ").unwrap().render(&normal));
+        assert_eq!(
+            "3> ",
+            Prompt::new("{count}{cont}> ").unwrap().render(&state)
+        );
+
+        let state = State {
+            count: 1,
+            force: false,
+            keymap: Keymap::Emacs,
+            normal: false,
+            pending: true,
+        };
+        assert_eq!("... ", Prompt::default().render(&state));
+        assert_eq!(
+            "... ",
+            Prompt::new("{count}:{mode}> ").unwrap().render(&state)
+        );
+        assert_eq!(
+            "1...> ",
+            Prompt::new("{count}{cont}> ").unwrap().render(&state)
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        Prompt::new("{count").unwrap_err();
+        Prompt::new("{line}> ").unwrap_err();
+    }
+}
@@ -0,0 +1,1059 @@
+use crate::autosave::{self, Autosave};
+use crate::context::literal;
+use crate::editor::{self, History, Keymap};
+use crate::envelope::{self, Kind};
+use crate::lexer::{self, Token};
+use crate::prompt::{self, Prompt};
+use crate::sink::{Event, OutputSink};
+use crate::transcript::Recorder;
+use crate::{bench, codegen, cost, graph, printer, Context, Error, Interpreter, Lang, Result};
+use std::collections::HashMap;
+use std::io::{stdout, BufRead, Write};
+use std::path::Path;
+
+/// Source of lines for the session
+pub trait Input {
+    /// Next line without terminator, `None` at the end of input
+    fn read_line(&mut self, prompt: &str) -> Result<Option<String>>;
+
+    /// Names completed with Tab, given before every line
+    fn set_completions(&mut self, _names: Vec<String>) {}
+
+    /// Key bindings of line editing, with prompt shown in vi normal mode,
+    /// given before every line
+    fn set_keymap(&mut self, _keymap: Keymap, _normal_prompt: String) {}
+
+    /// Problems which didn't stop reading, since the last call
+    fn take_warnings(&mut self) -> Vec<String> {
+        vec![]
+    }
+}
+
+/// Lines of any reader, the prompt is printed only for interactive one
+pub struct LineReader<R> {
+    reader: R,
+    interactive: bool,
+}
+
+impl<R: BufRead> LineReader<R> {
+    pub fn new(reader: R, interactive: bool) -> Self {
+        LineReader {
+            reader,
+            interactive,
+        }
+    }
+}
+
+impl<R: BufRead> Input for LineReader<R> {
+    fn read_line(&mut self, prompt: &str) -> Result<Option<String>> {
+        if self.interactive {
+            print!("{}", prompt);
+            stdout().flush().ok();
+        }
+
+        let report = |err: &_| eprintln!("Error: Cannot read input: {}, retrying", err);
+        editor::read_line(&mut self.reader, self.interactive, report)
+    }
+}
+
+const HELP: &str = "\
+Expressions:
+  x = 1 + 2 * 3        assignment, `const g = 9.81` cannot be reassigned
+  sq x => x * x        function definition, called as `sq 2` or `sq(2)`
+  a == b, a ? b : c    comparison and conditional
+  [1, 2, 3][0]         list and its item
+  let t = 2 in t * t   local binding
+  sum i = 1 to 10 of i fold, also `prod`
+  x |> f               pipe, passes `x` as the last argument
+Commands:
+  :help                this help
+  :vars, :funcs        user variables and functions
+  :show name           definition of symbol
+  :unset name          removes user symbol
+  :reset               removes all user symbols
+  :snapshot name       saves the session state, `:restore name` goes back to it
+  :set option value    changes option, eg. `:set precision 5`
+  :mode [float|decimal] switches number backend
+  :cost expression     estimated cost of expression
+  :tokens expression   tokens of expression with their spans
+  :ast expression      tree of parsed expression
+  :dot expression      the tree in Graphviz format
+  :bench f args runs   measures calls of function
+  :time expression     measures evaluations of expression
+  :explain [code]      describes error code
+  :export file         saves definitions
+  :compile file        compiles functions to WebAssembly module
+  :graph [dot|json]    dependencies of definitions
+  :recover             restores autosaved session
+  :quit                ends the session";
+
+/// Evaluations of expression measured by `:time`, unless set otherwise
+pub const DEFAULT_TIME_RUNS: usize = 10;
+
+/// Commands completed in interactive session
+pub const COMMANDS: &[&str] = &[
+    ":ast",
+    ":bench",
+    ":compile",
+    ":cost",
+    ":dot",
+    ":explain",
+    ":export",
+    ":funcs",
+    ":graph",
+    ":help",
+    ":mode",
+    ":quit",
+    ":recover",
+    ":reset",
+    ":restore",
+    ":set",
+    ":show",
+    ":snapshot",
+    ":time",
+    ":tokens",
+    ":unset",
+    ":vars",
+];
+
+/// Value of option which is `on` or `off`
+fn switch(value: &str) -> Result<bool> {
+    match value {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(format!("Expected `on` or `off`, got: {}", value).into()),
+    }
+}
+
+/// Statement continued on the next line, as the line ends with `\\` or
+/// leaves brackets open. Gives its source without `\\` and comment, which
+/// the next line is appended to.
+fn continued(src: &str) -> Option<String> {
+    let trimmed = src.trim_end();
+    let (code, escaped) = match trimmed.strip_suffix('\\') {
+        Some(code) => (code, true),
+        None => (trimmed, false),
+    };
+
+    // Invalid tokens are reported at once
+    let tokens: Vec<_> = lexer::tokenize(code).collect::<Result<_>>().ok()?;
+    let depth: isize = tokens
+        .iter()
+        .map(|(token, _)| match token {
+            Token::LBracket | Token::LSquare => 1,
+            Token::RBracket | Token::RSquare => -1,
+            _ => 0,
+        })
+        .sum();
+    if !escaped && depth <= 0 {
+        return None;
+    }
+
+    let end = tokens.last().map_or(0, |(_, span)| span.end);
+    Some(code[..end].to_owned())
+}
+
+/// Interactive session: evaluates lines and commands, recording and
+/// autosaving them if enabled
+pub struct Repl {
+    interpreter: Interpreter,
+    recorder: Option<Recorder<Box<dyn Write>>>,
+    autosave: Option<Autosave>,
+    autosave_error: Option<Error>,
+    check: bool,
+    mismatches: usize,
+    prompt: Prompt,
+    keymap: Keymap,
+    history: Option<History>,
+    lang: Lang,
+    base: u32,        // Base of shown integral results
+    count: usize,     // Lines processed so far
+    time_runs: usize, // Evaluations measured by `:time`
+    snapshots: HashMap<String, Context>,
+    quit: bool,
+    sandbox: bool, // Commands cannot write files
+}
+
+impl Repl {
+    pub fn new(interpreter: Interpreter) -> Self {
+        Repl {
+            interpreter,
+            recorder: None,
+            autosave: None,
+            autosave_error: None,
+            check: false,
+            mismatches: 0,
+            prompt: Prompt::default(),
+            keymap: Keymap::default(),
+            history: None,
+            lang: Lang::default(),
+            base: 10,
+            count: 0,
+            time_runs: DEFAULT_TIME_RUNS,
+            snapshots: HashMap::new(),
+            quit: false,
+            sandbox: false,
+        }
+    }
+
+    pub fn set_recorder(&mut self, recorder: Recorder<Box<dyn Write>>) {
+        self.recorder = Some(recorder);
+    }
+
+    pub fn set_autosave(&mut self, autosave: Autosave) {
+        self.autosave = Some(autosave);
+    }
+
+    pub fn set_history(&mut self, history: History) {
+        self.history = Some(history);
+    }
+
+    /// Rejects commands writing files, for sessions of remote users
+    pub fn set_sandbox(&mut self, sandbox: bool) {
+        self.sandbox = sandbox;
+    }
+
+    /// Language of command responses
+    pub fn set_lang(&mut self, lang: Lang) {
+        self.lang = lang;
+    }
+
+    /// Reports outputs differing from expected ones
+    pub fn set_check(&mut self, check: bool) {
+        self.check = check;
+    }
+
+    fn recover(&mut self) -> Result<String> {
+        let autosave = self.autosave.as_ref().ok_or("Autosave is disabled")?;
+        let definitions = autosave::recover(autosave.path())?;
+
+        for definition in &definitions {
+            self.interpreter
+                .eval(definition)
+                .map_err(|err| format!("Cannot recover `{}`: {}", definition, err))?;
+        }
+
+        Ok(format!("Recovered {} definitions", definitions.len()))
+    }
+
+    fn bench(&mut self, args: &str) -> Result<String> {
+        let (func, args, runs) = bench::parse_args(args)?;
+        let stats = bench::bench(self.interpreter.context_mut(), func, &args, runs)?;
+        Ok(format!("{}: {}", func, stats))
+    }
+
+    /// Evaluates expression parsed once as many times as `time-runs` tells
+    fn time(&mut self, src: &str) -> Result<String> {
+        if src.trim().is_empty() {
+            return Err("Usage: :time expression".into());
+        }
+
+        let expr = self.interpreter.parse(src)?;
+        let interpreter = &mut self.interpreter;
+        let stats = bench::measure(self.time_runs, || interpreter.eval_expr(&expr).map(drop))?;
+        Ok(stats.to_string())
+    }
+
+    fn set(&mut self, args: &str) -> Result<String> {
+        let (option, value) = args.split_once(' ').ok_or("Usage: :set option value")?;
+        let value = value.trim();
+
+        match option {
+            "eq-epsilon" => {
+                let epsilon = value
+                    .parse()
+                    .map_err(|err| format!("Invalid tolerance: {}, {}", value, err))?;
+                self.interpreter.context_mut().set_eq_epsilon(epsilon)?;
+                Ok(format!("{} = {}", option, epsilon))
+            }
+            "precision" => {
+                let digits = value
+                    .parse()
+                    .map_err(|err| format!("Invalid precision: {}, {}", value, err))?;
+                self.interpreter.context_mut().set_precision(digits)?;
+                Ok(format!("{} = {}", option, digits))
+            }
+            "prompt" => {
+                // Quotes allow leading and trailing whitespace
+                let template = value
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .unwrap_or(value);
+                self.prompt = Prompt::new(template)?;
+                Ok(format!("{} = \"{}\"", option, template))
+            }
+            "keymap" => {
+                self.keymap = value.parse()?;
+                Ok(format!("{} = {}", option, self.keymap.name()))
+            }
+            "base" => {
+                let base = match value {
+                    "2" | "8" | "10" | "16" => value.parse().unwrap_or(10),
+                    _ => {
+                        return Err(format!("Base has to be 2, 8, 10 or 16, got: {}", value).into())
+                    }
+                };
+                self.base = base;
+                Ok(format!("{} = {}", option, base))
+            }
+            "time-runs" => {
+                let runs =
+                    value.parse().ok().filter(|runs| *runs > 0).ok_or_else(|| {
+                        format!("Number of runs has to be positive, got: {}", value)
+                    })?;
+                self.time_runs = runs;
+                Ok(format!("{} = {}", option, runs))
+            }
+            "force" => {
+                self.interpreter.context_mut().set_force(switch(value)?);
+                Ok(format!("{} = {}", option, value))
+            }
+            "partial" => {
+                self.interpreter.context_mut().set_partial(switch(value)?);
+                Ok(format!("{} = {}", option, value))
+            }
+            "capture" => {
+                let context = self.interpreter.context_mut();
+                context.set_capture(value.parse()?);
+                Ok(format!("{} = {}", option, context.capture().name()))
+            }
+            _ => Err(format!("Unknown option: {}", option).into()),
+        }
+    }
+
+    fn export(&self, path: &str) -> Result<String> {
+        if path.is_empty() {
+            return Err("Usage: :export file".into());
+        }
+        if self.sandbox {
+            return Err("Files cannot be written in this session".into());
+        }
+
+        let definitions = self.interpreter.context().definitions()?;
+        let content: String = definitions.iter().map(|def| format!("{}\n", def)).collect();
+        std::fs::write(path, envelope::wrap(Kind::Session, &content))
+            .map_err(|err| format!("Cannot write {}: {}", path, err))?;
+        Ok(format!(
+            "Exported {} definitions to {}",
+            definitions.len(),
+            path
+        ))
+    }
+
+    /// Functions which cannot be compiled are listed with the reason
+    fn compile(&self, path: &str) -> Result<String> {
+        if path.is_empty() {
+            return Err("Usage: :compile file".into());
+        }
+        if self.sandbox {
+            return Err("Files cannot be written in this session".into());
+        }
+
+        let module = codegen::compile(self.interpreter.context());
+        std::fs::write(path, &module.bytes)
+            .map_err(|err| format!("Cannot write {}: {}", path, err))?;
+        let mut lines = vec![format!(
+            "Compiled {} functions to {}",
+            module.exported.len(),
+            path
+        )];
+        for (name, reason) in &module.skipped {
+            lines.push(format!("Skipped {}: {}", name, reason));
+        }
+        Ok(lines.join("\n"))
+    }
+
+    fn graph(&self, format: &str) -> Result<String> {
+        let nodes = self.interpreter.context().graph();
+        match format {
+            "" | "dot" => Ok(graph::to_dot(&nodes)),
+            "json" => Ok(graph::to_json(&nodes)),
+            _ => Err(format!("Unknown graph format: {}", format).into()),
+        }
+    }
+
+    fn vars(&self) -> String {
+        let context = self.interpreter.context();
+        let vars: Vec<_> = context
+            .variables()
+            .into_iter()
+            .map(|(name, val)| {
+                let keyword = if context.is_const(name) { "const " } else { "" };
+                format!("{}{} = {}", keyword, name, literal(val))
+            })
+            .collect();
+        if vars.is_empty() {
+            "No variables defined".to_owned()
+        } else {
+            vars.join("\n")
+        }
+    }
+
+    fn funcs(&self) -> String {
+        let funcs: Vec<_> = self
+            .interpreter
+            .context()
+            .functions()
+            .into_iter()
+            .map(|func| func.source.as_str())
+            .collect();
+        if funcs.is_empty() {
+            "No functions defined".to_owned()
+        } else {
+            funcs.join("\n")
+        }
+    }
+
+    /// Session ends after `:quit`, as at the end of input
+    pub fn quit(&self) -> bool {
+        self.quit
+    }
+
+    /// Source recreating user symbol, or what the builtin one is
+    fn show(&self, name: &str) -> Result<String> {
+        if name.is_empty() {
+            return Err("Usage: :show name".into());
+        }
+
+        let context = self.interpreter.context();
+        if let Some(definition) = context.definition(name) {
+            return Ok(definition);
+        }
+        let kind = context
+            .kind(name)
+            .ok_or_else(|| Error::UndefinedSymbol(name.to_owned()))?;
+        Ok(match (context.get_arity(name), context.get_var(name)) {
+            (Some(1), _) => format!("{}: {} of 1 argument", name, kind),
+            (Some(arity), _) => format!("{}: {} of {} arguments", name, kind, arity),
+            (None, Some(val)) => format!("{}: {} = {}", name, kind, literal(&val)),
+            (None, None) => format!("{}: {}", name, kind),
+        })
+    }
+
+    fn unset(&mut self, name: &str) -> Result<String> {
+        if name.is_empty() {
+            return Err("Usage: :unset name".into());
+        }
+
+        let kind = self.interpreter.context_mut().remove_symbol(name)?;
+        Ok(format!("Removed {} {}", kind, name))
+    }
+
+    fn reset(&mut self) -> String {
+        let removed = self.interpreter.context_mut().reset();
+        format!("Removed {} symbols", removed)
+    }
+
+    /// Whole context is kept, settings included
+    fn snapshot(&mut self, name: &str) -> Result<String> {
+        if name.is_empty() {
+            return Err("Usage: :snapshot name".into());
+        }
+
+        let context = self.interpreter.context().clone();
+        self.snapshots.insert(name.to_owned(), context);
+        Ok(format!("Saved snapshot {}", name))
+    }
+
+    /// Snapshot is kept, so it can be restored again
+    fn restore(&mut self, name: &str) -> Result<String> {
+        if name.is_empty() {
+            return Err("Usage: :restore name".into());
+        }
+
+        let snapshot = self
+            .snapshots
+            .get(name)
+            .ok_or_else(|| format!("Unknown snapshot: {}", name))?;
+        *self.interpreter.context_mut() = snapshot.clone();
+        Ok(format!("Restored snapshot {}", name))
+    }
+
+    /// Estimated cost of evaluating expression, which is not evaluated
+    fn cost(&self, expr: &str) -> Result<String> {
+        if expr.trim().is_empty() {
+            return Err("Usage: :cost expression".into());
+        }
+
+        let expr = self.interpreter.parse(expr)?;
+        Ok(cost::cost(&expr).to_string())
+    }
+
+    fn tokens(&self, src: &str) -> Result<String> {
+        if src.trim().is_empty() {
+            return Err("Usage: :tokens expression".into());
+        }
+
+        let tokens: Vec<_> = lexer::tokenize(src).collect::<Result<_>>()?;
+        let lines: Vec<_> = tokens
+            .iter()
+            .map(|(token, span)| format!("{}..{} {:?}", span.start, span.end, token))
+            .collect();
+        Ok(lines.join("\n"))
+    }
+
+    /// Tree of expression, which is not evaluated
+    fn ast(&self, src: &str) -> Result<String> {
+        if src.trim().is_empty() {
+            return Err("Usage: :ast expression".into());
+        }
+
+        let expr = self.interpreter.parse(src)?;
+        printer::tree(&expr, self.interpreter.context())
+    }
+
+    fn dot(&self, src: &str) -> Result<String> {
+        if src.trim().is_empty() {
+            return Err("Usage: :dot expression".into());
+        }
+
+        let expr = self.interpreter.parse(src)?;
+        printer::to_dot(&expr, self.interpreter.context())
+    }
+
+    /// Switches backend of numbers with fraction, or shows the current one
+    fn mode(&mut self, mode: &str) -> Result<String> {
+        let context = self.interpreter.context_mut();
+        if !mode.is_empty() {
+            context.set_mode(mode.parse()?);
+        }
+        Ok(format!("mode = {}", context.mode().name()))
+    }
+
+    /// Explanation of error code, or list of all codes
+    fn explain(&self, code: &str) -> Result<String> {
+        if code.is_empty() {
+            let codes: Vec<_> = self
+                .lang
+                .catalog()
+                .iter()
+                .map(|(code, title, _)| format!("{} {}", code, title))
+                .collect();
+            return Ok(codes.join("\n"));
+        }
+
+        self.lang
+            .explain(code)
+            .ok_or_else(|| format!("Unknown error code: {}", code).into())
+    }
+
+    /// Commands are lines starting with `:`, they are not valid expressions
+    fn command(&mut self, line: &str) -> Result<String> {
+        let (command, args) = line.split_once(' ').unwrap_or((line, ""));
+        match command {
+            ":help" => Ok(HELP.to_owned()),
+            ":vars" => Ok(self.vars()),
+            ":funcs" => Ok(self.funcs()),
+            ":quit" => {
+                self.quit = true;
+                Ok("Bye".to_owned())
+            }
+            ":recover" => self.recover(),
+            ":bench" => self.bench(args),
+            ":time" => self.time(args),
+            ":set" => self.set(args),
+            ":show" => self.show(args.trim()),
+            ":unset" => self.unset(args.trim()),
+            ":reset" => Ok(self.reset()),
+            ":snapshot" => self.snapshot(args.trim()),
+            ":restore" => self.restore(args.trim()),
+            ":graph" => self.graph(args.trim()),
+            ":export" => self.export(args.trim()),
+            ":compile" => self.compile(args.trim()),
+            ":explain" => self.explain(args.trim()),
+            ":cost" => self.cost(args),
+            ":tokens" => self.tokens(args),
+            ":ast" => self.ast(args),
+            ":dot" => self.dot(args),
+            ":mode" => self.mode(args.trim()),
+            _ => Err(format!("Unknown command: {}", command).into()),
+        }
+    }
+
+    /// Applies config file, every line is `option value` pair as for `:set`
+    pub fn configure(&mut self, path: &Path) -> Result<()> {
+        let config = std::fs::read_to_string(path)
+            .map_err(|err| format!("Cannot read config {}: {}", path.display(), err))?;
+
+        let lines = config.lines().map(str::trim).enumerate();
+        for (idx, line) in lines.filter(|(_, line)| !line.is_empty() && !line.starts_with('#')) {
+            self.set(line)
+                .map_err(|err| format!("{}:{}: {}", path.display(), idx + 1, err))?;
+        }
+
+        Ok(())
+    }
+
+    fn prompt(&self, pending: bool, normal: bool) -> String {
+        self.prompt.render(&prompt::State {
+            count: self.count + 1,
+            force: self.interpreter.context().force(),
+            keymap: self.keymap,
+            normal,
+            pending,
+        })
+    }
+
+    pub fn interpreter_mut(&mut self) -> &mut Interpreter {
+        &mut self.interpreter
+    }
+
+    /// Evaluates single line, `expected` output is compared with the actual
+    /// one if checking is enabled
+    pub fn line(
+        &mut self,
+        line: &str,
+        expected: Option<String>,
+        output: &mut impl OutputSink,
+    ) -> Result<()> {
+        self.count += 1;
+        let trimmed = line.trim();
+        let result = if trimmed.starts_with(':') {
+            self.command(trimmed).map(Event::Message)
+        } else {
+            let result = self.interpreter.eval(line);
+            let context = self.interpreter.context();
+            result.map(
+                |val| match val.as_ref().and_then(|val| val.radix_literal(self.base)) {
+                    // Other bases are only for display, so such result is plain text
+                    Some(literal) => Event::Message(format!("= {}", literal)),
+                    None => Event::Result(val.map(|val| context.rounded(val))),
+                },
+            )
+        };
+        let result = result.unwrap_or_else(|error| Event::Error {
+            error,
+            line: line.to_owned(),
+        });
+
+        let events: Vec<_> = self
+            .interpreter
+            .context_mut()
+            .take_warnings()
+            .into_iter()
+            .map(Event::Warning)
+            .chain(Some(result))
+            .collect();
+
+        // Transcript keeps output as it is shown to the user
+        let text = events
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.input(line)?;
+            recorder.output(&text)?;
+        }
+
+        self.autosave(false, output)?;
+
+        for event in events {
+            output.emit(event)?;
+        }
+
+        match expected {
+            Some(expected) if self.check && expected != text => {
+                self.mismatches += 1;
+                output.emit(Event::Mismatch {
+                    line: line.to_owned(),
+                    expected,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Outputs which did not match the expected ones so far
+    pub fn mismatches(&self) -> usize {
+        self.mismatches
+    }
+
+    /// Reads and evaluates lines until the end of input
+    pub fn run(&mut self, input: &mut impl Input, output: &mut impl OutputSink) -> Result<()> {
+        // Unfinished statement, continued by the next line
+        let mut pending: Option<String> = None;
+        loop {
+            let commands = COMMANDS.iter().map(|command| command.to_string());
+            let names = self.interpreter.context().complete("");
+            input.set_completions(commands.chain(names).collect());
+            input.set_keymap(self.keymap, self.prompt(pending.is_some(), true));
+            let prompt = self.prompt(pending.is_some(), false);
+            let read = input.read_line(&prompt)?;
+            for warning in input.take_warnings() {
+                output.emit(Event::Warning(warning))?;
+            }
+            let line = match (read, pending.take()) {
+                (Some(line), previous) => {
+                    if let Some(Err(err)) = self.history.as_mut().map(|history| history.push(&line))
+                    {
+                        output.emit(Event::Warning(err.to_string()))?;
+                    }
+                    let line = match previous {
+                        Some(previous) => format!("{} {}", previous, line),
+                        None => line,
+                    };
+                    match continued(&line) {
+                        Some(head) if !line.trim_start().starts_with(':') => {
+                            pending = Some(head);
+                            continue;
+                        }
+                        _ => line,
+                    }
+                }
+                // Unfinished statement is still evaluated, to report it
+                (None, Some(pending)) => pending,
+                (None, None) => break,
+            };
+            self.line(&line, None, output)?;
+            if self.quit {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Passes definitions to autosave, saving them right away if `save` is
+    /// set. Failures don't stop the session, they are reported as warnings -
+    /// once, as they usually affect many following lines.
+    fn autosave(&mut self, save: bool, output: &mut impl OutputSink) -> Result<()> {
+        let autosave = match self.autosave.as_mut() {
+            Some(autosave) => autosave,
+            None => return Ok(()),
+        };
+        let result = self
+            .interpreter
+            .context()
+            .definitions()
+            .and_then(|definitions| match save {
+                true => autosave.save(definitions),
+                false => autosave.update(definitions),
+            });
+
+        match result {
+            Ok(()) => self.autosave_error = None,
+            Err(err) if self.autosave_error.as_ref() != Some(&err) => {
+                output.emit(Event::Warning(format!(
+                    "session cannot be autosaved: {}",
+                    err
+                )))?;
+                self.autosave_error = Some(err);
+            }
+            Err(_) => (),
+        }
+        Ok(())
+    }
+
+    /// Saves the session, returns number of outputs not matching the
+    /// expected ones
+    pub fn finish(mut self, output: &mut impl OutputSink) -> Result<usize> {
+        self.autosave(true, output)?;
+        Ok(self.mismatches)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::Value;
+    use std::io::Cursor;
+
+    /// Lines as printed to terminal
+    fn run(src: &str) -> Vec<String> {
+        let mut repl = Repl::new(Interpreter::new());
+        let mut events: Vec<Event> = vec![];
+        repl.run(&mut LineReader::new(Cursor::new(src), false), &mut events)
+            .unwrap();
+        events
+            .iter()
+            .flat_map(|event| {
+                event
+                    .to_string()
+                    .lines()
+                    .map(str::to_owned)
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn session() {
+        assert_eq!(
+            vec!["= 2", "()", "= 4", "Error[E010]: Undefined symbol: b"],
+            run("a = 2\nsq x => x * x\nsq a\nb\n")
+        );
+        assert_eq!(
+            vec![
+                "eq-epsilon = 0.1",
+                "= 1",
+                "Error[E090]: Unknown command: :nope"
+            ],
+            run(":set eq-epsilon 0.1\n1 == 1.01\n:nope")
+        );
+        assert_eq!(
+            vec![
+                "Error[E002]: Invalid token: $",
+                "  1 + $",
+                "      ^",
+                "force = on",
+                "Warning: Builtin function sqrt is shadowed",
+                "()",
+            ],
+            run("1 + $\n:set force on\nsqrt x => x")
+        );
+
+        let explained = run(":explain e011\n:explain E999");
+        assert_eq!("E011: arity mismatch", explained[0]);
+        assert_eq!(
+            "Error[E090]: Unknown error code: E999",
+            explained.last().unwrap()
+        );
+    }
+
+    #[test]
+    fn precision() {
+        assert_eq!(
+            vec![
+                "= 0.3",
+                "precision = 3",
+                "= 3.14",
+                "= 1230000",
+                "= 1234567",
+                "Error[E090]: Precision has to be from 1 to 17 digits, got: 0"
+            ],
+            run("0.1 + 0.2\n:set precision 3\npi\n1234567.0\n1234567\n:set precision 0")
+        );
+    }
+
+    #[test]
+    fn capture() {
+        assert_eq!(
+            vec![
+                "= 10",
+                "Error[E010]: Undefined symbol: k",
+                "capture = reference",
+                "()",
+                "= 20",
+                "= 30",
+                "Error[E090]: Unknown capture: all, expected `none`, `copy` or `reference`"
+            ],
+            run("k = 10\naddk x => x + k\n:set capture reference\naddk x => x + k\nk = 20\naddk 10\n:set capture all")
+        );
+    }
+
+    #[test]
+    fn partial() {
+        assert_eq!(
+            vec![
+                "Error[E011]: Function `max` expects 2 arguments, got 1",
+                "partial = on",
+                "= max(1)",
+                "Error[E090]: Expected `on` or `off`, got: yes"
+            ],
+            run("max(1)\n:set partial on\nmax(1)\n:set partial yes")
+        );
+    }
+
+    #[test]
+    fn base() {
+        assert_eq!(
+            vec![
+                "= 255",
+                "base = 16",
+                "= 0xff",
+                "= -0x10",
+                "= 0.5",
+                "base = 2",
+                "= 0b1010",
+                "Error[E090]: Base has to be 2, 8, 10 or 16, got: 3"
+            ],
+            run("0xff\n:set base 16\n255\n0 - 16\n1 / 2\n:set base 2\n0b1000 + 2\n:set base 3")
+        );
+    }
+
+    #[test]
+    fn mode() {
+        assert_eq!(
+            vec![
+                "= 0",
+                "mode = decimal",
+                "= 1",
+                "= 0.1428571428571428571428571428571428571429",
+                "mode = decimal",
+                "Error[E090]: Unknown mode: big, expected `float` or `decimal`"
+            ],
+            run("0.1 + 0.2 === 0.3\n:mode decimal\n0.1 + 0.2 === 0.3\n1 / 7\n:mode\n:mode big")
+        );
+    }
+
+    #[test]
+    fn unset() {
+        assert_eq!(
+            vec![
+                "capture = reference",
+                "= 2",
+                "()",
+                "Error[E020]: x is used by f, use `--force` to remove anyway",
+                "Removed function f",
+                "Removed variable x",
+                "Error[E010]: Undefined symbol: x",
+                "()",
+                "Error[E020]: Cannot remove builtin function sqrt",
+            ],
+            run(concat!(
+                ":set capture reference\nx = 2\nf a => a * x\n",
+                ":unset x\n:unset f\n:unset x\nx\nf x => x\n:unset sqrt"
+            ))
+        );
+    }
+
+    #[test]
+    fn listings() {
+        assert_eq!(
+            vec![
+                "No variables defined",
+                "No functions defined",
+                "= 2",
+                "= 9.81",
+                "()",
+                "x = 2",
+                "const g = 9.81",
+                "sq x => x * x",
+                "Bye",
+            ],
+            run(":vars\n:funcs\nx = 2\nconst g = 9.81\nsq x => x * x\n:vars\n:funcs\n:quit\nx")
+        );
+        assert!(run(":help")[0].starts_with("Expressions"));
+        assert_eq!(
+            vec![
+                "()",
+                "mean a b => (a + b) / 2",
+                "sqrt: builtin function of 1 argument",
+                "e: constant = 2.718281828459045",
+                "Error[E010]: Undefined symbol: m",
+                "Error[E090]: Usage: :show name",
+            ],
+            run("mean a b=>( a+b )/2\n:show mean\n:show sqrt\n:show e\n:show m\n:show")
+        );
+    }
+
+    #[test]
+    fn continuation() {
+        assert_eq!(
+            vec![
+                "()",
+                "= 6",
+                "Error[E001]: Unexpected end of tokens list while parsing terminal expression",
+                "  [1,",
+                "     ^",
+            ],
+            run("f a b => \\\n  a * b # product\nmax(f(2, 3),\n 4)\n[1,")
+        );
+        assert_eq!(None, continued("f(1) # (\n"));
+        assert_eq!(Some("[(1".to_owned()), continued("[(1 # ]"));
+    }
+
+    #[test]
+    fn debugging() {
+        assert_eq!(
+            vec![
+                "0..1 Number(Int(2))",
+                "1..2 Operator(Mul)",
+                "2..3 Id(\"x\")",
+                "Value 6",
+                "Error[E090]: Usage: :ast expression",
+                "digraph ast {",
+                "    n0 [label=\"Define f a\"];",
+                "    n1 [label=\"Not\"];",
+                "    n0 -> n1;",
+                "    n2 [label=\"Argument a\"];",
+                "    n1 -> n2;",
+                "}",
+            ],
+            run(":tokens 2*x\n:ast 2 * 3\n:ast\n:dot f a => !a")
+        );
+    }
+
+    #[test]
+    fn timing() {
+        let shown = run(":set time-runs 3\nx = 0\n:time x = x + 1\nx\n:time\n:set time-runs 0");
+        assert_eq!("time-runs = 3", shown[0]);
+        assert!(shown[2].starts_with("3 runs: min "), "{}", shown[2]);
+        assert_eq!(
+            // Variables are substituted when the expression is parsed
+            vec![
+                "= 1",
+                "Error[E090]: Usage: :time expression",
+                "Error[E090]: Number of runs has to be positive, got: 0",
+            ],
+            shown[3..]
+        );
+    }
+
+    #[test]
+    fn snapshots() {
+        assert_eq!(
+            vec![
+                "force = on",
+                "= 1",
+                "Warning: Builtin function sqrt is shadowed",
+                "()",
+                "Saved snapshot a",
+                "= 2",
+                "Removed 2 symbols",
+                "= 2",
+                "Restored snapshot a",
+                "= 1",
+                "= 0",
+                "Error[E090]: Unknown snapshot: b",
+            ],
+            run(concat!(
+                ":set force on\nx = 1\nsqrt a => 0\n:snapshot a\nx = 2\n:reset\nsqrt 4\n",
+                ":restore a\nx\nsqrt 4\n:restore b"
+            ))
+        );
+    }
+
+    #[test]
+    fn autosave_failure() {
+        let mut repl = Repl::new(Interpreter::new());
+        let path = std::env::temp_dir().join("toy-missing-dir").join("session");
+        repl.set_autosave(Autosave::new(path));
+        let mut events = vec![];
+        repl.line("x = 1", None, &mut events).unwrap();
+        repl.line("y = 2", None, &mut events).unwrap();
+        assert_eq!(0, repl.finish(&mut events).unwrap());
+
+        let warnings = events
+            .iter()
+            .filter(|event| matches!(event, Event::Warning(_)))
+            .count();
+        assert_eq!(1, warnings);
+    }
+
+    #[test]
+    fn check() {
+        let mut repl = Repl::new(Interpreter::new());
+        repl.set_check(true);
+        let mut events = vec![];
+        repl.line("1 + 1", Some("= 2".to_owned()), &mut events)
+            .unwrap();
+        repl.line("1 + 2", Some("= 4".to_owned()), &mut events)
+            .unwrap();
+        assert_eq!(1, repl.finish(&mut events).unwrap());
+        assert_eq!(
+            vec![
+                Event::Result(Some(Value::Int(2))),
+                Event::Result(Some(Value::Int(3))),
+                Event::Mismatch {
+                    line: "1 + 2".to_owned(),
+                    expected: "= 4".to_owned()
+                }
+            ],
+            events
+        );
+    }
+}
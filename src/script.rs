@@ -0,0 +1,193 @@
+//! Non-interactive execution of script files, as `toy-interpreter script.calc`.
+//! Only results of expressions are outputs - assignments and definitions are
+//! silent, and the first failed line stops the script.
+
+use crate::lexer::{self, Token};
+use crate::parser::{self, Expr};
+use crate::sink::{Event, OutputSink};
+use crate::{check, import, printer, Error, Interpreter, Result};
+use std::io::Write;
+
+/// Line which stopped the script, counted from 1
+#[derive(Debug, PartialEq)]
+pub struct Failure {
+    pub line: usize,
+    pub source: String,
+    pub error: Error,
+}
+
+/// The last statement of line only binds its value to the name
+fn is_binding(line: &str) -> bool {
+    let tokens: Vec<_> = lexer::tokenize(line)
+        .filter_map(|token| token.ok().map(|(token, _)| token))
+        .collect();
+    let last = tokens
+        .rsplit(|token| *token == Token::Semicolon)
+        .next()
+        .unwrap_or(&[]);
+    matches!(last.first(), Some(Token::Assign(_)) | Some(Token::Const))
+}
+
+/// Evaluates script line by line, emitting outputs and warnings. `#!` line
+/// is skipped, so scripts can be executable.
+pub fn run(
+    interpreter: &mut Interpreter,
+    src: &str,
+    output: &mut impl OutputSink,
+) -> Result<Option<Failure>> {
+    let shebang = src.starts_with("#!");
+    let lines = src.lines().enumerate().skip(usize::from(shebang));
+    evaluate(interpreter, lines, output)
+}
+
+/// Evaluates lines as script, for expressions given with `-e`
+pub fn run_lines<'a>(
+    interpreter: &mut Interpreter,
+    lines: impl IntoIterator<Item = &'a str>,
+    output: &mut impl OutputSink,
+) -> Result<Option<Failure>> {
+    evaluate(interpreter, lines.into_iter().enumerate(), output)
+}
+
+fn evaluate<'a>(
+    interpreter: &mut Interpreter,
+    lines: impl Iterator<Item = (usize, &'a str)>,
+    output: &mut impl OutputSink,
+) -> Result<Option<Failure>> {
+    for (idx, line) in lines {
+        let result = interpreter.eval(line);
+        for warning in interpreter.context_mut().take_warnings() {
+            output.emit(Event::Warning(warning))?;
+        }
+        match result {
+            Ok(Some(val)) if !is_binding(line) => {
+                let val = interpreter.context().rounded(val);
+                output.emit(Event::Message(val.to_string()))?;
+            }
+            Ok(_) => (),
+            Err(error) => {
+                return Ok(Some(Failure {
+                    line: idx + 1,
+                    source: line.to_owned(),
+                    error,
+                }))
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Trees of statements of the line, definitions are declared for the
+/// following ones
+fn trees(interpreter: &mut Interpreter, line: &str) -> Result<Vec<String>> {
+    if let Some(import) = import::parse(line) {
+        import::load(interpreter, &import?)?;
+        return Ok(vec![]);
+    }
+
+    let tokens: Result<Vec<_>> = lexer::tokenize(line).collect();
+    let mut trees = vec![];
+    for statement in parser::statements(tokens?) {
+        let context = interpreter.context_mut();
+        let expr = context.parse(statement)?;
+        check::declare(context, &expr);
+        if let Expr::Assign(var, _) | Expr::Const(var, _) = &expr {
+            context.declare_var(var);
+        }
+        if expr != Expr::Unit {
+            trees.push(printer::to_json(&expr, context)?);
+        }
+    }
+    Ok(trees)
+}
+
+/// Writes tree of every statement of script as JSON line, instead of
+/// evaluating it - `{"line":2,"ast":{...}}`, see `printer::to_json`.
+/// Assigned variables are read where they are used, as their values are not
+/// known, and the first line which does not parse stops it.
+pub fn dump_ast(
+    interpreter: &mut Interpreter,
+    src: &str,
+    output: &mut impl Write,
+) -> Result<Option<Failure>> {
+    let shebang = src.starts_with("#!");
+    for (idx, line) in src.lines().enumerate().skip(usize::from(shebang)) {
+        let trees = match trees(interpreter, line) {
+            Ok(trees) => trees,
+            Err(error) => {
+                return Ok(Some(Failure {
+                    line: idx + 1,
+                    source: line.to_owned(),
+                    error,
+                }))
+            }
+        };
+        interpreter.context_mut().take_warnings();
+        for tree in trees {
+            writeln!(output, "{{\"line\":{},\"ast\":{}}}", idx + 1, tree)
+                .map_err(|err| Error::Io(format!("Cannot write output: {}", err)))?;
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn outputs(src: &str) -> (Vec<String>, Option<Failure>) {
+        let mut events: Vec<Event> = vec![];
+        let failure = run(&mut Interpreter::new(), src, &mut events).unwrap();
+        (events.iter().map(ToString::to_string).collect(), failure)
+    }
+
+    #[test]
+    fn outputs_only() {
+        let (shown, failure) = outputs(
+            "#!/usr/bin/env toy-interpreter\nr = 2\nsq x => x * x\nsq r\nconst g = 9.81; g * 2\n",
+        );
+        assert_eq!(vec!["4", "19.62"], shown);
+        assert_eq!(None, failure);
+
+        let mut events: Vec<Event> = vec![];
+        let failure = run_lines(&mut Interpreter::new(), vec!["x = 2", "x * 3"], &mut events);
+        assert_eq!(Ok(None), failure);
+        assert_eq!(vec![Event::Message("6".to_owned())], events);
+    }
+
+    #[test]
+    fn trees() {
+        let mut output = vec![];
+        let src = "x = 2\nsq a => a * x\nsq(x) # four\n\nconst g = 9.81; g\nb + 1\n1";
+        let failure = dump_ast(&mut Interpreter::new(), src, &mut output).unwrap();
+        let lines: Vec<_> = std::str::from_utf8(&output).unwrap().lines().collect();
+        assert_eq!(5, lines.len());
+        assert_eq!(
+            r#"{"line":1,"ast":{"node":"Assign","label":"x","children":[{"node":"Value","label":"2","children":[]}]}}"#,
+            lines[0]
+        );
+        assert!(lines[1].contains(r#"{"node":"Variable","label":"x","children":[]}"#));
+        assert!(lines[2].starts_with(r#"{"line":3,"ast":{"node":"Call","label":"sq""#));
+        assert_eq!(
+            r#"{"line":5,"ast":{"node":"Variable","label":"g","children":[]}}"#,
+            lines[4]
+        );
+        assert_eq!(Some(6), failure.map(|failure| failure.line));
+    }
+
+    #[test]
+    fn first_failure() {
+        let (shown, failure) = outputs("1\nb + 1\n2");
+        assert_eq!(vec!["1"], shown);
+        assert_eq!(
+            Some(Failure {
+                line: 2,
+                source: "b + 1".to_owned(),
+                error: Error::UndefinedSymbol("b".to_owned()),
+            }),
+            failure
+        );
+    }
+}
@@ -0,0 +1,106 @@
+//! Sessions over TCP, as `toy-interpreter --serve 127.0.0.1:7000`. Every
+//! connection runs its own interactive session, starting from copy of the
+//! same context, so clients never see definitions of each other. Lines are
+//! read without editing, and outputs are written as the terminal shows
+//! them. Remote users cannot touch files of the server - imports are
+//! disabled, and `:export` is rejected.
+
+use crate::feature::Feature;
+use crate::repl::{Input, Repl};
+use crate::sink::Text;
+use crate::{editor, Context, Error, Interpreter, Lang, Result};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+/// Lines sent by the client, prompt is sent back before every one
+struct Connection<R, W> {
+    reader: R,
+    writer: W,
+}
+
+impl<R: BufRead, W: Write> Input for Connection<R, W> {
+    fn read_line(&mut self, prompt: &str) -> Result<Option<String>> {
+        write!(self.writer, "{}", prompt)
+            .and_then(|_| self.writer.flush())
+            .map_err(|err| Error::Io(format!("Cannot write output: {}", err)))?;
+        editor::read_line(&mut self.reader, false, |_| ())
+    }
+}
+
+/// Session of single client, until it disconnects or quits
+pub fn session(stream: TcpStream, context: Context, lang: Lang) -> Result<()> {
+    let writer = stream
+        .try_clone()
+        .map_err(|err| Error::Io(format!("Cannot open connection: {}", err)))?;
+    let mut input = Connection {
+        reader: BufReader::new(stream),
+        writer: writer
+            .try_clone()
+            .map_err(|err| Error::Io(format!("Cannot open connection: {}", err)))?,
+    };
+
+    let mut interpreter = Interpreter::with_context(context);
+    interpreter
+        .context_mut()
+        .set_feature(Feature::Imports, false);
+    let mut repl = Repl::new(interpreter);
+    repl.set_lang(lang);
+    repl.set_sandbox(true);
+    repl.run(&mut input, &mut Text(writer, lang))
+}
+
+/// Accepts connections forever, every session in its own thread. Failures
+/// of sessions are reported on stderr, and do not stop the server.
+pub fn serve(listener: TcpListener, context: Context, lang: Lang) -> Result<()> {
+    for stream in listener.incoming() {
+        let stream =
+            stream.map_err(|err| Error::Io(format!("Cannot accept connection: {}", err)))?;
+        let peer = stream
+            .peer_addr()
+            .map_or("unknown client".to_owned(), |addr| addr.to_string());
+        let context = context.clone();
+        thread::spawn(move || {
+            if let Err(err) = session(stream, context, lang) {
+                eprintln!("{}: {}", peer, err);
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use std::io::Read;
+    use std::net::Shutdown;
+
+    fn client(addr: std::net::SocketAddr, lines: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(lines.as_bytes()).unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut output = String::new();
+        stream.read_to_string(&mut output).unwrap();
+        output
+    }
+
+    #[test]
+    fn sessions() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.eval("sq x => x * x").unwrap();
+        let context = interpreter.context().clone();
+        thread::spawn(move || serve(listener, context, Lang::En));
+
+        assert_eq!("> = 4\n> = 16\n> ", client(addr, "x = 2 + 2\nsq x\n"));
+        let output = client(addr, "x\n:export /tmp/toy-interpreter-served\n:quit\n1\n");
+        assert_eq!(
+            "> Error[E010]: Undefined symbol: x\n\
+             > Error[E090]: Files cannot be written in this session\n\
+             > Bye\n",
+            output
+        );
+    }
+}
@@ -0,0 +1,296 @@
+use crate::{columns, format_error, format_result, Error, Lang, Result, Value};
+use std::fmt;
+use std::io::Write;
+
+/// Everything the session emits
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// Value of evaluated line, `None` if it has none (eg. definition)
+    Result(Option<Value>),
+    /// Failure of evaluating `line`
+    Error {
+        error: Error,
+        line: String,
+    },
+    Warning(String),
+    /// Command response or notice
+    Message(String),
+    /// Output of `line` differs from the expected one
+    Mismatch {
+        line: String,
+        expected: String,
+    },
+}
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const HIGHLIGHT: &str = "\x1b[1;4;31m";
+const RESET: &str = "\x1b[0m";
+
+fn paint(color: &str, text: &str) -> String {
+    format!("{}{}{}", color, text, RESET)
+}
+
+impl Event {
+    /// Terminal representation in given language
+    pub fn render(&self, lang: Lang) -> String {
+        match self {
+            Event::Result(val) => format_result(Ok(val.clone())),
+            Event::Error { error, line } => format_error(error, line, lang),
+            Event::Warning(warning) => lang.warning(warning),
+            Event::Message(message) => message.clone(),
+            Event::Mismatch { line, expected } => lang.mismatch(line, expected),
+        }
+    }
+
+    /// Terminal representation with ANSI colors, the fragment of failed line
+    /// which error points is highlighted
+    pub fn colored(&self, lang: Lang) -> String {
+        match self {
+            Event::Result(Some(_)) => paint(GREEN, &self.render(lang)),
+            Event::Error { error, line } => {
+                let message = paint(RED, &lang.error(error));
+                match (error.span(), columns(error, line)) {
+                    (Some(span), Some((column, width))) => format!(
+                        "{}\n  {}{}{}\n  {}{}",
+                        message,
+                        &line[..span.start],
+                        paint(HIGHLIGHT, &line[span.start..span.end]),
+                        &line[span.end..],
+                        " ".repeat(column),
+                        paint(RED, &"^".repeat(width))
+                    ),
+                    _ => message,
+                }
+            }
+            Event::Warning(_) => paint(YELLOW, &self.render(lang)),
+            Event::Mismatch { .. } => paint(RED, &self.render(lang)),
+            Event::Result(None) | Event::Message(_) => self.render(lang),
+        }
+    }
+}
+
+/// Terminal representation, in English as stored in transcripts
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.render(Lang::En))
+    }
+}
+
+/// Receiver of everything the session emits
+pub trait OutputSink {
+    fn emit(&mut self, event: Event) -> Result<()>;
+}
+
+/// Prints events as they are shown to the user
+pub struct Terminal(pub Lang);
+
+impl OutputSink for Terminal {
+    fn emit(&mut self, event: Event) -> Result<()> {
+        println!("{}", event.render(self.0));
+        Ok(())
+    }
+}
+
+/// Writes events as `Terminal` prints them, to any writer
+pub struct Text<W>(pub W, pub Lang);
+
+impl<W: Write> OutputSink for Text<W> {
+    fn emit(&mut self, event: Event) -> Result<()> {
+        writeln!(self.0, "{}", event.render(self.1))
+            .map_err(|err| Error::Io(format!("Cannot write output: {}", err)))
+    }
+}
+
+/// Prints events as `Terminal`, but colored
+pub struct Colored(pub Lang);
+
+impl OutputSink for Colored {
+    fn emit(&mut self, event: Event) -> Result<()> {
+        println!("{}", event.colored(self.0));
+        Ok(())
+    }
+}
+
+/// Sink chosen when the session starts
+impl OutputSink for Box<dyn OutputSink> {
+    fn emit(&mut self, event: Event) -> Result<()> {
+        (**self).emit(event)
+    }
+}
+
+/// Captures events, mostly for tests
+impl OutputSink for Vec<Event> {
+    fn emit(&mut self, event: Event) -> Result<()> {
+        self.push(event);
+        Ok(())
+    }
+}
+
+/// Passes every event to the embedding application
+pub struct Callback<F>(pub F);
+
+impl<F: FnMut(Event)> OutputSink for Callback<F> {
+    fn emit(&mut self, event: Event) -> Result<()> {
+        (self.0)(event);
+        Ok(())
+    }
+}
+
+/// Writes every event as single line JSON object
+pub struct JsonLines<W>(pub W);
+
+pub(crate) fn json_string(text: &str) -> String {
+    let mut result = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => result += "\\\"",
+            '\\' => result += "\\\\",
+            '\n' => result += "\\n",
+            c if (c as u32) < 0x20 => result += &format!("\\u{:04x}", c as u32),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// Infinities and NaN are not JSON numbers
+fn json_value(val: &Value) -> String {
+    match val {
+        Value::Float(val) if !val.is_finite() => json_string(&val.to_string()),
+        Value::Str(text) => json_string(text),
+        Value::Func(func) => json_string(&func.name),
+        Value::List(items) => {
+            let items: Vec<_> = items.iter().map(json_value).collect();
+            format!("[{}]", items.join(","))
+        }
+        val => val.to_string(),
+    }
+}
+
+impl Event {
+    /// Single line JSON object, as `JsonLines` writes it
+    pub fn json(&self) -> String {
+        match self {
+            Event::Result(Some(val)) => format!("{{\"ok\":{}}}", json_value(val)),
+            Event::Result(None) => "{\"ok\":null}".to_owned(),
+            Event::Error { error, .. } => {
+                let offset = error
+                    .offset()
+                    .map_or("null".to_owned(), |offset| offset.to_string());
+                format!(
+                    "{{\"error\":{{\"message\":{},\"code\":\"{}\",\"kind\":\"{}\",\"offset\":{}}}}}",
+                    json_string(&error.to_string()),
+                    error.code(),
+                    error.kind(),
+                    offset
+                )
+            }
+            Event::Warning(warning) => format!("{{\"warning\":{}}}", json_string(warning)),
+            Event::Message(message) => format!("{{\"message\":{}}}", json_string(message)),
+            Event::Mismatch { line, expected } => format!(
+                "{{\"mismatch\":{},\"expected\":{}}}",
+                json_string(line),
+                json_string(expected)
+            ),
+        }
+    }
+}
+
+impl<W: Write> OutputSink for JsonLines<W> {
+    fn emit(&mut self, event: Event) -> Result<()> {
+        writeln!(self.0, "{}", event.json())
+            .map_err(|err| Error::Io(format!("Cannot write output: {}", err)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::Span;
+
+    #[test]
+    fn json() {
+        let mut sink = JsonLines(vec![]);
+        sink.emit(Event::Result(Some(Value::Float(2.5)))).unwrap();
+        sink.emit(Event::Result(None)).unwrap();
+        sink.emit(Event::Result(Some(Value::Float(f64::INFINITY))))
+            .unwrap();
+        sink.emit(Event::Error {
+            error: Error::Lex {
+                message: "Invalid token: \"$\"".to_owned(),
+                span: Span { start: 4, end: 7 },
+            },
+            line: "1 + \"$\"".to_owned(),
+        })
+        .unwrap();
+        sink.emit(Event::Warning("multi\nline".to_owned())).unwrap();
+
+        let expected = concat!(
+            "{\"ok\":2.5}\n",
+            "{\"ok\":null}\n",
+            "{\"ok\":\"inf\"}\n",
+            "{\"error\":{\"message\":\"Invalid token: \\\"$\\\"\",\"code\":\"E002\",\"kind\":\"lex\",\"offset\":4}}\n",
+            "{\"warning\":\"multi\\nline\"}\n",
+        );
+        assert_eq!(expected, String::from_utf8(sink.0).unwrap());
+    }
+
+    #[test]
+    fn render() {
+        let error = Event::Error {
+            error: Error::UndefinedSymbol("b".to_owned()),
+            line: "b".to_owned(),
+        };
+        assert_eq!("Error[E010]: Undefined symbol: b", error.to_string());
+        assert_eq!(
+            "Błąd[E010]: Niezdefiniowany symbol: b",
+            error.render(Lang::Pl)
+        );
+        assert_eq!(
+            "Ostrzeżenie: w",
+            Event::Warning("w".to_owned()).render(Lang::Pl)
+        );
+    }
+
+    #[test]
+    fn colors() {
+        let error = Event::Error {
+            error: Error::UndefinedSymbol("b".to_owned()),
+            line: "b".to_owned(),
+        };
+        assert_eq!(
+            "\x1b[31mError[E010]: Undefined symbol: b\x1b[0m",
+            error.colored(Lang::En)
+        );
+
+        let error = Event::Error {
+            error: Error::Parse {
+                message: "m".to_owned(),
+                span: crate::Span { start: 4, end: 5 },
+            },
+            line: "1 + $ + 2".to_owned(),
+        };
+        assert_eq!(
+            "\x1b[31mError[E001]: m\x1b[0m\n  1 + \x1b[1;4;31m$\x1b[0m + 2\n      \x1b[31m^\x1b[0m",
+            error.colored(Lang::En)
+        );
+        assert_eq!(
+            "\x1b[32m= 1\x1b[0m",
+            Event::Result(Some(Value::Int(1))).colored(Lang::En)
+        );
+        assert_eq!("()", Event::Result(None).colored(Lang::En));
+    }
+
+    #[test]
+    fn callback() {
+        let mut results = vec![];
+        let mut sink = Callback(|event: Event| results.push(event.to_string()));
+        sink.emit(Event::Result(Some(Value::Int(1)))).unwrap();
+        sink.emit(Event::Warning("w".to_owned())).unwrap();
+        assert_eq!(vec!["= 1", "Warning: w"], results);
+    }
+}
@@ -0,0 +1,306 @@
+//! Definitions of the session as JSON, so embedders can persist the state
+//! of interpreter and restore it later:
+//! ```text
+//! {"format":1,"interpreter":"0.1.0","definitions":[
+//!   {"name":"r","kind":"variable","source":"r = 2"},
+//!   {"name":"area","kind":"function","source":"area x => pi * x ^ 2"}]}
+//! ```
+//! Definitions are kept as source, every symbol after all symbols it uses,
+//! and restoring evaluates them - as the session is exported. Every source
+//! has to be a single definition of its symbol, and imports are disabled
+//! while restoring. Settings and symbols of the host are not saved.
+
+use crate::envelope::{FORMAT, VERSION};
+use crate::graph;
+use crate::json::{self, Json};
+use crate::parser::{self, Callee, Expr};
+use crate::sink::json_string;
+use crate::{lexer, Context, Error, Feature, Interpreter, Result};
+
+/// Definition of user symbol
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Definition {
+    pub name: String,
+    pub kind: String,
+    pub source: String,
+}
+
+/// User definitions of the context
+pub fn definitions(context: &Context) -> Result<Vec<Definition>> {
+    let nodes = context.graph();
+    Ok(graph::topological(&nodes)?
+        .into_iter()
+        .filter_map(|node| {
+            Some(Definition {
+                name: node.name.clone(),
+                kind: context.kind(&node.name)?.to_owned(),
+                source: context.definition(&node.name)?,
+            })
+        })
+        .collect())
+}
+
+pub fn to_json(context: &Context) -> Result<String> {
+    let definitions: Vec<_> = definitions(context)?
+        .iter()
+        .map(|def| {
+            format!(
+                "{{\"name\":{},\"kind\":{},\"source\":{}}}",
+                json_string(&def.name),
+                json_string(&def.kind),
+                json_string(&def.source)
+            )
+        })
+        .collect();
+
+    Ok(format!(
+        "{{\"format\":{},\"interpreter\":{},\"definitions\":[{}]}}",
+        FORMAT,
+        json_string(VERSION),
+        definitions.join(",")
+    ))
+}
+
+/// Definitions saved by `to_json`, state of other format is rejected
+pub fn from_json(src: &str) -> Result<Vec<Definition>> {
+    let json = json::parse(src)?;
+
+    match json.field("format") {
+        Some(Json::Number(format)) if *format == f64::from(FORMAT) => (),
+        Some(Json::Number(format)) => {
+            return Err(Error::Other(format!(
+                "State has format {}, expected {}",
+                format, FORMAT
+            )))
+        }
+        _ => return Err(invalid("no format")),
+    }
+
+    let definitions = match json.field("definitions") {
+        Some(Json::Array(items)) => items,
+        _ => return Err(invalid("no definitions")),
+    };
+    definitions
+        .iter()
+        .map(|item| {
+            let text = |field| match item.field(field) {
+                Some(Json::Str(text)) => Ok(text.clone()),
+                _ => Err(invalid(&format!("definition without {}", field))),
+            };
+            Ok(Definition {
+                name: text("name")?,
+                kind: text("kind")?,
+                source: text("source")?,
+            })
+        })
+        .collect()
+}
+
+/// Evaluates saved definitions, returns how many there were
+pub fn restore(interpreter: &mut Interpreter, src: &str) -> Result<usize> {
+    let definitions = from_json(src)?;
+    define_all(interpreter, &definitions)?;
+    Ok(definitions.len())
+}
+
+/// Definitions are evaluated in order, without imports - state may come
+/// from anywhere, and it should not read files
+fn define_all(interpreter: &mut Interpreter, definitions: &[Definition]) -> Result<()> {
+    let imports = interpreter.context().is_enabled(Feature::Imports);
+    interpreter
+        .context_mut()
+        .set_feature(Feature::Imports, false);
+    let result = definitions.iter().try_for_each(|def| {
+        define(interpreter, def)
+            .map_err(|err| Error::Eval(format!("Cannot restore `{}`: {}", def.source, err)))
+    });
+    interpreter
+        .context_mut()
+        .set_feature(Feature::Imports, imports);
+    result
+}
+
+/// Evaluates source of the definition, which has to define its symbol and
+/// nothing else
+fn define(interpreter: &mut Interpreter, def: &Definition) -> Result<()> {
+    let tokens: Result<Vec<_>> = lexer::tokenize(&def.source).collect();
+    let mut statements = parser::statements(tokens?);
+    let expr = match statements.pop() {
+        Some(statement) if statements.is_empty() => interpreter.context().parse(statement)?,
+        _ => return Err(invalid("definition is not a single statement")),
+    };
+    let (name, kind) = match &expr {
+        Expr::Define(func) => (&func.name, "function"),
+        Expr::Assign(var, val) if defines_nothing(val) => (var, "variable"),
+        Expr::Const(var, val) if defines_nothing(val) => (var, "constant"),
+        _ => return Err(invalid("source is not a definition")),
+    };
+    if *name != def.name || kind != def.kind {
+        return Err(invalid(&format!(
+            "source defines {} {}, not {} {}",
+            kind, name, def.kind, def.name
+        )));
+    }
+    interpreter.eval(&def.source)?;
+    Ok(())
+}
+
+/// Value assigns nothing, also in called user functions
+fn defines_nothing(val: &Expr) -> bool {
+    !val.walk().any(|expr| {
+        matches!(
+            expr,
+            Expr::Assign(..) | Expr::Const(..) | Expr::Call(Callee::Function(_), _)
+        )
+    })
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::Other(format!("Invalid state: {}", reason))
+}
+
+/// State as `to_json` writes it, for formats of `serde`
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct State {
+    format: u32,
+    interpreter: String,
+    definitions: Vec<Definition>,
+}
+
+/// Context is serialized as its definitions, like `to_json` does
+#[cfg(feature = "serde")]
+impl serde::Serialize for Context {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        let state = State {
+            format: FORMAT,
+            interpreter: VERSION.to_owned(),
+            definitions: definitions(self).map_err(serde::ser::Error::custom)?,
+        };
+        state.serialize(serializer)
+    }
+}
+
+/// Definitions are evaluated in fresh context, like `restore` does
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Context {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        use serde::de::Error as _;
+
+        let state = State::deserialize(deserializer)?;
+        if state.format != FORMAT {
+            return Err(D::Error::custom(format!(
+                "State has format {}, expected {}",
+                state.format, FORMAT
+            )));
+        }
+        let mut interpreter = Interpreter::new();
+        define_all(&mut interpreter, &state.definitions).map_err(D::Error::custom)?;
+        Ok(interpreter.context().clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut interpreter = Interpreter::new();
+        for line in &[
+            "r = 2",
+            "const g = 9.81",
+            "area x => pi * x ^ 2",
+            "s = \"a\\\"b\"",
+        ] {
+            interpreter.eval(line).unwrap();
+        }
+        let json = to_json(interpreter.context()).unwrap();
+        assert!(json.starts_with(&format!(
+            "{{\"format\":{},\"interpreter\":\"{}\",\"definitions\":[",
+            FORMAT, VERSION
+        )));
+
+        let definitions = from_json(&json).unwrap();
+        assert_eq!(
+            Definition {
+                name: "g".to_owned(),
+                kind: "constant".to_owned(),
+                source: "const g = 9.81".to_owned(),
+            },
+            definitions[1]
+        );
+
+        let mut restored = Interpreter::new();
+        assert_eq!(Ok(4), restore(&mut restored, &json));
+        assert_eq!(interpreter.eval("area r * g"), restored.eval("area r * g"));
+        assert_eq!(interpreter.get_var("s"), restored.get_var("s"));
+    }
+
+    #[test]
+    fn invalid_state() {
+        let state = |definitions: &str| format!("{{\"format\":1,\"definitions\":{}}}", definitions);
+        from_json(&state("[]")).unwrap();
+        from_json(&format!(" {} ", state("[ ]"))).unwrap();
+
+        from_json("").unwrap_err();
+        from_json("{\"format\":2,\"definitions\":[]}").unwrap_err();
+        from_json(&state("{}")).unwrap_err();
+        from_json(&state("[{\"name\":\"x\"}]")).unwrap_err();
+        from_json(&format!("{} 1", state("[]"))).unwrap_err();
+        from_json("{\"format\":1,\"definitions\":[\"unterminated]}").unwrap_err();
+
+        let mut interpreter = Interpreter::new();
+        let broken = state("[{\"name\":\"x\",\"kind\":\"variable\",\"source\":\"x = y\"}]");
+        restore(&mut interpreter, &broken).unwrap_err();
+    }
+
+    #[test]
+    fn only_definitions() {
+        let definition = |name: &str, kind: &str, source: &str| Definition {
+            name: name.to_owned(),
+            kind: kind.to_owned(),
+            source: source.to_owned(),
+        };
+        let mut interpreter = Interpreter::new();
+        let mut restore = |def| define_all(&mut interpreter, &[def]);
+        restore(definition("f", "function", "f x => y = x")).unwrap();
+        restore(definition("x", "variable", "x = [1, 0 - 2]")).unwrap();
+        restore(definition("c", "constant", "const c = 1 / 0")).unwrap();
+
+        restore(definition("y", "variable", "x = 1")).unwrap_err();
+        restore(definition("x", "constant", "x = 1")).unwrap_err();
+        restore(definition("x", "variable", "x = 1; y = 2")).unwrap_err();
+        restore(definition("x", "variable", "x = (y = 2)")).unwrap_err();
+        restore(definition("x", "variable", "x = f 1")).unwrap_err();
+        restore(definition("x", "variable", "1 + 2")).unwrap_err();
+        restore(definition("x", "variable", "import \"lib.calc\"")).unwrap_err();
+        assert_eq!(None, interpreter.get_var("y"));
+        assert!(interpreter.context().is_enabled(Feature::Imports));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        let mut interpreter = Interpreter::new();
+        interpreter.eval("r = 2; area x => pi * x ^ 2").unwrap();
+        let json = serde_json::to_string(interpreter.context()).unwrap();
+        assert_eq!(to_json(interpreter.context()).unwrap(), json);
+
+        let context: Context = serde_json::from_str(&json).unwrap();
+        let mut restored = Interpreter::with_context(context);
+        assert_eq!(interpreter.eval("area r"), restored.eval("area r"));
+
+        assert!(
+            serde_json::from_str::<Context>(&json.replace("\"format\":1", "\"format\":2")).is_err()
+        );
+        assert!(serde_json::from_str::<Context>(&json.replace("r = 2", "r = q")).is_err());
+    }
+}
@@ -0,0 +1,268 @@
+//! Instrumentation of lexing, parsing and evaluation. Phases of every
+//! statement are reported with their durations to `Tracer` given to
+//! `Builder::with_tracer`, so hosts can forward them to their own logging.
+//! Without tracer nothing is recorded, and messages are built only for
+//! levels the tracer records. With `tracing` feature every phase runs in
+//! `tracing` span of its name, and interpreters without tracer given
+//! forward records to `tracing`, see `Tracing`.
+
+use std::fmt;
+use std::time::Duration;
+
+/// Detail of records, more detailed levels are greater
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Phases with their durations
+    Info,
+    /// Also tokens, parsed trees and results
+    Debug,
+}
+
+impl Level {
+    /// Level of `-v` flags repeated given number of times, `None` for no flag
+    pub fn verbosity(count: usize) -> Option<Level> {
+        match count {
+            0 => None,
+            1 => Some(Level::Info),
+            _ => Some(Level::Debug),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    Lex,
+    Parse,
+    Eval,
+}
+
+impl Phase {
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Lex => "lex",
+            Phase::Parse => "parse",
+            Phase::Eval => "eval",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub level: Level,
+    pub phase: Phase,
+    pub elapsed: Duration,
+    pub message: String,
+}
+
+/// `[parse 12.5µs] statement 1`
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[{} {:?}] {}",
+            self.phase.name(),
+            self.elapsed,
+            self.message
+        )
+    }
+}
+
+pub trait Tracer {
+    /// The most detailed level recorded
+    fn level(&self) -> Level;
+    fn record(&mut self, record: Record);
+}
+
+/// Prints records of up to given level to stderr, as `-v` does
+pub struct Stderr(pub Level);
+
+impl Tracer for Stderr {
+    fn level(&self) -> Level {
+        self.0
+    }
+
+    fn record(&mut self, record: Record) {
+        eprintln!("{}", record);
+    }
+}
+
+/// Span of the phase in `tracing`, entered until it is dropped
+#[cfg(feature = "tracing")]
+pub(crate) type Entered = tracing::span::EnteredSpan;
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct Entered;
+
+/// Enters span named as the phase, for the time the phase runs
+#[cfg(feature = "tracing")]
+pub(crate) fn enter(phase: Phase) -> Entered {
+    let span = match phase {
+        Phase::Lex => tracing::info_span!("lex"),
+        Phase::Parse => tracing::info_span!("parse"),
+        Phase::Eval => tracing::info_span!("eval"),
+    };
+    span.entered()
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn enter(_: Phase) -> Entered {
+    Entered
+}
+
+/// Emits `Info` records as `INFO` events with duration of the phase in
+/// seconds as `elapsed` field, and `Debug` records as `DEBUG` events, built
+/// only if `tracing` subscriber takes them. Records are emitted while the
+/// phase is still running, so they are in span of their phase.
+#[cfg(feature = "tracing")]
+pub struct Tracing;
+
+#[cfg(feature = "tracing")]
+impl Tracer for Tracing {
+    fn level(&self) -> Level {
+        if tracing::enabled!(tracing::Level::DEBUG) {
+            Level::Debug
+        } else {
+            Level::Info
+        }
+    }
+
+    fn record(&mut self, record: Record) {
+        match record.level {
+            Level::Info => {
+                let elapsed = record.elapsed.as_secs_f64();
+                tracing::info!(elapsed, "{}", record.message);
+            }
+            Level::Debug => tracing::debug!("{}", record.message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::Interpreter;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct Collect(Level, Rc<RefCell<Vec<Record>>>);
+
+    impl Tracer for Collect {
+        fn level(&self) -> Level {
+            self.0
+        }
+
+        fn record(&mut self, record: Record) {
+            self.1.borrow_mut().push(record);
+        }
+    }
+
+    fn traced(level: Level, src: &str) -> Vec<(Level, Phase, String)> {
+        let records = Rc::new(RefCell::new(vec![]));
+        let mut interpreter = Interpreter::builder()
+            .with_tracer(Collect(level, Rc::clone(&records)))
+            .build()
+            .unwrap();
+        interpreter.eval(src).ok();
+        let records = records.borrow();
+        records
+            .iter()
+            .map(|record| (record.level, record.phase, record.message.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn phases() {
+        let info = |phase, message: &str| (Level::Info, phase, message.to_owned());
+        assert_eq!(
+            vec![
+                info(Phase::Lex, "6 tokens in 2 statements"),
+                info(Phase::Parse, "statement 1"),
+                info(Phase::Eval, "statement 1"),
+                info(Phase::Parse, "statement 2"),
+                info(Phase::Eval, "statement 2"),
+            ],
+            traced(Level::Info, "x = 1; x + 1")
+        );
+        assert_eq!(
+            vec![
+                info(Phase::Lex, "2 tokens in 1 statements"),
+                info(Phase::Parse, "statement 1 failed"),
+            ],
+            traced(Level::Info, "sqrt +")
+        );
+    }
+
+    #[test]
+    fn details() {
+        let records = traced(Level::Debug, "2 * 3");
+        let debug: Vec<_> = records
+            .iter()
+            .filter(|(level, _, _)| *level == Level::Debug)
+            .map(|(_, phase, message)| (*phase, message.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                (Phase::Lex, "tokens: 2 * 3"),
+                (Phase::Parse, "Value(Int(6))"),
+                (Phase::Eval, "result: 6"),
+            ],
+            debug
+        );
+        assert_eq!(Some(Level::Debug), Level::verbosity(2));
+        assert_eq!(None, Level::verbosity(0));
+    }
+
+    /// Names of created spans, and name of the span entered at every event
+    #[cfg(feature = "tracing")]
+    #[derive(Default)]
+    struct Spans(std::sync::Mutex<(Vec<&'static str>, Vec<u64>, Vec<&'static str>)>);
+
+    #[cfg(feature = "tracing")]
+    impl tracing::Subscriber for Spans {
+        fn enabled(&self, metadata: &tracing::Metadata) -> bool {
+            *metadata.level() <= tracing::Level::INFO
+        }
+
+        fn new_span(&self, span: &tracing::span::Attributes) -> tracing::span::Id {
+            let mut spans = self.0.lock().unwrap();
+            spans.0.push(span.metadata().name());
+            tracing::span::Id::from_u64(spans.0.len() as u64)
+        }
+
+        fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record) {}
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, _: &tracing::Event) {
+            let mut spans = self.0.lock().unwrap();
+            let name = match spans.1.last() {
+                Some(id) => spans.0[*id as usize - 1],
+                None => "none",
+            };
+            spans.2.push(name);
+        }
+
+        fn enter(&self, span: &tracing::span::Id) {
+            self.0.lock().unwrap().1.push(span.into_u64());
+        }
+
+        fn exit(&self, _: &tracing::span::Id) {
+            self.0.lock().unwrap().1.pop();
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn tracing() {
+        let spans = std::sync::Arc::new(Spans::default());
+        tracing::subscriber::with_default(std::sync::Arc::clone(&spans), || {
+            Interpreter::new().eval("x = 1; x + 1").unwrap();
+        });
+        let (names, entered, events) = &*spans.0.lock().unwrap();
+        let phases = vec!["lex", "parse", "eval", "parse", "eval"];
+        assert_eq!(&phases, names);
+        assert_eq!(&phases, events);
+        assert!(entered.is_empty());
+    }
+}
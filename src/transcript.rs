@@ -0,0 +1,104 @@
+use crate::{Error, Result};
+use std::io::{BufRead, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Single transcript line - an input typed by user or an interpreter response
+#[derive(Debug, PartialEq)]
+pub enum Entry {
+    Input(String),
+    Output(String),
+}
+
+/// Writes every entry prefixed with unix timestamp and direction marker:
+/// ```text
+/// 1571145600.123 > a = 4
+/// 1571145600.124 < = 4
+/// ```
+pub struct Recorder<W: Write> {
+    sink: W,
+}
+
+impl<W: Write> Recorder<W> {
+    pub fn new(sink: W) -> Self {
+        Recorder { sink }
+    }
+
+    pub fn input(&mut self, line: &str) -> Result<()> {
+        self.write('>', line)
+    }
+
+    /// Multiline output is stored as consecutive output entries
+    pub fn output(&mut self, output: &str) -> Result<()> {
+        output.lines().try_for_each(|line| self.write('<', line))
+    }
+
+    fn write(&mut self, marker: char, line: &str) -> Result<()> {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        writeln!(
+            self.sink,
+            "{}.{:03} {} {}",
+            time.as_secs(),
+            time.subsec_millis(),
+            marker,
+            line
+        )
+        .and_then(|_| self.sink.flush())
+        .map_err(|err| format!("Cannot write transcript: {}", err).into())
+    }
+}
+
+fn parse_entry(line: &str) -> Result<Entry> {
+    let invalid = || Error::from(format!("Invalid transcript line: {}", line));
+
+    // Timestamp is informative only, so it is not validated
+    let (_, entry) = line.split_once(' ').ok_or_else(invalid)?;
+    match entry.split_at_checked(2) {
+        Some(("> ", input)) => Ok(Entry::Input(input.to_owned())),
+        Some(("< ", output)) => Ok(Entry::Output(output.to_owned())),
+        _ => Err(invalid()),
+    }
+}
+
+pub fn read(src: impl BufRead) -> Result<Vec<Entry>> {
+    src.lines()
+        .map(|line| line.map_err(|err| Error::from(format!("Cannot read transcript: {}", err))))
+        .filter(|line| !matches!(line, Ok(line) if line.is_empty()))
+        .map(|line| parse_entry(&line?))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let mut recorder = Recorder::new(vec![]);
+        recorder.input("a = 4").unwrap();
+        recorder.output("= 4").unwrap();
+        recorder.input("").unwrap();
+        recorder.output("Error: Unexpected end of tokens").unwrap();
+        recorder.output("digraph {\n}").unwrap();
+
+        let expected = vec![
+            Entry::Input("a = 4".to_owned()),
+            Entry::Output("= 4".to_owned()),
+            Entry::Input("".to_owned()),
+            Entry::Output("Error: Unexpected end of tokens".to_owned()),
+            Entry::Output("digraph {".to_owned()),
+            Entry::Output("}".to_owned()),
+        ];
+
+        assert_eq!(Ok(expected), read(recorder.sink.as_slice()));
+    }
+
+    #[test]
+    fn invalid() {
+        read("1571145600.123 a = 4".as_bytes()).unwrap_err();
+        read("a = 4".as_bytes()).unwrap_err();
+    }
+}
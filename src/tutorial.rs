@@ -0,0 +1,218 @@
+//! Interactive lessons of `toy-interpreter tutorial`. Every step explains
+//! something and asks for input, which is accepted once its output matches
+//! the expected one - as outputs are checked when transcript is replayed.
+//!
+//! Lessons are embedded text files, first line is the title, and every step
+//! is its explanation followed by solution and expected output lines:
+//! ```text
+//! Variables
+//! Store 4 in variable `x`:
+//! > x = 4
+//! < = 4
+//! ```
+
+use crate::repl::{Input, Repl};
+use crate::sink::{Event, OutputSink};
+use crate::{Error, Interpreter, Result};
+
+const LESSONS: &[&str] = &[
+    include_str!("../lessons/01-variables.txt"),
+    include_str!("../lessons/02-precedence.txt"),
+    include_str!("../lessons/03-functions.txt"),
+];
+
+#[derive(Debug, PartialEq)]
+pub struct Step {
+    pub text: String,
+    pub solution: String,
+    /// All lines shown after the solution, as in transcript
+    pub expected: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Lesson {
+    pub title: String,
+    pub steps: Vec<Step>,
+}
+
+impl Lesson {
+    pub fn parse(src: &str) -> Result<Self> {
+        let mut lines = src.lines().enumerate();
+        let title = match lines.next() {
+            Some((_, title)) if !title.trim().is_empty() => title.trim().to_owned(),
+            _ => return Err("Lesson has no title".into()),
+        };
+
+        let mut steps: Vec<Step> = vec![];
+        let mut text: Vec<&str> = vec![];
+        let mut solution = None;
+        for (idx, line) in lines {
+            let invalid = |message| Error::from(format!("Line {}: {}", idx + 1, message));
+            if let Some(input) = line.strip_prefix("> ") {
+                if solution.is_some() {
+                    return Err(invalid("solution without expected output"));
+                }
+                solution = Some(input.to_owned());
+            } else if let Some(output) = line.strip_prefix("< ") {
+                match (solution.take(), steps.last_mut()) {
+                    (Some(solution), _) => steps.push(Step {
+                        text: std::mem::take(&mut text).join("\n"),
+                        solution,
+                        expected: output.to_owned(),
+                    }),
+                    (None, Some(step)) if text.is_empty() => {
+                        step.expected = format!("{}\n{}", step.expected, output)
+                    }
+                    _ => return Err(invalid("expected output without solution")),
+                }
+            } else if solution.is_some() {
+                return Err(invalid("solution without expected output"));
+            } else {
+                text.push(line);
+            }
+        }
+
+        if solution.is_some() || !text.is_empty() {
+            return Err(format!("Lesson {} ends without expected output", title).into());
+        }
+        Ok(Lesson { title, steps })
+    }
+}
+
+/// Embedded lessons, in order
+pub fn lessons() -> Result<Vec<Lesson>> {
+    LESSONS.iter().map(|src| Lesson::parse(src)).collect()
+}
+
+/// Lessons from given one (counted from 1) to the last, every lesson in
+/// fresh interpreter. `:hint` shows solution of the current step, and
+/// `:skip` evaluates it. The end of input or `:quit` ends the tutorial.
+pub fn run(first: usize, input: &mut impl Input, output: &mut impl OutputSink) -> Result<()> {
+    let lessons = lessons()?;
+    if !(1..=lessons.len()).contains(&first) {
+        return Err(format!(
+            "Lesson has to be from 1 to {}, got: {}",
+            lessons.len(),
+            first
+        )
+        .into());
+    }
+
+    for (idx, lesson) in lessons.iter().enumerate().skip(first - 1) {
+        output.emit(Event::Message(format!(
+            "Lesson {}: {}",
+            idx + 1,
+            lesson.title
+        )))?;
+
+        let mut repl = Repl::new(Interpreter::new());
+        repl.set_check(true);
+        for step in &lesson.steps {
+            output.emit(Event::Message(step.text.clone()))?;
+            loop {
+                let line = match input.read_line("> ")? {
+                    Some(line) => line,
+                    None => return Ok(()),
+                };
+
+                match line.trim() {
+                    ":hint" => output.emit(Event::Message(format!("Type `{}`", step.solution)))?,
+                    ":skip" => {
+                        repl.line(&step.solution, None, output)?;
+                        break;
+                    }
+                    command if command.starts_with(':') => {
+                        repl.line(&line, None, output)?;
+                        if repl.quit() {
+                            return Ok(());
+                        }
+                    }
+                    _ => {
+                        let mismatches = repl.mismatches();
+                        repl.line(&line, Some(step.expected.clone()), output)?;
+                        if repl.mismatches() == mismatches {
+                            break;
+                        }
+                        output.emit(Event::Message(
+                            "Try again, `:hint` shows the solution".to_owned(),
+                        ))?;
+                    }
+                }
+            }
+        }
+        output.emit(Event::Message(format!("Lesson {} completed", idx + 1)))?;
+    }
+
+    output.emit(Event::Message("Tutorial completed".to_owned()))
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::repl::LineReader;
+    use std::io::Cursor;
+
+    #[test]
+    fn solutions() {
+        for lesson in lessons().unwrap() {
+            let mut repl = Repl::new(Interpreter::new());
+            repl.set_check(true);
+            let mut events: Vec<Event> = vec![];
+            for step in &lesson.steps {
+                repl.line(&step.solution, Some(step.expected.clone()), &mut events)
+                    .unwrap();
+            }
+            assert_eq!(0, repl.mismatches(), "{}: {:?}", lesson.title, events);
+        }
+    }
+
+    #[test]
+    fn parsing() {
+        let lesson = Lesson::parse("Title\nFirst\nstep\n> sqrt x => x\n< Warning\n< ()").unwrap();
+        assert_eq!(
+            vec![Step {
+                text: "First\nstep".to_owned(),
+                solution: "sqrt x => x".to_owned(),
+                expected: "Warning\n()".to_owned(),
+            }],
+            lesson.steps
+        );
+
+        Lesson::parse("").unwrap_err();
+        Lesson::parse("Title\n> 1\nText").unwrap_err();
+        Lesson::parse("Title\nText\n< = 1").unwrap_err();
+        Lesson::parse("Title\n> 1\n< = 1\nText").unwrap_err();
+    }
+
+    #[test]
+    fn session() {
+        let steps = &lessons().unwrap()[0].steps;
+        let mut input = LineReader::new(Cursor::new("x = 4\nx * 2\n:hint\n:skip\n"), false);
+        let mut events: Vec<Event> = vec![];
+        run(1, &mut input, &mut events).unwrap();
+        let shown: Vec<_> = events.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            vec![
+                "Lesson 1: Variables",
+                &steps[0].text,
+                "= 4",
+                &steps[1].text,
+                "= 8",
+                "Mismatch for `x * 2`: expected `= 12`",
+                "Try again, `:hint` shows the solution",
+                "Type `x * 3`",
+                "= 12",
+                &steps[2].text,
+            ],
+            shown
+        );
+
+        run(4, &mut input, &mut events).unwrap_err();
+
+        let mut input = LineReader::new(Cursor::new(":quit\nx = 4\n"), false);
+        let mut events: Vec<Event> = vec![];
+        run(1, &mut input, &mut events).unwrap();
+        assert_eq!(Some(&Event::Message("Bye".to_owned())), events.last());
+    }
+}
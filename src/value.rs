@@ -0,0 +1,561 @@
+use crate::decimal::Decimal;
+use crate::lexer::approx_eq;
+use crate::parser::Reference;
+use crate::{Error, Result};
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+use std::num::ParseFloatError;
+use std::ops;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Value of the language - number, string, list or function. Integers stay exact as long
+/// as results fit into `i64`, fractions and results out of `i64` range are
+/// floats, or decimals in decimal mode.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Decimal(Decimal),
+    Str(Arc<str>),
+    List(Arc<[Value]>),
+    Func(Reference),
+}
+
+impl Value {
+    pub fn as_f64(&self) -> f64 {
+        match self {
+            Value::Int(val) => *val as f64,
+            Value::Float(val) => *val,
+            Value::Decimal(val) => val.to_f64(),
+            Value::Str(_) | Value::List(_) | Value::Func(_) => f64::NAN,
+        }
+    }
+
+    /// Exact value, only numbers but floats have one
+    fn decimal(&self) -> Option<Decimal> {
+        match self {
+            Value::Int(val) => Some(Decimal::from(*val)),
+            Value::Decimal(val) => Some(val.clone()),
+            _ => None,
+        }
+    }
+
+    /// Integral number as `i64`
+    fn integer(&self) -> Option<i64> {
+        match self {
+            Value::Int(val) => Some(*val),
+            Value::Float(val) if val.fract() == 0.0 && val.abs() < i64::MAX as f64 => {
+                Some(*val as i64)
+            }
+            Value::Decimal(val) => val.to_i64(),
+            _ => None,
+        }
+    }
+
+    /// Element of list at given index, `NaN` if it is not a list or the index
+    /// is out of its range
+    pub fn index(&self, idx: &Value) -> Value {
+        let element = match (self, idx.integer()) {
+            (Value::List(items), Some(idx)) => {
+                usize::try_from(idx).ok().and_then(|idx| items.get(idx))
+            }
+            _ => None,
+        };
+        element.cloned().unwrap_or(Value::Float(f64::NAN))
+    }
+
+    /// Approximate heap memory of the value itself, elements of lists are
+    /// shared, so they are not counted
+    pub fn heap_size(&self) -> usize {
+        match self {
+            Value::Str(text) => text.len(),
+            Value::List(items) => items.len() * std::mem::size_of::<Value>(),
+            Value::Decimal(val) => val.heap_size(),
+            _ => 0,
+        }
+    }
+
+    /// Number of list elements or string characters
+    pub fn length(&self) -> Option<usize> {
+        match self {
+            Value::List(items) => Some(items.len()),
+            Value::Str(text) => Some(text.chars().count()),
+            _ => None,
+        }
+    }
+
+    /// Content of string, numbers as they are shown
+    fn text(&self) -> String {
+        match self {
+            Value::Str(text) => text.to_string(),
+            val => val.to_string(),
+        }
+    }
+
+    /// `0`, empty string and empty list are false, and everything else is
+    /// true
+    pub fn is_true(&self) -> bool {
+        match self {
+            Value::Int(val) => *val != 0,
+            Value::Float(val) => *val != 0.0,
+            Value::Decimal(val) => !val.is_zero(),
+            Value::Str(text) => !text.is_empty(),
+            Value::List(items) => !items.is_empty(),
+            Value::Func(_) => true,
+        }
+    }
+
+    /// Integers are combined with `int`, and decimals (also with integers)
+    /// with `decimal`, unless it fails (eg. overflows). Anything else is
+    /// promoted to floats combined with `float` - strings are not numbers, so
+    /// they become `NaN`.
+    fn combine(
+        self,
+        other: Value,
+        int: impl Fn(i64, i64) -> Option<i64>,
+        decimal: impl Fn(&Decimal, &Decimal) -> Option<Decimal>,
+        float: impl Fn(f64, f64) -> f64,
+    ) -> Value {
+        if let (Value::Int(left), Value::Int(right)) = (&self, &other) {
+            if let Some(result) = int(*left, *right) {
+                return Value::Int(result);
+            }
+        } else if let (Some(left), Some(right)) = (self.decimal(), other.decimal()) {
+            if let Some(result) = decimal(&left, &right) {
+                return Value::Decimal(result);
+            }
+        }
+        Value::Float(float(self.as_f64(), other.as_f64()))
+    }
+
+    /// Exact power only for integer exponents, negative ones are exact only
+    /// for decimals
+    pub fn pow(self, other: Value) -> Value {
+        self.combine(
+            other,
+            |left, right| left.checked_pow(u32::try_from(right).ok()?),
+            |left, right| left.pow(right.to_i64()?),
+            f64::powf,
+        )
+    }
+
+    /// Numbers are ordered by value and strings lexicographically. Strings
+    /// and numbers, lists, functions and `NaN` are not ordered.
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Int(left), Value::Int(right)) => Some(left.cmp(right)),
+            (Value::Str(left), Value::Str(right)) => Some(left.cmp(right)),
+            (Value::Str(_) | Value::List(_) | Value::Func(_), _)
+            | (_, Value::Str(_) | Value::List(_) | Value::Func(_)) => None,
+            _ => match (self.decimal(), other.decimal()) {
+                (Some(left), Some(right)) => left.partial_cmp(&right),
+                _ => self.as_f64().partial_cmp(&other.as_f64()),
+            },
+        }
+    }
+
+    /// Integers are equal only if they are the same, comparison with float
+    /// or decimal allows given tolerance. Strings are never equal to numbers,
+    /// lists are equal if all their elements are.
+    pub fn approx_eq(&self, other: &Value, epsilon: f64) -> bool {
+        match (self, other) {
+            (Value::Int(left), Value::Int(right)) => left == right,
+            (Value::Str(left), Value::Str(right)) => left == right,
+            (Value::List(left), Value::List(right)) => {
+                left.len() == right.len()
+                    && left
+                        .iter()
+                        .zip(right.iter())
+                        .all(|(left, right)| left.approx_eq(right, epsilon))
+            }
+            (Value::Func(left), Value::Func(right)) => left == right,
+            (Value::Str(_), _) | (_, Value::Str(_)) => false,
+            (Value::List(_), _) | (_, Value::List(_)) => false,
+            (Value::Func(_), _) | (_, Value::Func(_)) => false,
+            _ => approx_eq(self.as_f64(), other.as_f64(), epsilon),
+        }
+    }
+
+    /// Numeric equality, regardless of representation
+    pub fn exact_eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::List(left), Value::List(right)) => {
+                return left.len() == right.len()
+                    && left
+                        .iter()
+                        .zip(right.iter())
+                        .all(|(left, right)| left.exact_eq(right))
+            }
+            (Value::List(_), _) | (_, Value::List(_)) => return false,
+            (Value::Func(left), Value::Func(right)) => return left == right,
+            (Value::Func(_), _) | (_, Value::Func(_)) => return false,
+            _ => (),
+        }
+        if let (Value::Str(_), _) | (_, Value::Str(_)) = (self, other) {
+            return self == other;
+        }
+
+        match (self.decimal(), other.decimal()) {
+            (Some(left), Some(right)) => left == right,
+            _ => self.as_f64() == other.as_f64(),
+        }
+    }
+
+    /// Integral value as literal in base 2, 8 or 16, eg. `0xff`
+    pub fn radix_literal(&self, radix: u32) -> Option<String> {
+        let val = self.integer()?;
+
+        let sign = if val < 0 { "-" } else { "" };
+        let val = val.unsigned_abs();
+        match radix {
+            2 => Some(format!("{}0b{:b}", sign, val)),
+            8 => Some(format!("{}0o{:o}", sign, val)),
+            16 => Some(format!("{}0x{:x}", sign, val)),
+            _ => None,
+        }
+    }
+
+    /// Literal which tokenizes back to the same value - integral floats get
+    /// fraction so they stay floats. Only finite non negative numbers have
+    /// one, and every string. Lists have one if all their elements have, and
+    /// functions are their names.
+    pub fn source(&self) -> String {
+        match self {
+            Value::Float(val) if val.fract() == 0.0 => format!("{:.1}", val),
+            Value::Decimal(val) => val.source(),
+            Value::Str(text) => format!("{:?}", text),
+            Value::List(items) => {
+                let items: Vec<_> = items.iter().map(Value::source).collect();
+                format!("[{}]", items.join(", "))
+            }
+            Value::Func(func) => func.source(),
+            val => val.to_string(),
+        }
+    }
+}
+
+/// Strings are concatenated, also with numbers, and lists with lists
+impl ops::Add for Value {
+    type Output = Value;
+
+    fn add(self, other: Value) -> Value {
+        if let (Value::List(left), Value::List(right)) = (&self, &other) {
+            return Value::List(left.iter().chain(right.iter()).cloned().collect());
+        }
+        if let (Value::Str(_), _) | (_, Value::Str(_)) = (&self, &other) {
+            return Value::from(self.text() + &other.text());
+        }
+
+        self.combine(
+            other,
+            i64::checked_add,
+            |left, right| Some(left.add(right)),
+            |left, right| left + right,
+        )
+    }
+}
+
+impl ops::Sub for Value {
+    type Output = Value;
+
+    fn sub(self, other: Value) -> Value {
+        self.combine(
+            other,
+            i64::checked_sub,
+            |left, right| Some(left.sub(right)),
+            |left, right| left - right,
+        )
+    }
+}
+
+impl ops::Mul for Value {
+    type Output = Value;
+
+    fn mul(self, other: Value) -> Value {
+        self.combine(
+            other,
+            i64::checked_mul,
+            |left, right| Some(left.mul(right)),
+            |left, right| left * right,
+        )
+    }
+}
+
+/// Quotient of integers is integer only if it is exact
+impl ops::Div for Value {
+    type Output = Value;
+
+    fn div(self, other: Value) -> Value {
+        self.combine(
+            other,
+            |left, right| match left.checked_rem(right) {
+                Some(0) => left.checked_div(right),
+                _ => None,
+            },
+            Decimal::div,
+            |left, right| left / right,
+        )
+    }
+}
+
+/// Remainder of operands truncated to integers, `NaN` for zero divisor
+impl ops::Rem for Value {
+    type Output = Value;
+
+    fn rem(self, other: Value) -> Value {
+        self.combine(other, i64::checked_rem, Decimal::rem, |left, right| {
+            left.trunc() % right.trunc()
+        })
+    }
+}
+
+impl From<i64> for Value {
+    fn from(val: i64) -> Self {
+        Value::Int(val)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(val: f64) -> Self {
+        Value::Float(val)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(text: &str) -> Self {
+        Value::Str(text.into())
+    }
+}
+
+impl From<String> for Value {
+    fn from(text: String) -> Self {
+        Value::Str(text.into())
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(items: Vec<Value>) -> Self {
+        Value::List(items.into())
+    }
+}
+
+/// Logical values are numbers: `1` is true and `0` is false
+impl From<bool> for Value {
+    fn from(val: bool) -> Self {
+        Value::Int(val as i64)
+    }
+}
+
+/// Integer if it fits into `i64`, float otherwise
+impl FromStr for Value {
+    type Err = ParseFloatError;
+
+    fn from_str(src: &str) -> std::result::Result<Self, Self::Err> {
+        match src.parse() {
+            Ok(val) => Ok(Value::Int(val)),
+            Err(_) => src.parse().map(Value::Float),
+        }
+    }
+}
+
+/// Integral results are shown the same way, whether they are floats or not,
+/// strings are quoted, and list elements are separated with commas
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(val) => write!(f, "{}", val),
+            Value::Float(val) => write!(f, "{}", val),
+            Value::Decimal(val) => write!(f, "{}", val),
+            Value::Str(text) => write!(f, "{:?}", text),
+            Value::List(items) => {
+                write!(f, "[")?;
+                for (idx, item) in items.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Value::Func(func) => write!(f, "{}", func),
+        }
+    }
+}
+
+/// Backend of numbers with fraction
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Mode {
+    #[default]
+    Float,
+    Decimal,
+}
+
+impl Mode {
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Float => "float",
+            Mode::Decimal => "decimal",
+        }
+    }
+
+    /// Literal as evaluated in this mode - lexer keeps fractions exact, and
+    /// all literals are decimals in decimal mode, so integer division is
+    /// exact too. Literals out of float range are errors in float mode,
+    /// instead of becoming infinity or zero.
+    pub fn literal(self, val: Value) -> std::result::Result<Value, String> {
+        let val = match (self, val) {
+            (Mode::Float, Value::Decimal(val)) => {
+                let float = val.to_f64();
+                let problem = if float.is_infinite() {
+                    "too big"
+                } else if float == 0.0 && !val.is_zero() {
+                    "too small"
+                } else {
+                    return Ok(Value::Float(float));
+                };
+                return Err(format!(
+                    "Number is {} for float, `:mode decimal` keeps it exact",
+                    problem
+                ));
+            }
+            (Mode::Decimal, Value::Int(val)) => Value::Decimal(Decimal::from(val)),
+            (Mode::Decimal, Value::Float(val)) => {
+                Decimal::from_f64(val).map_or(Value::Float(val), Value::Decimal)
+            }
+            (_, val) => val,
+        };
+        Ok(val)
+    }
+}
+
+impl FromStr for Mode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "float" => Ok(Mode::Float),
+            "decimal" => Ok(Mode::Decimal),
+            _ => Err(format!("Unknown mode: {}, expected `float` or `decimal`", s).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn integers() {
+        let (seven, three) = (Value::Int(7), Value::Int(3));
+        assert_eq!(Value::Int(10), seven.clone() + three.clone());
+        assert_eq!(Value::Int(21), seven.clone() * three.clone());
+        assert_eq!(Value::Int(1), seven.clone() % three.clone());
+        assert_eq!(Value::Int(1 << 40), Value::Int(2).pow(Value::Int(40)));
+        assert_eq!(Value::Int(2), Value::Int(6) / three);
+        assert_eq!(Value::Float(3.5), seven / Value::Int(2));
+    }
+
+    #[test]
+    fn promotion() {
+        assert_eq!(Value::Float(7.5), Value::Int(7) + Value::Float(0.5));
+        assert_eq!(Value::Float(0.5), Value::Int(2).pow(Value::Int(-1)));
+        assert_eq!(
+            Value::Float(i64::MAX as f64 * 2.0),
+            Value::Int(i64::MAX) * Value::Int(2)
+        );
+        assert!((Value::Int(1) % Value::Int(0)).as_f64().is_nan());
+        assert_eq!(Value::Float(f64::INFINITY), Value::Int(1) / Value::Int(0));
+    }
+
+    #[test]
+    fn decimals() {
+        let decimal = |val| Mode::Decimal.literal(Value::Float(val)).unwrap();
+        let third = Value::Int(1) / decimal(3.0);
+        assert_eq!(
+            "0.3333333333333333333333333333333333333333",
+            third.to_string()
+        );
+        assert!((decimal(0.1) + decimal(0.2)).exact_eq(&decimal(0.3)));
+        assert!(third.approx_eq(&Value::Float(1.0 / 3.0), 1e-9));
+        assert_eq!(decimal(0.125), decimal(2.0).pow(Value::Int(-3)));
+        assert_eq!(Value::Float(1.5), decimal(1.0) + Value::Float(0.5));
+        assert_eq!(Ok(Value::Float(0.5)), Mode::Float.literal(decimal(0.5)));
+    }
+
+    #[test]
+    fn strings() {
+        let hello = Value::from("hello");
+        assert_eq!(
+            Value::from("hello world"),
+            hello.clone() + Value::from(" world")
+        );
+        assert_eq!(
+            Value::from("x = 2.5"),
+            Value::from("x = ") + Value::Float(2.5)
+        );
+        assert!((hello.clone() - Value::Int(1)).as_f64().is_nan());
+        assert!(hello.approx_eq(&Value::from("hello"), 0.1));
+        assert!(!Value::from("1").exact_eq(&Value::Int(1)));
+        assert!(!Value::from("").is_true());
+        assert_eq!(
+            "\"say \\\"hi\\\"\\n\"",
+            Value::from("say \"hi\"\n").source()
+        );
+    }
+
+    #[test]
+    fn lists() {
+        let list = Value::from(vec![Value::Int(1), Value::from("a"), Value::Float(2.5)]);
+        assert_eq!("[1, \"a\", 2.5]", list.to_string());
+        assert_eq!(Value::from("a"), list.index(&Value::Int(1)));
+        assert_eq!(Value::from("a"), list.index(&Value::Float(1.0)));
+        assert!(list.index(&Value::Int(3)).as_f64().is_nan());
+        assert!(list.index(&Value::Int(-1)).as_f64().is_nan());
+        assert!(list.index(&Value::Float(0.5)).as_f64().is_nan());
+        assert_eq!(Some(3), list.length());
+        assert_eq!(Some(2), Value::from("żó").length());
+        assert_eq!(None, Value::Int(1).length());
+
+        let pair = Value::from(vec![Value::Int(1), Value::Int(2)]);
+        assert!(pair.exact_eq(&Value::from(vec![Value::Float(1.0), Value::Int(2)])));
+        assert!(!pair.approx_eq(&Value::Int(1), 0.1));
+        assert_eq!(
+            Value::from(vec![
+                Value::Int(1),
+                Value::Int(2),
+                Value::Int(1),
+                Value::Int(2)
+            ]),
+            pair.clone() + pair
+        );
+        assert!(!Value::from(vec![]).is_true());
+    }
+
+    #[test]
+    fn comparison() {
+        assert!(Value::Int(1).exact_eq(&Value::Float(1.0)));
+        assert!(
+            !Value::Int(9_007_199_254_740_993).approx_eq(&Value::Int(9_007_199_254_740_992), 1e-6)
+        );
+        assert!(Value::Float(0.1 + 0.2).approx_eq(&Value::Float(0.3), 1e-6));
+    }
+
+    #[test]
+    fn rendering() {
+        assert_eq!("4", Value::Float(4.0).to_string());
+        assert_eq!("4.0", Value::Float(4.0).source());
+        assert_eq!("4", Value::Int(4).source());
+        assert_eq!("0.25", Value::Float(0.25).source());
+        assert_eq!(Some("0xff".to_owned()), Value::Int(255).radix_literal(16));
+        assert_eq!(
+            Some("-0b101".to_owned()),
+            Value::Float(-5.0).radix_literal(2)
+        );
+        assert_eq!(None, Value::Float(2.5).radix_literal(8));
+        assert_eq!(Ok(Value::Int(10)), "10".parse());
+        assert_eq!(Ok(Value::Float(2.5)), "2.5".parse());
+    }
+}
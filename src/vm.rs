@@ -0,0 +1,517 @@
+//! Function bodies are compiled once they are defined, and calls run them on
+//! stack machine instead of walking the tree. Variables are substituted while
+//! parsing, so bodies load only their arguments and variables captured by
+//! reference.
+
+#[cfg(feature = "jit")]
+use crate::jit;
+use crate::parser::{call_argument, Callee, Expr, Reference};
+use crate::{Context, Operator, Value};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    Push(Value),
+    /// Argument of given index of the current call
+    Arg(usize),
+    /// Variable of given index, as it is when the instruction runs
+    Load(usize),
+    Binop(Operator),
+    Not,
+    /// Skips to given instruction if left operand of `&&` or `||` decides
+    /// the result
+    ShortCircuit(Operator, usize),
+    /// Pops the top of the stack, and skips to given instruction if it is
+    /// false
+    JumpUnless(usize),
+    Jump(usize),
+    /// Calls callee of given index with given number of arguments from the
+    /// top of the stack
+    Call {
+        callee: usize,
+        args: usize,
+    },
+    /// Assigns top of the stack to variable of given index, leaving it on
+    /// the stack
+    Store(usize),
+    /// Replaces given number of values on the top of the stack with list of
+    /// them
+    List(usize),
+    /// Element of list below the top at index on the top
+    Index,
+    /// Replaces given number of values on the top of the stack with the
+    /// function having them bound
+    Bind(Box<Reference>, usize),
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Program {
+    ops: Vec<Op>,
+    callees: Vec<Callee>,
+    names: Vec<String>,
+    /// Calls of the program, and its native code once it is hot
+    #[cfg(feature = "jit")]
+    hot: jit::Hot,
+}
+
+/// Work left to do while compiling, instructions are emitted in the order
+/// tasks are taken from the stack
+enum Task<'a> {
+    Compile(&'a Expr),
+    Emit(Op),
+    Store(&'a str),
+    Call(&'a Callee, usize),
+    /// Emits jump to be pointed at its target by `Else` or `Patch`
+    Jump(Op),
+    /// Emits jump over the other branch, and points the conditional jump
+    /// after it
+    Else,
+    /// Points the last emitted jump at the next instruction
+    Patch,
+}
+
+/// Bytecode of function body, `None` if it cannot be compiled
+pub fn compile(expr: &Expr) -> Option<Program> {
+    let mut program = Program::default();
+    if program.compile(expr) {
+        Some(program)
+    } else {
+        None
+    }
+}
+
+impl Program {
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    /// Functions called by `Op::Call`, by their indices
+    pub fn callees(&self) -> &[Callee] {
+        &self.callees
+    }
+
+    /// Variables loaded by `Op::Load`, by their indices
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Whether calls run native code
+    #[cfg(feature = "jit")]
+    pub fn is_compiled(&self) -> bool {
+        self.hot.is_compiled()
+    }
+
+    /// Appends instruction, returning its index
+    fn emit(&mut self, op: Op) -> usize {
+        self.ops.push(op);
+        self.ops.len() - 1
+    }
+
+    /// Points jump at given index to the next emitted instruction
+    fn patch(&mut self, jump: usize) {
+        let to = self.ops.len();
+        match &mut self.ops[jump] {
+            Op::ShortCircuit(_, target) | Op::JumpUnless(target) | Op::Jump(target) => *target = to,
+            _ => (),
+        }
+    }
+
+    /// Index of variable name, it is added if it is not known yet
+    fn name(&mut self, var: &str) -> usize {
+        match self.names.iter().position(|name| name == var) {
+            Some(idx) => idx,
+            None => {
+                self.names.push(var.to_owned());
+                self.names.len() - 1
+            }
+        }
+    }
+
+    /// Emits bytecode of the expression, `false` if it cannot be compiled.
+    /// Pending work is kept on explicit stack, as in evaluation, so deep
+    /// trees cannot overflow the native one.
+    fn compile(&mut self, expr: &Expr) -> bool {
+        let mut tasks = vec![Task::Compile(expr)];
+        // Jumps emitted, but not yet pointed at their targets
+        let mut jumps = vec![];
+
+        while let Some(task) = tasks.pop() {
+            let expr = match task {
+                Task::Compile(expr) => expr,
+                Task::Emit(op) => {
+                    self.emit(op);
+                    continue;
+                }
+                Task::Store(var) => {
+                    let idx = self.name(var);
+                    self.emit(Op::Store(idx));
+                    continue;
+                }
+                Task::Call(callee, args) => {
+                    self.callees.push(callee.clone());
+                    self.emit(Op::Call {
+                        callee: self.callees.len() - 1,
+                        args,
+                    });
+                    continue;
+                }
+                Task::Jump(op) => {
+                    jumps.push(self.emit(op));
+                    continue;
+                }
+                Task::Else => {
+                    let skip_other = self.emit(Op::Jump(0));
+                    if let Some(skip_then) = jumps.pop() {
+                        self.patch(skip_then);
+                    }
+                    jumps.push(skip_other);
+                    continue;
+                }
+                Task::Patch => {
+                    if let Some(jump) = jumps.pop() {
+                        self.patch(jump);
+                    }
+                    continue;
+                }
+            };
+
+            match expr {
+                Expr::Value(v) => {
+                    self.emit(Op::Push(v.clone()));
+                }
+                Expr::Argument(arg) => {
+                    self.emit(Op::Arg(*arg));
+                }
+                Expr::Variable(var) => {
+                    let idx = self.name(var);
+                    self.emit(Op::Load(idx));
+                }
+                Expr::Assign(var, val) => {
+                    tasks.push(Task::Store(var));
+                    tasks.push(Task::Compile(val));
+                }
+                Expr::Binary(op @ (Operator::And | Operator::Or), left, right) => {
+                    tasks.push(Task::Patch);
+                    tasks.push(Task::Emit(Op::Binop(*op)));
+                    tasks.push(Task::Compile(right));
+                    tasks.push(Task::Jump(Op::ShortCircuit(*op, 0)));
+                    tasks.push(Task::Compile(left));
+                }
+                Expr::Binary(op, left, right) => {
+                    tasks.push(Task::Emit(Op::Binop(*op)));
+                    tasks.push(Task::Compile(right));
+                    tasks.push(Task::Compile(left));
+                }
+                Expr::Not(expr) => {
+                    tasks.push(Task::Emit(Op::Not));
+                    tasks.push(Task::Compile(expr));
+                }
+                Expr::Cond(cond, then, other) => {
+                    tasks.push(Task::Patch);
+                    tasks.push(Task::Compile(other));
+                    tasks.push(Task::Else);
+                    tasks.push(Task::Compile(then));
+                    tasks.push(Task::Jump(Op::JumpUnless(0)));
+                    tasks.push(Task::Compile(cond));
+                }
+                Expr::Call(callee, args) => {
+                    tasks.push(Task::Call(callee, args.len()));
+                    tasks.extend(args.iter().rev().map(Task::Compile));
+                }
+                Expr::List(items) => {
+                    tasks.push(Task::Emit(Op::List(items.len())));
+                    tasks.extend(items.iter().rev().map(Task::Compile));
+                }
+                Expr::Partial(func, args) => {
+                    tasks.push(Task::Emit(Op::Bind(func.clone(), args.len())));
+                    tasks.extend(args.iter().rev().map(Task::Compile));
+                }
+                Expr::Index(list, idx) => {
+                    tasks.push(Task::Emit(Op::Index));
+                    tasks.push(Task::Compile(idx));
+                    tasks.push(Task::Compile(list));
+                }
+                // Locals would have to be kept out of the stack of operands
+                Expr::Unit | Expr::Define(_) | Expr::Let(..) | Expr::Fold(_) => return false,
+                // Constant can be defined only once, so it is rather statement
+                Expr::Const(..) => return false,
+            }
+        }
+        true
+    }
+
+    pub fn run(&self, context: &mut Context, args: &[Value]) -> Option<Value> {
+        let mut stack = args.to_vec();
+        self.execute(context, &mut stack, 0)
+    }
+
+    /// Arguments of the call are on the stack starting from `base`
+    fn execute(&self, context: &mut Context, stack: &mut Vec<Value>, base: usize) -> Option<Value> {
+        #[cfg(feature = "jit")]
+        if let Some(result) = self.hot.call(self, context, &stack[base..]) {
+            return result;
+        }
+
+        let mut next = 0;
+        while let Some(op) = self.ops.get(next) {
+            next += 1;
+            context.step()?;
+            match *op {
+                Op::Push(ref val) => stack.push(val.clone()),
+                Op::Arg(idx) => stack.push(stack[base + idx].clone()),
+                Op::Load(name) => stack.push(
+                    context
+                        .get_var(&self.names[name])
+                        .unwrap_or(Value::Float(f64::NAN)),
+                ),
+                Op::Binop(op) => {
+                    let right = stack.pop()?;
+                    let left = stack.pop()?;
+                    stack.push(match op {
+                        Operator::Eq => Value::from(left.approx_eq(&right, context.eq_epsilon())),
+                        op => context.allocate(op.eval(left, right)?)?,
+                    });
+                }
+                Op::Not => {
+                    let val = stack.pop()?;
+                    stack.push(Value::from(!val.is_true()));
+                }
+                Op::ShortCircuit(op, to) => {
+                    let left = stack.last()?;
+                    let decided = match op {
+                        Operator::And => !left.is_true(),
+                        _ => left.is_true(),
+                    };
+                    if decided {
+                        stack.pop();
+                        stack.push(Value::from(op == Operator::Or));
+                        next = to;
+                    }
+                }
+                Op::JumpUnless(to) => {
+                    if !stack.pop()?.is_true() {
+                        next = to;
+                    }
+                }
+                Op::Jump(to) => next = to,
+                Op::Call { callee, args } => {
+                    let at = stack.len() - args;
+                    let result = match &self.callees[callee] {
+                        Callee::Native(native) => native.call(context, &stack[at..])?,
+                        Callee::Function(body) => match &body.program {
+                            Some(program) => program.execute(context, stack, at)?,
+                            None => body.expr.evaluate(context, &stack[at..])?,
+                        },
+                        Callee::Argument(idx) => {
+                            let func = stack[base + idx].clone();
+                            call_argument(context, &func, &stack[at..])?
+                        }
+                    };
+                    stack.truncate(at);
+                    stack.push(result);
+                }
+                Op::Store(name) => {
+                    let val = stack.last()?.clone();
+                    if let Err(err) = context.update_var(&self.names[name], val) {
+                        context.fail(err);
+                        return None;
+                    }
+                }
+                Op::List(len) => {
+                    let items = stack.split_off(stack.len() - len);
+                    stack.push(context.allocate(Value::from(items))?);
+                }
+                Op::Bind(ref func, len) => {
+                    let args = stack.split_off(stack.len() - len);
+                    stack.push(Value::Func(func.bind(args)));
+                }
+                Op::Index => {
+                    let idx = stack.pop()?;
+                    let list = stack.pop()?;
+                    stack.push(list.index(&idx));
+                }
+            }
+        }
+
+        stack.pop()
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::parser::Body;
+    use crate::{Interpreter, Mode};
+    use std::sync::Arc;
+
+    fn body(interpreter: &Interpreter, func: &str) -> Arc<Body> {
+        match interpreter.context().get_func(func) {
+            Some(Callee::Function(body)) => body,
+            callee => panic!("Not user function: {:?}", callee),
+        }
+    }
+
+    fn ops(interpreter: &Interpreter, func: &str) -> Vec<Op> {
+        let body = body(interpreter, func);
+        body.program.as_ref().unwrap().ops().to_vec()
+    }
+
+    #[test]
+    fn bytecode() {
+        let mut interpreter = Interpreter::new();
+        interpreter.eval("f a b => a * 2 + b").unwrap();
+        assert_eq!(
+            vec![
+                Op::Arg(0),
+                Op::Push(Value::Int(2)),
+                Op::Binop(Operator::Mul),
+                Op::Arg(1),
+                Op::Binop(Operator::Add),
+            ],
+            ops(&interpreter, "f")
+        );
+
+        interpreter.eval("g a => a && !a").unwrap();
+        assert_eq!(
+            vec![
+                Op::Arg(0),
+                Op::ShortCircuit(Operator::And, 5),
+                Op::Arg(0),
+                Op::Not,
+                Op::Binop(Operator::And),
+            ],
+            ops(&interpreter, "g")
+        );
+    }
+
+    #[test]
+    fn jumps() {
+        let mut interpreter = Interpreter::new();
+        interpreter.eval("f a => a ? 1 : 2").unwrap();
+        assert_eq!(
+            vec![
+                Op::Arg(0),
+                Op::JumpUnless(4),
+                Op::Push(Value::Int(1)),
+                Op::Jump(5),
+                Op::Push(Value::Int(2)),
+            ],
+            ops(&interpreter, "f")
+        );
+        assert_eq!(Some(Value::Int(2)), interpreter.eval("f 0").unwrap());
+        assert_eq!(Some(Value::Int(1)), interpreter.eval("f 3").unwrap());
+
+        let mut interpreter = Interpreter::builder().force(true).build().unwrap();
+        interpreter.eval("abs x => x < 0 ? 0 - x : x").unwrap();
+        assert_eq!(
+            vec![
+                Op::Arg(0),
+                Op::Push(Value::Int(0)),
+                Op::Binop(Operator::Less),
+                Op::JumpUnless(8),
+                Op::Push(Value::Int(0)),
+                Op::Arg(0),
+                Op::Binop(Operator::Sub),
+                Op::Jump(9),
+                Op::Arg(0),
+            ],
+            ops(&interpreter, "abs")
+        );
+        assert_eq!(Some(Value::Int(7)), interpreter.eval("abs(0 - 7)").unwrap());
+        assert_eq!(
+            Some(Value::Float(2.5)),
+            interpreter.eval("abs 2.5").unwrap()
+        );
+    }
+
+    #[test]
+    fn partial() {
+        let mut interpreter = Interpreter::builder().partial(true).build().unwrap();
+        interpreter
+            .eval("add a b => a + b; adder n => add(n); twice f x => f (f x)")
+            .unwrap();
+        assert!(matches!(
+            ops(&interpreter, "adder")[..],
+            [Op::Arg(0), Op::Bind(_, 1)]
+        ));
+        assert_eq!(
+            Some(Value::Int(7)),
+            interpreter.eval("twice(adder 2, 3)").unwrap()
+        );
+    }
+
+    #[test]
+    fn calls() {
+        let mut interpreter = Interpreter::new();
+        interpreter.eval("sq x => x * x").unwrap();
+        interpreter.eval("f a b => sq(a) + max(a, b)").unwrap();
+        assert_eq!(
+            Some(Some(Value::Float(13.0))),
+            interpreter.eval("f 3 4").ok()
+        );
+        assert_eq!(
+            Some(Some(Value::Float(7.0))),
+            interpreter.eval("f 2 3").ok()
+        );
+
+        let ops = ops(&interpreter, "f");
+        assert_eq!(Op::Call { callee: 0, args: 1 }, ops[1]);
+        assert_eq!(Op::Call { callee: 1, args: 2 }, ops[4]);
+    }
+
+    #[test]
+    fn same_as_tree() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .eval("f a b => a || b / 0 == 1 / 0 && !(a % 2 ^ 3)")
+            .unwrap();
+        let body = body(&interpreter, "f");
+        let program = body.program.as_ref().unwrap();
+
+        let values = [
+            Value::Int(0),
+            Value::Int(1),
+            Value::Int(8),
+            Value::Int(-1),
+            Value::Float(0.5),
+            Value::Float(3.0),
+            Mode::Decimal.literal(Value::Float(0.5)).unwrap(),
+        ];
+        for left in &values {
+            for right in &values {
+                let args = [left.clone(), right.clone()];
+                let context = interpreter.context_mut();
+                assert_eq!(
+                    body.expr.evaluate(context, &args),
+                    program.run(context, &args),
+                    "{:?}",
+                    args
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn store() {
+        let mut interpreter = Interpreter::new();
+        interpreter.eval("x = 0").unwrap();
+        interpreter.eval("set a => x = a + 1").unwrap();
+        assert_eq!(Some(Value::Int(3)), interpreter.eval("set 2").unwrap());
+        assert_eq!(Some(Value::Int(3)), interpreter.get_var("x"));
+    }
+
+    #[test]
+    fn load() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .context_mut()
+            .set_capture(crate::Capture::Reference);
+        interpreter.eval("k = 2; inc => k = k + 1").unwrap();
+        assert_eq!(
+            vec![Op::Load(0), Op::Push(Value::Int(1))],
+            ops(&interpreter, "inc")[..2]
+        );
+        interpreter.eval("inc").unwrap();
+        assert_eq!(Some(Value::Int(4)), interpreter.eval("inc").unwrap());
+    }
+}
@@ -0,0 +1,165 @@
+//! Bindings of WebAssembly build for browser playground, built with
+//! `cargo build --lib --target wasm32-unknown-unknown --no-default-features --features wasm`.
+//! Strings are passed through linear memory as UTF-8 - JavaScript writes
+//! input to buffer from `alloc`, and the output is read from `output`
+//! after function returns its length:
+//! ```text
+//! const ptr = exports.alloc(bytes.length);
+//! new Uint8Array(exports.memory.buffer, ptr, bytes.length).set(bytes);
+//! const len = exports.eval(ptr, bytes.length);
+//! const text = decoder.decode(new Uint8Array(exports.memory.buffer, exports.output(), len));
+//! exports.dealloc(ptr, bytes.length);
+//! ```
+//! The session lives in the module instance, lines are evaluated as in the
+//! interactive session - commands included.
+
+use crate::repl::Repl;
+use crate::sink::Event;
+use crate::Interpreter;
+use std::cell::RefCell;
+
+struct Session {
+    repl: Repl,
+    output: String,
+}
+
+impl Session {
+    fn new() -> Self {
+        Session {
+            repl: Repl::new(Interpreter::new()),
+            output: String::new(),
+        }
+    }
+
+    /// Keeps the output to be read, returns its length in bytes
+    fn respond(&mut self, output: String) -> usize {
+        self.output = output;
+        self.output.len()
+    }
+}
+
+thread_local! {
+    static SESSION: RefCell<Session> = RefCell::new(Session::new());
+}
+
+/// Invalid UTF-8 is replaced, as JavaScript strings may hold lone surrogates
+unsafe fn input<'a>(ptr: *const u8, len: usize) -> std::borrow::Cow<'a, str> {
+    String::from_utf8_lossy(std::slice::from_raw_parts(ptr, len))
+}
+
+/// Buffer of `len` bytes for input, released with `dealloc`
+#[no_mangle]
+pub extern "C" fn alloc(len: usize) -> *mut u8 {
+    let mut buffer = Vec::<u8>::with_capacity(len);
+    let ptr = buffer.as_mut_ptr();
+    std::mem::forget(buffer);
+    ptr
+}
+
+/// # Safety
+///
+/// Buffer has to be allocated by `alloc` with the same `len`.
+#[no_mangle]
+pub unsafe extern "C" fn dealloc(ptr: *mut u8, len: usize) {
+    drop(Vec::from_raw_parts(ptr, 0, len));
+}
+
+/// Output of the last call, valid until the next one
+#[no_mangle]
+pub extern "C" fn output() -> *const u8 {
+    SESSION.with(|session| session.borrow().output.as_ptr())
+}
+
+/// Evaluates line, the output is every event it emitted, one per line - as
+/// the terminal shows them
+///
+/// # Safety
+///
+/// `ptr` has to point to `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn eval(ptr: *const u8, len: usize) -> usize {
+    let line = input(ptr, len);
+    SESSION.with(|session| {
+        let mut session = session.borrow_mut();
+        let mut events: Vec<Event> = vec![];
+        if let Err(error) = session.repl.line(&line, None, &mut events) {
+            events.push(Event::Error {
+                error,
+                line: line.to_string(),
+            });
+        }
+        let shown: Vec<_> = events.iter().map(ToString::to_string).collect();
+        session.respond(shown.join("\n"))
+    })
+}
+
+/// Definitions of the session as JSON, see `state`. The output is the
+/// error message if it cannot be saved.
+#[no_mangle]
+pub extern "C" fn save() -> usize {
+    SESSION.with(|session| {
+        let mut session = session.borrow_mut();
+        let state = session.repl.interpreter_mut().save_state();
+        session.respond(state.unwrap_or_else(|err| err.to_string()))
+    })
+}
+
+/// Evaluates definitions saved by `save`, the output is the error message
+/// if they cannot be restored, otherwise it is empty
+///
+/// # Safety
+///
+/// `ptr` has to point to `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn restore(ptr: *const u8, len: usize) -> usize {
+    let state = input(ptr, len);
+    SESSION.with(|session| {
+        let mut session = session.borrow_mut();
+        let result = session.repl.interpreter_mut().restore_state(&state);
+        session.respond(result.err().map_or(String::new(), |err| err.to_string()))
+    })
+}
+
+/// Starts new session, all definitions and settings are dropped
+#[no_mangle]
+pub extern "C" fn reset() {
+    SESSION.with(|session| *session.borrow_mut() = Session::new());
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn call(text: &str, func: unsafe extern "C" fn(*const u8, usize) -> usize) -> String {
+        let ptr = alloc(text.len());
+        let len = unsafe {
+            std::ptr::copy_nonoverlapping(text.as_ptr(), ptr, text.len());
+            let len = func(ptr, text.len());
+            dealloc(ptr, text.len());
+            len
+        };
+        read(len)
+    }
+
+    fn read(len: usize) -> String {
+        let bytes = unsafe { std::slice::from_raw_parts(output(), len) };
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn session() {
+        reset();
+        assert_eq!("= 4", call("x = 2 * 2", eval));
+        assert_eq!("= 8", call("x * 2", eval));
+        assert_eq!("x = 4", call(":vars", eval));
+        assert_eq!("Error[E010]: Undefined symbol: y", call("y + 1", eval));
+
+        let state = read(save());
+        reset();
+        call("x", eval).find("Undefined").unwrap();
+        assert_eq!("", call(&state, restore));
+        assert_eq!("= 5", call("x + 1", eval));
+        call("{", restore).find("Invalid JSON").unwrap();
+    }
+}
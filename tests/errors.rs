@@ -0,0 +1,66 @@
+use interpreter::{run_program, Error, Interpreter, Value};
+use std::thread;
+
+fn error(src: &str) -> Error {
+    run_program(src).pop().unwrap().unwrap_err()
+}
+
+#[test]
+fn invalid_tokens() {
+    assert_eq!(Some(4), error("1 + $").offset());
+    assert!(matches!(error("1.2.3"), Error::Lex { .. }));
+}
+
+#[test]
+fn invalid_expressions() {
+    assert!(matches!(error("1 +"), Error::Parse { .. }));
+    assert!(matches!(error("2 + 2 foo bar"), Error::Parse { .. }));
+    assert!(matches!(error("(1 + 2"), Error::Parse { .. }));
+}
+
+#[test]
+fn failure_does_not_break_session() {
+    let results = run_program("a = 1\na = $\na");
+    assert_eq!(Ok(Some(Value::Int(1))), results[2]);
+}
+
+/// Parser recurses into brackets and calls, so their nesting is limited to
+/// fit in stack of the main thread also in debug builds. Chains of
+/// operators are not limited.
+#[test]
+fn deep_nesting() {
+    let nested = || {
+        let mut interpreter = Interpreter::new();
+        interpreter.eval("f x => x").unwrap();
+        let parens = |depth| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        let chain = |term, terms| vec![term; terms].join(" + ");
+        let calls = |depth| format!("{}1", "f ".repeat(depth));
+
+        for src in &[parens(5_000), calls(5_000)] {
+            let err = interpreter.eval(src).unwrap_err();
+            assert!(matches!(err, Error::Parse { .. }), "{:?}", err);
+        }
+        assert_eq!(Ok(Some(Value::Int(1))), interpreter.eval(&parens(200)));
+        assert_eq!(Ok(Some(Value::Int(1))), interpreter.eval(&calls(200)));
+        assert_eq!(
+            Ok(Some(Value::Int(20_000))),
+            interpreter.eval(&chain("1", 20_000))
+        );
+
+        // Calls are not folded while parsing, so the chain is compiled and
+        // evaluated
+        let body = chain("f(x)", 20_000);
+        interpreter.eval(&format!("g x => {}", body)).unwrap();
+        assert_eq!(Ok(Some(Value::Int(40_000))), interpreter.eval("g 2"));
+        assert_eq!(
+            Ok(Some(Value::Int(60_000))),
+            interpreter.eval(&body.replace('x', "3"))
+        );
+    };
+    thread::Builder::new()
+        .stack_size(8 << 20)
+        .spawn(nested)
+        .unwrap()
+        .join()
+        .unwrap();
+}
@@ -0,0 +1,25 @@
+use interpreter::{run_program, Error, Value};
+
+#[test]
+fn sums_and_products() {
+    let results =
+        run_program("sum i = 1 to 10 of i\nprod i = 1 to 4 of i + 1\nsum i = 1 to 3 of \"ab\"\ni");
+    assert_eq!(
+        vec![
+            Ok(Some(Value::Int(55))),
+            Ok(Some(Value::Int(120))),
+            Ok(Some(Value::from("ababab"))),
+            Err(Error::UndefinedSymbol("i".to_owned())),
+        ],
+        results
+    );
+}
+
+#[test]
+fn in_functions() {
+    let results = run_program(
+        "sq x => x * x\nsquares n => sum i = 1 to n of sq(i)\nsquares 3\n1 + squares(2)",
+    );
+    assert_eq!(Ok(Some(Value::Int(14))), results[2]);
+    assert_eq!(Ok(Some(Value::Int(6))), results[3]);
+}
@@ -0,0 +1,197 @@
+use interpreter::{run_program, Capture, Error, Value};
+
+#[test]
+fn definition_and_call() {
+    let results = run_program("add a b => a + b\nadd 1 2\nadd add 1 2 3\nadd(4, 5) * 2");
+    assert_eq!(
+        vec![
+            Ok(None),
+            Ok(Some(Value::Int(3))),
+            Ok(Some(Value::Int(6))),
+            Ok(Some(Value::Int(18)))
+        ],
+        results
+    );
+}
+
+#[test]
+fn calls_between_functions() {
+    let src = "sq x => x * x\nsum_sq a b => sq(a) + sq(b)\nsum_sq 3 4";
+    assert_eq!(Ok(Some(Value::Int(25))), run_program(src)[2]);
+}
+
+#[test]
+fn builtins() {
+    let results = run_program("max(sqrt 16, 3)\nhypot 3 4\nfloor 2.7 + 1");
+    let expected = vec![
+        Ok(Some(Value::Float(4.0))),
+        Ok(Some(Value::Float(5.0))),
+        Ok(Some(Value::Float(3.0))),
+    ];
+    assert_eq!(expected, results);
+}
+
+#[test]
+fn arity() {
+    let results = run_program("avg a b c => (a + b + c) / 3\navg 1 2\navg(1, 2)");
+    let mismatch = Err(Error::ArityMismatch {
+        func: "avg".to_owned(),
+        expected: 3,
+        got: 2,
+    });
+    assert_eq!(vec![Ok(None), mismatch.clone(), mismatch], results);
+}
+
+#[test]
+fn name_conflicts() {
+    let results = run_program("x = 1\nx a => a\nf a => a\nf = 2");
+    assert!(matches!(results[1], Err(Error::NameConflict { .. })));
+    assert!(matches!(results[3], Err(Error::NameConflict { .. })));
+}
+
+#[test]
+fn higher_order() {
+    let results = run_program(
+        "sq x => x * x\ntwice f x => f (f x)\ntwice sq 3\ntwice(sq, 3)\nquad f x => twice f (twice(f, x))\nquad sq 2\ntwice(max, 1)",
+    );
+    assert_eq!(
+        vec![
+            Ok(None),
+            Ok(None),
+            Ok(Some(Value::Int(81))),
+            Ok(Some(Value::Int(81))),
+            Ok(None),
+            Ok(Some(Value::Int(65536))),
+        ],
+        results[..6]
+    );
+    assert!(results[6]
+        .as_ref()
+        .unwrap()
+        .as_ref()
+        .unwrap()
+        .as_f64()
+        .is_nan());
+}
+
+#[test]
+fn default_arguments() {
+    let results = run_program(
+        "steps lo hi by=1 => (hi - lo) / by\nsteps 0 10\nsteps(0, 10, 5)\nsteps 0 10 2 + 3",
+    );
+    assert_eq!(Ok(Some(Value::Int(10))), results[1]);
+    assert_eq!(Ok(Some(Value::Int(2))), results[2]);
+    assert_eq!(Ok(Some(Value::Int(2))), results[3]);
+    // Defaults see what bodies see
+    assert!(matches!(
+        run_program("k = 1\nf x=k => x")[1],
+        Err(Error::UndefinedSymbol(_))
+    ));
+}
+
+#[test]
+fn named_arguments() {
+    let results = run_program(
+        "area width height=1 => width * height\narea(height=3, width=4)\narea(width=5)\narea(height=2)",
+    );
+    assert_eq!(Ok(Some(Value::Int(12))), results[1]);
+    assert_eq!(Ok(Some(Value::Int(5))), results[2]);
+    assert!(matches!(results[3], Err(Error::Parse { .. })));
+}
+
+#[test]
+fn pipes() {
+    let results = run_program(
+        "sq x => x * x\nsub a b => a - b\nall x => x |> sub(10) |> sq\nall 7\n2 |> sub\n(2 |> sq) + 1",
+    );
+    assert_eq!(Ok(Some(Value::Int(9))), results[3]);
+    assert!(matches!(results[4], Err(Error::ArityMismatch { .. })));
+    assert_eq!(Ok(Some(Value::Int(5))), results[5]);
+}
+
+#[test]
+fn partial_application() {
+    let mut interpreter = interpreter::Interpreter::builder()
+        .partial(true)
+        .build()
+        .unwrap();
+    interpreter
+        .eval("add3 a b c => a + b + c; twice f x => f (f x)")
+        .unwrap();
+    assert_eq!(
+        Ok(Some(Value::Int(16))),
+        interpreter.eval("twice(add3(1, 2), 10)")
+    );
+    assert_eq!(
+        Ok(Some(Value::Int(9))),
+        interpreter.eval("let f = add3 1 in f 3 5")
+    );
+    assert_eq!(
+        Ok(Some(Value::Int(6))),
+        interpreter.eval("inc = add3(0, 1); twice inc 4")
+    );
+    assert_eq!(
+        "add3(0, 1)",
+        interpreter.get_var("inc").unwrap().to_string()
+    );
+    assert!(matches!(
+        interpreter.eval("add3(1, 2, 3, 4)"),
+        Err(Error::ArityMismatch { .. })
+    ));
+}
+
+#[test]
+fn endless_recursion() {
+    let mut interpreter = interpreter::Interpreter::new();
+    interpreter.eval("w f => f(f)").unwrap();
+    assert_eq!(Ok(None), interpreter.eval("w(w)"));
+    assert_eq!(1, interpreter.context_mut().take_warnings().len());
+    assert_eq!(Ok(None), interpreter.eval("w(w) || 2"));
+
+    interpreter
+        .eval("sq x => x * x; twice f x => f (f x)")
+        .unwrap();
+    assert_eq!(Ok(Some(Value::Int(16))), interpreter.eval("twice sq 2"));
+}
+
+#[test]
+fn captures() {
+    let capturing = |capture| {
+        interpreter::Interpreter::builder()
+            .with_capture(capture)
+            .build()
+            .unwrap()
+    };
+
+    let mut copy = capturing(Capture::Copy);
+    copy.eval("k = 10; addk x => x + k; k = 20").unwrap();
+    assert_eq!(Ok(Some(Value::Int(11))), copy.eval("addk 1"));
+    copy.eval("inc => k = k + 1").unwrap();
+    copy.eval("inc; inc").unwrap();
+    assert_eq!(Some(Value::Int(21)), copy.get_var("k"));
+
+    let mut reference = capturing(Capture::Reference);
+    reference.eval("k = 10; addk x => x + k; k = 20").unwrap();
+    assert_eq!(Ok(Some(Value::Int(21))), reference.eval("addk 1"));
+    reference.eval("inc => k = k + 1").unwrap();
+    reference.eval("inc; inc").unwrap();
+    assert_eq!(Some(Value::Int(22)), reference.get_var("k"));
+    assert_eq!(
+        vec!["k = 22", "addk x => x + k", "inc => k = k + 1"],
+        reference.context().definitions().unwrap()
+    );
+
+    // Arguments hide captured variables
+    assert_eq!(Ok(None), reference.eval("f k => k * 2"));
+    assert_eq!(Ok(Some(Value::Int(6))), reference.eval("f 3"));
+}
+
+#[test]
+fn completion() {
+    let mut interpreter = interpreter::Interpreter::new();
+    interpreter.eval("square x => x * x; sq = 2").unwrap();
+    assert_eq!(
+        vec!["sq", "sqrt", "square"],
+        interpreter.context().complete("sq")
+    );
+}
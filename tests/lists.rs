@@ -0,0 +1,42 @@
+use interpreter::{run_program, Value};
+
+#[test]
+fn indexing() {
+    let results = run_program(
+        "xs = [1, [2, 3], \"a\"]\nxs[1][0] + xs[0]\nsecond ys => ys[1]\nsecond xs\nxs[3]",
+    );
+    assert_eq!(
+        vec![
+            Ok(Some(Value::from(vec![
+                Value::Int(1),
+                Value::from(vec![Value::Int(2), Value::Int(3)]),
+                Value::from("a")
+            ]))),
+            Ok(Some(Value::Int(3))),
+            Ok(None),
+            Ok(Some(Value::from(vec![Value::Int(2), Value::Int(3)]))),
+        ],
+        results[..4]
+    );
+    assert!(results[4]
+        .as_ref()
+        .unwrap()
+        .as_ref()
+        .unwrap()
+        .as_f64()
+        .is_nan());
+}
+
+#[test]
+fn length() {
+    let results = run_program("len [1, 2, 3]\nlen(\"abc\") + len([])\nlen [1] + [2]\n[1, 2");
+    assert_eq!(
+        vec![
+            Ok(Some(Value::Int(3))),
+            Ok(Some(Value::Int(3))),
+            Ok(Some(Value::Int(2)))
+        ],
+        results[..3]
+    );
+    results[3].as_ref().unwrap_err();
+}
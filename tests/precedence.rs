@@ -0,0 +1,48 @@
+use interpreter::{run_program, Interpreter, Value};
+
+fn eval(src: &str) -> Option<Value> {
+    run_program(src).pop().unwrap().unwrap()
+}
+
+#[test]
+fn arithmetic() {
+    assert_eq!(Some(Value::Int(14)), eval("2 + 3 * 4"));
+    assert_eq!(Some(Value::Int(20)), eval("(2 + 3) * 4"));
+    assert_eq!(Some(Value::Int(1)), eval("7 % 3"));
+    assert_eq!(Some(Value::Int(2)), eval("8 / 2 / 2"));
+    assert_eq!(Some(Value::Int(5)), eval("10 - 3 - 2"));
+}
+
+#[test]
+fn power() {
+    assert_eq!(Some(Value::Int(512)), eval("2 ^ 3 ^ 2"));
+    assert_eq!(Some(Value::Int(18)), eval("2 * 3 ^ 2"));
+}
+
+#[test]
+fn logic_and_comparison() {
+    assert_eq!(Some(Value::Int(1)), eval("1 + 1 == 2 && 0 || 1"));
+    assert_eq!(Some(Value::Int(0)), eval("!1 || 0"));
+    assert_eq!(Some(Value::Int(1)), eval("0.1 + 0.2 == 0.3"));
+    assert_eq!(Some(Value::Int(1)), eval("1 + 1 < 3 && 2 > 1"));
+    assert_eq!(Some(Value::Int(0)), eval("2 * 2 < 4 || 1 > 1"));
+    assert_eq!(Some(Value::Int(1)), eval("\"a\" < \"b\""));
+    assert_eq!(Some(Value::Int(0)), eval("\"1\" < 2 || \"1\" > 2"));
+}
+
+#[test]
+fn conditional() {
+    assert_eq!(Some(Value::Int(7)), eval("1 + 1 == 2 ? 3 + 4 : 5"));
+    assert_eq!(Some(Value::Int(6)), eval("0 || 0 ? 1 : 2 * 3"));
+    assert_eq!(Some(Value::Int(2)), eval("0 ? 1 : 0 ? 3 : 2"));
+    assert_eq!(
+        Some(Value::Int(0)),
+        eval("x = 0; f a => a ? 1 : x = 3; f 2; x")
+    );
+
+    // Shadows builtin `abs`
+    let mut interpreter = Interpreter::builder().force(true).build().unwrap();
+    interpreter.eval("abs x => x < 0 ? 0 - x : x").unwrap();
+    assert_eq!(Some(Value::Int(5)), interpreter.eval("abs(0 - 5)").unwrap());
+    assert_eq!(Some(Value::Int(5)), interpreter.eval("abs 5").unwrap());
+}
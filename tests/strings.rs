@@ -0,0 +1,31 @@
+use interpreter::{run_program, Value};
+
+#[test]
+fn concatenation() {
+    let results = run_program(
+        "name = \"world\"\ngreet x => \"hello \" + x + \"!\"\ngreet name\n\"n = \" + 2 * 3",
+    );
+    assert_eq!(
+        vec![
+            Ok(Some(Value::from("world"))),
+            Ok(None),
+            Ok(Some(Value::from("hello world!"))),
+            Ok(Some(Value::from("n = 6")))
+        ],
+        results
+    );
+}
+
+#[test]
+fn comparison() {
+    let results = run_program("\"a\" == \"a\"\n\"a\" === \"b\"\n\"1\" == 1\n!\"\" && \"x\"");
+    assert_eq!(
+        vec![
+            Ok(Some(Value::Int(1))),
+            Ok(Some(Value::Int(0))),
+            Ok(Some(Value::Int(0))),
+            Ok(Some(Value::Int(1)))
+        ],
+        results
+    );
+}
@@ -0,0 +1,85 @@
+use interpreter::{run_program, Error, Interpreter, Value};
+
+#[test]
+fn assignment() {
+    let results = run_program("a = 4\na\na + 1\na = a + 1\na");
+    assert_eq!(
+        vec![
+            Ok(Some(Value::Int(4))),
+            Ok(Some(Value::Int(4))),
+            Ok(Some(Value::Int(5))),
+            Ok(Some(Value::Int(5))),
+            Ok(Some(Value::Int(5)))
+        ],
+        results
+    );
+}
+
+#[test]
+fn chained_assignment() {
+    let results = run_program("x = y = 7\nx + y");
+    assert_eq!(
+        vec![Ok(Some(Value::Int(7))), Ok(Some(Value::Int(14)))],
+        results
+    );
+}
+
+#[test]
+fn statements_and_last_result() {
+    let results = run_program("a = 1; b = a + 1; a + b\n_ * 2");
+    assert_eq!(
+        vec![Ok(Some(Value::Int(3))), Ok(Some(Value::Int(6)))],
+        results
+    );
+}
+
+#[test]
+fn constants() {
+    let results = run_program("tau == 2 * pi\npi = 3");
+    assert_eq!(Ok(Some(Value::Int(1))), results[0]);
+    assert!(matches!(results[1], Err(Error::Eval(_))));
+
+    let results = run_program("const g = 9.81\nfall t => g * t * t / 2\nfall 2\ng = 10\ng");
+    assert_eq!(Ok(Some(Value::Float(19.62))), results[2]);
+    assert!(matches!(results[3], Err(Error::Eval(_))));
+    assert_eq!(Ok(Some(Value::Float(9.81))), results[4]);
+}
+
+#[test]
+fn undefined() {
+    assert_eq!(
+        vec![Err(Error::UndefinedSymbol("b".to_owned()))],
+        run_program("b + 1")
+    );
+}
+
+#[test]
+fn removed() {
+    let mut interpreter = Interpreter::new();
+    interpreter.eval("x = 1; sq x => x * x").unwrap();
+    let context = interpreter.context_mut();
+    assert_eq!(Ok("variable"), context.remove_symbol("x"));
+    assert_eq!(Ok("function"), context.remove_symbol("sq"));
+    assert!(matches!(context.remove_symbol("pi"), Err(Error::Eval(_))));
+    assert!(context.definitions().unwrap().is_empty());
+    assert!(matches!(
+        interpreter.eval("sq 2"),
+        Err(Error::UndefinedSymbol(_))
+    ));
+    assert_eq!(Ok(Some(Value::Int(3))), interpreter.eval("sq = 3"));
+}
+
+#[test]
+fn local_bindings() {
+    let results = run_program("x = 4\nlet t = x * 2 in t + 1\nt\nlet x = 1 in x\nx");
+    assert_eq!(
+        vec![
+            Ok(Some(Value::Int(4))),
+            Ok(Some(Value::Int(9))),
+            Err(Error::UndefinedSymbol("t".to_owned())),
+            Ok(Some(Value::Int(1))),
+            Ok(Some(Value::Int(4))),
+        ],
+        results
+    );
+}